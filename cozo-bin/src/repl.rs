@@ -17,7 +17,7 @@ use clap::Args;
 use miette::{bail, miette, IntoDiagnostic};
 use serde_json::{json, Value};
 
-use cozo::{DataValue, DbInstance};
+use cozo::{DataValue, DbInstance, NamedRows};
 
 struct Indented;
 
@@ -58,6 +58,26 @@ impl rustyline::validate::Validator for Indented {
     }
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum DisplayMode {
+    Table,
+    Json,
+}
+
+struct DisplayOptions {
+    mode: DisplayMode,
+    max_rows: Option<usize>,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        Self {
+            mode: DisplayMode::Table,
+            max_rows: None,
+        }
+    }
+}
+
 #[derive(Args, Debug)]
 pub(crate) struct ReplArgs {
     /// Database engine, can be `mem`, `sqlite`, `rocksdb` and others.
@@ -98,6 +118,7 @@ pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
     let mut rl = rustyline::Editor::<Indented>::new()?;
     let mut params = BTreeMap::new();
     let mut save_next: Option<String> = None;
+    let mut display = DisplayOptions::default();
     rl.set_helper(Some(Indented));
 
     let history_file = ".cozo_repl_history";
@@ -109,7 +130,9 @@ pub(crate) fn repl_main(args: ReplArgs) -> Result<(), Box<dyn Error>> {
         let readline = rl.readline("=> ");
         match readline {
             Ok(line) => {
-                if let Err(err) = process_line(&line, &db, &mut params, &mut save_next) {
+                if let Err(err) =
+                    process_line(&line, &db, &mut params, &mut save_next, &mut display)
+                {
                     eprintln!("{err:?}");
                 }
                 rl.add_history_entry(line);
@@ -139,6 +162,7 @@ fn process_line(
     db: &DbInstance,
     params: &mut BTreeMap<String, DataValue>,
     save_next: &mut Option<String>,
+    display: &mut DisplayOptions,
 ) -> miette::Result<()> {
     let line = line.trim();
     if line.is_empty() {
@@ -171,6 +195,31 @@ fn process_line(
                 let display = serde_json::to_string_pretty(&json!(&params)).into_diagnostic()?;
                 println!("{display}");
             }
+            "display" => {
+                let (key, v_str) = payload
+                    .trim()
+                    .split_once(|c: char| c.is_whitespace())
+                    .unwrap_or((payload.trim(), ""));
+                match (key, v_str.trim()) {
+                    ("", _) => {
+                        let max_rows = match display.max_rows {
+                            None => "unlimited".to_string(),
+                            Some(n) => n.to_string(),
+                        };
+                        println!("mode: {:?}, max_rows: {}", display.mode, max_rows);
+                    }
+                    ("mode", "table") => display.mode = DisplayMode::Table,
+                    ("mode", "json") => display.mode = DisplayMode::Json,
+                    ("max_rows", "") | ("max_rows", "unlimited") => display.max_rows = None,
+                    ("max_rows", n) => {
+                        display.max_rows = Some(n.parse().into_diagnostic()?);
+                    }
+                    _ => bail!(
+                        "Bad display syntax. Should be '%display mode <table|json>' \
+                        or '%display max_rows <N|unlimited>'."
+                    ),
+                }
+            }
             "backup" => {
                 let path = payload.trim();
                 if path.is_empty() {
@@ -241,27 +290,42 @@ fn process_line(
                 .into_diagnostic()?;
             *save_next = None;
         } else {
-            use prettytable::format;
-            let mut table = prettytable::Table::new();
-            let headers = out
-                .headers
-                .iter()
-                .map(prettytable::Cell::from)
-                .collect::<Vec<_>>();
-            table.set_titles(prettytable::Row::new(headers));
-            let rows = out
-                .rows
-                .iter()
-                .map(|r| r.iter().map(|c| format!("{c}")).collect::<Vec<_>>())
-                .collect::<Vec<_>>();
-            let rows = rows
-                .iter()
-                .map(|r| r.iter().map(prettytable::Cell::from).collect::<Vec<_>>());
-            for row in rows {
-                table.add_row(prettytable::Row::new(row));
+            let total = out.rows.len();
+            let shown = display.max_rows.unwrap_or(total).min(total);
+            match display.mode {
+                DisplayMode::Json => {
+                    let shown_rows =
+                        NamedRows::new(out.headers.clone(), out.rows[..shown].to_vec());
+                    let display =
+                        serde_json::to_string_pretty(&shown_rows.into_json()).into_diagnostic()?;
+                    println!("{display}");
+                }
+                DisplayMode::Table => {
+                    use prettytable::format;
+                    let mut table = prettytable::Table::new();
+                    let headers = out
+                        .headers
+                        .iter()
+                        .map(prettytable::Cell::from)
+                        .collect::<Vec<_>>();
+                    table.set_titles(prettytable::Row::new(headers));
+                    let rows = out.rows[..shown]
+                        .iter()
+                        .map(|r| r.iter().map(|c| format!("{c}")).collect::<Vec<_>>())
+                        .collect::<Vec<_>>();
+                    let rows = rows
+                        .iter()
+                        .map(|r| r.iter().map(prettytable::Cell::from).collect::<Vec<_>>());
+                    for row in rows {
+                        table.add_row(prettytable::Row::new(row));
+                    }
+                    table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
+                    table.printstd();
+                }
+            }
+            if shown < total {
+                println!("({} of {} rows shown)", shown, total);
             }
-            table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
-            table.printstd();
         }
     }
     Ok(())
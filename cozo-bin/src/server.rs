@@ -34,7 +34,8 @@ use tower_http::compression::CompressionLayer;
 use tower_http::cors::{Any, CorsLayer};
 
 use cozo::{
-    format_error_as_json, DataValue, DbInstance, MultiTransaction, NamedRows, SimpleFixedRule,
+    format_error_as_json, CancelToken, DataValue, DbInstance, MultiTransaction, NamedRows,
+    SimpleFixedRule,
 };
 
 #[derive(Args, Debug)]
@@ -279,6 +280,14 @@ struct QueryPayload {
     params: BTreeMap<String, serde_json::Value>,
 }
 
+struct CancelOnDrop(CancelToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel()
+    }
+}
+
 async fn text_query(
     State(st): State<DbState>,
     Json(payload): Json<QueryPayload>,
@@ -288,7 +297,14 @@ async fn text_query(
         .into_iter()
         .map(|(k, v)| (k, DataValue::from(v)))
         .collect();
-    let result = spawn_blocking(move || st.db.run_script_fold_err(&payload.script, params)).await;
+    // the handler is dropped when the client disconnects, killing the queries of the script
+    let cancel_token = CancelToken::new();
+    let _cancel_on_drop = CancelOnDrop(cancel_token.clone());
+    let result = spawn_blocking(move || {
+        st.db
+            .run_script_fold_err_cancellable(&payload.script, params, &cancel_token)
+    })
+    .await;
     match result {
         Ok(res) => wrap_json(res),
         Err(err) => internal_error(err),
@@ -0,0 +1,429 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Programmatic construction of queries, for applications that generate them dynamically.
+//!
+//! ```
+//! use cozo::builder::{Atom, QueryBuilder, RuleBuilder, Term};
+//! use cozo::{DataValue, DbInstance};
+//!
+//! let db = DbInstance::new("mem", "", "").unwrap();
+//! let query = QueryBuilder::new()
+//!     .rule(
+//!         RuleBuilder::constant("people", ["name", "age"])
+//!             .row([DataValue::from("Alice"), DataValue::from(31)])
+//!             .row([DataValue::from("Bob"), DataValue::from(25)]),
+//!     )
+//!     .rule(
+//!         RuleBuilder::entry(["name"])
+//!             .atom(Atom::rule("people", [Term::var("name"), Term::var("age")]))
+//!             .atom(Atom::filter(Term::var("age").gt(Term::value(30)))),
+//!     );
+//! let result = db.run_query(&query).unwrap();
+//! assert_eq!(result.rows, vec![vec![DataValue::from("Alice")]]);
+//! ```
+//!
+//! The builder produces CozoScript together with its parameters, so the program is checked
+//! by the same parser as scripts written by hand. Identifiers are validated while building,
+//! and values are always passed as parameters, never spliced into the script text,
+//! so that no value can change the structure of the query.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use pest::Parser;
+use thiserror::Error;
+
+use crate::data::value::DataValue;
+use crate::parse::{CozoScriptParser, Rule};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Invalid {0} name {1:?} in query builder")]
+#[diagnostic(code(builder::bad_ident))]
+struct BadIdentifier(&'static str, String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Rule {0} has neither a body nor rows")]
+#[diagnostic(code(builder::empty_rule))]
+struct EmptyRule(String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Row of constant rule {0} has {2} values, but the rule head has {1} columns")]
+#[diagnostic(code(builder::row_width))]
+struct RowWidth(String, usize, usize);
+
+/// An expression appearing in a rule
+#[derive(Clone, Debug)]
+pub enum Term {
+    /// A variable
+    Var(String),
+    /// A constant, passed to the query as a parameter
+    Value(DataValue),
+    /// A function applied to arguments, e.g. `Apply("concat", ...)`
+    Apply(String, Vec<Term>),
+}
+
+impl Term {
+    /// A variable
+    pub fn var(name: impl Into<String>) -> Self {
+        Term::Var(name.into())
+    }
+    /// A constant
+    pub fn value(v: impl Into<DataValue>) -> Self {
+        Term::Value(v.into())
+    }
+    /// The function `func` applied to `args`
+    pub fn apply(func: impl Into<String>, args: impl IntoIterator<Item = Term>) -> Self {
+        Term::Apply(func.into(), args.into_iter().collect())
+    }
+    /// `self == other`
+    pub fn eq(self, other: Term) -> Self {
+        Term::apply("eq", [self, other])
+    }
+    /// `self != other`
+    pub fn ne(self, other: Term) -> Self {
+        Term::apply("neq", [self, other])
+    }
+    /// `self > other`
+    pub fn gt(self, other: Term) -> Self {
+        Term::apply("gt", [self, other])
+    }
+    /// `self >= other`
+    pub fn ge(self, other: Term) -> Self {
+        Term::apply("ge", [self, other])
+    }
+    /// `self < other`
+    pub fn lt(self, other: Term) -> Self {
+        Term::apply("lt", [self, other])
+    }
+    /// `self <= other`
+    pub fn le(self, other: Term) -> Self {
+        Term::apply("le", [self, other])
+    }
+    /// `self && other`
+    pub fn and(self, other: Term) -> Self {
+        Term::apply("and", [self, other])
+    }
+    /// `self || other`
+    pub fn or(self, other: Term) -> Self {
+        Term::apply("or", [self, other])
+    }
+}
+
+/// An atom in the body of a rule
+#[derive(Clone, Debug)]
+pub enum Atom {
+    /// A stored relation with some of its columns bound, as in `*rel{col: term}`
+    Stored(String, Vec<(String, Term)>),
+    /// An application of a rule, as in `rule[term, ...]`
+    Rule(String, Vec<Term>),
+    /// A condition that must hold
+    Filter(Term),
+    /// Binding a variable to the value of a term, as in `var = term`
+    Unify(String, Term),
+    /// The negation of an atom
+    Not(Box<Atom>),
+}
+
+impl Atom {
+    /// The stored relation `relation`, with the named columns bound to terms
+    pub fn stored<C: Into<String>>(
+        relation: impl Into<String>,
+        columns: impl IntoIterator<Item = (C, Term)>,
+    ) -> Self {
+        Atom::Stored(
+            relation.into(),
+            columns.into_iter().map(|(c, t)| (c.into(), t)).collect(),
+        )
+    }
+    /// The rule `name` applied to `args`
+    pub fn rule(name: impl Into<String>, args: impl IntoIterator<Item = Term>) -> Self {
+        Atom::Rule(name.into(), args.into_iter().collect())
+    }
+    /// A condition that must hold
+    pub fn filter(cond: Term) -> Self {
+        Atom::Filter(cond)
+    }
+    /// Binding `var` to the value of `term`
+    pub fn unify(var: impl Into<String>, term: Term) -> Self {
+        Atom::Unify(var.into(), term)
+    }
+    /// The negation of `atom`
+    pub fn negated(atom: Atom) -> Self {
+        Atom::Not(Box::new(atom))
+    }
+}
+
+/// A rule, defined either by a body of atoms or by constant rows
+#[derive(Clone, Debug)]
+pub struct RuleBuilder {
+    name: String,
+    head: Vec<(String, Option<String>)>,
+    body: Vec<Atom>,
+    rows: Option<Vec<Vec<DataValue>>>,
+}
+
+impl RuleBuilder {
+    /// A rule named `name`, with the variables `head` in its head
+    pub fn new<V: Into<String>>(
+        name: impl Into<String>,
+        head: impl IntoIterator<Item = V>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            head: head.into_iter().map(|v| (v.into(), None)).collect(),
+            body: vec![],
+            rows: None,
+        }
+    }
+    /// The entry rule `?`, whose rows are the result of the query
+    pub fn entry<V: Into<String>>(head: impl IntoIterator<Item = V>) -> Self {
+        Self::new("?", head)
+    }
+    /// A rule with the rows given by [row](Self::row), with columns named `head`
+    pub fn constant<V: Into<String>>(
+        name: impl Into<String>,
+        head: impl IntoIterator<Item = V>,
+    ) -> Self {
+        Self {
+            rows: Some(vec![]),
+            ..Self::new(name, head)
+        }
+    }
+    /// Add an aggregated column to the head, e.g. `count(var)`
+    pub fn aggr(mut self, aggr: impl Into<String>, var: impl Into<String>) -> Self {
+        self.head.push((var.into(), Some(aggr.into())));
+        self
+    }
+    /// Add an atom to the body
+    pub fn atom(mut self, atom: Atom) -> Self {
+        self.body.push(atom);
+        self
+    }
+    /// Add a row to a constant rule
+    pub fn row(mut self, row: impl IntoIterator<Item = DataValue>) -> Self {
+        self.rows
+            .get_or_insert_with(Vec::new)
+            .push(row.into_iter().collect());
+        self
+    }
+}
+
+/// How the result of a query is written to a stored relation
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum WriteOp {
+    /// `:create`
+    Create,
+    /// `:replace`
+    Replace,
+    /// `:put`
+    Put,
+    /// `:rm`
+    Rm,
+}
+
+/// A query, made of rules and options
+#[derive(Clone, Debug, Default)]
+pub struct QueryBuilder {
+    rules: Vec<RuleBuilder>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+    order: Vec<(String, bool)>,
+    write: Option<(WriteOp, String, Vec<String>, Vec<String>)>,
+}
+
+impl QueryBuilder {
+    /// An empty query
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Add a rule. Rules with the same name are alternatives, as in CozoScript.
+    pub fn rule(mut self, rule: RuleBuilder) -> Self {
+        self.rules.push(rule);
+        self
+    }
+    /// Return at most `n` rows
+    pub fn limit(mut self, n: usize) -> Self {
+        self.limit = Some(n);
+        self
+    }
+    /// Skip the first `n` rows
+    pub fn offset(mut self, n: usize) -> Self {
+        self.offset = Some(n);
+        self
+    }
+    /// Sort the rows by the column `var`, after the columns given before
+    pub fn order_by(mut self, var: impl Into<String>, descending: bool) -> Self {
+        self.order.push((var.into(), descending));
+        self
+    }
+    /// Write the rows to the stored relation `relation`, with the given key and value columns
+    pub fn write<K: Into<String>, V: Into<String>>(
+        mut self,
+        op: WriteOp,
+        relation: impl Into<String>,
+        keys: impl IntoIterator<Item = K>,
+        values: impl IntoIterator<Item = V>,
+    ) -> Self {
+        self.write = Some((
+            op,
+            relation.into(),
+            keys.into_iter().map(|k| k.into()).collect(),
+            values.into_iter().map(|v| v.into()).collect(),
+        ));
+        self
+    }
+    /// The CozoScript of the query, and the parameters to run it with
+    pub fn build(&self) -> Result<(String, BTreeMap<String, DataValue>)> {
+        let mut out = ScriptWriter::default();
+        for rule in &self.rules {
+            out.rule(rule)?;
+        }
+        if let Some(n) = self.limit {
+            writeln!(out.script, ":limit {n}").unwrap();
+        }
+        if let Some(n) = self.offset {
+            writeln!(out.script, ":offset {n}").unwrap();
+        }
+        if !self.order.is_empty() {
+            let mut sorters = vec![];
+            for (var, desc) in &self.order {
+                check_ident("variable", var)?;
+                sorters.push(format!("{}{var}", if *desc { "-" } else { "" }));
+            }
+            writeln!(out.script, ":order {}", sorters.join(", ")).unwrap();
+        }
+        if let Some((op, rel, keys, vals)) = &self.write {
+            check_relation(rel)?;
+            for col in keys.iter().chain(vals) {
+                check_ident("column", col)?;
+            }
+            let op = match op {
+                WriteOp::Create => ":create",
+                WriteOp::Replace => ":replace",
+                WriteOp::Put => ":put",
+                WriteOp::Rm => ":rm",
+            };
+            write!(out.script, "{op} {rel} {{{}", keys.join(", ")).unwrap();
+            if !vals.is_empty() {
+                write!(out.script, " => {}", vals.join(", ")).unwrap();
+            }
+            writeln!(out.script, "}}").unwrap();
+        }
+        Ok((out.script, out.params))
+    }
+}
+
+#[derive(Default)]
+struct ScriptWriter {
+    script: String,
+    params: BTreeMap<String, DataValue>,
+}
+
+impl ScriptWriter {
+    fn param(&mut self, val: DataValue) -> String {
+        let name = format!("__b{}", self.params.len());
+        self.params.insert(name.clone(), val);
+        format!("${name}")
+    }
+    fn rule(&mut self, rule: &RuleBuilder) -> Result<()> {
+        if rule.name != "?" {
+            check_ident("rule", &rule.name)?;
+        }
+        let mut head = vec![];
+        for (var, aggr) in &rule.head {
+            check_ident("variable", var)?;
+            match aggr {
+                None => head.push(var.clone()),
+                Some(aggr) => {
+                    check_ident("aggregation", aggr)?;
+                    head.push(format!("{aggr}({var})"));
+                }
+            }
+        }
+        write!(self.script, "{}[{}] ", rule.name, head.join(", ")).unwrap();
+        if let Some(rows) = &rule.rows {
+            if let Some(row) = rows.iter().find(|row| row.len() != rule.head.len()) {
+                bail!(RowWidth(rule.name.clone(), rule.head.len(), row.len()));
+            }
+            let rows = DataValue::List(
+                rows.iter()
+                    .map(|row| DataValue::List(row.clone()))
+                    .collect(),
+            );
+            let param = self.param(rows);
+            writeln!(self.script, "<- {param}").unwrap();
+            return Ok(());
+        }
+        if rule.body.is_empty() {
+            bail!(EmptyRule(rule.name.clone()));
+        }
+        let body: Vec<_> = rule.body.iter().map(|atom| self.atom(atom)).try_collect()?;
+        writeln!(self.script, ":= {}", body.join(", ")).unwrap();
+        Ok(())
+    }
+    fn atom(&mut self, atom: &Atom) -> Result<String> {
+        Ok(match atom {
+            Atom::Stored(rel, cols) => {
+                check_relation(rel)?;
+                let mut bound = vec![];
+                for (col, term) in cols {
+                    check_ident("column", col)?;
+                    bound.push(format!("{col}: {}", self.term(term)?));
+                }
+                format!("*{rel}{{{}}}", bound.join(", "))
+            }
+            Atom::Rule(name, args) => {
+                check_ident("rule", name)?;
+                let args: Vec<_> = args.iter().map(|t| self.term(t)).try_collect()?;
+                format!("{name}[{}]", args.join(", "))
+            }
+            Atom::Filter(cond) => self.term(cond)?,
+            Atom::Unify(var, term) => {
+                check_ident("variable", var)?;
+                format!("{var} = {}", self.term(term)?)
+            }
+            Atom::Not(inner) => format!("not {}", self.atom(inner)?),
+        })
+    }
+    fn term(&mut self, term: &Term) -> Result<String> {
+        Ok(match term {
+            Term::Var(var) => {
+                check_ident("variable", var)?;
+                var.clone()
+            }
+            Term::Value(val) => self.param(val.clone()),
+            Term::Apply(func, args) => {
+                check_ident("function", func)?;
+                let args: Vec<_> = args.iter().map(|t| self.term(t)).try_collect()?;
+                format!("{func}({})", args.join(", "))
+            }
+        })
+    }
+}
+
+fn check_ident(kind: &'static str, name: &str) -> Result<()> {
+    let ident_rule = match kind {
+        "variable" => Rule::var,
+        "rule" => Rule::underscore_ident,
+        _ => Rule::ident,
+    };
+    match CozoScriptParser::parse(ident_rule, name) {
+        Ok(parsed) if parsed.as_str() == name => Ok(()),
+        _ => bail!(BadIdentifier(kind, name.to_string())),
+    }
+}
+
+fn check_relation(name: &str) -> Result<()> {
+    match CozoScriptParser::parse(Rule::compound_ident, name) {
+        Ok(parsed) if parsed.as_str() == name => Ok(()),
+        _ => bail!(BadIdentifier("relation", name.to_string())),
+    }
+}
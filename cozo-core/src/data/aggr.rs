@@ -6,8 +6,10 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::hash_map::DefaultHasher;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Formatter};
+use std::hash::{Hash, Hasher};
 
 use miette::{bail, ensure, miette, Result};
 use rand::prelude::*;
@@ -214,6 +216,68 @@ impl NormalAggrObj for AggrCountUnique {
     }
 }
 
+define_aggr!(AGGR_APPROX_COUNT_DISTINCT, false);
+
+/// HyperLogLog estimator of the number of distinct values, using `2^precision` registers
+pub(crate) struct AggrApproxCountDistinct {
+    precision: u32,
+    registers: Vec<u8>,
+}
+
+impl AggrApproxCountDistinct {
+    const DEFAULT_PRECISION: u32 = 12;
+
+    fn new(precision: u32) -> Self {
+        Self {
+            precision,
+            registers: vec![0; 1 << precision],
+        }
+    }
+}
+
+impl Default for AggrApproxCountDistinct {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_PRECISION)
+    }
+}
+
+impl NormalAggrObj for AggrApproxCountDistinct {
+    fn set(&mut self, value: &DataValue) -> Result<()> {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let idx = (hash >> (64 - self.precision)) as usize;
+        let rest = hash << self.precision;
+        let rank = (rest.leading_zeros().min(64 - self.precision) + 1) as u8;
+        if self.registers[idx] < rank {
+            self.registers[idx] = rank;
+        }
+        Ok(())
+    }
+
+    fn get(&self) -> Result<DataValue> {
+        let m = self.registers.len() as f64;
+        let alpha = match self.registers.len() {
+            16 => 0.673,
+            32 => 0.697,
+            64 => 0.709,
+            _ => 0.7213 / (1. + 1.079 / m),
+        };
+        let sum: f64 = self
+            .registers
+            .iter()
+            .map(|r| 2f64.powi(-(*r as i32)))
+            .sum();
+        let mut estimate = alpha * m * m / sum;
+        let n_zeros = self.registers.iter().filter(|r| **r == 0).count();
+        // small range correction by linear counting
+        if estimate <= 2.5 * m && n_zeros > 0 {
+            estimate = m * (m / n_zeros as f64).ln();
+        }
+        Ok(DataValue::from(estimate.round() as i64))
+    }
+}
+
 define_aggr!(AGGR_UNION, true);
 
 #[derive(Default)]
@@ -1165,6 +1229,7 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
         "intersection" => &AGGR_INTERSECTION,
         "count" => &AGGR_COUNT,
         "count_unique" => &AGGR_COUNT_UNIQUE,
+        "approx_count_distinct" => &AGGR_APPROX_COUNT_DISTINCT,
         "variance" => &AGGR_VARIANCE,
         "std_dev" => &AGGR_STD_DEV,
         "sum" => &AGGR_SUM,
@@ -1186,6 +1251,36 @@ pub(crate) fn parse_aggr(name: &str) -> Option<&'static Aggregation> {
     })
 }
 
+/// Names of all builtin aggregations, as resolved by [parse_aggr].
+pub(crate) const AGGR_NAMES: &[&str] = &[
+    "and",
+    "or",
+    "unique",
+    "group_count",
+    "union",
+    "intersection",
+    "count",
+    "count_unique",
+    "approx_count_distinct",
+    "variance",
+    "std_dev",
+    "sum",
+    "product",
+    "min",
+    "max",
+    "mean",
+    "choice",
+    "collect",
+    "shortest",
+    "min_cost",
+    "bit_and",
+    "bit_or",
+    "bit_xor",
+    "latest_by",
+    "smallest_by",
+    "choice_rand",
+];
+
 impl Aggregation {
     pub(crate) fn meet_init(&mut self, _args: &[DataValue]) -> Result<()> {
         self.meet_op.replace(match self.name {
@@ -1231,6 +1326,24 @@ impl Aggregation {
             name if name == AGGR_LATEST_BY.name => Box::new(AggrLatestBy::default()),
             name if name == AGGR_SMALLEST_BY.name => Box::new(AggrSmallestBy::default()),
             name if name == AGGR_CHOICE_RAND.name => Box::new(AggrChoiceRand::default()),
+            name if name == AGGR_APPROX_COUNT_DISTINCT.name => Box::new({
+                if args.is_empty() {
+                    AggrApproxCountDistinct::default()
+                } else {
+                    let arg = args[0].get_int().ok_or_else(|| {
+                        miette!(
+                            "the argument to 'approx_count_distinct' must be an integer, got {:?}",
+                            args[0]
+                        )
+                    })?;
+                    ensure!(
+                        (4..=16).contains(&arg),
+                        "the precision of 'approx_count_distinct' must be between 4 and 16, got {}",
+                        arg
+                    );
+                    AggrApproxCountDistinct::new(arg as u32)
+                }
+            }),
             name if name == AGGR_COLLECT.name => Box::new({
                 if args.is_empty() {
                     AggrCollect::default()
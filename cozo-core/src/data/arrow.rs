@@ -0,0 +1,201 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type, UInt16Type, UInt32Type,
+    UInt64Type, UInt8Type,
+};
+use arrow_array::{
+    Array, ArrayRef, BinaryArray, BooleanArray, Float64Array, Int64Array, NullArray, RecordBatch,
+    StringArray,
+};
+use arrow_ipc::reader::StreamReader;
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{DataType, Field, Schema};
+use itertools::Itertools;
+use miette::{bail, miette, IntoDiagnostic, Result};
+
+use crate::data::json::JsonValue;
+use crate::data::value::{DataValue, Num};
+use crate::NamedRows;
+
+impl NamedRows {
+    /// Encode as an Arrow IPC stream containing a single record batch.
+    ///
+    /// The type of each column is inferred from its values: columns of only integers,
+    /// numbers, booleans, strings or bytes (possibly with nulls) become `Int64`, `Float64`,
+    /// `Boolean`, `Utf8` or `Binary` respectively. Columns containing other or mixed values
+    /// become `Utf8` columns holding the JSON representation of each value.
+    /// Only the current named rows are encoded, not those in `next`.
+    pub fn into_arrow_ipc(self) -> Result<Vec<u8>> {
+        let n_cols = self.headers.len();
+        let mut columns: Vec<Vec<DataValue>> = vec![Vec::with_capacity(self.rows.len()); n_cols];
+        for row in self.rows {
+            if row.len() != n_cols {
+                bail!(
+                    "row {:?} does not have the same length as the headers {:?}",
+                    row,
+                    self.headers
+                );
+            }
+            for (col, val) in columns.iter_mut().zip(row) {
+                col.push(val);
+            }
+        }
+        let (fields, arrays): (Vec<_>, Vec<_>) = self
+            .headers
+            .iter()
+            .zip(columns)
+            .map(|(name, col)| {
+                let array = column_to_array(col);
+                (Field::new(name, array.data_type().clone(), true), array)
+            })
+            .unzip();
+        let schema = Arc::new(Schema::new(fields));
+        let batch = RecordBatch::try_new(schema.clone(), arrays).into_diagnostic()?;
+        let mut buf = vec![];
+        let mut writer = StreamWriter::try_new(&mut buf, &schema).into_diagnostic()?;
+        writer.write(&batch).into_diagnostic()?;
+        writer.finish().into_diagnostic()?;
+        drop(writer);
+        Ok(buf)
+    }
+
+    /// Decode an Arrow IPC stream, concatenating all its record batches.
+    ///
+    /// Integer, floating point, boolean, string and binary columns are supported.
+    /// The result can be passed to `import_relations` for bulk import.
+    pub fn from_arrow_ipc(data: &[u8]) -> Result<Self> {
+        let reader = StreamReader::try_new(Cursor::new(data), None).into_diagnostic()?;
+        let headers = reader
+            .schema()
+            .fields()
+            .iter()
+            .map(|f| f.name().to_string())
+            .collect_vec();
+        let mut rows = vec![];
+        for batch in reader {
+            let batch = batch.into_diagnostic()?;
+            let columns: Vec<Vec<DataValue>> = batch
+                .columns()
+                .iter()
+                .zip(&headers)
+                .map(|(array, name)| {
+                    array_to_column(array)
+                        .map_err(|err| err.wrap_err(format!("when reading column '{name}'")))
+                })
+                .try_collect()?;
+            for i in 0..batch.num_rows() {
+                rows.push(columns.iter().map(|col| col[i].clone()).collect_vec());
+            }
+        }
+        Ok(NamedRows::new(headers, rows))
+    }
+}
+
+fn column_to_array(col: Vec<DataValue>) -> ArrayRef {
+    let non_null = || col.iter().filter(|v| **v != DataValue::Null);
+    if non_null().next().is_none() {
+        Arc::new(NullArray::new(col.len()))
+    } else if non_null().all(|v| matches!(v, DataValue::Num(Num::Int(_)))) {
+        Arc::new(col.iter().map(|v| v.get_int()).collect::<Int64Array>())
+    } else if non_null().all(|v| matches!(v, DataValue::Num(_))) {
+        Arc::new(col.iter().map(|v| v.get_float()).collect::<Float64Array>())
+    } else if non_null().all(|v| matches!(v, DataValue::Bool(_))) {
+        Arc::new(col.iter().map(|v| v.get_bool()).collect::<BooleanArray>())
+    } else if non_null().all(|v| matches!(v, DataValue::Str(_))) {
+        Arc::new(col.iter().map(|v| v.get_str()).collect::<StringArray>())
+    } else if non_null().all(|v| matches!(v, DataValue::Bytes(_))) {
+        Arc::new(
+            col.iter()
+                .map(|v| match v {
+                    DataValue::Bytes(b) => Some(b.as_slice()),
+                    _ => None,
+                })
+                .collect::<BinaryArray>(),
+        )
+    } else {
+        Arc::new(
+            col.into_iter()
+                .map(|v| match v {
+                    DataValue::Null => None,
+                    v => Some(JsonValue::from(v).to_string()),
+                })
+                .collect::<StringArray>(),
+        )
+    }
+}
+
+fn array_to_column(array: &ArrayRef) -> Result<Vec<DataValue>> {
+    macro_rules! primitive {
+        ($t:ty, $conv:expr) => {
+            array
+                .as_primitive::<$t>()
+                .iter()
+                .map(|v| match v {
+                    None => Ok(DataValue::Null),
+                    Some(v) => $conv(v),
+                })
+                .try_collect()
+        };
+    }
+    let int = |v: i64| -> Result<DataValue> { Ok(DataValue::from(v)) };
+    let float = |v: f64| -> Result<DataValue> { Ok(DataValue::from(v)) };
+    match array.data_type() {
+        DataType::Null => Ok(vec![DataValue::Null; array.len()]),
+        DataType::Int8 => primitive!(Int8Type, |v| int(v as i64)),
+        DataType::Int16 => primitive!(Int16Type, |v| int(v as i64)),
+        DataType::Int32 => primitive!(Int32Type, |v| int(v as i64)),
+        DataType::Int64 => primitive!(Int64Type, int),
+        DataType::UInt8 => primitive!(UInt8Type, |v| int(v as i64)),
+        DataType::UInt16 => primitive!(UInt16Type, |v| int(v as i64)),
+        DataType::UInt32 => primitive!(UInt32Type, |v| int(v as i64)),
+        DataType::UInt64 => primitive!(UInt64Type, |v: u64| {
+            let v = i64::try_from(v).map_err(|_| miette!("integer {} is too large", v))?;
+            int(v)
+        }),
+        DataType::Float32 => primitive!(Float32Type, |v| float(v as f64)),
+        DataType::Float64 => primitive!(Float64Type, float),
+        DataType::Boolean => Ok(array
+            .as_boolean()
+            .iter()
+            .map(|v| v.map(DataValue::Bool).unwrap_or(DataValue::Null))
+            .collect()),
+        DataType::Utf8 => Ok(array
+            .as_string::<i32>()
+            .iter()
+            .map(|v| v.map(DataValue::from).unwrap_or(DataValue::Null))
+            .collect()),
+        DataType::LargeUtf8 => Ok(array
+            .as_string::<i64>()
+            .iter()
+            .map(|v| v.map(DataValue::from).unwrap_or(DataValue::Null))
+            .collect()),
+        DataType::Binary => Ok(array
+            .as_binary::<i32>()
+            .iter()
+            .map(|v| {
+                v.map(|b| DataValue::Bytes(b.to_vec()))
+                    .unwrap_or(DataValue::Null)
+            })
+            .collect()),
+        DataType::LargeBinary => Ok(array
+            .as_binary::<i64>()
+            .iter()
+            .map(|v| {
+                v.map(|b| DataValue::Bytes(b.to_vec()))
+                    .unwrap_or(DataValue::Null)
+            })
+            .collect()),
+        t => bail!("unsupported Arrow data type {}", t),
+    }
+}
@@ -407,6 +407,71 @@ impl Expr {
         }
         Ok(())
     }
+    /// Replace the variable of each binding by the one at its tuple position in `vars`
+    pub(crate) fn rebind_by_position(&mut self, vars: &[Symbol]) {
+        match self {
+            Expr::Binding { var, tuple_pos } => {
+                if let Some(v) = tuple_pos.and_then(|i| vars.get(i)) {
+                    *var = v.clone();
+                }
+            }
+            Expr::Const { .. } => {}
+            Expr::Apply { args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.rebind_by_position(vars);
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.rebind_by_position(vars);
+                    val.rebind_by_position(vars);
+                }
+            }
+        }
+    }
+    pub(crate) fn rename_bindings(&mut self, renames: &BTreeMap<Symbol, Symbol>) {
+        match self {
+            Expr::Binding { var, .. } => {
+                if let Some(new_var) = renames.get(var) {
+                    *var = new_var.clone();
+                }
+            }
+            Expr::Const { .. } => {}
+            Expr::Apply { args, .. } => {
+                for arg in args.iter_mut() {
+                    arg.rename_bindings(renames);
+                }
+            }
+            Expr::Cond { clauses, .. } => {
+                for (cond, val) in clauses {
+                    cond.rename_bindings(renames);
+                    val.rename_bindings(renames);
+                }
+            }
+        }
+    }
+    /// The variable whose values this filter restricts to a range that
+    /// [Expr::extract_bound] can turn into scan bounds, if any
+    pub(crate) fn range_target(&self) -> Option<&Symbol> {
+        match self {
+            Expr::Apply { op, args, .. }
+                if [OP_GE.name, OP_GT.name, OP_LE.name, OP_LT.name].contains(&op.name) =>
+            {
+                match (args[0].get_binding(), args[1].get_binding()) {
+                    (Some(symb), None) if args[1].get_const().is_some() => Some(symb),
+                    (None, Some(symb)) if args[0].get_const().is_some() => Some(symb),
+                    _ => None,
+                }
+            }
+            Expr::Apply { op, args, .. } if op.name == OP_STARTS_WITH.name => {
+                match (args[0].get_binding(), args[1].get_const()) {
+                    (Some(symb), Some(DataValue::Str(_))) => Some(symb),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
     pub(crate) fn bindings(&self) -> BTreeSet<Symbol> {
         let mut ret = BTreeSet::new();
         self.collect_bindings(&mut ret);
@@ -689,6 +754,7 @@ impl Debug for Op {
 pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
     Some(match name {
         "coalesce" => &OP_COALESCE,
+        "if_null" => &OP_IF_NULL,
         "list" => &OP_LIST,
         "add" => &OP_ADD,
         "sub" => &OP_SUB,
@@ -750,6 +816,8 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "is_int" => &OP_IS_INT,
         "is_float" => &OP_IS_FLOAT,
         "is_num" => &OP_IS_NUM,
+        "is_bigint" => &OP_IS_BIGINT,
+        "is_decimal" => &OP_IS_DECIMAL,
         "is_string" => &OP_IS_STRING,
         "is_list" => &OP_IS_LIST,
         "is_bytes" => &OP_IS_BYTES,
@@ -764,6 +832,7 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "append" => &OP_APPEND,
         "prepend" => &OP_PREPEND,
         "unicode_normalize" => &OP_UNICODE_NORMALIZE,
+        "collate" => &OP_COLLATE,
         "haversine" => &OP_HAVERSINE,
         "haversine_deg_input" => &OP_HAVERSINE_DEG_INPUT,
         "deg_to_rad" => &OP_DEG_TO_RAD,
@@ -780,6 +849,10 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "regex_extract_first" => &OP_REGEX_EXTRACT_FIRST,
         "encode_base64" => &OP_ENCODE_BASE64,
         "decode_base64" => &OP_DECODE_BASE64,
+        "encode_hex" => &OP_ENCODE_HEX,
+        "decode_hex" => &OP_DECODE_HEX,
+        "sha256" => &OP_SHA256,
+        "xxhash64" => &OP_XXHASH64,
         "first" => &OP_FIRST,
         "last" => &OP_LAST,
         "chunks" => &OP_CHUNKS,
@@ -787,6 +860,10 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
         "windows" => &OP_WINDOWS,
         "to_int" => &OP_TO_INT,
         "to_float" => &OP_TO_FLOAT,
+        "to_bigint" => &OP_TO_BIGINT,
+        "to_decimal" => &OP_TO_DECIMAL,
+        "decimal_div" => &OP_DECIMAL_DIV,
+        "round_decimal" => &OP_ROUND_DECIMAL,
         "to_string" => &OP_TO_STRING,
         "rand_float" => &OP_RAND_FLOAT,
         "rand_bernoulli" => &OP_RAND_BERNOULLI,
@@ -809,6 +886,139 @@ pub(crate) fn get_op(name: &str) -> Option<&'static Op> {
     })
 }
 
+/// Names of all builtin functions, as resolved by [get_op].
+pub(crate) const FUNCTION_NAMES: &[&str] = &[
+    "coalesce",
+    "if_null",
+    "list",
+    "add",
+    "sub",
+    "mul",
+    "div",
+    "minus",
+    "abs",
+    "signum",
+    "floor",
+    "ceil",
+    "round",
+    "mod",
+    "max",
+    "min",
+    "pow",
+    "exp",
+    "exp2",
+    "ln",
+    "log2",
+    "log10",
+    "sin",
+    "cos",
+    "tan",
+    "asin",
+    "acos",
+    "atan",
+    "atan2",
+    "sinh",
+    "cosh",
+    "tanh",
+    "asinh",
+    "acosh",
+    "atanh",
+    "eq",
+    "neq",
+    "gt",
+    "ge",
+    "lt",
+    "le",
+    "or",
+    "and",
+    "negate",
+    "bit_and",
+    "bit_or",
+    "bit_not",
+    "bit_xor",
+    "pack_bits",
+    "unpack_bits",
+    "concat",
+    "str_includes",
+    "lowercase",
+    "uppercase",
+    "trim",
+    "trim_start",
+    "trim_end",
+    "starts_with",
+    "ends_with",
+    "is_null",
+    "is_int",
+    "is_float",
+    "is_num",
+    "is_bigint",
+    "is_decimal",
+    "is_string",
+    "is_list",
+    "is_bytes",
+    "is_in",
+    "is_finite",
+    "is_infinite",
+    "is_nan",
+    "is_uuid",
+    "length",
+    "sorted",
+    "reverse",
+    "append",
+    "prepend",
+    "unicode_normalize",
+    "collate",
+    "haversine",
+    "haversine_deg_input",
+    "deg_to_rad",
+    "rad_to_deg",
+    "get",
+    "maybe_get",
+    "chars",
+    "from_substrings",
+    "slice",
+    "regex_matches",
+    "regex_replace",
+    "regex_replace_all",
+    "regex_extract",
+    "regex_extract_first",
+    "encode_base64",
+    "decode_base64",
+    "encode_hex",
+    "decode_hex",
+    "sha256",
+    "xxhash64",
+    "first",
+    "last",
+    "chunks",
+    "chunks_exact",
+    "windows",
+    "to_int",
+    "to_float",
+    "to_bigint",
+    "to_decimal",
+    "decimal_div",
+    "round_decimal",
+    "to_string",
+    "rand_float",
+    "rand_bernoulli",
+    "rand_int",
+    "rand_choose",
+    "assert",
+    "union",
+    "intersection",
+    "difference",
+    "to_uuid",
+    "to_bool",
+    "to_unity",
+    "rand_uuid_v1",
+    "rand_uuid_v4",
+    "uuid_timestamp",
+    "now",
+    "format_timestamp",
+    "parse_timestamp",
+];
+
 impl Op {
     pub(crate) fn post_process_args(&self, args: &mut [Expr]) {
         if self.name.starts_with("OP_REGEX_") {
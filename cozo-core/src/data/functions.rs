@@ -6,7 +6,7 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::cmp::Reverse;
+use std::cmp::{Ordering, Reverse};
 use std::collections::BTreeSet;
 use std::ops::{Div, Rem};
 use std::str::FromStr;
@@ -19,15 +19,21 @@ use itertools::Itertools;
 #[cfg(target_arch = "wasm32")]
 use js_sys::Date;
 use miette::{bail, ensure, miette, Result};
-use num_traits::FloatConst;
+use num_bigint::BigInt;
+use num_traits::{FloatConst, Signed, ToPrimitive};
 use rand::prelude::*;
+use sha2::{Digest, Sha256};
 use smartstring::SmartString;
 use unicode_normalization::UnicodeNormalization;
 use uuid::v1::Timestamp;
+use xxhash_rust::xxh64::xxh64;
 
 use crate::data::expr::Op;
 use crate::data::json::JsonValue;
-use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+use crate::data::relation::Collation;
+use crate::data::value::{
+    DataValue, Decimal, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs,
+};
 
 macro_rules! define_op {
     ($name:ident, $min_arity:expr, $vararg:expr) => {
@@ -40,13 +46,23 @@ macro_rules! define_op {
     };
 }
 
+/// Ordering comparisons involving null are false rather than errors, so that
+/// filters drop rows with missing values, e.g. those padded by `optional`.
+/// Equality is structural: `null == null` holds.
+fn has_null(args: &[DataValue]) -> bool {
+    args.contains(&DataValue::Null)
+}
+
 fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
     use DataValue::*;
     if !matches!(
         (a, b),
         (Null, Null)
             | (Bool(_), Bool(_))
-            | (Num(_), Num(_))
+            | (
+                Num(_) | BigInt(_) | Decimal(_),
+                Num(_) | BigInt(_) | Decimal(_)
+            )
             | (Str(_), Str(_))
             | (Bytes(_), Bytes(_))
             | (Regex(_), Regex(_))
@@ -63,6 +79,78 @@ fn ensure_same_value_type(a: &DataValue, b: &DataValue) -> Result<()> {
     Ok(())
 }
 
+fn has_exact_num(args: &[DataValue]) -> bool {
+    args.iter()
+        .any(|arg| matches!(arg, DataValue::BigInt(_) | DataValue::Decimal(_)))
+}
+
+/// Whether both arguments are numbers and one of them is a big integer or a decimal
+fn mixes_exact_nums(args: &[DataValue]) -> bool {
+    has_exact_num(args)
+        && args.iter().all(|arg| {
+            matches!(
+                arg,
+                DataValue::Num(_) | DataValue::BigInt(_) | DataValue::Decimal(_)
+            )
+        })
+}
+
+/// Compares numbers as [mixes_exact_nums] requires them exactly, taking finite floats
+/// to be the decimals they are displayed as. `None` if a float is NaN.
+fn cmp_exact_nums(a: &DataValue, b: &DataValue) -> Option<Ordering> {
+    let exact = |v: &DataValue| match v {
+        DataValue::Num(Num::Float(f)) if f.is_finite() => Decimal::from_str(&f.to_string()).ok(),
+        DataValue::Num(Num::Float(_)) => None,
+        v => get_exact_num(v).ok(),
+    };
+    let approx = |v: &DataValue| match v {
+        DataValue::Num(n) => n.get_float(),
+        DataValue::BigInt(i) => i.to_f64(),
+        DataValue::Decimal(d) => d.to_f64(),
+        _ => f64::NAN,
+    };
+    match (exact(a), exact(b)) {
+        (Some(l), Some(r)) => Some(l.cmp(&r)),
+        _ => approx(a).partial_cmp(&approx(b)),
+    }
+}
+
+/// Big integers and decimals are only mixed with integers in arithmetic,
+/// so that precision is never silently lost by going through floats.
+fn get_exact_num(arg: &DataValue) -> Result<Decimal> {
+    Ok(match arg {
+        DataValue::Num(Num::Int(i)) => Decimal::from(BigInt::from(*i)),
+        DataValue::BigInt(i) => Decimal::from(i.0.clone()),
+        DataValue::Decimal(d) => d.clone(),
+        DataValue::Num(Num::Float(_)) => bail!(
+            "floats cannot be mixed with big integers or decimals, convert them with 'to_decimal' first"
+        ),
+        v => bail!("{:?} is not a number", v),
+    })
+}
+
+/// The result is a decimal if any argument is one, and a big integer otherwise.
+fn exact_num_result(d: Decimal, args: &[DataValue]) -> DataValue {
+    if args.iter().any(|arg| matches!(arg, DataValue::Decimal(_))) {
+        return DataValue::Decimal(d);
+    }
+    match d.to_bigint() {
+        Some(i) => DataValue::from(i),
+        None => DataValue::Decimal(d),
+    }
+}
+
+fn fold_exact_nums(
+    args: &[DataValue],
+    f: impl Fn(&Decimal, &Decimal) -> Decimal,
+) -> Result<DataValue> {
+    let mut accum = get_exact_num(&args[0])?;
+    for arg in &args[1..] {
+        accum = f(&accum, &get_exact_num(arg)?);
+    }
+    Ok(exact_num_result(accum, args))
+}
+
 define_op!(OP_LIST, 0, true);
 pub(crate) fn op_list(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::List(args.to_vec()))
@@ -78,11 +166,20 @@ pub(crate) fn op_coalesce(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::Null)
 }
 
+define_op!(OP_IF_NULL, 2, false);
+pub(crate) fn op_if_null(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        DataValue::Null => args[1].clone(),
+        val => val.clone(),
+    })
+}
+
 define_op!(OP_EQ, 2, false);
 pub(crate) fn op_eq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 == *f,
+        (a, b) if mixes_exact_nums(args) => cmp_exact_nums(a, b) == Some(Ordering::Equal),
         (a, b) => a == b,
     }))
 }
@@ -106,52 +203,77 @@ pub(crate) fn op_neq(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(f)), DataValue::Num(Num::Int(i)))
         | (DataValue::Num(Num::Int(i)), DataValue::Num(Num::Float(f))) => *i as f64 != *f,
+        (a, b) if mixes_exact_nums(args) => cmp_exact_nums(a, b) != Some(Ordering::Equal),
         (a, b) => a != b,
     }))
 }
 
 define_op!(OP_GT, 2, false);
 pub(crate) fn op_gt(args: &[DataValue]) -> Result<DataValue> {
+    if has_null(args) {
+        return Ok(DataValue::from(false));
+    }
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l > *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 > *r,
+        (a, b) if mixes_exact_nums(args) => cmp_exact_nums(a, b) == Some(Ordering::Greater),
         (a, b) => a > b,
     }))
 }
 
 define_op!(OP_GE, 2, false);
 pub(crate) fn op_ge(args: &[DataValue]) -> Result<DataValue> {
+    if has_null(args) {
+        return Ok(DataValue::from(false));
+    }
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l >= *r as f64,
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => *l as f64 >= *r,
+        (a, b) if mixes_exact_nums(args) => matches!(
+            cmp_exact_nums(a, b),
+            Some(Ordering::Greater | Ordering::Equal)
+        ),
         (a, b) => a >= b,
     }))
 }
 
 define_op!(OP_LT, 2, false);
 pub(crate) fn op_lt(args: &[DataValue]) -> Result<DataValue> {
+    if has_null(args) {
+        return Ok(DataValue::from(false));
+    }
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l < (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) < *r,
+        (a, b) if mixes_exact_nums(args) => cmp_exact_nums(a, b) == Some(Ordering::Less),
         (a, b) => a < b,
     }))
 }
 
 define_op!(OP_LE, 2, false);
 pub(crate) fn op_le(args: &[DataValue]) -> Result<DataValue> {
+    if has_null(args) {
+        return Ok(DataValue::from(false));
+    }
     ensure_same_value_type(&args[0], &args[1])?;
     Ok(DataValue::from(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Float(l)), DataValue::Num(Num::Int(r))) => *l <= (*r as f64),
         (DataValue::Num(Num::Int(l)), DataValue::Num(Num::Float(r))) => (*l as f64) <= *r,
+        (a, b) if mixes_exact_nums(args) => {
+            matches!(cmp_exact_nums(a, b), Some(Ordering::Less | Ordering::Equal))
+        }
         (a, b) => a <= b,
     }))
 }
 
 define_op!(OP_ADD, 0, true);
 pub(crate) fn op_add(args: &[DataValue]) -> Result<DataValue> {
+    if has_exact_num(args) {
+        return fold_exact_nums(args, Decimal::add);
+    }
     let mut i_accum = 0i64;
     let mut f_accum = 0.0f64;
     for arg in args {
@@ -200,6 +322,9 @@ pub(crate) fn op_min(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_SUB, 2, false);
 pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
+    if has_exact_num(args) {
+        return fold_exact_nums(args, Decimal::sub);
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Int(*a - *b))
@@ -219,6 +344,9 @@ pub(crate) fn op_sub(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MUL, 0, true);
 pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
+    if has_exact_num(args) {
+        return fold_exact_nums(args, Decimal::mul);
+    }
     let mut i_accum = 1i64;
     let mut f_accum = 1.0f64;
     for arg in args {
@@ -237,6 +365,9 @@ pub(crate) fn op_mul(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_DIV, 2, false);
 pub(crate) fn op_div(args: &[DataValue]) -> Result<DataValue> {
+    if has_exact_num(args) {
+        bail!("division of big integers or decimals requires a scale, use 'decimal_div' instead")
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Float((*a as f64) / (*b as f64)))
@@ -259,6 +390,8 @@ pub(crate) fn op_minus(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(-(*i))),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(-(*f))),
+        DataValue::BigInt(i) => DataValue::from(-&i.0),
+        DataValue::Decimal(d) => DataValue::Decimal(d.neg()),
         _ => bail!("minus can only be applied to numbers"),
     })
 }
@@ -268,6 +401,8 @@ pub(crate) fn op_abs(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Num(Num::Int(i)) => DataValue::Num(Num::Int(i.abs())),
         DataValue::Num(Num::Float(f)) => DataValue::Num(Num::Float(f.abs())),
+        DataValue::BigInt(i) => DataValue::from(i.0.abs()),
+        DataValue::Decimal(d) => DataValue::Decimal(d.abs()),
         _ => bail!("'abs' requires numbers"),
     })
 }
@@ -287,6 +422,8 @@ pub(crate) fn op_signum(args: &[DataValue]) -> Result<DataValue> {
                 DataValue::from(f64::NAN)
             }
         }
+        DataValue::BigInt(i) => DataValue::from(i.0.signum().to_i64().unwrap()),
+        DataValue::Decimal(d) => DataValue::from(d.mantissa().signum().to_i64().unwrap()),
         _ => bail!("'signum' requires numbers"),
     })
 }
@@ -521,6 +658,15 @@ pub(crate) fn op_pow(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_MOD, 2, false);
 pub(crate) fn op_mod(args: &[DataValue]) -> Result<DataValue> {
+    if has_exact_num(args) {
+        let a = get_exact_num(&args[0])?;
+        let b = get_exact_num(&args[1])?;
+        return match (a.to_bigint(), b.to_bigint()) {
+            (Some(_), Some(b)) if b == BigInt::default() => bail!("'mod' by zero"),
+            (Some(a), Some(b)) => Ok(DataValue::from(a % b)),
+            _ => bail!("'mod' cannot be applied to decimals"),
+        };
+    }
     Ok(match (&args[0], &args[1]) {
         (DataValue::Num(Num::Int(a)), DataValue::Num(Num::Int(b))) => {
             DataValue::Num(Num::Int(a.rem(b)))
@@ -709,11 +855,22 @@ pub(crate) fn op_concat(args: &[DataValue]) -> Result<DataValue> {
                 if let DataValue::Str(s) = arg {
                     ret += s;
                 } else {
-                    bail!("'concat' requires strings, or lists");
+                    bail!("'concat' requires strings, bytes or lists");
                 }
             }
             Ok(DataValue::from(ret))
         }
+        DataValue::Bytes(_) => {
+            let mut ret = vec![];
+            for arg in args {
+                if let DataValue::Bytes(b) = arg {
+                    ret.extend_from_slice(b);
+                } else {
+                    bail!("'concat' requires strings, bytes or lists");
+                }
+            }
+            Ok(DataValue::Bytes(ret))
+        }
         DataValue::List(_) | DataValue::Set(_) => {
             let mut ret = vec![];
             for arg in args {
@@ -722,7 +879,7 @@ pub(crate) fn op_concat(args: &[DataValue]) -> Result<DataValue> {
                 } else if let DataValue::Set(s) = arg {
                     ret.extend(s.iter().cloned());
                 } else {
-                    bail!("'concat' requires strings, or lists");
+                    bail!("'concat' requires strings, bytes or lists");
                 }
             }
             Ok(DataValue::List(ret))
@@ -900,6 +1057,16 @@ pub(crate) fn op_is_num(args: &[DataValue]) -> Result<DataValue> {
     )))
 }
 
+define_op!(OP_IS_BIGINT, 1, false);
+pub(crate) fn op_is_bigint(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(args[0], DataValue::BigInt(_))))
+}
+
+define_op!(OP_IS_DECIMAL, 1, false);
+pub(crate) fn op_is_decimal(args: &[DataValue]) -> Result<DataValue> {
+    Ok(DataValue::from(matches!(args[0], DataValue::Decimal(_))))
+}
+
 define_op!(OP_IS_FINITE, 1, false);
 pub(crate) fn op_is_finite(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
@@ -1002,6 +1169,19 @@ pub(crate) fn op_unicode_normalize(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_COLLATE, 2, false);
+pub(crate) fn op_collate(args: &[DataValue]) -> Result<DataValue> {
+    let collation = match &args[1] {
+        DataValue::Str(n) => Collation::from_name(n)
+            .ok_or_else(|| miette!("unknown collation {} for 'collate'", n))?,
+        _ => bail!("'collate' requires a string as the collation"),
+    };
+    match &args[0] {
+        DataValue::Str(_) => Ok(collation.apply(args[0].clone())),
+        _ => bail!("'collate' requires strings"),
+    }
+}
+
 define_op!(OP_SORTED, 1, false);
 pub(crate) fn op_sorted(args: &[DataValue]) -> Result<DataValue> {
     let mut arg = args[0]
@@ -1181,15 +1361,21 @@ pub(crate) fn op_maybe_get(args: &[DataValue]) -> Result<DataValue> {
 
 define_op!(OP_SLICE, 3, false);
 pub(crate) fn op_slice(args: &[DataValue]) -> Result<DataValue> {
-    let l = args[0]
-        .get_slice()
-        .ok_or_else(|| miette!("first argument to 'slice' mut be a list"))?;
     let m = args[1]
         .get_int()
         .ok_or_else(|| miette!("second argument to 'slice' mut be an integer"))?;
     let n = args[2]
         .get_int()
         .ok_or_else(|| miette!("third argument to 'slice' mut be an integer"))?;
+    if let DataValue::Bytes(b) = &args[0] {
+        let m = get_index(m, b.len())?;
+        let n = get_index(n, b.len())?;
+        ensure!(m <= n, "'slice' requires the start to be before the end");
+        return Ok(DataValue::Bytes(b[m..n].to_vec()));
+    }
+    let l = args[0]
+        .get_slice()
+        .ok_or_else(|| miette!("first argument to 'slice' mut be a list or bytes"))?;
     let m = get_index(m, l.len())?;
     let n = get_index(n, l.len())?;
     Ok(DataValue::List(l[m..n].to_vec()))
@@ -1262,6 +1448,61 @@ pub(crate) fn op_decode_base64(args: &[DataValue]) -> Result<DataValue> {
     }
 }
 
+define_op!(OP_ENCODE_HEX, 1, false);
+pub(crate) fn op_encode_hex(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Bytes(b) => {
+            let mut s = String::with_capacity(b.len() * 2);
+            for byte in b {
+                s.push_str(&format!("{byte:02x}"));
+            }
+            Ok(DataValue::from(s))
+        }
+        _ => bail!("'encode_hex' requires bytes"),
+    }
+}
+
+define_op!(OP_DECODE_HEX, 1, false);
+pub(crate) fn op_decode_hex(args: &[DataValue]) -> Result<DataValue> {
+    match &args[0] {
+        DataValue::Str(s) => {
+            ensure!(
+                s.len() % 2 == 0 && s.is_ascii(),
+                "Data is not properly encoded"
+            );
+            let b: Vec<u8> = (0..s.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&s[i..i + 2], 16))
+                .try_collect()
+                .map_err(|_| miette!("Data is not properly encoded"))?;
+            Ok(DataValue::Bytes(b))
+        }
+        _ => bail!("'decode_hex' requires strings"),
+    }
+}
+
+/// Strings are hashed as their UTF-8 bytes
+fn get_bytes_to_hash<'a>(arg: &'a DataValue, op: &str) -> Result<&'a [u8]> {
+    match arg {
+        DataValue::Bytes(b) => Ok(b),
+        DataValue::Str(s) => Ok(s.as_bytes()),
+        _ => bail!("'{}' requires bytes or strings", op),
+    }
+}
+
+define_op!(OP_SHA256, 1, false);
+pub(crate) fn op_sha256(args: &[DataValue]) -> Result<DataValue> {
+    let data = get_bytes_to_hash(&args[0], "sha256")?;
+    Ok(DataValue::Bytes(Sha256::digest(data).to_vec()))
+}
+
+define_op!(OP_XXHASH64, 1, false);
+pub(crate) fn op_xxhash64(args: &[DataValue]) -> Result<DataValue> {
+    let data = get_bytes_to_hash(&args[0], "xxhash64")?;
+    // the unsigned hash reinterpreted as a signed integer
+    Ok(DataValue::from(xxh64(data, 0) as i64))
+}
+
 define_op!(OP_TO_BOOL, 1, false);
 pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
     Ok(DataValue::from(match &args[0] {
@@ -1275,6 +1516,8 @@ pub(crate) fn op_to_bool(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => !l.is_empty(),
         DataValue::Set(s) => !s.is_empty(),
         DataValue::Validity(vld) => vld.is_assert.0,
+        DataValue::BigInt(i) => i.0 != BigInt::default(),
+        DataValue::Decimal(d) => !d.is_zero(),
         DataValue::Bot => false,
     }))
 }
@@ -1292,6 +1535,8 @@ pub(crate) fn op_to_unity(args: &[DataValue]) -> Result<DataValue> {
         DataValue::List(l) => i64::from(!l.is_empty()),
         DataValue::Set(s) => i64::from(!s.is_empty()),
         DataValue::Validity(vld) => i64::from(vld.is_assert.0),
+        DataValue::BigInt(i) => i64::from(i.0 != BigInt::default()),
+        DataValue::Decimal(d) => i64::from(!d.is_zero()),
         DataValue::Bot => 0,
     }))
 }
@@ -1315,6 +1560,17 @@ pub(crate) fn op_to_int(args: &[DataValue]) -> Result<DataValue> {
                 .into()
         }
         DataValue::Validity(vld) => DataValue::Num(Num::Int(vld.timestamp.0 .0)),
+        DataValue::BigInt(i) => {
+            i.0.to_i64()
+                .ok_or_else(|| miette!("The big integer is too large for an int"))?
+                .into()
+        }
+        DataValue::Decimal(d) => d
+            .trunc()
+            .to_bigint()
+            .and_then(|i| i.to_i64())
+            .ok_or_else(|| miette!("The decimal is too large for an int"))?
+            .into(),
         v => bail!("'to_int' does not recognize {:?}", v),
     })
 }
@@ -1335,6 +1591,8 @@ pub(crate) fn op_to_float(args: &[DataValue]) -> Result<DataValue> {
                 .map_err(|_| miette!("The string cannot be interpreted as float"))?
                 .into(),
         },
+        DataValue::BigInt(i) => i.0.to_f64().unwrap_or(f64::NAN).into(),
+        DataValue::Decimal(d) => d.to_f64().into(),
         v => bail!("'to_float' does not recognize {:?}", v),
     })
 }
@@ -1343,6 +1601,8 @@ define_op!(OP_TO_STRING, 1, false);
 pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     Ok(match &args[0] {
         DataValue::Str(s) => DataValue::Str(s.clone()),
+        DataValue::BigInt(i) => DataValue::from(i.0.to_string()),
+        DataValue::Decimal(d) => DataValue::from(d.to_string()),
         v => {
             let jv = JsonValue::from(v.clone());
             let s = jv.to_string();
@@ -1351,6 +1611,75 @@ pub(crate) fn op_to_string(args: &[DataValue]) -> Result<DataValue> {
     })
 }
 
+define_op!(OP_TO_BIGINT, 1, false);
+pub(crate) fn op_to_bigint(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        d @ DataValue::BigInt(_) => d.clone(),
+        DataValue::Num(Num::Int(i)) => DataValue::from(BigInt::from(*i)),
+        DataValue::Str(s) => DataValue::from(
+            BigInt::from_str(s)
+                .map_err(|_| miette!("The string cannot be interpreted as a big integer"))?,
+        ),
+        v @ (DataValue::Num(Num::Float(_)) | DataValue::Decimal(_)) => {
+            let d = match op_to_decimal(std::slice::from_ref(v))? {
+                DataValue::Decimal(d) => d,
+                _ => unreachable!(),
+            };
+            DataValue::from(d.to_bigint().ok_or_else(|| {
+                miette!("'to_bigint' requires whole numbers, use 'round_decimal' first")
+            })?)
+        }
+        v => bail!("'to_bigint' does not recognize {:?}", v),
+    })
+}
+
+define_op!(OP_TO_DECIMAL, 1, false);
+pub(crate) fn op_to_decimal(args: &[DataValue]) -> Result<DataValue> {
+    Ok(match &args[0] {
+        d @ DataValue::Decimal(_) => d.clone(),
+        DataValue::Num(Num::Int(i)) => DataValue::Decimal(Decimal::from(BigInt::from(*i))),
+        DataValue::BigInt(i) => DataValue::Decimal(Decimal::from(i.0.clone())),
+        DataValue::Num(Num::Float(f)) => {
+            ensure!(
+                f.is_finite(),
+                "'to_decimal' cannot convert the non-finite float {}",
+                f
+            );
+            // the shortest representation that parses back to the same float
+            DataValue::Decimal(Decimal::from_str(&f.to_string())?)
+        }
+        DataValue::Str(s) => DataValue::Decimal(Decimal::from_str(s)?),
+        v => bail!("'to_decimal' does not recognize {:?}", v),
+    })
+}
+
+fn get_decimal_scale(arg: &DataValue, op: &str) -> Result<u32> {
+    arg.get_non_neg_int()
+        .and_then(|i| u32::try_from(i).ok())
+        .ok_or_else(|| miette!("'{}' requires a non-negative integer as the scale", op))
+}
+
+define_op!(OP_DECIMAL_DIV, 3, false);
+pub(crate) fn op_decimal_div(args: &[DataValue]) -> Result<DataValue> {
+    let a = get_exact_num(&args[0])?;
+    let b = get_exact_num(&args[1])?;
+    let scale = get_decimal_scale(&args[2], "decimal_div")?;
+    let res = a
+        .div(&b, scale)
+        .ok_or_else(|| miette!("'decimal_div' by zero"))?;
+    Ok(DataValue::Decimal(res))
+}
+
+define_op!(OP_ROUND_DECIMAL, 2, false);
+pub(crate) fn op_round_decimal(args: &[DataValue]) -> Result<DataValue> {
+    let d = match &args[0] {
+        DataValue::Decimal(d) => d,
+        _ => bail!("'round_decimal' requires a decimal"),
+    };
+    let scale = get_decimal_scale(&args[1], "round_decimal")?;
+    Ok(DataValue::Decimal(d.round(scale)))
+}
+
 define_op!(OP_RAND_FLOAT, 0, false);
 pub(crate) fn op_rand_float(_args: &[DataValue]) -> Result<DataValue> {
     Ok(thread_rng().gen::<f64>().into())
@@ -100,6 +100,9 @@ impl From<DataValue> for JsonValue {
             DataValue::Validity(v) => {
                 json!([v.timestamp.0, v.is_assert])
             }
+            // as strings, since JSON numbers are usually read as floats
+            DataValue::BigInt(i) => JsonValue::String(i.0.to_string()),
+            DataValue::Decimal(d) => JsonValue::String(d.to_string()),
         }
     }
 }
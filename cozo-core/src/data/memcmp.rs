@@ -12,9 +12,12 @@ use std::io::Write;
 use std::str::FromStr;
 
 use byteorder::{BigEndian, ByteOrder, WriteBytesExt};
+use num_bigint::{BigInt, BigUint, Sign};
 use regex::Regex;
 
-use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+use crate::data::value::{
+    BigIntWrapper, DataValue, Decimal, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs,
+};
 
 const INIT_TAG: u8 = 0x00;
 const NULL_TAG: u8 = 0x01;
@@ -33,8 +36,14 @@ const BOT_TAG: u8 = 0xFF;
 const IS_FLOAT: u8 = 0b00010000;
 const IS_APPROX_INT: u8 = 0b00000100;
 const IS_EXACT_INT: u8 = 0b00000000;
+const IS_BIGINT: u8 = 0b00001000;
+const IS_DECIMAL: u8 = 0b00001100;
 const EXACT_INT_BOUND: i64 = 0x20_0000_0000_0000;
 
+const NEGATIVE: u8 = 0x00;
+const ZERO: u8 = 0x01;
+const POSITIVE: u8 = 0x02;
+
 pub(crate) trait MemCmpEncoder: Write {
     fn encode_datavalue(&mut self, v: &DataValue) {
         match v {
@@ -88,9 +97,72 @@ pub(crate) trait MemCmpEncoder: Write {
                 self.write_u64::<BigEndian>(ts_flipped).unwrap();
                 self.write_u8(!vld.is_assert.0 as u8).unwrap();
             }
+            // sorted together with other numbers by their nearest float first
+            DataValue::BigInt(i) => {
+                self.write_u8(NUM_TAG).unwrap();
+                self.write_u64::<BigEndian>(order_encode_f64(i.to_f64()))
+                    .unwrap();
+                self.write_u8(IS_BIGINT).unwrap();
+                self.encode_bigint(&i.0);
+            }
+            DataValue::Decimal(d) => {
+                self.write_u8(NUM_TAG).unwrap();
+                self.write_u64::<BigEndian>(order_encode_f64(d.to_f64()))
+                    .unwrap();
+                self.write_u8(IS_DECIMAL).unwrap();
+                self.encode_decimal(d);
+            }
             DataValue::Bot => self.write_u8(BOT_TAG).unwrap(),
         }
     }
+    /// The sign, then the length and big-endian bytes of the magnitude,
+    /// all inverted for negative numbers so that larger magnitudes sort first.
+    fn encode_bigint(&mut self, i: &BigInt) {
+        let (sign, magnitude) = i.to_bytes_be();
+        match sign {
+            Sign::NoSign => self.write_u8(ZERO).unwrap(),
+            Sign::Plus => {
+                self.write_u8(POSITIVE).unwrap();
+                self.write_u32::<BigEndian>(magnitude.len() as u32).unwrap();
+                self.write_all(&magnitude).unwrap();
+            }
+            Sign::Minus => {
+                self.write_u8(NEGATIVE).unwrap();
+                self.write_u32::<BigEndian>(!(magnitude.len() as u32))
+                    .unwrap();
+                for b in magnitude {
+                    self.write_u8(!b).unwrap();
+                }
+            }
+        }
+    }
+    /// The sign, then the value written as `0.d1d2...dn * 10^exponent` with `d1` non-zero:
+    /// the exponent followed by the digits, each plus one, and a zero terminator,
+    /// all inverted for negative numbers.
+    fn encode_decimal(&mut self, d: &Decimal) {
+        let sign = d.mantissa().sign();
+        if sign == Sign::NoSign {
+            self.write_u8(ZERO).unwrap();
+            return;
+        }
+        let all_digits = d.mantissa().magnitude().to_string();
+        let exponent = all_digits.len() as i64 - d.scale() as i64;
+        let digits = all_digits.trim_end_matches('0').as_bytes();
+        let mut exponent = order_encode_i64(exponent);
+        let flip = if sign == Sign::Minus {
+            exponent = !exponent;
+            self.write_u8(NEGATIVE).unwrap();
+            0xFF
+        } else {
+            self.write_u8(POSITIVE).unwrap();
+            0x00
+        };
+        self.write_u64::<BigEndian>(exponent).unwrap();
+        for digit in digits {
+            self.write_u8((digit - b'0' + 1) ^ flip).unwrap();
+        }
+        self.write_u8(flip).unwrap();
+    }
     fn encode_num(&mut self, v: Num) {
         let f = v.get_float();
         let u = order_encode_f64(f);
@@ -158,6 +230,67 @@ pub fn decode_bytes(data: &[u8]) -> (Vec<u8>, &[u8]) {
     }
 }
 
+fn decode_bigint(bs: &[u8]) -> (BigInt, &[u8]) {
+    let (sign, rest) = bs.split_first().unwrap();
+    match *sign {
+        ZERO => (BigInt::default(), rest),
+        POSITIVE => {
+            let (len, rest) = rest.split_at(4);
+            let len = BigEndian::read_u32(len) as usize;
+            let (magnitude, rest) = rest.split_at(len);
+            (BigInt::from_bytes_be(Sign::Plus, magnitude), rest)
+        }
+        NEGATIVE => {
+            let (len, rest) = rest.split_at(4);
+            let len = !BigEndian::read_u32(len) as usize;
+            let (magnitude, rest) = rest.split_at(len);
+            let magnitude = magnitude.iter().map(|b| !b).collect::<Vec<_>>();
+            (BigInt::from_bytes_be(Sign::Minus, &magnitude), rest)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn decode_decimal(bs: &[u8]) -> (Decimal, &[u8]) {
+    let (sign, rest) = bs.split_first().unwrap();
+    let flip = match *sign {
+        ZERO => return (Decimal::default(), rest),
+        POSITIVE => 0x00,
+        NEGATIVE => 0xFF,
+        _ => unreachable!(),
+    };
+    let (exponent, mut rest) = rest.split_at(8);
+    let mut exponent = BigEndian::read_u64(exponent);
+    if flip != 0 {
+        exponent = !exponent;
+    }
+    let exponent = order_decode_i64(exponent);
+    let mut digits = vec![];
+    loop {
+        let (b, next) = rest.split_first().unwrap();
+        rest = next;
+        let b = b ^ flip;
+        if b == 0 {
+            break;
+        }
+        digits.push(b - 1 + b'0');
+    }
+    let n_digits = digits.len() as i64;
+    let mut mantissa = BigInt::from(BigUint::parse_bytes(&digits, 10).unwrap());
+    if flip != 0 {
+        mantissa = -mantissa;
+    }
+    let decimal = if exponent >= n_digits {
+        Decimal::new(
+            mantissa * BigInt::from(10).pow((exponent - n_digits) as u32),
+            0,
+        )
+    } else {
+        Decimal::new(mantissa, (n_digits - exponent) as u32)
+    };
+    (decimal, rest)
+}
+
 const SIGN_MARK: u64 = 0x8000000000000000;
 
 fn order_encode_i64(v: i64) -> u64 {
@@ -228,10 +361,20 @@ impl DataValue {
             NULL_TAG => (DataValue::Null, remaining),
             FALSE_TAG => (DataValue::from(false), remaining),
             TRUE_TAG => (DataValue::from(true), remaining),
-            NUM_TAG => {
-                let (n, remaining) = Num::decode_from_key(remaining);
-                (DataValue::Num(n), remaining)
-            }
+            NUM_TAG => match remaining[8] {
+                IS_BIGINT => {
+                    let (i, rest) = decode_bigint(&remaining[9..]);
+                    (DataValue::BigInt(BigIntWrapper(i)), rest)
+                }
+                IS_DECIMAL => {
+                    let (d, rest) = decode_decimal(&remaining[9..]);
+                    (DataValue::Decimal(d), rest)
+                }
+                _ => {
+                    let (n, remaining) = Num::decode_from_key(remaining);
+                    (DataValue::Num(n), remaining)
+                }
+            },
             STR_TAG => {
                 let (bytes, remaining) = decode_bytes(remaining);
                 let s = unsafe { String::from_utf8_unchecked(bytes) };
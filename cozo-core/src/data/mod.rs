@@ -7,10 +7,13 @@
  */
 
 pub(crate) mod aggr;
+#[cfg(feature = "arrow")]
+pub(crate) mod arrow;
 pub(crate) mod expr;
 pub(crate) mod functions;
 pub(crate) mod json;
 pub(crate) mod memcmp;
+pub(crate) mod msgpack;
 pub(crate) mod program;
 pub(crate) mod relation;
 pub(crate) mod symb;
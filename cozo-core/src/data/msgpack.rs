@@ -0,0 +1,96 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+pub(crate) use rmpv::Value as MsgpackValue;
+
+use crate::data::json::JsonValue;
+use crate::data::value::{DataValue, Num};
+
+impl From<MsgpackValue> for DataValue {
+    fn from(v: MsgpackValue) -> Self {
+        match v {
+            MsgpackValue::Nil => DataValue::Null,
+            MsgpackValue::Boolean(b) => DataValue::Bool(b),
+            MsgpackValue::Integer(i) => match i.as_i64() {
+                Some(i) => DataValue::from(i),
+                None => DataValue::from(i.as_f64().unwrap_or(f64::NAN)),
+            },
+            MsgpackValue::F32(f) => DataValue::from(f as f64),
+            MsgpackValue::F64(f) => DataValue::from(f),
+            MsgpackValue::String(s) => {
+                if s.is_str() {
+                    DataValue::from(s.into_str().unwrap())
+                } else {
+                    DataValue::Bytes(s.into_bytes())
+                }
+            }
+            MsgpackValue::Binary(b) => DataValue::Bytes(b),
+            MsgpackValue::Array(arr) => {
+                DataValue::List(arr.into_iter().map(DataValue::from).collect())
+            }
+            MsgpackValue::Map(d) => DataValue::List(
+                d.into_iter()
+                    .map(|(k, v)| DataValue::List([DataValue::from(k), DataValue::from(v)].into()))
+                    .collect(),
+            ),
+            MsgpackValue::Ext(_, data) => DataValue::Bytes(data),
+        }
+    }
+}
+
+impl From<DataValue> for MsgpackValue {
+    fn from(v: DataValue) -> Self {
+        match v {
+            DataValue::Null => MsgpackValue::Nil,
+            DataValue::Bool(b) => MsgpackValue::Boolean(b),
+            DataValue::Num(Num::Int(i)) => MsgpackValue::from(i),
+            DataValue::Num(Num::Float(f)) => MsgpackValue::F64(f),
+            DataValue::Str(t) => MsgpackValue::from(t.as_str()),
+            DataValue::Bytes(bytes) => MsgpackValue::Binary(bytes),
+            DataValue::List(l) => {
+                MsgpackValue::Array(l.into_iter().map(MsgpackValue::from).collect())
+            }
+            DataValue::Bot => panic!("found bottom"),
+            DataValue::Set(l) => {
+                MsgpackValue::Array(l.into_iter().map(MsgpackValue::from).collect())
+            }
+            DataValue::Regex(r) => MsgpackValue::from(r.0.as_str()),
+            DataValue::Uuid(u) => MsgpackValue::from(u.0.to_string()),
+            DataValue::Validity(v) => MsgpackValue::Array(vec![
+                MsgpackValue::from(v.timestamp.0 .0),
+                MsgpackValue::Boolean(v.is_assert.0),
+            ]),
+            DataValue::BigInt(i) => MsgpackValue::from(i.0.to_string()),
+            DataValue::Decimal(d) => MsgpackValue::from(d.to_string()),
+        }
+    }
+}
+
+/// Convert JSON to msgpack, used for values such as errors that are only built as JSON.
+pub(crate) fn msgpack_from_json(v: JsonValue) -> MsgpackValue {
+    match v {
+        JsonValue::Null => MsgpackValue::Nil,
+        JsonValue::Bool(b) => MsgpackValue::Boolean(b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => MsgpackValue::from(i),
+            None => match n.as_u64() {
+                Some(u) => MsgpackValue::from(u),
+                None => MsgpackValue::F64(n.as_f64().unwrap_or(f64::NAN)),
+            },
+        },
+        JsonValue::String(s) => MsgpackValue::from(s),
+        JsonValue::Array(arr) => {
+            MsgpackValue::Array(arr.into_iter().map(msgpack_from_json).collect())
+        }
+        JsonValue::Object(d) => MsgpackValue::Map(
+            d.into_iter()
+                .map(|(k, v)| (MsgpackValue::from(k), msgpack_from_json(v)))
+                .collect(),
+        ),
+    }
+}
@@ -11,14 +11,14 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::sync::Arc;
 
-use miette::{ensure, Diagnostic, Result};
+use miette::{bail, ensure, Diagnostic, LabeledSpan, Result};
 use smallvec::SmallVec;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
-use crate::data::relation::StoredRelationMetadata;
+use crate::data::relation::{Collation, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::{FixedRule, FixedRuleHandle};
@@ -38,10 +38,77 @@ pub(crate) struct QueryOutOptions {
     pub(crate) limit: Option<usize>,
     pub(crate) offset: Option<usize>,
     pub(crate) timeout: Option<f64>,
+    /// set by `:partial_on_timeout`, to return the rows derived so far when the query
+    /// exceeds its timeout, instead of failing
+    pub(crate) partial_on_timeout: Option<SourceSpan>,
     pub(crate) sleep: Option<f64>,
     pub(crate) sorters: Vec<(Symbol, SortDir)>,
+    /// set by `collate` in `:order`, how the strings held by sort keys are compared
+    pub(crate) sort_collations: BTreeMap<Symbol, Collation>,
+    /// set by `:order` given expressions in parentheses, the expressions over the head
+    /// computing the sort keys, under the symbols standing for them in `sorters`
+    pub(crate) sort_exprs: BTreeMap<Symbol, Expr>,
     pub(crate) store_relation: Option<(InputRelationHandle, RelationOp)>,
+    /// set by relation options given `<- rule`, the rules whose results are written
+    /// to stored relations besides the result of the query, in the same run
+    pub(crate) secondary_outputs: Vec<(Symbol, InputRelationHandle, RelationOp)>,
     pub(crate) assertion: Option<QueryAssertion>,
+    pub(crate) set_var: Option<SmartString<LazyCompact>>,
+    pub(crate) windows: Vec<WindowDef>,
+    /// set by `:counts`, to return the numbers of rows affected instead of just the status
+    pub(crate) counts: Option<SourceSpan>,
+    /// set by `:profile`, to return the numbers of storage operations performed
+    /// instead of the result
+    pub(crate) profile: Option<SourceSpan>,
+    /// saved queries whose rules are imported into the program by `:import`
+    pub(crate) imports: Vec<Symbol>,
+    /// set by `:at`, the validity for stored relations not given one with `@`
+    pub(crate) default_validity: Option<ValidityTs>,
+    /// rules annotated with `@cached`, which are computed once in full
+    /// instead of being specialized to each way they are called
+    pub(crate) cached_rules: BTreeSet<Symbol>,
+    /// rules annotated with `@no_magic`, which are computed like `@cached` rules
+    /// and in addition do not specialize the rules they apply
+    pub(crate) no_magic_rules: BTreeSet<Symbol>,
+    /// rules annotated with `@max_depth(n)` or `@strict_max_depth(n)`, whose evaluation stops
+    /// after `n` iterations, and with the flag set fails if it has not converged by then
+    pub(crate) max_depth_rules: BTreeMap<Symbol, (usize, bool)>,
+    /// rules annotated with `@allow_full_scan`, which may scan stored relations in full
+    /// and join without shared variables in safe mode
+    pub(crate) full_scan_rules: BTreeSet<Symbol>,
+    /// set by `:since_last`, the name under which the last rows of the append-only relation
+    /// seen by the query are recorded, so that the next run skips them, and that relation
+    pub(crate) since_last: Option<(Symbol, Symbol)>,
+}
+
+impl QueryOutOptions {
+    /// The rules whose annotations keep them from being inlined, and those written
+    /// to stored relations
+    pub(crate) fn no_inline_rules(&self) -> BTreeSet<Symbol> {
+        self.cached_rules
+            .union(&self.no_magic_rules)
+            .chain(self.max_depth_rules.keys())
+            .chain(self.full_scan_rules.iter())
+            .chain(self.secondary_outputs.iter().map(|(rule, _, _)| rule))
+            .cloned()
+            .collect()
+    }
+    /// The rules that are computed once in full: those annotated with `@cached`,
+    /// and those written to stored relations, which are not specialized to the entry
+    pub(crate) fn full_rules(&self) -> BTreeSet<Symbol> {
+        self.cached_rules
+            .iter()
+            .chain(self.secondary_output_rules().iter())
+            .cloned()
+            .collect()
+    }
+    /// The rules whose results are written to stored relations besides the result of the query
+    pub(crate) fn secondary_output_rules(&self) -> BTreeSet<Symbol> {
+        self.secondary_outputs
+            .iter()
+            .map(|(rule, _, _)| rule.clone())
+            .collect()
+    }
 }
 
 impl Debug for QueryOutOptions {
@@ -61,14 +128,37 @@ impl Display for QueryOutOptions {
         if let Some(l) = self.timeout {
             writeln!(f, ":timeout {l};")?;
         }
+        if self.partial_on_timeout.is_some() {
+            writeln!(f, ":partial_on_timeout;")?;
+        }
         for (symb, dir) in &self.sorters {
             write!(f, ":order ")?;
             if *dir == SortDir::Dsc {
                 write!(f, "-")?;
             }
-            writeln!(f, "{symb};")?;
+            match self.sort_exprs.get(symb) {
+                Some(expr) => write!(f, "({expr})")?,
+                None => write!(f, "{symb}")?,
+            }
+            if let Some(collation) = self.sort_collations.get(symb) {
+                write!(f, " collate {collation}")?;
+            }
+            writeln!(f, ";")?;
+        }
+        for window in &self.windows {
+            writeln!(f, ":window {window};")?;
         }
-        if let Some((
+        let outputs = self
+            .store_relation
+            .iter()
+            .map(|(handle, op)| (None, handle, op))
+            .chain(
+                self.secondary_outputs
+                    .iter()
+                    .map(|(rule, handle, op)| (Some(rule), handle, op)),
+            );
+        for (
+            rule,
             InputRelationHandle {
                 name,
                 metadata: StoredRelationMetadata { keys, non_keys },
@@ -77,7 +167,7 @@ impl Display for QueryOutOptions {
                 ..
             },
             op,
-        )) = &self.store_relation
+        ) in outputs
         {
             match op {
                 RelationOp::Create => {
@@ -129,7 +219,11 @@ impl Display for QueryOutOptions {
                     write!(f, " = {bind}")?;
                 }
             }
-            writeln!(f, "}};")?;
+            write!(f, "}}")?;
+            if let Some(rule) = rule {
+                write!(f, " <- {rule}")?;
+            }
+            writeln!(f, ";")?;
         }
 
         if let Some(a) = &self.assertion {
@@ -143,6 +237,26 @@ impl Display for QueryOutOptions {
             }
         }
 
+        if let Some(name) = &self.set_var {
+            writeln!(f, ":set_var {name};")?;
+        }
+
+        for import in &self.imports {
+            writeln!(f, ":import {import};")?;
+        }
+        if self.counts.is_some() {
+            writeln!(f, ":counts;")?;
+        }
+        if self.profile.is_some() {
+            writeln!(f, ":profile;")?;
+        }
+        if let Some(vld) = self.default_validity {
+            writeln!(f, ":at {};", vld.0 .0)?;
+        }
+        if let Some((name, rel)) = &self.since_last {
+            writeln!(f, ":since_last {name} {rel};")?;
+        }
+
         Ok(())
     }
 }
@@ -157,6 +271,39 @@ impl QueryOutOptions {
     }
 }
 
+/// A column computed over the sorted output rows, declared with the `:window` option
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct WindowDef {
+    pub(crate) name: Symbol,
+    pub(crate) func: WindowFn,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) enum WindowFn {
+    RowNumber,
+    /// rank by the sort keys, with gaps after ties
+    Rank,
+    /// rank by the sort keys, without gaps after ties
+    DenseRank,
+    CumSum(Symbol),
+    Lag(Symbol, usize),
+    Lead(Symbol, usize),
+}
+
+impl Display for WindowDef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = ", self.name)?;
+        match &self.func {
+            WindowFn::RowNumber => write!(f, "row_number()"),
+            WindowFn::Rank => write!(f, "rank()"),
+            WindowFn::DenseRank => write!(f, "dense_rank()"),
+            WindowFn::CumSum(col) => write!(f, "cumsum({col})"),
+            WindowFn::Lag(col, n) => write!(f, "lag({col}, {n})"),
+            WindowFn::Lead(col, n) => write!(f, "lead({col}, {n})"),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum SortDir {
     Asc,
@@ -196,6 +343,41 @@ pub(crate) enum InputInlineRulesOrFixed {
 }
 
 impl InputInlineRulesOrFixed {
+    /// The head of the rule, with aggregated columns named after their aggregations
+    pub(crate) fn out_head(&self) -> Result<Vec<Symbol>> {
+        match self {
+            InputInlineRulesOrFixed::Rules { rules } => {
+                let head = &rules.last().unwrap().head;
+                let mut ret = Vec::with_capacity(head.len());
+                let aggrs = &rules.last().unwrap().aggr;
+                for (symb, aggr) in head.iter().zip(aggrs.iter()) {
+                    if let Some((aggr, _)) = aggr {
+                        ret.push(Symbol::new(
+                            format!(
+                                "{}({})",
+                                aggr.name
+                                    .strip_prefix("AGGR_")
+                                    .unwrap()
+                                    .to_ascii_lowercase(),
+                                symb
+                            ),
+                            symb.span,
+                        ))
+                    } else {
+                        ret.push(symb.clone())
+                    }
+                }
+                Ok(ret)
+            }
+            InputInlineRulesOrFixed::Fixed { fixed } => {
+                if fixed.head.is_empty() {
+                    Err(EntryHeadNotExplicitlyDefinedError(self.first_span()).into())
+                } else {
+                    Ok(fixed.head.to_vec())
+                }
+            }
+        }
+    }
     pub(crate) fn first_span(&self) -> SourceSpan {
         match self {
             InputInlineRulesOrFixed::Rules { rules, .. } => rules[0].span,
@@ -521,17 +703,145 @@ struct EntryHeadNotExplicitlyDefinedError(#[label] SourceSpan);
 #[diagnostic(help("You need to have one rule named '?'"))]
 pub(crate) struct NoEntryError;
 
+/// A rule applied with a number of arguments different from the arity of its definitions,
+/// labelling every definition and application of the rule
+#[derive(Debug)]
+struct RuleArityConflict {
+    name: String,
+    arity: usize,
+    definitions: Vec<SourceSpan>,
+    applications: Vec<(usize, SourceSpan)>,
+}
+
+impl std::error::Error for RuleArityConflict {}
+
+impl Display for RuleArityConflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Rule {} is applied with an arity different from that of its definition",
+            self.name
+        )
+    }
+}
+
+impl Diagnostic for RuleArityConflict {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new("eval::rule_arity_mismatch"))
+    }
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(format!(
+            "Rule {} is defined with arity {}",
+            self.name, self.arity
+        )))
+    }
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let definitions = self.definitions.iter().map(|span| {
+            LabeledSpan::new_with_span(Some(format!("defined with arity {}", self.arity)), span)
+        });
+        let applications = self.applications.iter().map(|(arity, span)| {
+            LabeledSpan::new_with_span(Some(format!("applied to {arity} arguments")), span)
+        });
+        Some(Box::new(definitions.chain(applications)))
+    }
+}
+
+impl InputAtom {
+    fn collect_rule_applications(&self, coll: &mut Vec<(Symbol, usize, SourceSpan)>) {
+        match self {
+            InputAtom::Rule { inner } => {
+                coll.push((inner.name.clone(), inner.args.len(), inner.span))
+            }
+            InputAtom::Negation { inner, .. }
+            | InputAtom::Existence { inner, .. }
+            | InputAtom::Optional { inner, .. } => inner.collect_rule_applications(coll),
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => {
+                for atom in inner {
+                    atom.collect_rule_applications(coll)
+                }
+            }
+            InputAtom::NamedFieldRelation { .. }
+            | InputAtom::Relation { .. }
+            | InputAtom::Predicate { .. }
+            | InputAtom::Unification { .. } => {}
+        }
+    }
+}
+
 impl InputProgram {
-    pub(crate) fn needs_write_lock(&self) -> Option<SmartString<LazyCompact>> {
-        if let Some((h, _)) = &self.out_opts.store_relation {
-            if !h.name.name.starts_with('_') {
-                Some(h.name.name.clone())
-            } else {
-                None
+    /// Check that every rule is applied with the arity it is defined with, so that a
+    /// mismatch is reported with all the places involved before anything is evaluated.
+    pub(crate) fn check_rule_arities(&self) -> Result<()> {
+        let mut applications = vec![];
+        for rules_or_fixed in self.prog.values() {
+            if let InputInlineRulesOrFixed::Rules { rules } = rules_or_fixed {
+                for rule in rules {
+                    for atom in &rule.body {
+                        atom.collect_rule_applications(&mut applications);
+                    }
+                }
+            }
+        }
+        for (name, arity, _) in &applications {
+            let (defined_arity, definitions) = match self.prog.get(name) {
+                None => continue,
+                Some(InputInlineRulesOrFixed::Rules { rules }) => (
+                    rules[0].head.len(),
+                    rules
+                        .iter()
+                        .map(|rule| match (rule.head.first(), rule.head.last()) {
+                            (Some(first), Some(last)) => first.span.merge(last.span),
+                            _ => rule.span,
+                        })
+                        .collect(),
+                ),
+                Some(InputInlineRulesOrFixed::Fixed { fixed }) => (fixed.arity, vec![fixed.span]),
+            };
+            if *arity != defined_arity {
+                bail!(RuleArityConflict {
+                    name: name.to_string(),
+                    arity: defined_arity,
+                    definitions,
+                    applications: applications
+                        .iter()
+                        .filter(|(n, _, _)| n == name)
+                        .map(|(_, arity, span)| (*arity, *span))
+                        .collect(),
+                });
             }
-        } else {
-            None
         }
+        Ok(())
+    }
+    /// The largest number of rows given to a constant rule of the program
+    pub(crate) fn max_const_rule_rows(&self) -> usize {
+        self.prog
+            .values()
+            .filter_map(|rules| match rules {
+                InputInlineRulesOrFixed::Fixed { fixed }
+                    if fixed.fixed_handle.name.name == "Constant" =>
+                {
+                    fixed
+                        .options
+                        .get("data")
+                        .and_then(|data| data.get_const())
+                        .and_then(|data| data.get_slice())
+                        .map(|rows| rows.len())
+                }
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+    /// The stored relations written by the query, which are locked while it runs
+    pub(crate) fn needs_write_lock(&self) -> BTreeSet<SmartString<LazyCompact>> {
+        self.out_opts
+            .store_relation
+            .iter()
+            .map(|(h, _)| h)
+            .chain(self.out_opts.secondary_outputs.iter().map(|(_, h, _)| h))
+            .filter(|h| !h.name.name.starts_with('_'))
+            .map(|h| h.name.name.clone())
+            .collect()
     }
 
     pub(crate) fn get_entry_arity(&self) -> Result<usize> {
@@ -556,47 +866,29 @@ impl InputProgram {
         }
     }
     pub(crate) fn get_entry_out_head(&self) -> Result<Vec<Symbol>> {
-        if let Some(entry) = self.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
-            return match entry {
-                InputInlineRulesOrFixed::Rules { rules } => {
-                    let head = &rules.last().unwrap().head;
-                    let mut ret = Vec::with_capacity(head.len());
-                    let aggrs = &rules.last().unwrap().aggr;
-                    for (symb, aggr) in head.iter().zip(aggrs.iter()) {
-                        if let Some((aggr, _)) = aggr {
-                            ret.push(Symbol::new(
-                                format!(
-                                    "{}({})",
-                                    aggr.name
-                                        .strip_prefix("AGGR_")
-                                        .unwrap()
-                                        .to_ascii_lowercase(),
-                                    symb
-                                ),
-                                symb.span,
-                            ))
-                        } else {
-                            ret.push(symb.clone())
-                        }
-                    }
-                    Ok(ret)
-                }
-                InputInlineRulesOrFixed::Fixed { fixed } => {
-                    if fixed.head.is_empty() {
-                        Err(EntryHeadNotExplicitlyDefinedError(entry.first_span()).into())
-                    } else {
-                        Ok(fixed.head.to_vec())
-                    }
-                }
-            };
+        match self.prog.get(&Symbol::new(PROG_ENTRY, SourceSpan(0, 0))) {
+            Some(entry) => entry.out_head(),
+            None => Err(NoEntryError.into()),
+        }
+    }
+    /// The output head of the rule `name`, whose results are written to a stored relation
+    /// by a secondary output
+    pub(crate) fn get_rule_out_head(&self, name: &Symbol) -> Result<Vec<Symbol>> {
+        #[derive(Debug, Diagnostic, Error)]
+        #[error("Rule {0} written to a stored relation is not defined")]
+        #[diagnostic(code(parser::output_rule_not_found))]
+        struct OutputRuleNotFound(String, #[label] SourceSpan);
+
+        match self.prog.get(name) {
+            Some(rule) => rule.out_head(),
+            None => bail!(OutputRuleNotFound(name.to_string(), name.span)),
         }
-
-        Err(NoEntryError.into())
     }
     pub(crate) fn into_normalized_program(
         self,
         tx: &SessionTx<'_>,
     ) -> Result<(NormalFormProgram, QueryOutOptions)> {
+        self.check_rule_arities()?;
         let mut prog: BTreeMap<Symbol, _> = Default::default();
         for (k, rules_or_fixed) in self.prog {
             match rules_or_fixed {
@@ -865,7 +1157,9 @@ impl MagicInlineRule {
         let mut coll = BTreeSet::new();
         for atom in self.body.iter() {
             match atom {
-                MagicAtom::Rule(rule) | MagicAtom::NegatedRule(rule) => {
+                MagicAtom::Rule(rule)
+                | MagicAtom::NegatedRule(rule)
+                | MagicAtom::ExistsRule(rule) => {
                     coll.insert(rule.name.clone());
                 }
                 _ => {}
@@ -893,6 +1187,18 @@ pub(crate) enum InputAtom {
         inner: Box<InputAtom>,
         span: SourceSpan,
     },
+    /// `exists`, holding if the inner application has any match, without binding
+    /// the variables that are not bound elsewhere
+    Existence {
+        inner: Box<InputAtom>,
+        span: SourceSpan,
+    },
+    /// `optional(...)`, binding the variables of the inner application to null
+    /// for rows that have no match
+    Optional {
+        inner: Box<InputAtom>,
+        span: SourceSpan,
+    },
     Conjunction {
         inner: Vec<InputAtom>,
         span: SourceSpan,
@@ -943,6 +1249,12 @@ impl Display for InputAtom {
             InputAtom::Negation { inner, .. } => {
                 write!(f, "not {inner}")?;
             }
+            InputAtom::Existence { inner, .. } => {
+                write!(f, "exists {inner}")?;
+            }
+            InputAtom::Optional { inner, .. } => {
+                write!(f, "optional({inner})")?;
+            }
             InputAtom::Conjunction { inner, .. } => {
                 for (i, a) in inner.iter().enumerate() {
                     if i > 0 {
@@ -995,6 +1307,8 @@ impl InputAtom {
     pub(crate) fn span(&self) -> SourceSpan {
         match self {
             InputAtom::Negation { span, .. }
+            | InputAtom::Existence { span, .. }
+            | InputAtom::Optional { span, .. }
             | InputAtom::Conjunction { span, .. }
             | InputAtom::Disjunction { span, .. } => *span,
             InputAtom::Rule { inner, .. } => inner.span,
@@ -1012,6 +1326,8 @@ pub(crate) enum NormalFormAtom {
     Relation(NormalFormRelationApplyAtom),
     NegatedRule(NormalFormRuleApplyAtom),
     NegatedRelation(NormalFormRelationApplyAtom),
+    ExistsRule(NormalFormRuleApplyAtom),
+    ExistsRelation(NormalFormRelationApplyAtom),
     Predicate(Expr),
     Unification(Unification),
 }
@@ -1023,9 +1339,29 @@ pub(crate) enum MagicAtom {
     Predicate(Expr),
     NegatedRule(MagicRuleApplyAtom),
     NegatedRelation(MagicRelationApplyAtom),
+    ExistsRule(MagicRuleApplyAtom),
+    ExistsRelation(MagicRelationApplyAtom),
     Unification(Unification),
 }
 
+/// A hint given to a stored relation atom, overriding the choice of the index to scan
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum IndexHint {
+    /// `@use_index(name)`: scan the named index of the relation
+    UseIndex(Symbol),
+    /// `@no_index`: scan the relation itself
+    NoIndex,
+}
+
+impl Display for IndexHint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexHint::UseIndex(idx) => write!(f, "@use_index({idx})"),
+            IndexHint::NoIndex => write!(f, "@no_index"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub(crate) struct InputRuleApplyAtom {
     pub(crate) name: Symbol,
@@ -1038,6 +1374,9 @@ pub(crate) struct InputNamedFieldRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: BTreeMap<SmartString<LazyCompact>, Expr>,
     pub(crate) valid_at: Option<ValidityTs>,
+    /// The valid time selected by `valid_at` for bi-temporal relations
+    pub(crate) valid_time: Option<Expr>,
+    pub(crate) hint: Option<IndexHint>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1046,6 +1385,9 @@ pub(crate) struct InputRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Expr>,
     pub(crate) valid_at: Option<ValidityTs>,
+    /// The valid time selected by `valid_at` for bi-temporal relations
+    pub(crate) valid_time: Option<Expr>,
+    pub(crate) hint: Option<IndexHint>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1061,6 +1403,7 @@ pub(crate) struct NormalFormRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Symbol>,
     pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) hint: Option<IndexHint>,
     pub(crate) span: SourceSpan,
 }
 
@@ -1076,6 +1419,7 @@ pub(crate) struct MagicRelationApplyAtom {
     pub(crate) name: Symbol,
     pub(crate) args: Vec<Symbol>,
     pub(crate) valid_at: Option<ValidityTs>,
+    pub(crate) hint: Option<IndexHint>,
     pub(crate) span: SourceSpan,
 }
 
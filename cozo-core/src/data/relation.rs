@@ -17,9 +17,13 @@ use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::UnicodeNormalization;
 
 use crate::data::expr::Expr;
+use crate::data::functions::{op_to_bigint, op_to_decimal};
 use crate::data::value::{DataValue, UuidWrapper, Validity, ValidityTs};
+use crate::utils::near_misses;
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
 pub(crate) struct NullableColType {
@@ -38,6 +42,8 @@ impl Display for NullableColType {
             ColType::Bytes => f.write_str("Bytes")?,
             ColType::Uuid => f.write_str("Uuid")?,
             ColType::Validity => f.write_str("Validity")?,
+            ColType::BigInt => f.write_str("BigInt")?,
+            ColType::Decimal => f.write_str("Decimal")?,
             ColType::List { eltype, len } => {
                 f.write_str("[")?;
                 write!(f, "{eltype}")?;
@@ -65,6 +71,80 @@ impl Display for NullableColType {
     }
 }
 
+/// How strings are compared in a collated index column, or when sorted by `:order`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
+pub(crate) enum Collation {
+    /// Ignore case
+    NoCase,
+    /// Ignore case and accents
+    NoAccent,
+    /// Compare runs of digits by the numbers they denote, so that `x9` comes before `x10`
+    Numeric,
+}
+
+impl Collation {
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "nocase" => Collation::NoCase,
+            "noaccent" => Collation::NoAccent,
+            "numeric" => Collation::Numeric,
+            _ => return None,
+        })
+    }
+    /// The key under which strings that are equal under the collation are stored.
+    /// Values other than strings are returned as they are.
+    pub(crate) fn apply(&self, val: DataValue) -> DataValue {
+        let s = match val {
+            DataValue::Str(s) => s,
+            v => return v,
+        };
+        DataValue::from(match self {
+            Collation::NoCase => s.to_lowercase(),
+            Collation::NoAccent => s
+                .nfkd()
+                .filter(|c| !is_combining_mark(*c))
+                .collect::<String>()
+                .to_lowercase(),
+            Collation::Numeric => numeric_collation_key(&s),
+        })
+    }
+}
+
+/// Each run of digits is replaced by its length without leading zeros, written with three digits,
+/// followed by the digits themselves: shorter numbers then come first, and numbers of the same
+/// length are compared digit by digit. Digits still come before letters as in plain strings.
+fn numeric_collation_key(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 3);
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        if !c.is_ascii_digit() {
+            ret.push(c);
+            continue;
+        }
+        let mut digits = String::from(c);
+        while let Some(d) = chars.next_if(|d| d.is_ascii_digit()) {
+            digits.push(d);
+        }
+        let digits = match digits.trim_start_matches('0') {
+            "" => "0",
+            d => d,
+        };
+        ret.push_str(&format!("{:03}", digits.len().min(999)));
+        ret.push_str(digits);
+    }
+    ret
+}
+
+impl Display for Collation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Collation::NoCase => f.write_str("nocase"),
+            Collation::NoAccent => f.write_str("noaccent"),
+            Collation::Numeric => f.write_str("numeric"),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
 pub(crate) enum ColType {
     Any,
@@ -80,6 +160,8 @@ pub(crate) enum ColType {
     },
     Tuple(Vec<NullableColType>),
     Validity,
+    BigInt,
+    Decimal,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize)]
@@ -138,9 +220,21 @@ impl StoredRelationMetadata {
         #[derive(Debug, Error, Diagnostic)]
         #[error("required column {0} not found")]
         #[diagnostic(code(eval::required_col_not_found))]
-        struct ColumnNotFound(String);
+        struct ColumnNotFound(String, #[help] Option<String>);
 
-        bail!(ColumnNotFound(col.name.to_string()))
+        let misses = near_misses(
+            &col.name,
+            self.keys
+                .iter()
+                .chain(self.non_keys.iter())
+                .map(|c| c.name.as_str()),
+        );
+        let help = if misses.is_empty() {
+            None
+        } else {
+            Some(format!("Did you mean: {}", misses.join(", ")))
+        };
+        bail!(ColumnNotFound(col.name.to_string(), help))
     }
 }
 
@@ -209,6 +303,24 @@ impl NullableColType {
                 _ => bail!(make_err()),
             },
             ColType::Uuid => DataValue::Uuid(UuidWrapper(data.get_uuid().ok_or_else(make_err)?)),
+            ColType::BigInt => match data {
+                DataValue::Num(_)
+                | DataValue::Str(_)
+                | DataValue::BigInt(_)
+                | DataValue::Decimal(_) => {
+                    op_to_bigint(std::slice::from_ref(&data)).map_err(|_| make_err())?
+                }
+                _ => bail!(make_err()),
+            },
+            ColType::Decimal => match data {
+                DataValue::Num(_)
+                | DataValue::Str(_)
+                | DataValue::BigInt(_)
+                | DataValue::Decimal(_) => {
+                    op_to_decimal(std::slice::from_ref(&data)).map_err(|_| make_err())?
+                }
+                _ => bail!(make_err()),
+            },
             ColType::List { eltype, len } => {
                 if let DataValue::List(l) = data {
                     if let Some(expected) = len {
@@ -9,7 +9,7 @@
 use approx::AbsDiffEq;
 use itertools::Itertools;
 
-use crate::data::aggr::parse_aggr;
+use crate::data::aggr::{parse_aggr, AGGR_NAMES};
 use crate::data::value::DataValue;
 
 #[test]
@@ -210,6 +210,32 @@ fn test_count_unique() {
     assert_eq!(count_unique_aggr.get().unwrap(), DataValue::from(3));
 }
 
+#[test]
+fn test_approx_count_distinct() {
+    let mut aggr = parse_aggr("approx_count_distinct").unwrap().clone();
+    aggr.normal_init(&[]).unwrap();
+
+    let mut small_aggr = aggr.normal_op.unwrap();
+    for i in [1, 2, 3, 1, 2, 1] {
+        small_aggr.set(&DataValue::from(i)).unwrap();
+    }
+    assert_eq!(small_aggr.get().unwrap(), DataValue::from(3));
+
+    let mut aggr = parse_aggr("approx_count_distinct").unwrap().clone();
+    aggr.normal_init(&[DataValue::from(14)]).unwrap();
+    let mut large_aggr = aggr.normal_op.unwrap();
+    for _ in 0..2 {
+        for i in 0..100000 {
+            large_aggr.set(&DataValue::from(i)).unwrap();
+        }
+    }
+    let estimate = large_aggr.get().unwrap().get_int().unwrap();
+    assert!((97000..103000).contains(&estimate), "{}", estimate);
+
+    let mut aggr = parse_aggr("approx_count_distinct").unwrap().clone();
+    assert!(aggr.normal_init(&[DataValue::from(20)]).is_err());
+}
+
 #[test]
 fn test_collect() {
     let mut aggr = parse_aggr("collect").unwrap().clone();
@@ -572,3 +598,14 @@ fn test_bit_xor() {
     bit_xor_aggr.set(&DataValue::Bytes(vec![0b01011])).unwrap();
     assert_eq!(bit_xor_aggr.get().unwrap(), DataValue::Bytes(vec![0b10111]));
 }
+
+#[test]
+fn test_aggr_names() {
+    for name in AGGR_NAMES {
+        let aggr = parse_aggr(name).unwrap();
+        assert_eq!(
+            aggr.name.strip_prefix("AGGR_").unwrap().to_lowercase(),
+            *name
+        );
+    }
+}
@@ -0,0 +1,68 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use crate::data::value::DataValue;
+use crate::NamedRows;
+
+#[test]
+fn test_arrow_ipc_roundtrip() {
+    let rows = NamedRows::new(
+        vec![
+            "i".to_string(),
+            "f".to_string(),
+            "s".to_string(),
+            "b".to_string(),
+            "mixed".to_string(),
+            "nothing".to_string(),
+        ],
+        vec![
+            vec![
+                DataValue::from(1),
+                DataValue::from(1.5),
+                DataValue::from("a"),
+                DataValue::Bytes(vec![1, 2]),
+                DataValue::List(vec![DataValue::from(1)]),
+                DataValue::Null,
+            ],
+            vec![
+                DataValue::Null,
+                DataValue::from(2),
+                DataValue::Null,
+                DataValue::Bytes(vec![]),
+                DataValue::from("x"),
+                DataValue::Null,
+            ],
+        ],
+    );
+    let encoded = rows.clone().into_arrow_ipc().unwrap();
+    let decoded = NamedRows::from_arrow_ipc(&encoded).unwrap();
+    assert_eq!(decoded.headers, rows.headers);
+    assert_eq!(
+        decoded.rows,
+        vec![
+            vec![
+                DataValue::from(1),
+                DataValue::from(1.5),
+                DataValue::from("a"),
+                DataValue::Bytes(vec![1, 2]),
+                DataValue::from("[1]"),
+                DataValue::Null,
+            ],
+            vec![
+                DataValue::Null,
+                DataValue::from(2.),
+                DataValue::Null,
+                DataValue::Bytes(vec![]),
+                DataValue::from("\"x\""),
+                DataValue::Null,
+            ],
+        ]
+    );
+
+    assert!(NamedRows::from_arrow_ipc(b"not arrow").is_err());
+}
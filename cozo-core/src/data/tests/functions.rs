@@ -169,7 +169,11 @@ fn test_comparators() {
         op_ge(&[DataValue::from(1), DataValue::from(2)]).unwrap(),
         DataValue::from(false)
     );
-    assert!(op_ge(&[DataValue::Null, DataValue::from(true)]).is_err());
+    assert!(op_ge(&[DataValue::Str("a".into()), DataValue::from(true)]).is_err());
+    assert_eq!(
+        op_ge(&[DataValue::Null, DataValue::from(1)]).unwrap(),
+        DataValue::from(false)
+    );
     assert_eq!(
         op_gt(&[DataValue::from(2), DataValue::from(1)]).unwrap(),
         DataValue::from(true)
@@ -194,7 +198,11 @@ fn test_comparators() {
         op_gt(&[DataValue::from(1), DataValue::from(2)]).unwrap(),
         DataValue::from(false)
     );
-    assert!(op_gt(&[DataValue::Null, DataValue::from(true)]).is_err());
+    assert!(op_gt(&[DataValue::Str("a".into()), DataValue::from(true)]).is_err());
+    assert_eq!(
+        op_gt(&[DataValue::Null, DataValue::from(1)]).unwrap(),
+        DataValue::from(false)
+    );
     assert_eq!(
         op_le(&[DataValue::from(2), DataValue::from(1)]).unwrap(),
         DataValue::from(false)
@@ -219,7 +227,11 @@ fn test_comparators() {
         op_le(&[DataValue::from(1), DataValue::from(2)]).unwrap(),
         DataValue::from(true)
     );
-    assert!(op_le(&[DataValue::Null, DataValue::from(true)]).is_err());
+    assert!(op_le(&[DataValue::Str("a".into()), DataValue::from(true)]).is_err());
+    assert_eq!(
+        op_le(&[DataValue::Null, DataValue::from(1)]).unwrap(),
+        DataValue::from(false)
+    );
     assert_eq!(
         op_lt(&[DataValue::from(2), DataValue::from(1)]).unwrap(),
         DataValue::from(false)
@@ -244,7 +256,11 @@ fn test_comparators() {
         op_lt(&[DataValue::from(1), DataValue::from(2)]).unwrap(),
         DataValue::from(true)
     );
-    assert!(op_lt(&[DataValue::Null, DataValue::from(true)]).is_err());
+    assert!(op_lt(&[DataValue::Str("a".into()), DataValue::from(true)]).is_err());
+    assert_eq!(
+        op_lt(&[DataValue::Null, DataValue::from(1)]).unwrap(),
+        DataValue::from(false)
+    );
 }
 
 #[test]
@@ -952,6 +968,19 @@ fn test_unicode_normalize() {
     )
 }
 
+#[test]
+fn test_collate() {
+    assert_eq!(
+        op_collate(&[DataValue::from("AbC"), DataValue::from("nocase")]).unwrap(),
+        DataValue::from("abc")
+    );
+    assert_eq!(
+        op_collate(&[DataValue::from("Crème Brûlée"), DataValue::from("noaccent")]).unwrap(),
+        DataValue::from("creme brulee")
+    );
+    assert!(op_collate(&[DataValue::from("abc"), DataValue::from("klingon")]).is_err());
+}
+
 #[test]
 fn test_sort_reverse() {
     assert_eq!(
@@ -1455,4 +1484,173 @@ fn test_coalesce() {
         .unwrap()
         .rows;
     assert_eq!(res[0][0], DataValue::from(2));
+    let res = db
+        .run_script(
+            "?[a, b, c] := a = if_null(null, 1), b = if_null(2, 1), c = is_null(if_null(null, null))",
+            Default::default(),
+        )
+        .unwrap()
+        .rows;
+    assert_eq!(
+        res[0],
+        vec![
+            DataValue::from(1),
+            DataValue::from(2),
+            DataValue::from(true)
+        ]
+    );
+    // ordering comparisons with null filter rows out instead of failing the query
+    let res = db
+        .run_script("?[a] := a in [1, null, 3], a > 1", Default::default())
+        .unwrap()
+        .rows;
+    assert_eq!(res, vec![vec![DataValue::from(3)]]);
+    let res = db
+        .run_script("?[a] := a in [1, null, 3], a == null", Default::default())
+        .unwrap()
+        .rows;
+    assert_eq!(res, vec![vec![DataValue::Null]]);
+}
+
+#[test]
+fn test_function_names() {
+    use crate::data::expr::{get_op, FUNCTION_NAMES};
+
+    for name in FUNCTION_NAMES {
+        let op = get_op(name).unwrap();
+        assert_eq!(op.name.strip_prefix("OP_").unwrap().to_lowercase(), *name);
+    }
+}
+
+#[test]
+fn test_bigint_and_decimal() {
+    let big = op_to_bigint(&[DataValue::from("123456789012345678901234567890")]).unwrap();
+    assert!(op_is_bigint(&[big.clone()]).unwrap().get_bool().unwrap());
+    assert_eq!(
+        op_to_string(&[op_add(&[big.clone(), DataValue::from(10)]).unwrap()]).unwrap(),
+        DataValue::from("123456789012345678901234567900")
+    );
+    assert_eq!(
+        op_to_string(&[op_mul(&[big.clone(), big.clone()]).unwrap()]).unwrap(),
+        DataValue::from("15241578753238836750495351562536198787501905199875019052100")
+    );
+    assert!(op_add(&[big.clone(), DataValue::from(1.5)]).is_err());
+    assert!(op_div(&[big.clone(), DataValue::from(2)]).is_err());
+    assert_eq!(
+        op_mod(&[big.clone(), DataValue::from(11)]).unwrap(),
+        op_to_bigint(&[DataValue::from(7)]).unwrap()
+    );
+    assert!(op_to_int(&[big]).is_err());
+
+    let a = op_to_decimal(&[DataValue::from("0.1")]).unwrap();
+    let b = op_to_decimal(&[DataValue::from(0.2)]).unwrap();
+    let sum = op_add(&[a.clone(), b]).unwrap();
+    assert!(op_is_decimal(&[sum.clone()]).unwrap().get_bool().unwrap());
+    assert_eq!(sum, op_to_decimal(&[DataValue::from("0.30")]).unwrap());
+    assert_eq!(op_to_string(&[sum]).unwrap(), DataValue::from("0.3"));
+    assert_eq!(
+        op_to_string(&[op_sub(&[a.clone(), DataValue::from(1)]).unwrap()]).unwrap(),
+        DataValue::from("-0.9")
+    );
+    let third =
+        op_decimal_div(&[DataValue::from(1), DataValue::from(3), DataValue::from(5)]).unwrap();
+    assert_eq!(
+        op_to_string(&[third.clone()]).unwrap(),
+        DataValue::from("0.33333")
+    );
+    assert_eq!(
+        op_to_string(&[op_round_decimal(&[third, DataValue::from(2)]).unwrap()]).unwrap(),
+        DataValue::from("0.33")
+    );
+    let two_thirds = op_to_decimal(&[DataValue::from("-0.665")]).unwrap();
+    assert_eq!(
+        op_to_string(&[op_round_decimal(&[two_thirds, DataValue::from(2)]).unwrap()]).unwrap(),
+        DataValue::from("-0.67")
+    );
+    assert!(op_decimal_div(&[a.clone(), DataValue::from(0), DataValue::from(2)]).is_err());
+    assert_eq!(op_to_float(&[a.clone()]).unwrap(), DataValue::from(0.1));
+    assert!(
+        op_lt(&[a.clone(), op_to_decimal(&[DataValue::from(1)]).unwrap()])
+            .unwrap()
+            .get_bool()
+            .unwrap()
+    );
+    assert!(op_lt(&[a.clone(), DataValue::from(1)])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    assert!(op_eq(&[a.clone(), DataValue::from(0.1)])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    assert!(op_lt(&[a, DataValue::Str("1".into())]).is_err());
+    let five = op_to_bigint(&[DataValue::from(5)]).unwrap();
+    assert!(op_eq(&[five.clone(), DataValue::from(5)])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    assert!(!op_neq(&[five.clone(), DataValue::from(5.0)])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    assert!(op_ge(&[five, DataValue::from(4.5)])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    let huge = op_to_bigint(&[DataValue::from("1000000000000000000000")]).unwrap();
+    assert!(op_gt(&[huge.clone(), DataValue::from(1)])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    assert!(op_le(&[DataValue::from(f64::NEG_INFINITY), huge])
+        .unwrap()
+        .get_bool()
+        .unwrap());
+    assert!(op_to_bigint(&[op_to_decimal(&[DataValue::from("2.5")]).unwrap()]).is_err());
+    assert!(op_to_decimal(&[DataValue::from("1.2.3")]).is_err());
+}
+
+#[test]
+fn test_bytes_functions() {
+    let b = DataValue::Bytes(vec![0x00, 0xab, 0x10, 0xff]);
+    let hex = op_encode_hex(&[b.clone()]).unwrap();
+    assert_eq!(hex, DataValue::from("00ab10ff"));
+    assert_eq!(op_decode_hex(&[hex]).unwrap(), b);
+    assert_eq!(op_decode_hex(&[DataValue::from("00AB10FF")]).unwrap(), b);
+    assert!(op_decode_hex(&[DataValue::from("abc")]).is_err());
+    assert!(op_decode_hex(&[DataValue::from("zz")]).is_err());
+
+    assert_eq!(
+        op_slice(&[b.clone(), DataValue::from(1), DataValue::from(-1)]).unwrap(),
+        DataValue::Bytes(vec![0xab, 0x10])
+    );
+    assert_eq!(
+        op_concat(&[b.clone(), DataValue::Bytes(vec![1])]).unwrap(),
+        DataValue::Bytes(vec![0x00, 0xab, 0x10, 0xff, 1])
+    );
+    assert!(op_concat(&[b.clone(), DataValue::from("x")]).is_err());
+    assert_eq!(op_length(&[b]).unwrap(), DataValue::from(4));
+
+    assert_eq!(
+        op_encode_hex(&[op_sha256(&[DataValue::from("abc")]).unwrap()]).unwrap(),
+        DataValue::from("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+    );
+    assert_eq!(
+        op_sha256(&[DataValue::from("abc")]).unwrap(),
+        op_sha256(&[DataValue::Bytes(b"abc".to_vec())]).unwrap()
+    );
+    for (data, expected) in [
+        ("", 0xef46db3751d8e999u64),
+        ("abc", 0x44bc2cf5ad770999),
+        (
+            "Nobody inspects the spammish repetition",
+            0xfbcea83c8a378bf1,
+        ),
+    ] {
+        assert_eq!(
+            op_xxhash64(&[DataValue::from(data)]).unwrap(),
+            DataValue::from(expected as i64)
+        );
+    }
+    assert!(op_xxhash64(&[DataValue::from(1)]).is_err());
 }
@@ -135,3 +135,80 @@ fn encode_decode_datavalues() {
     assert!(remaining.is_empty());
     assert_eq!(decoded, v);
 }
+
+#[test]
+fn encode_decode_bigints_and_decimals() {
+    use std::str::FromStr;
+
+    use num_bigint::BigInt;
+
+    use crate::data::value::Decimal;
+
+    let mut bigints = vec![];
+    for s in [
+        "0",
+        "1",
+        "-1",
+        "255",
+        "256",
+        "-255",
+        "-256",
+        "123456789012345678901234567890",
+        "-123456789012345678901234567890",
+    ] {
+        bigints.push(DataValue::from(BigInt::from_str(s).unwrap()));
+    }
+    let mut decimals = vec![];
+    for s in [
+        "0", "1", "-1", "0.1", "-0.1", "0.12", "-0.12", "0.123", "-0.123", "10", "-10", "99.99",
+        "100", "1e30", "-1e30", "1e-30", "-1e-30", "12.5", "12.50001",
+    ] {
+        decimals.push(DataValue::Decimal(Decimal::from_str(s).unwrap()));
+    }
+    let mut mixed = vec![
+        DataValue::from(-3),
+        DataValue::from(0),
+        DataValue::from(2),
+        DataValue::from(2.5),
+        DataValue::from(1e300),
+        DataValue::from(f64::INFINITY),
+        DataValue::from(i64::MAX),
+        DataValue::from(i64::MAX - 1),
+    ];
+    mixed.extend(bigints.iter().cloned());
+    mixed.extend(decimals.iter().cloned());
+    mixed.push(DataValue::from(""));
+    mixed.push(DataValue::Null);
+    for vals in [bigints, decimals, mixed] {
+        let mut encoded = vec![];
+        for v in &vals {
+            let mut encoder = vec![];
+            encoder.encode_datavalue(v);
+            let (decoded, remaining) = DataValue::decode_from_key(&encoder);
+            assert!(remaining.is_empty());
+            assert_eq!(&decoded, v);
+            encoded.push((encoder, v.clone()));
+        }
+        let mut by_value = encoded.clone();
+        by_value.sort_by(|a, b| a.1.cmp(&b.1));
+        encoded.sort();
+        assert_eq!(encoded, by_value);
+    }
+    let sorted = |strs: &[&str]| {
+        let mut vals = vec![
+            DataValue::from(5),
+            DataValue::from(BigInt::from_str(strs[0]).unwrap()),
+            DataValue::Decimal(Decimal::from_str(strs[1]).unwrap()),
+        ];
+        vals.sort();
+        vals
+    };
+    assert_eq!(
+        sorted(&["1000000000000000000000", "-2.5"]),
+        vec![
+            DataValue::Decimal(Decimal::from_str("-2.5").unwrap()),
+            DataValue::from(5),
+            DataValue::from(BigInt::from_str("1000000000000000000000").unwrap()),
+        ]
+    );
+}
@@ -7,6 +7,8 @@
  */
 
 mod aggrs;
+#[cfg(feature = "arrow")]
+mod arrow;
 mod exprs;
 mod functions;
 mod json;
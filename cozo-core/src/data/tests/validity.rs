@@ -7,7 +7,10 @@
  *
  */
 
-use crate::data::value::DataValue;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use crate::data::value::{DataValue, ValidityTs};
 use crate::DbInstance;
 use serde_json::json;
 use std::env;
@@ -213,3 +216,279 @@ fn test_validity() {
 
     println!("{}", json!(res));
 }
+
+#[test]
+fn test_session_default_validity() {
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(":create vld {a, v: Validity => d}", Default::default())
+        .unwrap();
+    db.run_script(
+        r#"
+    ?[a, v, d] <- [[1, [0, true], 0], [1, [10, true], 1]]
+    :put vld {a, v => d}
+    "#,
+        Default::default(),
+    )
+    .unwrap();
+
+    let res = db
+        .run_script("?[a, d] := *vld{a, d}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    let session = db
+        .new_session()
+        .with_default_validity(ValidityTs(Reverse(5)));
+    let res = session
+        .run_script("?[a, d] := *vld{a, d}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 0]]));
+    let res = session
+        .run_script("?[a, d] := *vld{a, d @ 20}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 1]]));
+}
+
+#[test]
+fn test_validity_params() {
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(":create vld {a, v: Validity => d}", Default::default())
+        .unwrap();
+    db.run_script(
+        r#"
+    ?[a, v, d] <- [[1, [0, true], 0], [1, [10, true], 1], [2, [10, true], 2]]
+    :put vld {a, v => d}
+    "#,
+        Default::default(),
+    )
+    .unwrap();
+    let at = |ts: i64| BTreeMap::from([("ts".to_string(), DataValue::from(ts))]);
+
+    let res = db.run_script("?[a, d] := *vld{a, d @ $ts}", at(5)).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 0]]));
+    let res = db
+        .run_script("?[a, d] := *vld{a, d} :at $ts", at(5))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 0]]));
+    let res = db
+        .run_script("?[a, d] := *vld{a, d} :at $ts", at(15))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 1], [2, 2]]));
+    // an explicit validity takes precedence
+    let res = db
+        .run_script("?[a, d] := *vld{a, d @ 5} :at $ts", at(15))
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 0]]));
+
+    // `:at` only applies to its own query
+    let res = db
+        .run_script(
+            "{?[a, d] := *vld{a, d} :at 5} {?[a, d] := *vld{a, d}}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 3);
+    assert!(db
+        .run_script("?[a, d] := *vld{a, d} :at 'yesterday'", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_history() {
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(":create vld {a, v: Validity => d}", Default::default())
+        .unwrap();
+    db.run_script(
+        r#"
+    ?[a, v, d] <- [[1, [0, true], 'x'], [1, [10, true], 'y'], [1, [20, false], null], [2, [5, true], 'z']]
+    :put vld {a, v => d}
+    "#,
+        Default::default(),
+    )
+    .unwrap();
+
+    let res = db
+        .run_script("::history vld [1]", Default::default())
+        .unwrap();
+    assert_eq!(res.headers, vec!["d", "op", "validity"]);
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            [null, "retract", 20],
+            ["y", "assert", 10],
+            ["x", "assert", 0]
+        ])
+    );
+    let res = db
+        .run_script(
+            "::history vld $key",
+            BTreeMap::from([("key".to_string(), json!([3]).into())]),
+        )
+        .unwrap();
+    assert!(res.rows.is_empty());
+
+    let err = db
+        .run_script("::history vld 1", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::bad_history_key");
+    let err = db
+        .run_script("::history vld [1, 2]", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::history_key_length");
+    db.run_script(":create plain {a => d}", Default::default())
+        .unwrap();
+    let err = db
+        .run_script("::history plain [1]", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::history_without_validity"
+    );
+}
+
+#[test]
+fn test_scheduled_visibility() {
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(":create vld {a, v: Validity => d}", Default::default())
+        .unwrap();
+    db.run_script(
+        r#"
+    ?[a, v, d] <- [[1, [0, true], 'published'], [2, [4102444800000000, true], 'scheduled']]
+    :put vld {a, v => d}
+    "#,
+        Default::default(),
+    )
+    .unwrap();
+
+    let res = db
+        .run_script("?[a, d] := *vld{a, d}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    db.run_script("::schedule_visibility vld", Default::default())
+        .unwrap();
+    let res = db
+        .run_script("?[a, d] := *vld{a, d}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, "published"]]));
+    let res = db
+        .run_script(
+            "?[a, d] := *vld{a, d @ 4102444800000000}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+    let res = db
+        .new_session()
+        .with_default_validity(ValidityTs(Reverse(5000000000000000)))
+        .run_script("?[a, d] := *vld{a, d}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    db.run_script("::unschedule_visibility vld", Default::default())
+        .unwrap();
+    let res = db
+        .run_script("?[a, d] := *vld{a, d}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    db.run_script(":create plain {a => d}", Default::default())
+        .unwrap();
+    let err = db
+        .run_script("::schedule_visibility plain", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::bad_scheduled_relation"
+    );
+}
+
+#[test]
+fn test_bi_temporal() {
+    let db = crate::new_cozo_mem().unwrap();
+    db.run_script(
+        ":create policy {id, from_ts: Int, tt: Validity => to_ts: Int?, premium: Int}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::valid_time policy from_ts, to_ts", Default::default())
+        .unwrap();
+    // the writes are made at explicit transaction times
+    db.do_run_script(
+        r#"
+    ?[id, from_ts, tt, to_ts, premium] <- [[1, 0, 'ASSERT', 100, 10], [1, 100, 'ASSERT', null, 20]]
+    :put policy {id, from_ts, tt => to_ts, premium}
+    "#,
+        &Default::default(),
+        ValidityTs(Reverse(1000)),
+        None,
+        None,
+    )
+    .unwrap();
+
+    let res = db
+        .run_script(
+            "?[premium] := *policy{id: 1, premium valid_at 50}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[10]]));
+    let res = db
+        .run_script(
+            "?[p] := *policy[1, _, _, _, p valid_at 150]",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[20]]));
+
+    db.do_run_script(
+        r#"
+    ?[id, from_ts, tt, to_ts, premium] <- [[1, 100, 'ASSERT', null, 25]]
+    :put policy {id, from_ts, tt => to_ts, premium}
+    "#,
+        &Default::default(),
+        ValidityTs(Reverse(2000)),
+        None,
+        None,
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            "?[premium] := *policy{id: 1, premium @ 'NOW' valid_at 150}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[25]]));
+    let res = db
+        .run_script(
+            "?[premium] := *policy{id: 1, premium @ $t valid_at 150}",
+            BTreeMap::from([("t".to_string(), DataValue::from(1500))]),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[20]]));
+
+    let err = db
+        .run_script(
+            r#"
+    ?[id, from_ts, tt, to_ts, premium] <- [[1, 200, [0, true], null, 30]]
+    :put policy {id, from_ts, tt => to_ts, premium}
+    "#,
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::explicit_tx_time");
+
+    db.run_script(
+        ":create plain {a: Int, v: Validity => d}",
+        Default::default(),
+    )
+    .unwrap();
+    let err = db
+        .run_script("?[d] := *plain{d valid_at 0}", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::no_valid_time");
+    let err = db
+        .run_script("::valid_time plain a, d", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::bad_valid_time");
+}
@@ -10,6 +10,9 @@
 use std::collections::{BTreeMap, HashMap};
 use std::mem::size_of;
 
+use chrono::{DateTime, TimeZone, Utc};
+use uuid::Uuid;
+
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 
@@ -55,3 +58,43 @@ fn display_datavalues() {
         ])
     );
 }
+
+#[test]
+fn rust_conversions() {
+    assert_eq!(DataValue::from(3u8), DataValue::from(3i64));
+    assert_eq!(DataValue::try_from(3usize).unwrap(), DataValue::from(3i64));
+    assert!(DataValue::try_from(u64::MAX).is_err());
+    assert_eq!(DataValue::from(Some("a")), DataValue::from("a"));
+    assert_eq!(DataValue::from(None::<i32>), DataValue::Null);
+    assert_eq!(
+        DataValue::from(vec![1, 2]),
+        DataValue::List(vec![DataValue::from(1), DataValue::from(2)])
+    );
+    assert_eq!(
+        DataValue::from(&b"ab"[..]),
+        DataValue::Bytes(b"ab".to_vec())
+    );
+
+    assert_eq!(i64::try_from(DataValue::from(7)).unwrap(), 7);
+    assert_eq!(f64::try_from(DataValue::from(7)).unwrap(), 7.);
+    assert!(i64::try_from(DataValue::from("7")).is_err());
+    assert_eq!(String::try_from(DataValue::from("s")).unwrap(), "s");
+    assert!(bool::try_from(DataValue::Null).is_err());
+    assert_eq!(
+        Vec::<i64>::try_from(DataValue::from(vec![1, 2])).unwrap(),
+        vec![1, 2]
+    );
+    assert!(Vec::<i64>::try_from(DataValue::from(vec!["a"])).is_err());
+
+    let uuid = Uuid::new_v4();
+    assert_eq!(Uuid::try_from(DataValue::from(uuid)).unwrap(), uuid);
+
+    let ts = Utc.timestamp_opt(1_600_000_000, 250_000_000).unwrap();
+    let val = DataValue::from(ts);
+    assert_eq!(val, DataValue::from(1_600_000_000.25));
+    assert_eq!(DateTime::<Utc>::try_from(val).unwrap(), ts);
+    assert_eq!(
+        DateTime::<Utc>::try_from(DataValue::from("2020-09-13T12:26:40.25Z")).unwrap(),
+        ts
+    );
+}
@@ -12,7 +12,12 @@ use std::cmp::{Ordering, Reverse};
 use std::collections::BTreeSet;
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
+use chrono::{DateTime, TimeZone, Utc};
+use miette::miette;
+use num_bigint::{BigInt, Sign};
+use num_traits::{Signed, ToPrimitive, Zero};
 use ordered_float::OrderedFloat;
 use regex::Regex;
 use serde::{Deserialize, Deserializer, Serialize};
@@ -88,6 +93,192 @@ impl PartialOrd for RegexWrapper {
     }
 }
 
+/// Arbitrary-precision integer in the database
+#[derive(
+    Clone, Hash, Eq, PartialEq, Ord, PartialOrd, serde_derive::Deserialize, serde_derive::Serialize,
+)]
+pub struct BigIntWrapper(pub BigInt);
+
+impl BigIntWrapper {
+    pub(crate) fn to_f64(&self) -> f64 {
+        self.0.to_f64().unwrap_or(f64::NAN)
+    }
+}
+
+/// Arbitrary-precision decimal in the database, whose value is `mantissa * 10^-scale`.
+///
+/// Decimals are kept normalized, without trailing zeros in the mantissa when the scale
+/// is positive, so that equal values always have the same representation.
+#[derive(
+    Clone, Default, Hash, Eq, PartialEq, serde_derive::Deserialize, serde_derive::Serialize,
+)]
+pub struct Decimal {
+    mantissa: BigInt,
+    scale: u32,
+}
+
+impl Decimal {
+    /// Create the decimal `mantissa * 10^-scale`
+    pub fn new(mut mantissa: BigInt, mut scale: u32) -> Self {
+        let ten = BigInt::from(10);
+        if mantissa.is_zero() {
+            scale = 0;
+        }
+        while scale > 0 && (&mantissa % &ten).is_zero() {
+            mantissa /= &ten;
+            scale -= 1;
+        }
+        Self { mantissa, scale }
+    }
+    /// The mantissa of the normalized decimal
+    pub fn mantissa(&self) -> &BigInt {
+        &self.mantissa
+    }
+    /// The number of digits after the decimal point in the normalized decimal
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+    /// The mantissa for the same value with a scale no smaller than the current one
+    fn rescaled(&self, scale: u32) -> BigInt {
+        &self.mantissa * BigInt::from(10).pow(scale - self.scale)
+    }
+    pub(crate) fn add(&self, other: &Self) -> Self {
+        let scale = self.scale.max(other.scale);
+        Self::new(self.rescaled(scale) + other.rescaled(scale), scale)
+    }
+    pub(crate) fn sub(&self, other: &Self) -> Self {
+        let scale = self.scale.max(other.scale);
+        Self::new(self.rescaled(scale) - other.rescaled(scale), scale)
+    }
+    pub(crate) fn mul(&self, other: &Self) -> Self {
+        Self::new(&self.mantissa * &other.mantissa, self.scale + other.scale)
+    }
+    pub(crate) fn neg(&self) -> Self {
+        Self::new(-&self.mantissa, self.scale)
+    }
+    pub(crate) fn abs(&self) -> Self {
+        Self::new(self.mantissa.abs(), self.scale)
+    }
+    pub(crate) fn is_zero(&self) -> bool {
+        self.mantissa.is_zero()
+    }
+    /// Divide, keeping `scale` digits after the decimal point and truncating the rest.
+    /// Returns `None` when dividing by zero.
+    pub(crate) fn div(&self, other: &Self, scale: u32) -> Option<Self> {
+        if other.is_zero() {
+            return None;
+        }
+        let num = &self.mantissa * BigInt::from(10).pow(scale + other.scale);
+        let den = &other.mantissa * BigInt::from(10).pow(self.scale);
+        Some(Self::new(num / den, scale))
+    }
+    /// Round to `scale` digits after the decimal point, with halves rounded away from zero
+    pub(crate) fn round(&self, scale: u32) -> Self {
+        if self.scale <= scale {
+            return self.clone();
+        }
+        let factor = BigInt::from(10).pow(self.scale - scale);
+        let mut quotient = &self.mantissa / &factor;
+        let remainder = &self.mantissa % &factor;
+        if remainder.abs() * 2 >= factor {
+            quotient += self.mantissa.signum();
+        }
+        Self::new(quotient, scale)
+    }
+    /// Drop the fractional part
+    pub(crate) fn trunc(&self) -> Self {
+        Self::new(&self.mantissa / BigInt::from(10).pow(self.scale), 0)
+    }
+    /// The integer with the same value, if the decimal has no fractional part
+    pub(crate) fn to_bigint(&self) -> Option<BigInt> {
+        if self.scale == 0 {
+            Some(self.mantissa.clone())
+        } else {
+            None
+        }
+    }
+    pub(crate) fn to_f64(&self) -> f64 {
+        f64::from_str(&self.to_string()).unwrap_or(f64::NAN)
+    }
+}
+
+impl From<BigInt> for Decimal {
+    fn from(value: BigInt) -> Self {
+        Self::new(value, 0)
+    }
+}
+
+/// Parses decimal notation with an optional exponent, e.g. `-12.50` or `1.25e-3`
+impl FromStr for Decimal {
+    type Err = miette::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || miette!("cannot interpret {:?} as a decimal", s);
+        let (num, exp) = match s.find(['e', 'E']) {
+            None => (s, 0),
+            Some(pos) => (&s[..pos], i64::from_str(&s[pos + 1..]).map_err(|_| err())?),
+        };
+        let (int_part, frac_part) = match num.split_once('.') {
+            None => (num, ""),
+            Some(parts) => parts,
+        };
+        let (negative, int_part) = match int_part.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, int_part.strip_prefix('+').unwrap_or(int_part)),
+        };
+        if int_part.is_empty() && frac_part.is_empty()
+            || !int_part
+                .chars()
+                .chain(frac_part.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(err());
+        }
+        let digits = format!("{int_part}{frac_part}");
+        let mut mantissa = BigInt::from_str(&digits).map_err(|_| err())?;
+        if negative {
+            mantissa = -mantissa;
+        }
+        let scale = frac_part.len() as i64 - exp;
+        if scale >= 0 {
+            let scale = u32::try_from(scale).map_err(|_| err())?;
+            Ok(Self::new(mantissa, scale))
+        } else {
+            let shift = u32::try_from(-scale).map_err(|_| err())?;
+            Ok(Self::new(mantissa * BigInt::from(10).pow(shift), 0))
+        }
+    }
+}
+
+impl Display for Decimal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.mantissa.sign() == Sign::Minus {
+            f.write_str("-")?;
+        }
+        let digits = self.mantissa.magnitude().to_string();
+        let scale = self.scale as usize;
+        if scale == 0 {
+            return f.write_str(&digits);
+        }
+        let digits = format!("{digits:0>width$}", width = scale + 1);
+        let (int_part, frac_part) = digits.split_at(digits.len() - scale);
+        write!(f, "{int_part}.{frac_part}")
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let scale = self.scale.max(other.scale);
+        self.rescaled(scale).cmp(&other.rescaled(scale))
+    }
+}
+
 /// Timestamp part of validity
 #[derive(
     Copy,
@@ -123,9 +314,12 @@ pub struct Validity {
 }
 
 /// A Value in the database
-#[derive(
-    Clone, PartialEq, Eq, PartialOrd, Ord, serde_derive::Deserialize, serde_derive::Serialize, Hash,
-)]
+///
+/// Values of different kinds sort in the order the kinds are listed here,
+/// except that big integers and decimals sort together with numbers.
+/// The `BigInt` and `Decimal` variants are new since 0.5.0, which breaks exhaustive
+/// matches on this type.
+#[derive(Clone, PartialEq, Eq, serde_derive::Deserialize, serde_derive::Serialize, Hash)]
 pub enum DataValue {
     /// null
     Null,
@@ -148,6 +342,10 @@ pub enum DataValue {
     Set(BTreeSet<DataValue>),
     /// validity
     Validity(Validity),
+    /// arbitrary-precision integer
+    BigInt(BigIntWrapper),
+    /// arbitrary-precision decimal
+    Decimal(Decimal),
     /// bottom type, used internally only
     Bot,
 }
@@ -182,6 +380,169 @@ impl From<bool> for DataValue {
     }
 }
 
+macro_rules! int_from {
+    ($($t:ty),*) => {
+        $(
+            impl From<$t> for DataValue {
+                fn from(v: $t) -> Self {
+                    DataValue::Num(Num::Int(v.into()))
+                }
+            }
+        )*
+    };
+}
+
+int_from!(i8, i16, i32, u8, u16, u32);
+
+impl TryFrom<u64> for DataValue {
+    type Error = miette::Error;
+
+    fn try_from(v: u64) -> Result<Self, Self::Error> {
+        match i64::try_from(v) {
+            Ok(i) => Ok(DataValue::from(i)),
+            Err(_) => Err(miette!("integer {} is too large for a value", v)),
+        }
+    }
+}
+
+impl TryFrom<usize> for DataValue {
+    type Error = miette::Error;
+
+    fn try_from(v: usize) -> Result<Self, Self::Error> {
+        DataValue::try_from(v as u64)
+    }
+}
+
+impl From<f32> for DataValue {
+    fn from(v: f32) -> Self {
+        DataValue::Num(Num::Float(v.into()))
+    }
+}
+
+impl From<BigInt> for DataValue {
+    fn from(v: BigInt) -> Self {
+        DataValue::BigInt(BigIntWrapper(v))
+    }
+}
+
+impl From<Decimal> for DataValue {
+    fn from(v: Decimal) -> Self {
+        DataValue::Decimal(v)
+    }
+}
+
+impl From<&[u8]> for DataValue {
+    fn from(v: &[u8]) -> Self {
+        DataValue::Bytes(v.to_vec())
+    }
+}
+
+impl From<Uuid> for DataValue {
+    fn from(v: Uuid) -> Self {
+        DataValue::Uuid(UuidWrapper(v))
+    }
+}
+
+/// Timestamps are represented as seconds since the UNIX epoch, as returned by `now()`
+impl<Tz: TimeZone> From<DateTime<Tz>> for DataValue {
+    fn from(v: DateTime<Tz>) -> Self {
+        DataValue::from(v.timestamp_micros() as f64 / 1_000_000.)
+    }
+}
+
+impl<T: Into<DataValue>> From<Option<T>> for DataValue {
+    fn from(v: Option<T>) -> Self {
+        match v {
+            None => DataValue::Null,
+            Some(v) => v.into(),
+        }
+    }
+}
+
+impl<T: Into<DataValue>> From<Vec<T>> for DataValue {
+    fn from(v: Vec<T>) -> Self {
+        DataValue::List(v.into_iter().map(|v| v.into()).collect())
+    }
+}
+
+impl TryFrom<DataValue> for i64 {
+    type Error = miette::Error;
+
+    fn try_from(v: DataValue) -> Result<Self, Self::Error> {
+        v.get_int()
+            .ok_or_else(|| miette!("cannot convert {} to an integer", v))
+    }
+}
+
+impl TryFrom<DataValue> for f64 {
+    type Error = miette::Error;
+
+    fn try_from(v: DataValue) -> Result<Self, Self::Error> {
+        v.get_float()
+            .ok_or_else(|| miette!("cannot convert {} to a float", v))
+    }
+}
+
+impl TryFrom<DataValue> for bool {
+    type Error = miette::Error;
+
+    fn try_from(v: DataValue) -> Result<Self, Self::Error> {
+        v.get_bool()
+            .ok_or_else(|| miette!("cannot convert {} to a boolean", v))
+    }
+}
+
+impl TryFrom<DataValue> for String {
+    type Error = miette::Error;
+
+    fn try_from(v: DataValue) -> Result<Self, Self::Error> {
+        match v {
+            DataValue::Str(s) => Ok(s.into()),
+            v => Err(miette!("cannot convert {} to a string", v)),
+        }
+    }
+}
+
+impl TryFrom<DataValue> for Uuid {
+    type Error = miette::Error;
+
+    fn try_from(v: DataValue) -> Result<Self, Self::Error> {
+        v.get_uuid()
+            .ok_or_else(|| miette!("cannot convert {} to a UUID", v))
+    }
+}
+
+/// Numbers are taken as seconds since the UNIX epoch, and strings are parsed as RFC 3339
+impl TryFrom<DataValue> for DateTime<Utc> {
+    type Error = miette::Error;
+
+    fn try_from(v: DataValue) -> Result<Self, Self::Error> {
+        let converted = match &v {
+            DataValue::Num(n) => {
+                Utc.timestamp_micros((n.get_float() * 1_000_000.).round() as i64)
+                    .single()
+            }
+            DataValue::Str(s) => DateTime::parse_from_rfc3339(s)
+                .ok()
+                .map(|dt| dt.with_timezone(&Utc)),
+            _ => None,
+        };
+        converted.ok_or_else(|| miette!("cannot convert {} to a timestamp", v))
+    }
+}
+
+impl<T: TryFrom<DataValue, Error = miette::Error>> TryFrom<DataValue> for Vec<T> {
+    type Error = miette::Error;
+
+    fn try_from(v: DataValue) -> Result<Self, Self::Error> {
+        match v {
+            DataValue::List(l) => l.into_iter().map(T::try_from).collect(),
+            DataValue::Set(s) => s.into_iter().map(T::try_from).collect(),
+            v => Err(miette!("cannot convert {} to a list", v)),
+        }
+    }
+}
+
 /// Representing a number
 #[derive(Copy, Clone, serde_derive::Deserialize, serde_derive::Serialize)]
 pub enum Num {
@@ -290,6 +651,37 @@ impl Ord for Num {
     }
 }
 
+impl PartialOrd for DataValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for DataValue {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (DataValue::Null, DataValue::Null) | (DataValue::Bot, DataValue::Bot) => {
+                Ordering::Equal
+            }
+            (DataValue::Bool(l), DataValue::Bool(r)) => l.cmp(r),
+            (DataValue::Str(l), DataValue::Str(r)) => l.cmp(r),
+            (DataValue::Bytes(l), DataValue::Bytes(r)) => l.cmp(r),
+            (DataValue::Uuid(l), DataValue::Uuid(r)) => l.cmp(r),
+            (DataValue::Regex(l), DataValue::Regex(r)) => l.cmp(r),
+            (DataValue::List(l), DataValue::List(r)) => l.cmp(r),
+            (DataValue::Set(l), DataValue::Set(r)) => l.cmp(r),
+            (DataValue::Validity(l), DataValue::Validity(r)) => l.cmp(r),
+            (DataValue::Num(l), DataValue::Num(r)) => l.cmp(r),
+            (DataValue::BigInt(l), DataValue::BigInt(r)) => l.cmp(r),
+            (DataValue::Decimal(l), DataValue::Decimal(r)) => l.cmp(r),
+            (l, r) => match (l.number_sort_key(), r.number_sort_key()) {
+                (Some((lf, lk)), Some((rf, rk))) => lf.total_cmp(&rf).then(lk.cmp(&rk)),
+                _ => l.kind_rank().cmp(&r.kind_rank()),
+            },
+        }
+    }
+}
+
 impl Debug for DataValue {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(f, "{self}")
@@ -316,6 +708,8 @@ impl Display for DataValue {
             }
             DataValue::List(ls) => f.debug_list().entries(ls).finish(),
             DataValue::Set(s) => f.debug_list().entries(s).finish(),
+            DataValue::BigInt(i) => write!(f, "to_bigint(\"{}\")", i.0),
+            DataValue::Decimal(d) => write!(f, "to_decimal(\"{d}\")"),
             DataValue::Bot => write!(f, "null"),
             DataValue::Validity(v) => f
                 .debug_struct("Validity")
@@ -326,6 +720,35 @@ impl Display for DataValue {
     }
 }
 
+impl DataValue {
+    fn kind_rank(&self) -> u8 {
+        match self {
+            DataValue::Null => 0,
+            DataValue::Bool(_) => 1,
+            DataValue::Num(_) | DataValue::BigInt(_) | DataValue::Decimal(_) => 2,
+            DataValue::Str(_) => 3,
+            DataValue::Bytes(_) => 4,
+            DataValue::Uuid(_) => 5,
+            DataValue::Regex(_) => 6,
+            DataValue::List(_) => 7,
+            DataValue::Set(_) => 8,
+            DataValue::Validity(_) => 9,
+            DataValue::Bot => 10,
+        }
+    }
+    /// Numbers of different kinds sort by their nearest float first, then by their kind:
+    /// integers, big integers, decimals and floats, as in the memcmp encoding.
+    fn number_sort_key(&self) -> Option<(f64, u8)> {
+        match self {
+            DataValue::Num(Num::Int(i)) => Some((*i as f64, 0)),
+            DataValue::BigInt(i) => Some((i.to_f64(), 1)),
+            DataValue::Decimal(d) => Some((d.to_f64(), 2)),
+            DataValue::Num(Num::Float(f)) => Some((*f, 3)),
+            _ => None,
+        }
+    }
+}
+
 impl DataValue {
     /// Returns a slice of DataValues if this one is a List
     pub fn get_slice(&self) -> Option<&[DataValue]> {
@@ -0,0 +1,230 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! C API with a stable ABI, enabled by the `capi` feature.
+//!
+//! All strings passed in and out are null-terminated and UTF-8 encoded, and structured data
+//! is passed as JSON, or as msgpack with [cozo_db_run_script_msgpack].
+//! Functions acting on a database return one of the [CozoStatus] codes.
+//! Strings and bytes returned through out-pointers are owned by the caller, and must be freed
+//! with [cozo_string_free] and [cozo_bytes_free] respectively.
+//! The ABI only changes in backward compatible ways as long as [COZO_ABI_VERSION] stays the same.
+#![allow(clippy::missing_safety_doc)]
+
+use std::collections::BTreeMap;
+use std::ffi::{c_char, CStr, CString};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use serde_json::json;
+
+use crate::data::json::JsonValue;
+use crate::data::msgpack::MsgpackValue;
+use crate::data::value::DataValue;
+use crate::DbInstance;
+
+/// Version of the C ABI, bumped on any incompatible change.
+pub const COZO_ABI_VERSION: u32 = 1;
+
+/// Status codes returned by the C API. Codes are never renumbered.
+#[repr(i32)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CozoStatus {
+    /// The call succeeded
+    Ok = 0,
+    /// A string argument is not valid UTF-8
+    InvalidUtf8 = 1,
+    /// A pointer argument is null, or the params are not a JSON map
+    InvalidArgument = 2,
+    /// No open database has the given id
+    DbNotFound = 3,
+    /// The database could not be opened
+    OpenFailed = 4,
+    /// The script failed; the output contains the error as JSON
+    ScriptFailed = 5,
+}
+
+lazy_static! {
+    static ref NEXT_ID: AtomicI32 = AtomicI32::new(0);
+    static ref DBS: Mutex<BTreeMap<i32, DbInstance>> = Mutex::new(BTreeMap::new());
+}
+
+/// The version of the C ABI implemented, see [COZO_ABI_VERSION].
+#[no_mangle]
+pub extern "C" fn cozo_abi_version() -> u32 {
+    COZO_ABI_VERSION
+}
+
+/// Open a database.
+///
+/// `engine`, `path` and `options` are as for [DbInstance::new], `options` being JSON.
+/// On success `db_id` receives the id of the database. On failure, if `err` is not null
+/// it receives the error message as a JSON object.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_db_create(
+    engine: *const c_char,
+    path: *const c_char,
+    options: *const c_char,
+    db_id: *mut i32,
+    err: *mut *mut c_char,
+) -> CozoStatus {
+    if db_id.is_null() {
+        return CozoStatus::InvalidArgument;
+    }
+    let (engine, path, options) = match (to_str(engine), to_str(path), to_str(options)) {
+        (Ok(engine), Ok(path), Ok(options)) => (engine, path, options),
+        (Err(status), _, _) | (_, Err(status), _) | (_, _, Err(status)) => return status,
+    };
+    match DbInstance::new(engine, path, options) {
+        Ok(db) => {
+            let id = NEXT_ID.fetch_add(1, Ordering::AcqRel);
+            DBS.lock().unwrap().insert(id, db);
+            *db_id = id;
+            CozoStatus::Ok
+        }
+        Err(e) => {
+            write_out(err, json!({"ok": false, "message": e.to_string()}));
+            CozoStatus::OpenFailed
+        }
+    }
+}
+
+/// Close the database with id `db_id`.
+#[no_mangle]
+pub extern "C" fn cozo_db_close(db_id: i32) -> CozoStatus {
+    match DBS.lock().unwrap().remove(&db_id) {
+        Some(_) => CozoStatus::Ok,
+        None => CozoStatus::DbNotFound,
+    }
+}
+
+/// Run a script against the database with id `db_id`.
+///
+/// `params` is a JSON map of the parameters, and may be null if there are none.
+/// `out` receives the result as JSON, in the same form as [DbInstance::run_script_str].
+/// It is set both on success and when the status is [CozoStatus::ScriptFailed].
+#[no_mangle]
+pub unsafe extern "C" fn cozo_db_run_script(
+    db_id: i32,
+    script: *const c_char,
+    params: *const c_char,
+    out: *mut *mut c_char,
+) -> CozoStatus {
+    if out.is_null() {
+        return CozoStatus::InvalidArgument;
+    }
+    let script = match to_str(script) {
+        Ok(s) => s,
+        Err(status) => return status,
+    };
+    let params = if params.is_null() {
+        BTreeMap::new()
+    } else {
+        let params = match to_str(params) {
+            Ok(s) => s,
+            Err(status) => return status,
+        };
+        match serde_json::from_str::<BTreeMap<String, JsonValue>>(params) {
+            Ok(map) => map
+                .into_iter()
+                .map(|(k, v)| (k, DataValue::from(v)))
+                .collect(),
+            Err(_) => return CozoStatus::InvalidArgument,
+        }
+    };
+    let db = match DBS.lock().unwrap().get(&db_id) {
+        Some(db) => db.clone(),
+        None => return CozoStatus::DbNotFound,
+    };
+    let res = db.run_script_fold_err(script, params);
+    let ok = res.get("ok") == Some(&JsonValue::Bool(true));
+    write_out(out, res);
+    if ok {
+        CozoStatus::Ok
+    } else {
+        CozoStatus::ScriptFailed
+    }
+}
+
+/// Run a script against the database with id `db_id`, with the request and the result
+/// encoded as msgpack instead of JSON.
+///
+/// `request` points to `request_len` bytes holding the request in the form taken by
+/// [DbInstance::run_script_msgpack]. `out` and `out_len` receive the result, which must be
+/// freed with [cozo_bytes_free]. They are set both on success and when the status is
+/// [CozoStatus::ScriptFailed] or [CozoStatus::InvalidArgument] for a malformed request.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_db_run_script_msgpack(
+    db_id: i32,
+    request: *const u8,
+    request_len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> CozoStatus {
+    if request.is_null() || out.is_null() || out_len.is_null() {
+        return CozoStatus::InvalidArgument;
+    }
+    let request = std::slice::from_raw_parts(request, request_len);
+    let db = match DBS.lock().unwrap().get(&db_id) {
+        Some(db) => db.clone(),
+        None => return CozoStatus::DbNotFound,
+    };
+    let res = db.run_script_msgpack_fold_err(request);
+    let status = if res["ok"] == MsgpackValue::Boolean(true) {
+        CozoStatus::Ok
+    } else if res["display"].is_nil() {
+        // only errors raised by running the script are rendered for display
+        CozoStatus::InvalidArgument
+    } else {
+        CozoStatus::ScriptFailed
+    };
+    let mut buf = vec![];
+    rmpv::encode::write_value(&mut buf, &res).expect("writing msgpack to memory failed");
+    let buf = buf.into_boxed_slice();
+    *out_len = buf.len();
+    *out = Box::into_raw(buf) as *mut u8;
+    status
+}
+
+/// Free the bytes returned by [cozo_db_run_script_msgpack], passing the length received
+/// with them. Must be called exactly once for each such buffer.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_bytes_free(bytes: *mut u8, len: usize) {
+    if !bytes.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(
+            bytes, len,
+        )));
+    }
+}
+
+/// Free a string returned by the C API. Must be called exactly once for each such string.
+#[no_mangle]
+pub unsafe extern "C" fn cozo_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+unsafe fn to_str<'a>(s: *const c_char) -> Result<&'a str, CozoStatus> {
+    if s.is_null() {
+        return Err(CozoStatus::InvalidArgument);
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|_| CozoStatus::InvalidUtf8)
+}
+
+unsafe fn write_out(out: *mut *mut c_char, val: JsonValue) {
+    if !out.is_null() {
+        *out = CString::new(val.to_string())
+            .map(CString::into_raw)
+            .unwrap_or(null_mut());
+    }
+}
@@ -34,7 +34,7 @@ use crate::data::value::DataValue;
 use crate::fixed_rule::algos::*;
 use crate::fixed_rule::utilities::*;
 use crate::parse::SourceSpan;
-use crate::runtime::db::Poison;
+use crate::runtime::db::{seconds_since_the_epoch, Poison};
 use crate::runtime::temp_store::{EpochStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 use crate::NamedRows;
@@ -92,7 +92,10 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
                 Box::new(store.all_iter().map(|t| Ok(t.into_tuple())))
             }
             MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
-                let relation = self.tx.get_relation(name, false)?;
+                let relation = self
+                    .tx
+                    .get_relation(name, false)?
+                    .read_at(seconds_since_the_epoch()?);
                 if let Some(valid_at) = valid_at {
                     Box::new(relation.skip_scan_all(self.tx, *valid_at))
                 } else {
@@ -112,7 +115,10 @@ impl<'a, 'b> FixedRuleInputRelation<'a, 'b> {
                 Box::new(store.prefix_iter(&t).map(|t| Ok(t.into_tuple())))
             }
             MagicFixedRuleRuleArg::Stored { name, valid_at, .. } => {
-                let relation = self.tx.get_relation(name, false)?;
+                let relation = self
+                    .tx
+                    .get_relation(name, false)?
+                    .read_at(seconds_since_the_epoch()?);
                 let t = vec![prefix.clone()];
                 if let Some(valid_at) = valid_at {
                     Box::new(relation.skip_scan_prefix(self.tx, &t, *valid_at))
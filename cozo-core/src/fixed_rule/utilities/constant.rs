@@ -76,15 +76,16 @@ impl FixedRule for Constant {
         options: &mut BTreeMap<SmartString<LazyCompact>, Expr>,
         span: SourceSpan,
     ) -> Result<()> {
+        // taken out of the options so that large data are not copied
         let data = options
-            .get("data")
+            .remove("data")
             .ok_or_else(|| WrongFixedRuleOptionError {
                 name: "data".to_string(),
                 span: Default::default(),
                 rule_name: "Constant".to_string(),
                 help: "a list of lists is required".to_string(),
             })?;
-        let data = match data.clone().eval_to_const()? {
+        let data = match data.eval_to_const()? {
             DataValue::List(l) => l,
             _ => bail!(WrongFixedRuleOptionError {
                 name: "data".to_string(),
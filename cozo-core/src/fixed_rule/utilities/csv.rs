@@ -13,7 +13,9 @@ use miette::{bail, ensure, IntoDiagnostic, Result};
 use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
-use crate::data::functions::{op_to_float, op_to_uuid, TERMINAL_VALIDITY};
+use crate::data::functions::{
+    op_to_bigint, op_to_decimal, op_to_float, op_to_uuid, TERMINAL_VALIDITY,
+};
 use crate::data::program::{FixedRuleOptionNotFoundError, WrongFixedRuleOptionError};
 use crate::data::relation::{ColType, NullableColType};
 use crate::data::symb::Symbol;
@@ -115,6 +117,26 @@ impl FixedRule for CsvReader {
                                     }
                                 }
                             }),
+                            ColType::BigInt => out_tuple.push(match op_to_bigint(&[dv]) {
+                                Ok(data) => data,
+                                Err(err) => {
+                                    if typ.nullable {
+                                        DataValue::Null
+                                    } else {
+                                        bail!(err)
+                                    }
+                                }
+                            }),
+                            ColType::Decimal => out_tuple.push(match op_to_decimal(&[dv]) {
+                                Ok(data) => data,
+                                Err(err) => {
+                                    if typ.nullable {
+                                        DataValue::Null
+                                    } else {
+                                        bail!(err)
+                                    }
+                                }
+                            }),
                             ColType::Float => out_tuple.push(match op_to_float(&[dv]) {
                                 Ok(data) => data,
                                 Err(err) => {
@@ -25,6 +25,9 @@
 //! We created an in-memory database above. There are other persistent options:
 //! see [DbInstance::new]. It is perfectly fine to run multiple storage engines in the same process.
 //!
+//! The types commonly needed by embedders are gathered in the [prelude], whose contents
+//! are stable across minor versions.
+//!
 #![doc = document_features::document_features!()]
 #![warn(rust_2018_idioms, future_incompatible)]
 #![warn(missing_docs)]
@@ -33,11 +36,13 @@
 
 use std::collections::BTreeMap;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 #[allow(unused_imports)]
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
-use crossbeam::channel::{bounded, Receiver, Sender};
+use crossbeam::channel::{bounded, Receiver, RecvTimeoutError, Sender, TryRecvError};
 use lazy_static::lazy_static;
 pub use miette::Error;
 use miette::Report;
@@ -48,35 +53,77 @@ use miette::{
 };
 use serde_json::json;
 
-pub use data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+pub use data::value::{
+    BigIntWrapper, DataValue, Decimal, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs,
+};
 pub use fixed_rule::{FixedRule, FixedRuleInputRelation, FixedRulePayload};
+pub use runtime::db::CancelToken;
 pub use runtime::db::Db;
 pub use runtime::db::NamedRows;
 pub use runtime::relation::decode_tuple_from_kv;
+pub use runtime::session::Session;
 pub use runtime::temp_store::RegularTempStore;
 pub use storage::mem::{new_cozo_mem, MemStorage};
 #[cfg(feature = "storage-rocksdb")]
-pub use storage::rocks::{new_cozo_rocksdb, RocksDbStorage};
+pub use storage::rocks::{new_cozo_rocksdb, new_cozo_rocksdb_with_tx_mode, RocksDbStorage};
 #[cfg(feature = "storage-sled")]
 pub use storage::sled::{new_cozo_sled, SledStorage};
 #[cfg(feature = "storage-sqlite")]
 pub use storage::sqlite::{new_cozo_sqlite, SqliteStorage};
 #[cfg(feature = "storage-tikv")]
 pub use storage::tikv::{new_cozo_tikv, TiKvStorage};
-pub use storage::{Storage, StoreTx};
+pub use storage::{RangeEstimate, Storage, StoreTx};
 
 pub use crate::data::expr::Expr;
 use crate::data::json::JsonValue;
+use crate::data::msgpack::{msgpack_from_json, MsgpackValue};
 pub use crate::data::symb::Symbol;
 pub use crate::fixed_rule::SimpleFixedRule;
-pub use crate::parse::SourceSpan;
+pub use crate::parse::format::format_script;
+pub use crate::parse::{parse_to_json, SourceSpan};
 pub use crate::runtime::callback::CallbackOp;
 pub use crate::runtime::db::Poison;
 pub use crate::runtime::db::TransactionPayload;
+pub use crate::runtime::kv::KvNamespace;
+pub use crate::runtime::metrics::{DbMetrics, LatencyHistogram};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::runtime::sync_hook::SyncBatch;
+
+/// Enter a `tracing` span lasting until the end of the enclosing block,
+/// if the `tracing` feature is enabled.
+macro_rules! trace_span {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!($($args)*).entered();
+    };
+}
+
+/// Evaluate `$body` within a `tracing` span named `$name`, if the `tracing` feature is enabled.
+macro_rules! in_span {
+    ($name:literal, $body:expr) => {{
+        trace_span!($name);
+        $body
+    }};
+}
+
+/// Emit a `tracing` event, if the `tracing` feature is enabled.
+/// The arguments are only evaluated in that case.
+macro_rules! trace_event {
+    ($($args:tt)*) => {
+        #[cfg(feature = "tracing")]
+        tracing::debug!($($args)*);
+    };
+}
 
+pub mod builder;
 pub(crate) mod data;
+#[cfg(feature = "capi")]
+pub mod ffi;
 pub(crate) mod fixed_rule;
 pub(crate) mod parse;
+pub mod prelude;
+#[cfg(feature = "python")]
+pub mod python;
 pub(crate) mod query;
 pub(crate) mod runtime;
 pub(crate) mod storage;
@@ -123,16 +170,30 @@ impl DbInstance {
     /// some of the engines are available. The `mem` engine is always available.
     ///
     /// `path` is ignored for `mem` and `tikv` engines.
-    /// `options` is ignored for every engine except `tikv`.
+    /// `options` is ignored for every engine except `rocksdb` and `tikv`. For both, setting
+    /// `optimistic` to `true` selects optimistic transactions, e.g. `{"optimistic": true}`.
+    /// Empty or blank options stand for the defaults.
     #[allow(unused_variables)]
     pub fn new(engine: &str, path: impl AsRef<Path>, options: &str) -> Result<Self> {
-        let options = if options.is_empty() { "{}" } else { options };
+        let options = if options.trim().is_empty() {
+            "{}"
+        } else {
+            options
+        };
         Ok(match engine {
             "mem" => Self::Mem(new_cozo_mem()?),
             #[cfg(feature = "storage-sqlite")]
             "sqlite" => Self::Sqlite(new_cozo_sqlite(path)?),
             #[cfg(feature = "storage-rocksdb")]
-            "rocksdb" => Self::RocksDb(new_cozo_rocksdb(path)?),
+            "rocksdb" => {
+                #[derive(serde_derive::Deserialize)]
+                struct RocksDbOpts {
+                    #[serde(default = "Default::default")]
+                    optimistic: bool,
+                }
+                let opts: RocksDbOpts = serde_json::from_str(options).into_diagnostic()?;
+                Self::RocksDb(new_cozo_rocksdb_with_tx_mode(path, opts.optimistic)?)
+            }
             #[cfg(feature = "storage-sled")]
             "sled" => Self::Sled(new_cozo_sled(path)?),
             #[cfg(feature = "storage-tikv")]
@@ -178,6 +239,44 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.run_script(payload, params),
         }
     }
+    /// Dispatcher method. See [crate::Db::run_script_cancellable].
+    pub fn run_script_cancellable(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        cancel_token: &CancelToken,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.run_script_cancellable(payload, params, cancel_token),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_script_cancellable(payload, params, cancel_token),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_script_cancellable(payload, params, cancel_token),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_script_cancellable(payload, params, cancel_token),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_script_cancellable(payload, params, cancel_token),
+        }
+    }
+    /// Run a query constructed with a [QueryBuilder](builder::QueryBuilder).
+    pub fn run_query(&self, query: &builder::QueryBuilder) -> Result<NamedRows> {
+        let (script, params) = query.build()?;
+        self.run_script(&script, params)
+    }
+    /// Run the CozoScript passed in, and write the rows it returns into the stored relation
+    /// `relation` of `target`, as is done for [Self::import_relations]. This is the same
+    /// as the `::push` system op, for databases already opened.
+    /// Returns the number of rows pushed.
+    pub fn push_query_result(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        target: &DbInstance,
+        relation: &str,
+    ) -> Result<usize> {
+        let rows = self.run_script(payload, params)?;
+        runtime::push::push_rows(rows, target, relation)
+    }
     /// Run the CozoScript passed in. The `params` argument is a map of parameters.
     /// Fold any error into the return JSON itself.
     /// See [crate::Db::run_script].
@@ -186,10 +285,25 @@ impl DbInstance {
         payload: &str,
         params: BTreeMap<String, DataValue>,
     ) -> JsonValue {
+        self.fold_err(payload, |db| db.run_script(payload, params))
+    }
+    /// Same as [Self::run_script_fold_err], but the queries are killed once
+    /// `cancel_token` is cancelled. See [crate::Db::run_script_cancellable].
+    pub fn run_script_fold_err_cancellable(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        cancel_token: &CancelToken,
+    ) -> JsonValue {
+        self.fold_err(payload, |db| {
+            db.run_script_cancellable(payload, params, cancel_token)
+        })
+    }
+    fn fold_err(&self, payload: &str, run: impl FnOnce(&Self) -> Result<NamedRows>) -> JsonValue {
         #[cfg(not(target_arch = "wasm32"))]
         let start = Instant::now();
 
-        match self.run_script(payload, params) {
+        match run(self) {
             Ok(named_rows) => {
                 let mut j_val = named_rows.into_json();
                 #[cfg(not(target_arch = "wasm32"))]
@@ -223,6 +337,114 @@ impl DbInstance {
         };
         self.run_script_fold_err(payload, params_json).to_string()
     }
+    /// Run a script sent as a msgpack map with the keys `script` and optionally `params`,
+    /// the latter being a map of parameters. The result is a msgpack map with the same keys
+    /// as the JSON returned by [DbInstance::run_script_fold_err], except that integers and floats
+    /// stay distinct and bytes are sent as binary, both in the params and in the rows.
+    ///
+    /// This avoids the cost of formatting and parsing JSON for embedders sending many
+    /// queries or receiving large results.
+    pub fn run_script_msgpack(&self, request: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        rmpv::encode::write_value(&mut out, &self.run_script_msgpack_fold_err(request))
+            .expect("writing msgpack to memory failed");
+        out
+    }
+    pub(crate) fn run_script_msgpack_fold_err(&self, request: &[u8]) -> MsgpackValue {
+        match decode_msgpack_request(request) {
+            Ok((script, params)) => {
+                #[cfg(not(target_arch = "wasm32"))]
+                let start = Instant::now();
+
+                match self.run_script(&script, params) {
+                    Ok(named_rows) => {
+                        let mut m_val = named_rows.into_msgpack();
+                        let map = match &mut m_val {
+                            MsgpackValue::Map(map) => map,
+                            _ => unreachable!(),
+                        };
+                        map.push((MsgpackValue::from("ok"), MsgpackValue::Boolean(true)));
+                        #[cfg(not(target_arch = "wasm32"))]
+                        map.push((
+                            MsgpackValue::from("took"),
+                            MsgpackValue::F64(start.elapsed().as_secs_f64()),
+                        ));
+                        m_val
+                    }
+                    Err(err) => msgpack_from_json(format_error_as_json(err, Some(&script))),
+                }
+            }
+            Err(msg) => MsgpackValue::Map(vec![
+                (MsgpackValue::from("ok"), MsgpackValue::Boolean(false)),
+                (MsgpackValue::from("message"), MsgpackValue::from(msg)),
+            ]),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::run_scripts].
+    pub fn run_scripts(
+        &self,
+        payloads: &[&str],
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<NamedRows>> {
+        match self {
+            DbInstance::Mem(db) => db.run_scripts(payloads, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.run_scripts(payloads, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.run_scripts(payloads, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.run_scripts(payloads, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.run_scripts(payloads, params),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::parse_only].
+    pub fn parse_only(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        match self {
+            DbInstance::Mem(db) => db.parse_only(payload, params),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.parse_only(payload, params),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.parse_only(payload, params),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.parse_only(payload, params),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.parse_only(payload, params),
+        }
+    }
+    /// Parse the CozoScript passed in without executing it, with `params` formatted as JSON.
+    /// On failure, the returned JSON contains the diagnostics with their spans.
+    /// See [crate::Db::parse_only].
+    pub fn parse_only_str(&self, payload: &str, params: &str) -> String {
+        let params_json = if params.is_empty() {
+            BTreeMap::default()
+        } else {
+            match serde_json::from_str::<BTreeMap<String, JsonValue>>(params) {
+                Ok(map) => map
+                    .into_iter()
+                    .map(|(k, v)| (k, DataValue::from(v)))
+                    .collect(),
+                Err(_) => {
+                    return json!({"ok": false, "message": "params argument is not a JSON map"})
+                        .to_string()
+                }
+            }
+        };
+        match self.parse_only(payload, params_json) {
+            Ok(named_rows) => {
+                let mut j_val = named_rows.into_json();
+                let map = j_val.as_object_mut().unwrap();
+                map.insert("ok".to_string(), json!(true));
+                j_val
+            }
+            Err(err) => format_error_as_json(err, Some(payload)),
+        }
+        .to_string()
+    }
     /// Dispatcher method. See [crate::Db::export_relations].
     pub fn export_relations<'a, I, T>(&self, relations: I) -> Result<BTreeMap<String, NamedRows>>
     where
@@ -241,6 +463,20 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.export_relations(relations),
         }
     }
+    /// Dispatcher method. See [crate::Db::generate_rust_types].
+    pub fn generate_rust_types(&self) -> Result<String> {
+        match self {
+            DbInstance::Mem(db) => db.generate_rust_types(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.generate_rust_types(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.generate_rust_types(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.generate_rust_types(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.generate_rust_types(),
+        }
+    }
     /// Export relations to JSON-encoded string.
     /// See [crate::Db::export_relations]
     pub fn export_relations_str(&self, data: &str) -> String {
@@ -299,6 +535,20 @@ impl DbInstance {
         let j_obj: BTreeMap<String, NamedRows> = serde_json::from_str(data).into_diagnostic()?;
         self.import_relations(j_obj)
     }
+    /// Dispatcher method. See [crate::Db::clone_to].
+    pub fn clone_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.clone_to(path),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.clone_to(path),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.clone_to(path),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.clone_to(path),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.clone_to(path),
+        }
+    }
     /// Dispatcher method. See [crate::Db::backup_db].
     pub fn backup_db(&self, out_file: impl AsRef<Path>) -> Result<()> {
         match self {
@@ -415,6 +665,48 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.unregister_callback(id),
         }
     }
+    /// Dispatcher method. See [crate::Db::next_in_sequence].
+    pub fn next_in_sequence(&self, name: &str) -> Result<i64> {
+        match self {
+            DbInstance::Mem(db) => db.next_in_sequence(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.next_in_sequence(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.next_in_sequence(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.next_in_sequence(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.next_in_sequence(name),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::reserve_in_sequence].
+    pub fn reserve_in_sequence(&self, name: &str, count: u32) -> Result<i64> {
+        match self {
+            DbInstance::Mem(db) => db.reserve_in_sequence(name, count),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.reserve_in_sequence(name, count),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.reserve_in_sequence(name, count),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.reserve_in_sequence(name, count),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.reserve_in_sequence(name, count),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::completions].
+    pub fn completions(&self, prefix: &str, context: &str) -> Result<Vec<String>> {
+        match self {
+            DbInstance::Mem(db) => db.completions(prefix, context),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.completions(prefix, context),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.completions(prefix, context),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.completions(prefix, context),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.completions(prefix, context),
+        }
+    }
     /// Dispatcher method. See [crate::Db::register_fixed_rule].
     pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
     where
@@ -466,6 +758,402 @@ impl DbInstance {
             DbInstance::TiKv(db) => db.run_multi_transaction(write, payloads, results),
         }
     }
+    /// Register a handler receiving the changes committed to `relations`, with at-least-once delivery.
+    ///
+    /// The changes are journaled in the stored relation `sync_journal.<name>` within the
+    /// transaction making them, and delivered in commit order to `handler` on a dedicated thread,
+    /// as the name of the relation changed and the same data as for [DbInstance::register_callback].
+    /// A change is removed from the journal only after the handler returns `Ok`, otherwise
+    /// it is retried. The journal survives restarts: registering a hook with the same name
+    /// again delivers the changes not yet acknowledged. Handlers should therefore be idempotent.
+    ///
+    /// Errors of the handler or of reading and acknowledging the journal are passed to
+    /// `on_error`, and delivery is retried with exponential backoff.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn register_sync_hook<F, E>(
+        &self,
+        name: &str,
+        relations: &[&str],
+        mut handler: F,
+        mut on_error: E,
+    ) -> Result<()>
+    where
+        F: FnMut(&str, CallbackOp, NamedRows, NamedRows) -> Result<()> + Send + 'static,
+        E: FnMut(Error) + Send + 'static,
+    {
+        const BATCH_SIZE: usize = 64;
+        const MIN_RETRY_DELAY: Duration = Duration::from_millis(100);
+        const MAX_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+        let notify = self.register_sync_hook_entry(name, relations)?;
+        let db = self.clone();
+        let name = name.to_string();
+        thread::spawn(move || {
+            let mut retry_delay = MIN_RETRY_DELAY;
+            loop {
+                // set if a wake-up is consumed while checking whether the hook is unregistered
+                let mut woken = false;
+                let delivered = db.pending_sync_batches(&name, BATCH_SIZE).and_then(|batches| {
+                    let n = batches.len();
+                    for batch in batches {
+                        match notify.try_recv() {
+                            Err(TryRecvError::Disconnected) => return Ok(None),
+                            Ok(()) => woken = true,
+                            Err(TryRecvError::Empty) => {}
+                        }
+                        handler(&batch.relation, batch.op, batch.new, batch.old)?;
+                        db.ack_sync_batch(&name, batch.seq)?;
+                    }
+                    Ok(Some(n))
+                });
+                match delivered {
+                    Ok(None) => return,
+                    Ok(Some(n)) => {
+                        retry_delay = MIN_RETRY_DELAY;
+                        if n < BATCH_SIZE && !woken && notify.recv().is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => {
+                        on_error(err);
+                        // wake-ups do not cut the delay short
+                        let deadline = Instant::now() + retry_delay;
+                        loop {
+                            match notify.recv_deadline(deadline) {
+                                Ok(()) => {}
+                                Err(RecvTimeoutError::Timeout) => break,
+                                Err(RecvTimeoutError::Disconnected) => return,
+                            }
+                        }
+                        retry_delay = (retry_delay * 2).min(MAX_RETRY_DELAY);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+    /// Dispatcher method. See [crate::Db::unregister_sync_hook]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn unregister_sync_hook(&self, name: &str) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.unregister_sync_hook(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unregister_sync_hook(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unregister_sync_hook(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unregister_sync_hook(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unregister_sync_hook(name),
+        }
+    }
+    /// Dispatcher method.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn register_sync_hook_entry(
+        &self,
+        name: &str,
+        relations: &[&str],
+    ) -> Result<Receiver<()>> {
+        match self {
+            DbInstance::Mem(db) => db.register_sync_hook_entry(name, relations),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_sync_hook_entry(name, relations),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_sync_hook_entry(name, relations),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_sync_hook_entry(name, relations),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_sync_hook_entry(name, relations),
+        }
+    }
+    /// Dispatcher method.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn pending_sync_batches(
+        &self,
+        name: &str,
+        limit: usize,
+    ) -> Result<Vec<SyncBatch>> {
+        match self {
+            DbInstance::Mem(db) => db.pending_sync_batches(name, limit),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.pending_sync_batches(name, limit),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.pending_sync_batches(name, limit),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.pending_sync_batches(name, limit),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.pending_sync_batches(name, limit),
+        }
+    }
+    /// Dispatcher method.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn ack_sync_batch(&self, name: &str, seq: i64) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.ack_sync_batch(name, seq),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.ack_sync_batch(name, seq),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.ack_sync_batch(name, seq),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.ack_sync_batch(name, seq),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.ack_sync_batch(name, seq),
+        }
+    }
+    /// Dispatcher method. See [crate::KvNamespace::get]
+    pub fn kv_get(&self, namespace: &str, key: &DataValue) -> Result<Option<DataValue>> {
+        match self {
+            DbInstance::Mem(db) => db.kv(namespace).get(key),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.kv(namespace).get(key),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.kv(namespace).get(key),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.kv(namespace).get(key),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.kv(namespace).get(key),
+        }
+    }
+    /// Dispatcher method. See [crate::KvNamespace::put]
+    pub fn kv_put(
+        &self,
+        namespace: &str,
+        key: &DataValue,
+        val: &DataValue,
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.kv(namespace).put(key, val),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.kv(namespace).put(key, val),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.kv(namespace).put(key, val),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.kv(namespace).put(key, val),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.kv(namespace).put(key, val),
+        }
+    }
+    /// Dispatcher method. See [crate::KvNamespace::remove]
+    pub fn kv_remove(&self, namespace: &str, key: &DataValue) -> Result<bool> {
+        match self {
+            DbInstance::Mem(db) => db.kv(namespace).remove(key),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.kv(namespace).remove(key),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.kv(namespace).remove(key),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.kv(namespace).remove(key),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.kv(namespace).remove(key),
+        }
+    }
+    /// Dispatcher method. See [crate::KvNamespace::range]
+    pub fn kv_range(
+        &self,
+        namespace: &str,
+        lower: &DataValue,
+        upper: &DataValue,
+    ) -> Result<Vec<(DataValue, DataValue)>> {
+        match self {
+            DbInstance::Mem(db) => db.kv(namespace).range(lower, upper),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.kv(namespace).range(lower, upper),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.kv(namespace).range(lower, upper),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.kv(namespace).range(lower, upper),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.kv(namespace).range(lower, upper),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_const_rule_row_limit]
+    pub fn set_const_rule_row_limit(&self, limit: Option<usize>) {
+        match self {
+            DbInstance::Mem(db) => db.set_const_rule_row_limit(limit),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_const_rule_row_limit(limit),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_const_rule_row_limit(limit),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_const_rule_row_limit(limit),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_const_rule_row_limit(limit),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_tx_write_limit]
+    pub fn set_tx_write_limit(&self, limit: Option<usize>) {
+        match self {
+            DbInstance::Mem(db) => db.set_tx_write_limit(limit),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_tx_write_limit(limit),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_tx_write_limit(limit),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_tx_write_limit(limit),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_tx_write_limit(limit),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::set_script_file_access]
+    pub fn set_script_file_access(&self, allow: bool) {
+        match self {
+            DbInstance::Mem(db) => db.set_script_file_access(allow),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.set_script_file_access(allow),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.set_script_file_access(allow),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.set_script_file_access(allow),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.set_script_file_access(allow),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::current_tx_id]
+    pub fn current_tx_id(&self) -> u64 {
+        match self {
+            DbInstance::Mem(db) => db.current_tx_id(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.current_tx_id(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.current_tx_id(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.current_tx_id(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.current_tx_id(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::import_relations_chunked]
+    pub fn import_relations_chunked(
+        &self,
+        data: BTreeMap<String, NamedRows>,
+        chunk_size: usize,
+        progress: impl FnMut(&str, usize, usize),
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.import_relations_chunked(data, chunk_size, progress),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.import_relations_chunked(data, chunk_size, progress),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.import_relations_chunked(data, chunk_size, progress),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.import_relations_chunked(data, chunk_size, progress),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.import_relations_chunked(data, chunk_size, progress),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::create_index_chunked]
+    pub fn create_index_chunked(
+        &self,
+        script: &str,
+        chunk_size: usize,
+        progress: impl FnMut(usize),
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.create_index_chunked(script, chunk_size, progress),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.create_index_chunked(script, chunk_size, progress),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.create_index_chunked(script, chunk_size, progress),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.create_index_chunked(script, chunk_size, progress),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.create_index_chunked(script, chunk_size, progress),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::create_index_online]
+    pub fn create_index_online(
+        &self,
+        script: &str,
+        chunk_size: usize,
+        progress: impl FnMut(usize),
+    ) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.create_index_online(script, chunk_size, progress),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.create_index_online(script, chunk_size, progress),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.create_index_online(script, chunk_size, progress),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.create_index_online(script, chunk_size, progress),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.create_index_online(script, chunk_size, progress),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::close]
+    pub fn close(&self, grace: Duration) -> Result<()> {
+        match self {
+            DbInstance::Mem(db) => db.close(grace),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.close(grace),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.close(grace),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.close(grace),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.close(grace),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::metrics]
+    pub fn metrics(&self) -> DbMetrics {
+        match self {
+            DbInstance::Mem(db) => db.metrics(),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.metrics(),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.metrics(),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.metrics(),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.metrics(),
+        }
+    }
+    /// Dispatcher method. See [crate::Db::unschedule_script]
+    pub fn unschedule_script(&self, name: &str) -> bool {
+        match self {
+            DbInstance::Mem(db) => db.unschedule_script(name),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.unschedule_script(name),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.unschedule_script(name),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.unschedule_script(name),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.unschedule_script(name),
+        }
+    }
+    /// Dispatcher method.
+    pub(crate) fn register_schedule(
+        &self,
+        name: &str,
+        script: &str,
+        interval: Duration,
+    ) -> Result<Arc<AtomicBool>> {
+        match self {
+            DbInstance::Mem(db) => db.register_schedule(name, script, interval),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.register_schedule(name, script, interval),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.register_schedule(name, script, interval),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.register_schedule(name, script, interval),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.register_schedule(name, script, interval),
+        }
+    }
+    /// Dispatcher method.
+    pub(crate) fn record_schedule_run(&self, name: &str, error: Option<String>) {
+        match self {
+            DbInstance::Mem(db) => db.record_schedule_run(name, error),
+            #[cfg(feature = "storage-sqlite")]
+            DbInstance::Sqlite(db) => db.record_schedule_run(name, error),
+            #[cfg(feature = "storage-rocksdb")]
+            DbInstance::RocksDb(db) => db.record_schedule_run(name, error),
+            #[cfg(feature = "storage-sled")]
+            DbInstance::Sled(db) => db.record_schedule_run(name, error),
+            #[cfg(feature = "storage-tikv")]
+            DbInstance::TiKv(db) => db.record_schedule_run(name, error),
+        }
+    }
     /// A higher-level, blocking wrapper for [crate::Db::run_multi_transaction]. Runs the transaction on a dedicated thread.
     /// Write transactions _may_ block other reads, but we guarantee that this does not happen for the RocksDB backend.
     pub fn multi_transaction(&self, write: bool) -> MultiTransaction {
@@ -478,6 +1166,37 @@ impl DbInstance {
             receiver: db2app_recv,
         }
     }
+    /// Run `script` with `params` every `interval`, on a dedicated thread, until
+    /// [DbInstance::unschedule_script] is called with the same `name`.
+    /// The first run happens one interval after registration.
+    /// The number of runs and the status of the last run are listed by the `::schedules` system op.
+    pub fn schedule_script(
+        &self,
+        name: &str,
+        script: &str,
+        params: BTreeMap<String, DataValue>,
+        interval: Duration,
+    ) -> Result<()> {
+        let cancelled = self.register_schedule(name, script, interval)?;
+        let db = self.clone();
+        let name = name.to_string();
+        let script = script.to_string();
+        thread::spawn(move || loop {
+            let deadline = Instant::now() + interval;
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                if cancelled.load(Ordering::Relaxed) {
+                    return;
+                }
+                thread::sleep(remaining.min(Duration::from_millis(100)));
+            }
+            if cancelled.load(Ordering::Relaxed) {
+                return;
+            }
+            let res = db.run_script(&script, params.clone());
+            db.record_schedule_run(&name, res.err().map(|err| err.to_string()));
+        });
+        Ok(())
+    }
 }
 
 /// A multi-transaction handle.
@@ -529,7 +1248,54 @@ impl MultiTransaction {
     }
 }
 
+fn decode_msgpack_request(
+    mut request: &[u8],
+) -> std::result::Result<(String, BTreeMap<String, DataValue>), &'static str> {
+    let fields = match rmpv::decode::read_value(&mut request) {
+        Ok(MsgpackValue::Map(fields)) => fields,
+        _ => return Err("request is not a msgpack map"),
+    };
+    let mut script = None;
+    let mut params = BTreeMap::default();
+    for (k, v) in fields {
+        match (k.as_str(), v) {
+            (Some("script"), MsgpackValue::String(s)) => match s.into_str() {
+                Some(s) => script = Some(s),
+                None => return Err("script is not valid UTF-8"),
+            },
+            (Some("params"), MsgpackValue::Map(ps)) => {
+                for (pk, pv) in ps {
+                    match pk.as_str() {
+                        Some(pk) => {
+                            params.insert(pk.to_string(), DataValue::from(pv));
+                        }
+                        None => return Err("params argument is not a map keyed by strings"),
+                    }
+                }
+            }
+            (Some("params"), MsgpackValue::Nil) => {}
+            (Some("params"), _) => return Err("params argument is not a map keyed by strings"),
+            _ => {}
+        }
+    }
+    match script {
+        Some(script) => Ok((script, params)),
+        None => Err("request has no script"),
+    }
+}
+
 /// Convert error raised by the database into friendly JSON format
+///
+/// The `code` field of the output, when present, is a stable identifier of the kind of
+/// error that clients can branch on. Common ones include:
+///
+/// * `query::relation_not_found`: a stored relation does not exist
+/// * `eval::rule_arity_mismatch`: a rule or stored relation is applied with the wrong arity
+/// * `eval::stored_relation_conflict`: a stored relation to create already exists
+/// * `eval::killed`: the query was killed by `::kill`
+/// * `eval::timeout`: the query exceeded the time set by `:timeout`
+/// * `storage::write_conflict`: the transaction conflicted with a concurrent one and may be retried
+/// * `tx::import_header_not_found`: data to import lacks a column of the relation
 pub fn format_error_as_json(mut err: Report, source: Option<&str>) -> JsonValue {
     if err.source_code().is_none() {
         if let Some(src) = source {
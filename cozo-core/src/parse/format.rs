@@ -0,0 +1,389 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::Result;
+use pest::error::InputLocation;
+use pest::Parser;
+
+use crate::parse::{CozoScriptParser, Pair, ParseError, Rule, SourceSpan};
+
+const INDENT: &str = "    ";
+const MAX_WIDTH: usize = 80;
+
+/// Format a CozoScript into canonical form.
+///
+/// Whitespace is normalized, each rule and option goes on its own line with options
+/// following the rules in a fixed order, nested blocks are indented, and rules too long
+/// for a single line have their body atoms aligned on continuation lines.
+/// Comments, and the `;` ending rules and options, are preserved.
+/// Only syntax is checked: no database is needed.
+pub fn format_script(src: &str) -> Result<String> {
+    let parsed = CozoScriptParser::parse(Rule::script, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap();
+    let mut toks = vec![];
+    emit(parsed, src, 0, &mut toks);
+    Ok(render(&toks))
+}
+
+#[derive(Debug)]
+enum Tok<'a> {
+    Word(&'a str),
+    /// unary operators, with nothing between them and their operands
+    Prefix(&'a str),
+    Punct(&'a str),
+    /// opening brackets, possibly attached to the preceding token as in `f(x)`
+    Open(&'a str, bool),
+    /// punctuation without space on either side, as in `rel:idx`
+    Glue(&'a str),
+    Comment(&'a str),
+    Line(usize),
+}
+
+fn render(toks: &[Tok<'_>]) -> String {
+    let mut ret = String::new();
+    let mut prev: Option<&Tok<'_>> = None;
+    for (i, tok) in toks.iter().enumerate() {
+        if matches!(tok, Tok::Line(_)) && matches!(toks.get(i + 1), Some(Tok::Line(_))) {
+            continue;
+        }
+        if let Some(p) = prev {
+            if needs_space(p, tok) {
+                ret.push(' ');
+            }
+        }
+        match tok {
+            Tok::Word(s)
+            | Tok::Prefix(s)
+            | Tok::Punct(s)
+            | Tok::Open(s, _)
+            | Tok::Glue(s)
+            | Tok::Comment(s) => ret.push_str(s),
+            Tok::Line(indent) => {
+                ret.push('\n');
+                for _ in 0..*indent {
+                    ret.push_str(INDENT);
+                }
+            }
+        }
+        prev = Some(tok);
+    }
+    ret
+}
+
+fn needs_space(prev: &Tok<'_>, next: &Tok<'_>) -> bool {
+    match (prev, next) {
+        (Tok::Line(_), _) | (_, Tok::Line(_)) => false,
+        (Tok::Prefix(_), _) | (Tok::Open(..), _) | (Tok::Glue(_), _) | (_, Tok::Glue(_)) => false,
+        (Tok::Punct("::"), _) => false,
+        (_, Tok::Open(_, tight)) => !tight,
+        (_, Tok::Punct(p)) => !matches!(*p, "," | ")" | "]" | "}" | ";" | ":" | "?"),
+        _ => true,
+    }
+}
+
+fn emit<'a>(pair: Pair<'a>, src: &'a str, indent: usize, toks: &mut Vec<Tok<'a>>) {
+    match pair.as_rule() {
+        Rule::query_script
+        | Rule::query_script_inner
+        | Rule::query_script_inner_no_bracket
+        | Rule::imperative_script
+        | Rule::imperative_block => emit_items(pair, src, indent, toks),
+        Rule::rule => {
+            let start = toks.len();
+            emit_children(pair.clone(), src, indent, false, toks);
+            let too_wide = render(&toks[start..])
+                .lines()
+                .any(|l| indent * INDENT.len() + l.len() > MAX_WIDTH);
+            let n_atoms = pair
+                .clone()
+                .into_inner()
+                .find(|p| p.as_rule() == Rule::rule_body)
+                .map(|body| body.into_inner().count())
+                .unwrap_or(0);
+            if too_wide && n_atoms > 1 {
+                toks.truncate(start);
+                emit_children(pair, src, indent, true, toks);
+            }
+        }
+        Rule::quoted_string | Rule::s_quoted_string | Rule::raw_string => {
+            toks.push(Tok::Word(pair.as_str()))
+        }
+        Rule::minus | Rule::negate | Rule::sort_asc | Rule::sort_desc => {
+            toks.push(Tok::Prefix(pair.as_str()))
+        }
+        Rule::var
+        | Rule::param
+        | Rule::ident
+        | Rule::underscore_ident
+        | Rule::relation_ident
+        | Rule::compound_ident
+        | Rule::compound_or_index_ident
+        | Rule::out_arg
+        | Rule::prog_entry
+        | Rule::pos_int
+        | Rule::hex_pos_int
+        | Rule::octo_pos_int
+        | Rule::bin_pos_int
+        | Rule::dot_float
+        | Rule::sci_float
+        | Rule::op_or
+        | Rule::op_and
+        | Rule::op_concat
+        | Rule::op_add
+        | Rule::op_sub
+        | Rule::op_mul
+        | Rule::op_div
+        | Rule::op_mod
+        | Rule::op_eq
+        | Rule::op_ne
+        | Rule::op_gt
+        | Rule::op_lt
+        | Rule::op_ge
+        | Rule::op_le
+        | Rule::op_pow
+        | Rule::op_coalesce
+        | Rule::atom_hint => toks.push(Tok::Word(pair.as_str())),
+        Rule::data_table_header | Rule::data_table_row => {
+            toks.push(Tok::Line(indent + 1));
+            emit_children(pair, src, indent, false, toks)
+        }
+        Rule::EOI => {}
+        _ => emit_children(pair, src, indent, false, toks),
+    }
+}
+
+/// Emit the children of `pair` together with the tokens in the gaps between them.
+/// With `brk` set, the atoms of a rule body are put on separate lines.
+fn emit_children<'a>(
+    pair: Pair<'a>,
+    src: &'a str,
+    indent: usize,
+    brk: bool,
+    toks: &mut Vec<Tok<'a>>,
+) {
+    let pair_rule = pair.as_rule();
+    let mut pos = pair.as_span().start();
+    for child in pair.clone().into_inner() {
+        let span = child.as_span();
+        lex_gap(&src[pos..span.start()], pair_rule, indent, brk, toks);
+        pos = span.end();
+        if brk && child.as_rule() == Rule::rule_body {
+            emit_children(child, src, indent, true, toks);
+        } else {
+            emit(child, src, indent, toks);
+        }
+    }
+    lex_gap(
+        &src[pos..pair.as_span().end()],
+        pair_rule,
+        indent,
+        brk,
+        toks,
+    );
+}
+
+/// Emit a sequence of rules, options or imperative statements, each on its own line.
+/// Options are placed after rules, in the order given by [option_rank].
+fn emit_items<'a>(pair: Pair<'a>, src: &'a str, indent: usize, toks: &mut Vec<Tok<'a>>) {
+    let pair_rule = pair.as_rule();
+    let item_indent = match pair_rule {
+        Rule::query_script | Rule::imperative_script => indent,
+        _ => indent + 1,
+    };
+    let mut rules = vec![];
+    let mut options = vec![];
+    let mut comments = vec![];
+    let mut pos = pair.as_span().start();
+    for child in pair.clone().into_inner() {
+        let span = child.as_span();
+        collect_comments(&src[pos..span.start()], &mut comments);
+        pos = span.end();
+        if child.as_rule() == Rule::EOI {
+            continue;
+        }
+        let mut item = vec![];
+        for comment in comments.drain(..) {
+            item.push(Tok::Comment(comment));
+            item.push(Tok::Line(item_indent));
+        }
+        let rank = option_rank(child.as_rule());
+        emit(child, src, item_indent, &mut item);
+        // the `;` ending an option lies outside of it, and stays with it
+        if rank.is_some() && src[span.end()..].trim_start().starts_with(';') {
+            item.push(Tok::Punct(";"));
+        }
+        match rank {
+            None => rules.push(item),
+            Some(rank) => options.push((rank, item)),
+        }
+    }
+    collect_comments(&src[pos..pair.as_span().end()], &mut comments);
+    options.sort_by_key(|(rank, _)| *rank);
+
+    if pair_rule == Rule::query_script_inner {
+        toks.push(Tok::Open("{", false));
+    }
+    let items = rules
+        .into_iter()
+        .chain(options.into_iter().map(|(_, item)| item))
+        .chain(comments.into_iter().map(|c| vec![Tok::Comment(c)]));
+    for (i, item) in items.enumerate() {
+        if i > 0 || item_indent > indent {
+            toks.push(Tok::Line(item_indent));
+        }
+        toks.extend(item);
+    }
+    if item_indent > indent {
+        toks.push(Tok::Line(indent));
+    }
+    if pair_rule == Rule::query_script_inner {
+        toks.push(Tok::Punct("}"));
+    }
+}
+
+fn option_rank(option_rule: Rule) -> Option<usize> {
+    Some(match option_rule {
+        Rule::sort_option => 0,
+        Rule::window_option => 1,
+        Rule::offset_option => 2,
+        Rule::limit_option => 3,
+        Rule::timeout_option => 4,
+        Rule::partial_on_timeout_option => 5,
+        Rule::sleep_option => 6,
+        Rule::assert_none_option | Rule::assert_some_option => 7,
+        Rule::set_var_option => 8,
+        Rule::relation_option => 9,
+        Rule::counts_option => 10,
+        Rule::profile_option => 11,
+        Rule::import_option => 12,
+        Rule::at_option => 13,
+        Rule::since_last_option => 14,
+        _ => return None,
+    })
+}
+
+fn collect_comments<'a>(gap: &'a str, collector: &mut Vec<&'a str>) {
+    let mut toks = vec![];
+    lex_gap(gap, Rule::EOI, 0, false, &mut toks);
+    for tok in toks {
+        if let Tok::Comment(c) = tok {
+            collector.push(c);
+        }
+    }
+}
+
+/// Tokenize the text between the children of a pair, consisting of whitespace, comments,
+/// keywords and punctuation. `ctx` is the rule of the pair the gap belongs to.
+fn lex_gap<'a>(gap: &'a str, ctx: Rule, indent: usize, brk: bool, toks: &mut Vec<Tok<'a>>) {
+    let mut rest = gap;
+    while let Some(c) = rest.chars().next() {
+        let len = if c.is_whitespace() {
+            c.len_utf8()
+        } else if c == '#' {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            toks.push(Tok::Comment(rest[..end].trim_end()));
+            toks.push(Tok::Line(indent + 1));
+            end
+        } else if rest.starts_with("/*") {
+            let end = block_comment_len(rest);
+            toks.push(Tok::Comment(&rest[..end]));
+            end
+        } else if let Some(p) = ["::", ":=", "<-", "<~", "=>", "->"]
+            .into_iter()
+            .find(|p| rest.starts_with(p))
+        {
+            toks.push(Tok::Punct(&rest[..p.len()]));
+            p.len()
+        } else if c.is_alphanumeric()
+            || c == '_'
+            || ((c == ':' || c == '%') && rest[1..].starts_with(|c: char| c.is_alphabetic()))
+        {
+            let end = rest[c.len_utf8()..]
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map(|i| i + c.len_utf8())
+                .unwrap_or(rest.len());
+            toks.push(Tok::Word(&rest[..end]));
+            end
+        } else {
+            let s = &rest[..c.len_utf8()];
+            match c {
+                '[' | '(' | '{' => toks.push(Tok::Open(s, is_attached(c, ctx))),
+                ':' if matches!(ctx, Rule::index_create | Rule::index_drop) => {
+                    toks.push(Tok::Glue(s))
+                }
+                ';' => {
+                    if matches!(
+                        ctx,
+                        Rule::list_type | Rule::rule | Rule::const_rule | Rule::fixed_rule
+                    ) {
+                        toks.push(Tok::Punct(s))
+                    }
+                }
+                ',' => {
+                    toks.push(Tok::Punct(s));
+                    if brk && ctx == Rule::rule_body {
+                        toks.push(Tok::Line(indent + 1));
+                    }
+                }
+                _ => toks.push(Tok::Punct(s)),
+            }
+            c.len_utf8()
+        };
+        rest = &rest[len..];
+    }
+}
+
+fn is_attached(bracket: char, ctx: Rule) -> bool {
+    match bracket {
+        '[' => matches!(
+            ctx,
+            Rule::rule_head
+                | Rule::rule_apply
+                | Rule::relation_apply
+                | Rule::fixed_rule_rel
+                | Rule::fixed_relation_rel
+        ),
+        '(' => matches!(
+            ctx,
+            Rule::apply | Rule::aggr_arg | Rule::fixed_args_list | Rule::optional
+        ),
+        '{' => matches!(
+            ctx,
+            Rule::relation_named_apply | Rule::fixed_named_relation_rel
+        ),
+        _ => false,
+    }
+}
+
+fn block_comment_len(s: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < s.len() {
+        if s[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if s[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += s[i..].chars().next().unwrap().len_utf8();
+        }
+    }
+    s.len()
+}
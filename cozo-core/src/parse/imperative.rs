@@ -12,10 +12,11 @@ use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
-use miette::{Diagnostic, Result};
+use miette::{ensure, Diagnostic, Result};
 use smartstring::SmartString;
 use thiserror::Error;
 
+use crate::data::program::InputProgram;
 use crate::parse::query::parse_query;
 use crate::parse::{ExtractSpan, ImperativeProgram, ImperativeStmt, Pair, Rule, SourceSpan};
 use crate::{DataValue, FixedRule, ValidityTs};
@@ -53,6 +54,25 @@ struct CannotManipulatePermRel(#[label] SourceSpan);
 #[diagnostic(code(parser::dup_marker))]
 struct DuplicateMarker(#[label] SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error(":set_var cannot be used in imperative scripts")]
+#[diagnostic(code(parser::set_var_in_imperative))]
+#[diagnostic(help("Run the query setting the variable as a script of its own"))]
+struct SetVarInImperative(#[label] SourceSpan);
+
+/// Parse a query of an imperative script, whose rows are never stored in session variables
+fn parse_stmt_query(
+    pair: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    fixed_rules: &BTreeMap<String, Arc<Box<dyn FixedRule>>>,
+    cur_vld: ValidityTs,
+) -> Result<InputProgram> {
+    let span = pair.extract_span();
+    let prog = parse_query(pair.into_inner(), param_pool, fixed_rules, cur_vld)?;
+    ensure!(prog.out_opts.set_var.is_none(), SetVarInImperative(span));
+    Ok(prog)
+}
+
 fn parse_imperative_stmt(
     pair: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
@@ -86,7 +106,7 @@ fn parse_imperative_stmt(
                         rets.push(Right(rel));
                     }
                     Rule::query_script_inner => {
-                        let prog = parse_query(p.into_inner(), param_pool, fixed_rules, cur_vld)?;
+                        let prog = parse_stmt_query(p, param_pool, fixed_rules, cur_vld)?;
                         rets.push(Left(prog))
                     }
                     _ => unreachable!(),
@@ -101,8 +121,8 @@ fn parse_imperative_stmt(
             let condition = inner.next().unwrap();
             let cond = match condition.as_rule() {
                 Rule::underscore_ident => Left(SmartString::from(condition.as_str())),
-                Rule::query_script_inner => Right(parse_query(
-                    condition.into_inner(),
+                Rule::query_script_inner => Right(parse_stmt_query(
+                    condition,
                     param_pool,
                     fixed_rules,
                     cur_vld,
@@ -164,12 +184,12 @@ fn parse_imperative_stmt(
             }
         }
         Rule::query_script_inner => {
-            let prog = parse_query(pair.into_inner(), param_pool, fixed_rules, cur_vld)?;
+            let prog = parse_stmt_query(pair, param_pool, fixed_rules, cur_vld)?;
             ImperativeStmt::Program { prog }
         }
         Rule::ignore_error_script => {
             let pair = pair.into_inner().next().unwrap();
-            let prog = parse_query(pair.into_inner(), param_pool, fixed_rules, cur_vld)?;
+            let prog = parse_stmt_query(pair, param_pool, fixed_rules, cur_vld)?;
             ImperativeStmt::IgnoreErrorProgram { prog }
         }
         r => unreachable!("{r:?}"),
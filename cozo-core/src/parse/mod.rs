@@ -12,22 +12,26 @@ use std::fmt::{Display, Formatter};
 use std::sync::Arc;
 
 use either::{Either, Left};
+use itertools::Itertools;
 use miette::{bail, Diagnostic, IntoDiagnostic, Result};
 use pest::error::InputLocation;
 use pest::Parser;
+use serde_json::json;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::json::JsonValue;
 use crate::data::program::InputProgram;
 use crate::data::relation::NullableColType;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::imperative::parse_imperative_block;
-use crate::parse::query::parse_query;
+use crate::parse::query::{parse_literal_write, parse_query, LiteralWrite};
 use crate::parse::schema::parse_nullable_type;
 use crate::parse::sys::{parse_sys, SysOp};
 use crate::FixedRule;
 
 pub(crate) mod expr;
+pub(crate) mod format;
 pub(crate) mod imperative;
 pub(crate) mod query;
 pub(crate) mod schema;
@@ -41,9 +45,10 @@ pub(crate) type Pair<'a> = pest::iterators::Pair<'a, Rule>;
 pub(crate) type Pairs<'a> = pest::iterators::Pairs<'a, Rule>;
 
 pub(crate) enum CozoScript {
-    Single(InputProgram),
+    Single(Box<InputProgram>),
     Imperative(ImperativeProgram),
     Sys(SysOp),
+    LiteralWrite(Box<LiteralWrite>),
 }
 
 #[derive(Debug)]
@@ -95,16 +100,12 @@ impl ImperativeStmt {
         match self {
             ImperativeStmt::Program { prog, .. }
             | ImperativeStmt::IgnoreErrorProgram { prog, .. } => {
-                if let Some(name) = prog.needs_write_lock() {
-                    collector.insert(name);
-                }
+                collector.extend(prog.needs_write_lock());
             }
             ImperativeStmt::Return { returns, .. } => {
                 for ret in returns {
                     if let Left(prog) = ret {
-                        if let Some(name) = prog.needs_write_lock() {
-                            collector.insert(name);
-                        }
+                        collector.extend(prog.needs_write_lock());
                     }
                 }
             }
@@ -115,9 +116,7 @@ impl ImperativeStmt {
                 ..
             } => {
                 if let ImperativeCondition::Right(prog) = condition {
-                    if let Some(name) = prog.needs_write_lock() {
-                        collector.insert(name);
-                    }
+                    collector.extend(prog.needs_write_lock());
                 }
                 for prog in then_branch.iter().chain(else_branch.iter()) {
                     prog.needs_write_locks(collector);
@@ -143,8 +142,8 @@ impl CozoScript {
         #[diagnostic(code(parser::expect_singleton))]
         struct ExpectSingleProgram;
         match self {
-            CozoScript::Single(s) => Ok(s),
-            CozoScript::Imperative(_) | CozoScript::Sys(_) => {
+            CozoScript::Single(s) => Ok(*s),
+            CozoScript::Imperative(_) | CozoScript::Sys(_) | CozoScript::LiteralWrite(_) => {
                 bail!(ExpectSingleProgram)
             }
         }
@@ -222,7 +221,7 @@ pub(crate) fn parse_script(
     Ok(match parsed.as_rule() {
         Rule::query_script => {
             let q = parse_query(parsed.into_inner(), param_pool, fixed_rules, cur_vld)?;
-            CozoScript::Single(q)
+            CozoScript::Single(Box::new(q))
         }
         Rule::imperative_script => {
             let p = parse_imperative_block(parsed, param_pool, fixed_rules, cur_vld)?;
@@ -235,10 +234,64 @@ pub(crate) fn parse_script(
             fixed_rules,
             cur_vld,
         )?),
+        Rule::literal_write_script => CozoScript::LiteralWrite(Box::new(parse_literal_write(
+            parsed.into_inner(),
+            param_pool,
+        )?)),
         _ => unreachable!(),
     })
 }
 
+/// Parse the CozoScript passed in and return its syntax tree as JSON, for use by external
+/// tooling such as formatters, linters and syntax highlighters.
+///
+/// Each node is an object with the grammar rule name under `rule`, the byte offsets
+/// `[start, end]` into `src` under `span`, and either its `children` or, for leaf nodes,
+/// its `text`. The top-level object additionally contains `variables`, mapping each
+/// variable name to the spans of all its occurrences.
+///
+/// Only syntax is checked: the script is not compiled and no database is needed.
+pub fn parse_to_json(src: &str) -> Result<JsonValue> {
+    let parsed = CozoScriptParser::parse(Rule::script, src)
+        .map_err(|err| {
+            let span = match err.location {
+                InputLocation::Pos(p) => SourceSpan(p, 0),
+                InputLocation::Span((start, end)) => SourceSpan(start, end - start),
+            };
+            ParseError { span }
+        })?
+        .next()
+        .unwrap();
+    let mut variables: BTreeMap<String, Vec<JsonValue>> = BTreeMap::new();
+    let mut ret = pair_to_json(parsed, &mut variables);
+    ret.as_object_mut()
+        .unwrap()
+        .insert("variables".to_string(), json!(variables));
+    Ok(ret)
+}
+
+fn pair_to_json(pair: Pair<'_>, variables: &mut BTreeMap<String, Vec<JsonValue>>) -> JsonValue {
+    let rule = format!("{:?}", pair.as_rule());
+    let span = pair.as_span();
+    let span_json = json!([span.start(), span.end()]);
+    if pair.as_rule() == Rule::var {
+        variables
+            .entry(pair.as_str().to_string())
+            .or_default()
+            .push(span_json.clone());
+    }
+    let text = pair.as_str();
+    let children = pair
+        .into_inner()
+        .map(|p| pair_to_json(p, variables))
+        .collect_vec();
+    if children.is_empty() {
+        json!({"rule": rule, "span": span_json, "text": text})
+    } else {
+        json!({"rule": rule, "span": span_json, "children": children})
+    }
+}
+
 trait ExtractSpan {
     fn extract_span(&self) -> SourceSpan;
 }
@@ -15,7 +15,7 @@ use std::sync::Arc;
 
 use either::{Left, Right};
 use itertools::Itertools;
-use miette::{bail, ensure, Diagnostic, LabeledSpan, Report, Result};
+use miette::{bail, ensure, Diagnostic, LabeledSpan, Report, Result, WrapErr};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
@@ -23,17 +23,20 @@ use crate::data::aggr::{parse_aggr, Aggregation};
 use crate::data::expr::Expr;
 use crate::data::functions::{str2vld, MAX_VALIDITY_TS};
 use crate::data::program::{
-    FixedRuleApply, FixedRuleArg, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
+    FixedRuleApply, FixedRuleArg, IndexHint, InputAtom, InputInlineRule, InputInlineRulesOrFixed,
     InputNamedFieldRelationApplyAtom, InputProgram, InputRelationApplyAtom, InputRuleApplyAtom,
-    QueryAssertion, QueryOutOptions, RelationOp, SortDir, Unification,
+    QueryAssertion, QueryOutOptions, RelationOp, SortDir, Unification, WindowDef, WindowFn,
+};
+use crate::data::relation::{
+    ColType, Collation, ColumnDef, NullableColType, StoredRelationMetadata,
 };
-use crate::data::relation::{ColType, ColumnDef, NullableColType, StoredRelationMetadata};
 use crate::data::symb::{Symbol, PROG_ENTRY};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::{FixedRuleHandle, FixedRuleNotFoundError};
 use crate::parse::expr::build_expr;
-use crate::parse::schema::parse_schema;
+use crate::parse::schema::{parse_nullable_type, parse_schema};
+use crate::parse::sys::UnknownCollation;
 use crate::parse::{ExtractSpan, Pair, Pairs, Rule, SourceSpan};
 use crate::runtime::relation::InputRelationHandle;
 use crate::FixedRule;
@@ -61,6 +64,35 @@ struct MultipleRuleDefinitionError(String, Vec<SourceSpan>);
 #[diagnostic(code(parser::multiple_out_assert))]
 struct DuplicateQueryAssertion(#[label] SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation {0} is written more than once by the query")]
+#[diagnostic(code(parser::duplicate_write_target))]
+#[diagnostic(help("Combine the rules writing to it into one"))]
+struct DuplicateWriteTarget(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Unknown rule annotation @{0}")]
+#[diagnostic(code(parser::unknown_rule_annotation))]
+#[diagnostic(help(
+    "The available annotations are @cached, @no_magic, @allow_full_scan, \
+    @max_depth(<n>) and @strict_max_depth(<n>)"
+))]
+struct UnknownRuleAnnotation(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad arguments for rule annotation @{0}")]
+#[diagnostic(code(parser::bad_rule_annotation_args))]
+#[diagnostic(help(
+    "@max_depth and @strict_max_depth take a positive integer, the other annotations take nothing"
+))]
+struct BadRuleAnnotationArgs(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Bad hint {0} for stored relation atom")]
+#[diagnostic(code(parser::bad_atom_hint))]
+#[diagnostic(help("The available hints are @use_index(<index name>) and @no_index"))]
+struct BadAtomHint(String, #[label] SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Multiple query yields defined")]
 #[diagnostic(code(parser::multiple_yields))]
@@ -89,6 +121,59 @@ impl Diagnostic for MultipleRuleDefinitionError {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Row of data table has {1} cells, but the header has {0} columns")]
+#[diagnostic(code(parser::data_table_row_width))]
+struct DataTableRowWidth(usize, usize, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cell of data table is not constant")]
+#[diagnostic(code(parser::data_table_cell_not_constant))]
+struct DataTableCellNotConstant(#[label] SourceSpan, #[related] [Report; 1]);
+
+/// Parse a data table: a header line of column names with optional types,
+/// followed by rows of constant cells, each line delimited by `|`.
+/// Cells in typed columns are coerced as if put into a stored relation.
+fn parse_data_table(
+    src: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    cur_vld: ValidityTs,
+) -> Result<(Vec<Symbol>, Vec<DataValue>)> {
+    let mut src = src.into_inner();
+    let mut header = vec![];
+    let mut types = vec![];
+    for col in src.next().unwrap().into_inner() {
+        let mut col = col.into_inner();
+        let name_p = col.next().unwrap();
+        header.push(Symbol::new(name_p.as_str(), name_p.extract_span()));
+        types.push(col.next().map(parse_nullable_type).transpose()?);
+    }
+    let mut rows = vec![];
+    for row_p in src {
+        let span = row_p.extract_span();
+        let cells: Vec<_> = row_p.into_inner().collect();
+        ensure!(
+            cells.len() == header.len(),
+            DataTableRowWidth(header.len(), cells.len(), span)
+        );
+        let mut row = Vec::with_capacity(cells.len());
+        for ((cell, typ), col) in cells.into_iter().zip(types.iter()).zip(header.iter()) {
+            let cell_span = cell.extract_span();
+            let val = build_expr(cell, param_pool)?
+                .eval_to_const()
+                .map_err(|err| DataTableCellNotConstant(cell_span, [err]))?;
+            row.push(match typ {
+                None => val,
+                Some(typ) => typ
+                    .coerce(val, cur_vld)
+                    .wrap_err_with(|| format!("when coercing the cell of column '{col}'"))?,
+            });
+        }
+        rows.push(DataValue::List(row));
+    }
+    Ok((header, rows))
+}
+
 fn merge_spans(symbs: &[Symbol]) -> SourceSpan {
     let mut fst = symbs.first().unwrap().span;
     for nxt in symbs.iter().skip(1) {
@@ -106,11 +191,45 @@ pub(crate) fn parse_query(
     let mut progs: BTreeMap<Symbol, InputInlineRulesOrFixed> = Default::default();
     let mut out_opts: QueryOutOptions = Default::default();
     let mut stored_relation = None;
+    let mut secondary_outputs = vec![];
 
     for pair in src {
         match pair.as_rule() {
             Rule::rule => {
-                let (name, rule) = parse_rule(pair, param_pool, cur_vld)?;
+                let (name, rule, annotations) = parse_rule(pair, param_pool, cur_vld)?;
+                for (annotation, arg) in annotations {
+                    match (&annotation.name as &str, arg) {
+                        ("cached", None) => {
+                            out_opts.cached_rules.insert(name.clone());
+                        }
+                        ("no_magic", None) => {
+                            out_opts.no_magic_rules.insert(name.clone());
+                        }
+                        ("allow_full_scan", None) => {
+                            out_opts.full_scan_rules.insert(name.clone());
+                        }
+                        ("max_depth", Some(n)) if n > 0 => {
+                            out_opts.max_depth_rules.insert(name.clone(), (n, false));
+                        }
+                        ("strict_max_depth", Some(n)) if n > 0 => {
+                            out_opts.max_depth_rules.insert(name.clone(), (n, true));
+                        }
+                        (
+                            "cached" | "no_magic" | "allow_full_scan" | "max_depth"
+                            | "strict_max_depth",
+                            _,
+                        ) => {
+                            bail!(BadRuleAnnotationArgs(
+                                annotation.to_string(),
+                                annotation.span
+                            ))
+                        }
+                        _ => bail!(UnknownRuleAnnotation(
+                            annotation.to_string(),
+                            annotation.span
+                        )),
+                    }
+                }
 
                 match progs.entry(name) {
                     Entry::Vacant(e) => {
@@ -176,7 +295,7 @@ pub(crate) fn parse_query(
             Rule::const_rule => {
                 let span = pair.extract_span();
                 let mut src = pair.into_inner();
-                let (name, head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
+                let (name, mut head, aggr) = parse_rule_head(src.next().unwrap(), param_pool)?;
 
                 if let Some(found) = progs.get(&name) {
                     let mut found_span = match found {
@@ -203,7 +322,19 @@ pub(crate) fn parse_query(
                     ensure!(a.is_none(), AggrInConstRuleError(v.span));
                 }
 
-                let data = build_expr(src.next().unwrap(), param_pool)?;
+                let data_p = src.next().unwrap();
+                let data = if data_p.as_rule() == Rule::data_table {
+                    let (header, rows) = parse_data_table(data_p, param_pool, cur_vld)?;
+                    if head.is_empty() {
+                        head = header;
+                    }
+                    Expr::Const {
+                        val: DataValue::List(rows),
+                        span,
+                    }
+                } else {
+                    build_expr(data_p, param_pool)?
+                };
                 let mut options = BTreeMap::new();
                 options.insert(SmartString::from("data"), data);
                 let handle = FixedRuleHandle {
@@ -244,6 +375,9 @@ pub(crate) fn parse_query(
                 ensure!(timeout > 0., OptionNotPosIntError("timeout", span));
                 out_opts.timeout = Some(timeout);
             }
+            Rule::partial_on_timeout_option => {
+                out_opts.partial_on_timeout = Some(pair.extract_span());
+            }
             Rule::sleep_option => {
                 #[cfg(target_arch = "wasm32")]
                 bail!(":sleep is not supported under WASM");
@@ -261,6 +395,37 @@ pub(crate) fn parse_query(
                     out_opts.sleep = Some(sleep);
                 }
             }
+            Rule::set_var_option => {
+                let name = pair.into_inner().next().unwrap().as_str();
+                out_opts.set_var = Some(SmartString::from(name));
+            }
+            Rule::counts_option => {
+                out_opts.counts = Some(pair.extract_span());
+            }
+            Rule::profile_option => {
+                out_opts.profile = Some(pair.extract_span());
+            }
+            Rule::since_last_option => {
+                let mut src = pair.into_inner();
+                let name = src.next().unwrap();
+                let rel = src.next().unwrap();
+                out_opts.since_last = Some((
+                    Symbol::new(name.as_str(), name.extract_span()),
+                    Symbol::new(rel.as_str(), rel.extract_span()),
+                ));
+            }
+            Rule::at_option => {
+                let vld_inner = pair.into_inner().next().unwrap();
+                let vld_expr = build_expr(vld_inner, param_pool)?;
+                out_opts.default_validity = Some(expr2vld_spec(vld_expr, cur_vld)?);
+            }
+            Rule::import_option => {
+                for p in pair.into_inner() {
+                    out_opts
+                        .imports
+                        .push(Symbol::new(p.as_str(), p.extract_span()));
+                }
+            }
             Rule::limit_option => {
                 let pair = pair.into_inner().next().unwrap();
                 let span = pair.extract_span();
@@ -281,10 +446,17 @@ pub(crate) fn parse_query(
                     .ok_or(OptionNotNonNegIntError("offset", span))?;
                 out_opts.offset = Some(offset as usize);
             }
+            Rule::window_option => {
+                for def in pair.into_inner() {
+                    out_opts.windows.push(parse_window_def(def, param_pool)?);
+                }
+            }
             Rule::sort_option => {
                 for part in pair.into_inner() {
                     let mut var = "";
+                    let mut expr = None;
                     let mut dir = SortDir::Asc;
+                    let mut collation = None;
                     let mut span = part.extract_span();
                     for a in part.into_inner() {
                         match a.as_rule() {
@@ -292,12 +464,36 @@ pub(crate) fn parse_query(
                                 var = a.as_str();
                                 span = a.extract_span();
                             }
+                            Rule::expr => {
+                                span = a.extract_span();
+                                expr = Some(build_expr(a, param_pool)?);
+                            }
                             Rule::sort_asc => dir = SortDir::Asc,
                             Rule::sort_desc => dir = SortDir::Dsc,
+                            Rule::ident => {
+                                collation =
+                                    Some(Collation::from_name(a.as_str()).ok_or_else(|| {
+                                        UnknownCollation(a.as_str().to_string(), a.extract_span())
+                                    })?)
+                            }
                             _ => unreachable!(),
                         }
                     }
-                    out_opts.sorters.push((Symbol::new(var, span), dir));
+                    let var = match expr {
+                        None => Symbol::new(var, span),
+                        Some(expr) => {
+                            let var = Symbol::new(
+                                &format!("*sort{}", out_opts.sort_exprs.len()) as &str,
+                                span,
+                            );
+                            out_opts.sort_exprs.insert(var.clone(), expr);
+                            var
+                        }
+                    };
+                    if let Some(collation) = collation {
+                        out_opts.sort_collations.insert(var.clone(), collation);
+                    }
+                    out_opts.sorters.push((var, dir));
                 }
             }
             Rule::relation_option => {
@@ -315,22 +511,31 @@ pub(crate) fn parse_query(
 
                 let name_p = args.next().unwrap();
                 let name = Symbol::new(name_p.as_str(), name_p.extract_span());
-                match args.next() {
-                    None => stored_relation = Some(Left((name, span, op))),
-                    Some(schema_p) => {
-                        let (metadata, key_bindings, dep_bindings) = parse_schema(schema_p)?;
-                        stored_relation = Some(Right((
-                            InputRelationHandle {
-                                name,
-                                metadata,
-                                key_bindings,
-                                dep_bindings,
-                                span,
-                            },
-                            op,
-                        )))
+                let mut target = Left((name.clone(), span, op));
+                let mut from_rule = None;
+                for p in args {
+                    match p.as_rule() {
+                        Rule::table_schema => {
+                            let (metadata, key_bindings, dep_bindings) = parse_schema(p)?;
+                            target = Right((
+                                InputRelationHandle {
+                                    name: name.clone(),
+                                    metadata,
+                                    key_bindings,
+                                    dep_bindings,
+                                    span,
+                                },
+                                op,
+                            ))
+                        }
+                        Rule::ident => from_rule = Some(Symbol::new(p.as_str(), p.extract_span())),
+                        r => unreachable!("{:?}", r),
                     }
                 }
+                match from_rule {
+                    None => stored_relation = Some(target),
+                    Some(rule) => secondary_outputs.push((rule, target)),
+                }
             }
             Rule::assert_none_option => {
                 ensure!(
@@ -377,38 +582,33 @@ pub(crate) fn parse_query(
     match stored_relation {
         None => {}
         Some(Left((name, span, op))) => {
-            let head = prog.get_entry_out_head()?;
-            for symb in &head {
-                symb.ensure_valid_field()?;
-            }
-
-            let metadata = StoredRelationMetadata {
-                keys: head
-                    .iter()
-                    .map(|s| ColumnDef {
-                        name: s.name.clone(),
-                        typing: NullableColType {
-                            coltype: ColType::Any,
-                            nullable: true,
-                        },
-                        default_gen: None,
-                    })
-                    .collect(),
-                non_keys: vec![],
-            };
-
-            let handle = InputRelationHandle {
-                name,
-                metadata,
-                key_bindings: head,
-                dep_bindings: vec![],
-                span,
-            };
+            let mut head = prog.get_entry_out_head()?;
+            head.extend(prog.out_opts.windows.iter().map(|w| w.name.clone()));
+            let handle = keyed_by_head(name, span, head)?;
             prog.out_opts.store_relation = Some((handle, op))
         }
         Some(Right(r)) => prog.out_opts.store_relation = Some(r),
     }
 
+    let mut write_targets: BTreeSet<_> = prog
+        .out_opts
+        .store_relation
+        .iter()
+        .map(|(handle, _)| handle.name.name.clone())
+        .collect();
+    for (rule, target) in secondary_outputs {
+        let head = prog.get_rule_out_head(&rule)?;
+        let (handle, op) = match target {
+            Left((name, span, op)) => (keyed_by_head(name, span, head)?, op),
+            Right(r) => r,
+        };
+        ensure!(
+            write_targets.insert(handle.name.name.clone()),
+            DuplicateWriteTarget(handle.name.name.to_string(), handle.span)
+        );
+        prog.out_opts.secondary_outputs.push((rule, handle, op));
+    }
+
     if prog.prog.is_empty() {
         if let Some((handle, RelationOp::Create)) = &prog.out_opts.store_relation {
             let mut bindings = handle.dep_bindings.clone();
@@ -426,24 +626,200 @@ pub(crate) fn parse_query(
         let head_args = prog.get_entry_out_head()?;
 
         for (sorter, _) in &prog.out_opts.sorters {
+            match prog.out_opts.sort_exprs.get(sorter) {
+                None => ensure!(
+                    head_args.contains(sorter),
+                    SortKeyNotFound(sorter.to_string(), sorter.span)
+                ),
+                Some(expr) => {
+                    for var in expr.bindings() {
+                        ensure!(
+                            head_args.contains(&var),
+                            SortKeyNotFound(var.to_string(), var.span)
+                        )
+                    }
+                }
+            }
+        }
+    }
+
+    if !prog.out_opts.windows.is_empty() {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Window column '{0}' not found")]
+        #[diagnostic(code(parser::window_col_not_found))]
+        struct WindowColumnNotFound(String, #[label] SourceSpan);
+
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Window output '{0}' conflicts with another output column")]
+        #[diagnostic(code(parser::window_name_conflict))]
+        struct WindowNameConflict(String, #[label] SourceSpan);
+
+        let mut head_args = prog.get_entry_out_head()?;
+        for window in &prog.out_opts.windows {
+            match &window.func {
+                WindowFn::CumSum(col) | WindowFn::Lag(col, _) | WindowFn::Lead(col, _) => {
+                    ensure!(
+                        head_args.contains(col),
+                        WindowColumnNotFound(col.to_string(), col.span)
+                    )
+                }
+                WindowFn::RowNumber | WindowFn::Rank | WindowFn::DenseRank => {}
+            }
             ensure!(
-                head_args.contains(sorter),
-                SortKeyNotFound(sorter.to_string(), sorter.span)
-            )
+                !head_args.contains(&window.name),
+                WindowNameConflict(window.name.to_string(), window.name.span)
+            );
+            head_args.push(window.name.clone());
         }
     }
 
+    if let Some(span) = prog.out_opts.counts {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error(":counts can only be used when putting into or removing from a stored relation")]
+        #[diagnostic(code(parser::counts_without_write))]
+        struct CountsWithoutWrite(#[label] SourceSpan);
+
+        ensure!(
+            matches!(
+                prog.out_opts.store_relation,
+                Some((
+                    _,
+                    RelationOp::Put | RelationOp::Rm | RelationOp::Create | RelationOp::Replace
+                ))
+            ),
+            CountsWithoutWrite(span)
+        );
+    }
+
+    if let Some(span) = prog.out_opts.partial_on_timeout {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error(":partial_on_timeout cannot be used with queries writing to stored relations")]
+        #[diagnostic(code(parser::partial_write))]
+        #[diagnostic(help(
+            "Rows derived before a timeout are incomplete, so they are only returned"
+        ))]
+        struct PartialWrite(#[label] SourceSpan);
+
+        ensure!(
+            prog.out_opts.store_relation.is_none() && prog.out_opts.secondary_outputs.is_empty(),
+            PartialWrite(span)
+        );
+    }
+
     Ok(prog)
 }
 
+/// The handle of a stored relation given without a schema, whose keys are the columns of `head`
+fn keyed_by_head(name: Symbol, span: SourceSpan, head: Vec<Symbol>) -> Result<InputRelationHandle> {
+    for symb in &head {
+        symb.ensure_valid_field()?;
+    }
+
+    let metadata = StoredRelationMetadata {
+        keys: head
+            .iter()
+            .map(|s| ColumnDef {
+                name: s.name.clone(),
+                typing: NullableColType {
+                    coltype: ColType::Any,
+                    nullable: true,
+                },
+                default_gen: None,
+            })
+            .collect(),
+        non_keys: vec![],
+    };
+
+    Ok(InputRelationHandle {
+        name,
+        metadata,
+        key_bindings: head,
+        dep_bindings: vec![],
+        span,
+    })
+}
+
+fn parse_window_def(
+    src: Pair<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<WindowDef> {
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Unknown window function '{0}'")]
+    #[diagnostic(code(parser::unknown_window_fn))]
+    #[diagnostic(help(
+        "Available are row_number(), rank(), dense_rank(), cumsum(col), lag(col, n) and lead(col, n)"
+    ))]
+    struct UnknownWindowFn(String, #[label] SourceSpan);
+
+    #[derive(Debug, Error, Diagnostic)]
+    #[error("Wrong arguments for window function '{0}'")]
+    #[diagnostic(code(parser::bad_window_fn_args))]
+    struct BadWindowFnArgs(String, #[label] SourceSpan);
+
+    let span = src.extract_span();
+    let mut src = src.into_inner();
+    let name_p = src.next().unwrap();
+    let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+    let fn_p = src.next().unwrap();
+    let fn_name = fn_p.as_str();
+    let col = src
+        .next()
+        .map(|p| Symbol::new(p.as_str(), p.extract_span()));
+    let offset = match src.next() {
+        None => None,
+        Some(p) => {
+            let span = p.extract_span();
+            let n = build_expr(p, param_pool)?
+                .eval_to_const()
+                .map_err(|err| OptionNotConstantError("window", span, [err]))?
+                .get_non_neg_int()
+                .ok_or(OptionNotNonNegIntError("window", span))?;
+            Some(n as usize)
+        }
+    };
+    let func = match (fn_name, col, offset) {
+        ("row_number", None, None) => WindowFn::RowNumber,
+        ("rank", None, None) => WindowFn::Rank,
+        ("dense_rank", None, None) => WindowFn::DenseRank,
+        ("cumsum", Some(col), None) => WindowFn::CumSum(col),
+        ("lag", Some(col), n) => WindowFn::Lag(col, n.unwrap_or(1)),
+        ("lead", Some(col), n) => WindowFn::Lead(col, n.unwrap_or(1)),
+        ("row_number" | "rank" | "dense_rank" | "cumsum" | "lag" | "lead", _, _) => {
+            bail!(BadWindowFnArgs(fn_name.to_string(), span))
+        }
+        _ => bail!(UnknownWindowFn(fn_name.to_string(), fn_p.extract_span())),
+    };
+    Ok(WindowDef { name, func })
+}
+
+/// Parse a Horn-clause rule, returning its name, the rule and its annotations
+/// together with their integer arguments.
 fn parse_rule(
     src: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
     cur_vld: ValidityTs,
-) -> Result<(Symbol, InputInlineRule)> {
+) -> Result<(Symbol, InputInlineRule, Vec<(Symbol, Option<usize>)>)> {
     let span = src.extract_span();
     let mut src = src.into_inner();
-    let head = src.next().unwrap();
+    let mut annotations = vec![];
+    let mut head = src.next().unwrap();
+    while head.as_rule() == Rule::rule_annotation {
+        let annotation_span = head.extract_span();
+        let mut inner = head.into_inner();
+        let ident = inner.next().unwrap();
+        let symb = Symbol::new(ident.as_str(), ident.extract_span());
+        let arg = match inner.next() {
+            None => None,
+            Some(n) => Some(
+                n.as_str()
+                    .replace('_', "")
+                    .parse::<usize>()
+                    .map_err(|_| BadRuleAnnotationArgs(symb.to_string(), annotation_span))?,
+            ),
+        };
+        annotations.push((symb, arg));
+        head = src.next().unwrap();
+    }
     let head_span = head.extract_span();
     let (name, head, aggr) = parse_rule_head(head, param_pool)?;
 
@@ -473,6 +849,7 @@ fn parse_rule(
             body: body_clauses,
             span,
         },
+        annotations,
     ))
 }
 
@@ -526,6 +903,32 @@ fn parse_atom(
                 span,
             }
         }
+        Rule::existence => {
+            let span = src.extract_span();
+            let inner = parse_atom(
+                src.into_inner().next().unwrap(),
+                param_pool,
+                cur_vld,
+                ignored_counter,
+            )?;
+            InputAtom::Existence {
+                inner: inner.into(),
+                span,
+            }
+        }
+        Rule::optional => {
+            let span = src.extract_span();
+            let inner = parse_atom(
+                src.into_inner().next().unwrap(),
+                param_pool,
+                cur_vld,
+                ignored_counter,
+            )?;
+            InputAtom::Optional {
+                inner: inner.into(),
+                span,
+            }
+        }
         Rule::expr => {
             let expr = build_expr(src, param_pool)?;
             InputAtom::Predicate { inner: expr }
@@ -596,18 +999,15 @@ fn parse_atom(
                 .into_inner()
                 .map(|v| build_expr(v, param_pool))
                 .try_collect()?;
-            let valid_at = match src.next() {
-                None => None,
-                Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
-                    Some(expr2vld_spec(vld_expr, cur_vld)?)
-                }
-            };
+            let (valid_at, valid_time, hint) =
+                parse_relation_apply_suffix(src, param_pool, cur_vld)?;
             InputAtom::Relation {
                 inner: InputRelationApplyAtom {
                     name: Symbol::new(&name.as_str()[1..], name.extract_span()),
                     args,
                     valid_at,
+                    valid_time,
+                    hint,
                     span,
                 },
             }
@@ -635,26 +1035,74 @@ fn parse_atom(
                     Ok((name, arg))
                 })
                 .try_collect()?;
-            let valid_at = match src.next() {
-                None => None,
-                Some(vld_clause) => {
-                    let vld_expr = build_expr(vld_clause.into_inner().next().unwrap(), param_pool)?;
-                    Some(expr2vld_spec(vld_expr, cur_vld)?)
-                }
-            };
+            let (valid_at, valid_time, hint) =
+                parse_relation_apply_suffix(src, param_pool, cur_vld)?;
             InputAtom::NamedFieldRelation {
                 inner: InputNamedFieldRelationApplyAtom {
                     name,
                     args,
                     span,
                     valid_at,
+                    valid_time,
+                    hint,
                 },
             }
         }
-        rule => unreachable!("{:?}", rule),
+        r => unreachable!("{:?}", r),
     })
 }
 
+/// The validity clause, the valid-time clause and the hint following the arguments
+/// of a stored relation atom
+fn parse_relation_apply_suffix(
+    src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+    cur_vld: ValidityTs,
+) -> Result<(Option<ValidityTs>, Option<Expr>, Option<IndexHint>)> {
+    let mut valid_at = None;
+    let mut valid_time = None;
+    let mut hint = None;
+    for pair in src {
+        match pair.as_rule() {
+            Rule::validity_clause => {
+                let vld_expr = build_expr(pair.into_inner().next().unwrap(), param_pool)?;
+                valid_at = Some(expr2vld_spec(vld_expr, cur_vld)?);
+            }
+            Rule::valid_time_clause => {
+                let mut expr = build_expr(pair.into_inner().next().unwrap(), param_pool)?;
+                // times given as strings are read like those of validity clauses
+                if let Expr::Const {
+                    val: DataValue::Str(_),
+                    span,
+                } = expr
+                {
+                    let ts = expr2vld_spec(expr, cur_vld)?;
+                    expr = Expr::Const {
+                        val: DataValue::from(ts.0 .0),
+                        span,
+                    };
+                }
+                valid_time = Some(expr);
+            }
+            Rule::atom_hint => {
+                let span = pair.extract_span();
+                let text = pair.as_str().to_string();
+                let mut inner = pair.into_inner();
+                let name = inner.next().unwrap().as_str();
+                hint = Some(match (name, inner.next()) {
+                    ("use_index", Some(idx)) => {
+                        IndexHint::UseIndex(Symbol::new(idx.as_str(), idx.extract_span()))
+                    }
+                    ("no_index", None) => IndexHint::NoIndex,
+                    _ => bail!(BadAtomHint(text, span)),
+                });
+            }
+            r => unreachable!("{:?}", r),
+        }
+    }
+    Ok((valid_at, valid_time, hint))
+}
+
 fn parse_rule_head(
     src: Pair<'_>,
     param_pool: &BTreeMap<String, DataValue>,
@@ -917,6 +1365,123 @@ struct FixedRuleHeadArityMismatch(usize, usize, #[label] SourceSpan);
 #[diagnostic(code(parser::const_rule_empty_row))]
 struct EmptyRowForConstRule(#[label] SourceSpan);
 
+/// A write of literal rows into a stored relation, as in `:put rel [[1, 'a']]`.
+/// The columns written are only known once the relation is looked up, see [LiteralWrite::into_program].
+pub(crate) struct LiteralWrite {
+    pub(crate) name: Symbol,
+    pub(crate) op: RelationOp,
+    pub(crate) data: Expr,
+    pub(crate) span: SourceSpan,
+}
+
+pub(crate) fn parse_literal_write(
+    mut src: Pairs<'_>,
+    param_pool: &BTreeMap<String, DataValue>,
+) -> Result<LiteralWrite> {
+    let op = match src.next().unwrap().as_rule() {
+        Rule::relation_put => RelationOp::Put,
+        Rule::relation_rm => RelationOp::Rm,
+        _ => unreachable!(),
+    };
+    let name_p = src.next().unwrap();
+    let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+    let data_p = src.next().unwrap();
+    let span = data_p.extract_span();
+    let data = build_expr(data_p, param_pool)?;
+    Ok(LiteralWrite {
+        name,
+        op,
+        data,
+        span,
+    })
+}
+
+impl LiteralWrite {
+    /// Turn into a query writing the rows into the relation described by `metadata`.
+    /// The rows must contain all columns of the relation, except that rows to be removed
+    /// may contain only the keys.
+    pub(crate) fn into_program(self, metadata: &StoredRelationMetadata) -> Result<InputProgram> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Rows to write into '{0}' have {1} columns, but the relation has {2}")]
+        #[diagnostic(code(parser::literal_write_arity_mismatch))]
+        struct LiteralWriteArityMismatch(String, usize, usize, #[label] SourceSpan);
+
+        let mut options = BTreeMap::new();
+        options.insert(SmartString::from("data"), self.data);
+        let fixed_impl = Box::new(Constant);
+        fixed_impl.init_options(&mut options, self.span)?;
+        let arity = fixed_impl.arity(&options, &[], self.span)?;
+        ensure!(arity != 0, EmptyRowForConstRule(self.span));
+
+        let n_keys = metadata.keys.len();
+        let with_deps = !(self.op == RelationOp::Rm && arity == n_keys);
+        let n_cols = if with_deps {
+            n_keys + metadata.non_keys.len()
+        } else {
+            n_keys
+        };
+        ensure!(
+            arity == n_cols,
+            LiteralWriteArityMismatch(self.name.to_string(), arity, n_cols, self.span)
+        );
+        let to_symbols = |cols: &[ColumnDef]| {
+            cols.iter()
+                .map(|col| Symbol::new(col.name.clone(), self.span))
+                .collect_vec()
+        };
+        let key_bindings = to_symbols(&metadata.keys);
+        let dep_bindings = if with_deps {
+            to_symbols(&metadata.non_keys)
+        } else {
+            vec![]
+        };
+        let head = key_bindings
+            .iter()
+            .chain(dep_bindings.iter())
+            .cloned()
+            .collect_vec();
+
+        let mut prog = BTreeMap::new();
+        prog.insert(
+            Symbol::new(PROG_ENTRY, self.span),
+            InputInlineRulesOrFixed::Fixed {
+                fixed: FixedRuleApply {
+                    fixed_handle: FixedRuleHandle {
+                        name: Symbol::new("Constant", self.span),
+                    },
+                    rule_args: vec![],
+                    options: Arc::new(options),
+                    head,
+                    arity,
+                    span: self.span,
+                    fixed_impl: Arc::new(fixed_impl),
+                },
+            },
+        );
+        let out_opts = QueryOutOptions {
+            store_relation: Some((
+                InputRelationHandle {
+                    name: self.name,
+                    metadata: StoredRelationMetadata {
+                        keys: metadata.keys.clone(),
+                        non_keys: if with_deps {
+                            metadata.non_keys.clone()
+                        } else {
+                            vec![]
+                        },
+                    },
+                    key_bindings,
+                    dep_bindings,
+                    span: self.span,
+                },
+                self.op,
+            )),
+            ..Default::default()
+        };
+        Ok(InputProgram { prog, out_opts })
+    }
+}
+
 fn make_empty_const_rule(prog: &mut InputProgram, bindings: &[Symbol]) {
     let entry_symbol = Symbol::new(PROG_ENTRY, Default::default());
     let mut options = BTreeMap::new();
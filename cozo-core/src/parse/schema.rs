@@ -122,6 +122,8 @@ fn parse_type_inner(pair: Pair<'_>) -> Result<ColType> {
         Rule::string_type => ColType::String,
         Rule::bytes_type => ColType::Bytes,
         Rule::uuid_type => ColType::Uuid,
+        Rule::bigint_type => ColType::BigInt,
+        Rule::decimal_type => ColType::Decimal,
         Rule::validity_type => ColType::Validity,
         Rule::list_type => {
             let mut inner = pair.into_inner();
@@ -10,33 +10,74 @@ use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use itertools::Itertools;
-use miette::{ensure, miette, Diagnostic, Result};
+use miette::{bail, ensure, miette, Diagnostic, Result};
 use thiserror::Error;
 
+use crate::data::expr::Expr;
 use crate::data::program::InputProgram;
+use crate::data::relation::Collation;
 use crate::data::symb::Symbol;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::expr::build_expr;
 use crate::parse::query::parse_query;
 use crate::parse::{ExtractSpan, Pairs, Rule, SourceSpan};
 use crate::runtime::relation::AccessLevel;
+use crate::runtime::saved_query::SavedQuery;
 use crate::FixedRule;
 
 pub(crate) enum SysOp {
     Compact,
+    Vacuum(bool),
+    /// Whether to repair the inconsistencies found
+    IntegrityCheck(bool),
     ListRelation(Symbol),
+    ScanRelation(Symbol, usize, usize),
+    AnalyzeRelation(Symbol),
+    ShowStats(Symbol),
+    StorageStats,
+    /// The relation, equality filters on its columns, the sample size and the seed
+    SampleEntities(Symbol, Vec<(Symbol, DataValue)>, usize, u64),
+    /// The relation and the values of its key columns other than the validity
+    History(Symbol, Vec<DataValue>, SourceSpan),
+    ExportRelation(Symbol, String),
+    ImportRelation(Symbol, String),
+    /// The relation of the other database, its engine and path, and the query giving the rows
+    Push(Symbol, String, String, Box<InputProgram>),
     ListRelations,
     ListRunning,
+    ListSchedules,
     ListFixedRules,
     KillRunning(u64),
     Explain(Box<InputProgram>),
+    Estimate(Box<InputProgram>),
     RemoveRelation(Vec<Symbol>),
+    /// The relation and the value of its first key column. The rows are deleted as a key range,
+    /// like `::remove` does, so `on rm` triggers and change callbacks do not see them.
+    RemovePartition(Symbol, DataValue),
     RenameRelation(Vec<(Symbol, Symbol)>),
     ShowTrigger(Symbol),
     SetTriggers(Symbol, Vec<String>, Vec<String>, Vec<String>),
     SetAccessLevel(Vec<Symbol>, AccessLevel),
-    CreateIndex(Symbol, Symbol, Vec<Symbol>),
+    SetAppendOnly(Vec<Symbol>, bool),
+    /// The relation and the column holding the expiry time of its rows, if any
+    SetTtl(Symbol, Option<Symbol>),
+    SweepExpired(Symbol),
+    /// The relation, the column, whether to deprecate rather than undeprecate it,
+    /// and the column replacing it, if any
+    SetDeprecated(Symbol, Symbol, bool, Option<Symbol>),
+    /// The relation, the derived attribute, and the expression computing it,
+    /// or `None` to remove the attribute
+    SetDerived(Symbol, Symbol, Option<Expr>),
+    SetScheduled(Symbol, bool),
+    /// The relation, and the columns holding the start and the end of the valid time,
+    /// or `None` to make it an ordinary relation with validity
+    SetValidTime(Symbol, Option<(Symbol, Symbol)>),
+    CreateIndex(Symbol, Symbol, Vec<Symbol>, Vec<(usize, Collation)>, Option<Expr>),
     RemoveIndex(Symbol, Symbol),
+    SaveQuery(Symbol, SavedQuery),
+    RemoveSavedQuery(Symbol),
+    ListSavedQueries,
+    CallSavedQuery(Symbol, BTreeMap<String, DataValue>),
 }
 
 #[derive(Debug, Diagnostic, Error)]
@@ -44,6 +85,43 @@ pub(crate) enum SysOp {
 #[diagnostic(code(parser::not_proc_id))]
 struct ProcessIdError(String, #[label] SourceSpan);
 
+#[derive(Debug, Diagnostic, Error)]
+#[error("Unknown collation {0}")]
+#[diagnostic(code(parser::unknown_collation))]
+#[diagnostic(help("Available collations are 'nocase', 'noaccent' and 'numeric'"))]
+pub(crate) struct UnknownCollation(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Expected a string for the script or description of a saved query, got {0}")]
+#[diagnostic(code(parser::saved_query_not_string))]
+struct SavedQueryNotStringError(String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("The {0} of a relation scan must be a non-negative integer, got {1}")]
+#[diagnostic(code(parser::bad_scan_bound))]
+struct ScanBoundError(&'static str, String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("The {0} of an entity sample must be a non-negative integer, got {1}")]
+#[diagnostic(code(parser::bad_sample_arg))]
+struct SampleArgError(&'static str, String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("The key for the history of a relation must be a list, got {0}")]
+#[diagnostic(code(parser::bad_history_key))]
+struct HistoryKeyError(String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Expected a string for the file path, got {0}")]
+#[diagnostic(code(parser::bad_file_path))]
+struct FilePathError(String, #[label] SourceSpan);
+
+#[derive(Debug, Diagnostic, Error)]
+#[error("Expected a string for the storage engine, got {0}")]
+#[diagnostic(code(parser::bad_engine_name))]
+#[diagnostic(help("Engines are named as in `DbInstance::new`, e.g. 'sqlite' or 'rocksdb'"))]
+struct EngineNameError(String, #[label] SourceSpan);
+
 pub(crate) fn parse_sys(
     mut src: Pairs<'_>,
     param_pool: &BTreeMap<String, DataValue>,
@@ -53,7 +131,13 @@ pub(crate) fn parse_sys(
     let inner = src.next().unwrap();
     Ok(match inner.as_rule() {
         Rule::compact_op => SysOp::Compact,
+        Rule::vacuum_op => SysOp::Vacuum(inner.into_inner().next().is_some()),
+        Rule::integrity_check_op => {
+            SysOp::IntegrityCheck(inner.into_inner().next().is_some())
+        }
         Rule::running_op => SysOp::ListRunning,
+        Rule::schedules_op => SysOp::ListSchedules,
+        Rule::storage_stats_op => SysOp::StorageStats,
         Rule::kill_op => {
             let i_expr = inner.into_inner().next().unwrap();
             let i_val = build_expr(i_expr, param_pool)?;
@@ -72,7 +156,64 @@ pub(crate) fn parse_sys(
             )?;
             SysOp::Explain(Box::new(prog))
         }
+        Rule::estimate_op => {
+            let prog = parse_query(
+                inner.into_inner().next().unwrap().into_inner(),
+                param_pool,
+                algorithms,
+                cur_vld,
+            )?;
+            SysOp::Estimate(Box::new(prog))
+        }
         Rule::list_relations_op => SysOp::ListRelations,
+        Rule::save_query_op => {
+            let mut src = inner.into_inner();
+            let name_p = src.next().unwrap();
+            let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+            let mut params = vec![];
+            let mut strings = vec![];
+            for p in src {
+                match p.as_rule() {
+                    Rule::ident => params.push(p.as_str().to_string()),
+                    _ => {
+                        let span = p.extract_span();
+                        match build_expr(p, param_pool)?.eval_to_const()? {
+                            DataValue::Str(s) => strings.push(s.to_string()),
+                            v => bail!(SavedQueryNotStringError(v.to_string(), span)),
+                        }
+                    }
+                }
+            }
+            let mut strings = strings.into_iter();
+            let script = strings.next().unwrap();
+            let description = strings.next().unwrap_or_default();
+            SysOp::SaveQuery(
+                name,
+                SavedQuery {
+                    script,
+                    description,
+                    params,
+                },
+            )
+        }
+        Rule::remove_query_op => {
+            let name_p = inner.into_inner().next().unwrap();
+            SysOp::RemoveSavedQuery(Symbol::new(name_p.as_str(), name_p.extract_span()))
+        }
+        Rule::saved_queries_op => SysOp::ListSavedQueries,
+        Rule::call_op => {
+            let mut src = inner.into_inner();
+            let name_p = src.next().unwrap();
+            let name = Symbol::new(name_p.as_str(), name_p.extract_span());
+            let mut args = BTreeMap::new();
+            for arg_p in src {
+                let mut arg_src = arg_p.into_inner();
+                let arg_name = arg_src.next().unwrap().as_str().to_string();
+                let val = build_expr(arg_src.next().unwrap(), param_pool)?.eval_to_const()?;
+                args.insert(arg_name, val);
+            }
+            SysOp::CallSavedQuery(name, args)
+        }
         Rule::remove_relations_op => {
             let rel = inner
                 .into_inner()
@@ -81,11 +222,121 @@ pub(crate) fn parse_sys(
 
             SysOp::RemoveRelation(rel)
         }
+        Rule::remove_partition_op => {
+            let mut src = inner.into_inner();
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let val = build_expr(src.next().unwrap(), param_pool)?.eval_to_const()?;
+            SysOp::RemovePartition(rel, val)
+        }
         Rule::list_relation_op => {
             let rels_p = inner.into_inner().next().unwrap();
             let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
             SysOp::ListRelation(rel)
         }
+        Rule::analyze_op => {
+            let rel_p = inner.into_inner().next().unwrap();
+            SysOp::AnalyzeRelation(Symbol::new(rel_p.as_str(), rel_p.extract_span()))
+        }
+        Rule::stats_op => {
+            let rel_p = inner.into_inner().next().unwrap();
+            SysOp::ShowStats(Symbol::new(rel_p.as_str(), rel_p.extract_span()))
+        }
+        Rule::relation_scan_op => {
+            let mut src = inner.into_inner();
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let mut bounds = [0usize; 2];
+            for (bound, name) in bounds.iter_mut().zip(["offset", "limit"]) {
+                let p = src.next().unwrap();
+                let span = p.extract_span();
+                let val = build_expr(p, param_pool)?.eval_to_const()?;
+                *bound = match val.get_int() {
+                    Some(i) if i >= 0 => i as usize,
+                    _ => bail!(ScanBoundError(name, val.to_string(), span)),
+                };
+            }
+            SysOp::ScanRelation(rel, bounds[0], bounds[1])
+        }
+        Rule::sample_entities_op => {
+            let mut src = inner.into_inner().peekable();
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let mut filters = vec![];
+            if let Some(filters_p) = src.next_if(|p| p.as_rule() == Rule::sample_filters) {
+                for filter_p in filters_p.into_inner() {
+                    let mut filter_p = filter_p.into_inner();
+                    let col_p = filter_p.next().unwrap();
+                    let col = Symbol::new(col_p.as_str(), col_p.extract_span());
+                    let val = build_expr(filter_p.next().unwrap(), param_pool)?.eval_to_const()?;
+                    filters.push((col, val));
+                }
+            }
+            let mut args = [0u64; 2];
+            for (arg, name) in args.iter_mut().zip(["size", "seed"]) {
+                let p = src.next().unwrap();
+                let span = p.extract_span();
+                let val = build_expr(p, param_pool)?.eval_to_const()?;
+                *arg = match val.get_int() {
+                    Some(i) if i >= 0 => i as u64,
+                    _ => bail!(SampleArgError(name, val.to_string(), span)),
+                };
+            }
+            SysOp::SampleEntities(rel, filters, args[0] as usize, args[1])
+        }
+        Rule::history_op => {
+            let mut src = inner.into_inner();
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let key_p = src.next().unwrap();
+            let span = key_p.extract_span();
+            let key = match build_expr(key_p, param_pool)?.eval_to_const()? {
+                DataValue::List(l) => l,
+                v => bail!(HistoryKeyError(v.to_string(), span)),
+            };
+            SysOp::History(rel, key, span)
+        }
+        Rule::export_relation_op | Rule::import_relation_op => {
+            let is_export = inner.as_rule() == Rule::export_relation_op;
+            let mut src = inner.into_inner();
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let path_p = src.next().unwrap();
+            let span = path_p.extract_span();
+            let path = match build_expr(path_p, param_pool)?.eval_to_const()? {
+                DataValue::Str(s) => s.to_string(),
+                v => bail!(FilePathError(v.to_string(), span)),
+            };
+            if is_export {
+                SysOp::ExportRelation(rel, path)
+            } else {
+                SysOp::ImportRelation(rel, path)
+            }
+        }
+        Rule::push_op => {
+            let mut src = inner.into_inner();
+            let rel_p = src.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let engine_p = src.next().unwrap();
+            let span = engine_p.extract_span();
+            let engine = match build_expr(engine_p, param_pool)?.eval_to_const()? {
+                DataValue::Str(s) => s.to_string(),
+                v => bail!(EngineNameError(v.to_string(), span)),
+            };
+            let path_p = src.next().unwrap();
+            let span = path_p.extract_span();
+            let path = match build_expr(path_p, param_pool)?.eval_to_const()? {
+                DataValue::Str(s) => s.to_string(),
+                v => bail!(FilePathError(v.to_string(), span)),
+            };
+            let prog = parse_query(
+                src.next().unwrap().into_inner(),
+                param_pool,
+                algorithms,
+                cur_vld,
+            )?;
+            SysOp::Push(rel, engine, path, Box::new(prog))
+        }
         Rule::rename_relations_op => {
             let rename_pairs = inner
                 .into_inner()
@@ -116,6 +367,69 @@ pub(crate) fn parse_sys(
             }
             SysOp::SetAccessLevel(rels, access_level)
         }
+        Rule::relation_kind_op => {
+            let mut ps = inner.into_inner();
+            let append_only = match ps.next().unwrap().as_str() {
+                "normal" => false,
+                "append_only" => true,
+                _ => unreachable!(),
+            };
+            let mut rels = vec![];
+            for rel_p in ps {
+                let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+                rels.push(rel)
+            }
+            SysOp::SetAppendOnly(rels, append_only)
+        }
+        Rule::set_ttl_op => {
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let col = ps.next().map(|p| Symbol::new(p.as_str(), p.extract_span()));
+            SysOp::SetTtl(rel, col)
+        }
+        Rule::deprecate_op | Rule::undeprecate_op => {
+            let deprecate = inner.as_rule() == Rule::deprecate_op;
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let col_p = ps.next().unwrap();
+            let col = Symbol::new(col_p.as_str(), col_p.extract_span());
+            let replacement = ps.next().map(|p| Symbol::new(p.as_str(), p.extract_span()));
+            SysOp::SetDeprecated(rel, col, deprecate, replacement)
+        }
+        Rule::schedule_visibility_op | Rule::unschedule_visibility_op => {
+            let scheduled = inner.as_rule() == Rule::schedule_visibility_op;
+            let rel_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            SysOp::SetScheduled(rel, scheduled)
+        }
+        Rule::valid_time_op => {
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let cols = ps
+                .map(|p| Symbol::new(p.as_str(), p.extract_span()))
+                .collect_tuple();
+            SysOp::SetValidTime(rel, cols)
+        }
+        Rule::derive_op | Rule::underive_op => {
+            let mut ps = inner.into_inner();
+            let rel_p = ps.next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            let attr_p = ps.next().unwrap();
+            let attr = Symbol::new(attr_p.as_str(), attr_p.extract_span());
+            let expr = match ps.next() {
+                None => None,
+                Some(p) => Some(build_expr(p, param_pool)?),
+            };
+            SysOp::SetDerived(rel, attr, expr)
+        }
+        Rule::sweep_expired_op => {
+            let rel_p = inner.into_inner().next().unwrap();
+            let rel = Symbol::new(rel_p.as_str(), rel_p.extract_span());
+            SysOp::SweepExpired(rel)
+        }
         Rule::trigger_relation_show_op => {
             let rels_p = inner.into_inner().next().unwrap();
             let rel = Symbol::new(rels_p.as_str(), rels_p.extract_span());
@@ -156,9 +470,34 @@ pub(crate) fn parse_sys(
                     let mut inner = inner.into_inner();
                     let rel = inner.next().unwrap();
                     let name = inner.next().unwrap();
-                    let cols = inner
-                        .map(|p| Symbol::new(p.as_str(), p.extract_span()))
-                        .collect_vec();
+                    let mut cols = vec![];
+                    let mut collations = vec![];
+                    let mut filter = None;
+                    for p in inner {
+                        match p.as_rule() {
+                            Rule::index_filter => {
+                                filter = Some(build_expr(
+                                    p.into_inner().next().unwrap(),
+                                    param_pool,
+                                )?)
+                            }
+                            _ => {
+                                let mut col_p = p.into_inner();
+                                let name_p = col_p.next().unwrap();
+                                if let Some(coll_p) = col_p.next() {
+                                    let collation = Collation::from_name(coll_p.as_str())
+                                        .ok_or_else(|| {
+                                            UnknownCollation(
+                                                coll_p.as_str().to_string(),
+                                                coll_p.extract_span(),
+                                            )
+                                        })?;
+                                    collations.push((cols.len(), collation));
+                                }
+                                cols.push(Symbol::new(name_p.as_str(), name_p.extract_span()));
+                            }
+                        }
+                    }
 
                     #[derive(Debug, Diagnostic, Error)]
                     #[error("index must have at least one column specified")]
@@ -170,6 +509,8 @@ pub(crate) fn parse_sys(
                         Symbol::new(rel.as_str(), rel.extract_span()),
                         Symbol::new(name.as_str(), name.extract_span()),
                         cols,
+                        collations,
+                        filter,
                     )
                 }
                 Rule::index_drop => {
@@ -185,6 +526,6 @@ pub(crate) fn parse_sys(
             }
         }
         Rule::list_fixed_rules => SysOp::ListFixedRules,
-        rule => unreachable!("{:?}", rule),
+        r => unreachable!("{:?}", r),
     })
 }
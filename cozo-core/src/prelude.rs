@@ -0,0 +1,44 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! The types needed to embed CozoDB, gathered in one place:
+//!
+//! ```
+//! use std::collections::BTreeMap;
+//!
+//! use cozo::prelude::*;
+//!
+//! let db = DbInstance::new("mem", "", "").unwrap();
+//! let params = BTreeMap::from([("n".to_string(), DataValue::from(2))]);
+//! let result: NamedRows = db.run_script("?[a] := a in [1, $n]", params).unwrap();
+//! assert_eq!(result.rows, vec![vec![DataValue::from(1)], vec![DataValue::from(2)]]);
+//! ```
+//!
+//! The items re-exported here follow semantic versioning: they are only removed or changed
+//! incompatibly in a new major version. None of them requires touching the internals of the
+//! query engine, e.g. values are passed as [DataValue] and results come back as [NamedRows].
+//! Other items at the root of the crate, such as [Expr](crate::Expr),
+//! [Symbol](crate::Symbol) and the [FixedRule](crate::FixedRule) trait built on them,
+//! expose those internals and may change with any release.
+
+pub use crate::data::value::{DataValue, Num, RegexWrapper, UuidWrapper, Validity, ValidityTs};
+pub use crate::fixed_rule::SimpleFixedRule;
+pub use crate::parse::format::format_script;
+pub use crate::runtime::callback::CallbackOp;
+pub use crate::runtime::db::{CancelToken, Db, NamedRows};
+pub use crate::runtime::metrics::{DbMetrics, LatencyHistogram};
+pub use crate::storage::mem::{new_cozo_mem, MemStorage};
+#[cfg(feature = "storage-rocksdb")]
+pub use crate::storage::rocks::{new_cozo_rocksdb, RocksDbStorage};
+#[cfg(feature = "storage-sled")]
+pub use crate::storage::sled::{new_cozo_sled, SledStorage};
+#[cfg(feature = "storage-sqlite")]
+pub use crate::storage::sqlite::{new_cozo_sqlite, SqliteStorage};
+#[cfg(feature = "storage-tikv")]
+pub use crate::storage::tikv::{new_cozo_tikv, TiKvStorage};
+pub use crate::{format_error_as_json, DbInstance, Error, MultiTransaction};
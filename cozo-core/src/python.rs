@@ -0,0 +1,408 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+//! Python classes wrapping the database, enabled by the `python` feature.
+//!
+//! These are the classes of the `cozo_embedded` extension module built by `cozo-lib-python`.
+//! Call [add_to_module] from a `#[pymodule]` function to expose them.
+
+use std::collections::BTreeMap;
+use std::thread;
+
+use miette::{IntoDiagnostic, Report, Result};
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict, PyList, PyString, PyTuple};
+
+use crate::data::tuple::Tuple;
+use crate::data::value::{DataValue, Num};
+use crate::{format_error_as_json, DbInstance, MultiTransaction, NamedRows, SimpleFixedRule};
+
+/// Add the `CozoDbPy`, `CozoDbMulTx` and `Rows` classes to the module `m`.
+pub fn add_to_module(m: &PyModule) -> PyResult<()> {
+    m.add_class::<CozoDbPy>()?;
+    m.add_class::<CozoDbMulTx>()?;
+    m.add_class::<PyRows>()?;
+    Ok(())
+}
+
+/// A database instance.
+#[pyclass]
+pub struct CozoDbPy {
+    db: Option<DbInstance>,
+}
+
+/// A multi-statement transaction.
+#[pyclass]
+pub struct CozoDbMulTx {
+    tx: MultiTransaction,
+}
+
+const DB_CLOSED_MSG: &str = r##"{"ok":false,"message":"database closed"}"##;
+
+#[pymethods]
+impl CozoDbPy {
+    #[new]
+    #[args(engine = "\"mem\"", path = "\"\"", options = "\"{}\"")]
+    fn new(engine: &str, path: &str, options: &str) -> PyResult<Self> {
+        match DbInstance::new(engine, path, options) {
+            Ok(db) => Ok(Self { db: Some(db) }),
+            Err(err) => Err(PyException::new_err(format!("{err:?}"))),
+        }
+    }
+    /// Run a script, with `params` bound to the `$` parameters of the script.
+    #[args(params = "None")]
+    pub fn run(&self, py: Python<'_>, script: &str, params: Option<&PyDict>) -> PyResult<PyRows> {
+        let db = self.db()?;
+        let params = match params {
+            None => BTreeMap::new(),
+            Some(params) => convert_params(params)?,
+        };
+        let res = py
+            .allow_threads(|| db.run_script(script, params))
+            .map_err(|err| report_to_py(err, script))?;
+        Ok(PyRows {
+            headers: res.headers,
+            rows: res.rows,
+            cursor: 0,
+        })
+    }
+    /// Run a script, returning the result as a dict of `headers`, `rows` and `next`.
+    pub fn run_script(&self, py: Python<'_>, query: &str, params: &PyDict) -> PyResult<PyObject> {
+        let db = self.db()?;
+        let params = convert_params(params)?;
+        match py.allow_threads(|| db.run_script(query, params)) {
+            Ok(rows) => Ok(named_rows_to_py(rows, py)),
+            Err(err) => Err(report_to_py_json(err, query, py)),
+        }
+    }
+    /// Call `callback` with the operation, new rows and old rows on every change to `rel`.
+    pub fn register_callback(&self, rel: &str, callback: &PyAny) -> PyResult<u32> {
+        let db = self.db()?;
+        let cb: Py<PyAny> = callback.into();
+        let (id, ch) = db.register_callback(rel, None);
+        thread::spawn(move || {
+            for (op, new, old) in ch {
+                Python::with_gil(|py| {
+                    let op = PyString::new(py, op.as_str()).into();
+                    let new_py = rows_to_py_rows(&new.rows, py);
+                    let old_py = rows_to_py_rows(&old.rows, py);
+                    let args = PyTuple::new(py, [op, new_py, old_py]);
+                    let callable = cb.as_ref(py);
+                    if let Err(err) = callable.call1(args) {
+                        eprintln!("{}", err);
+                    }
+                })
+            }
+        });
+        Ok(id)
+    }
+    /// Register `callback` as a fixed rule named `name`, returning rows of `arity` columns.
+    pub fn register_fixed_rule(
+        &self,
+        name: String,
+        arity: usize,
+        callback: &PyAny,
+    ) -> PyResult<()> {
+        let db = self.db()?;
+        let cb: Py<PyAny> = callback.into();
+        let rule_impl = SimpleFixedRule::new(arity, move |inputs, options| -> Result<_> {
+            Python::with_gil(|py| -> Result<NamedRows> {
+                let py_inputs =
+                    PyList::new(py, inputs.iter().map(|nr| rows_to_py_rows(&nr.rows, py)));
+                let py_opts = options_to_py(options, py).into_diagnostic()?;
+                let args = PyTuple::new(py, vec![PyObject::from(py_inputs), py_opts]);
+                let res = cb.as_ref(py).call1(args).into_diagnostic()?;
+                Ok(NamedRows::new(vec![], py_to_rows(res).into_diagnostic()?))
+            })
+        });
+        db.register_fixed_rule(name, rule_impl).map_err(report2py)
+    }
+    /// Unregister a callback by the ID returned from `register_callback`.
+    pub fn unregister_callback(&self, id: u32) -> bool {
+        match &self.db {
+            Some(db) => db.unregister_callback(id),
+            None => false,
+        }
+    }
+    /// Unregister a fixed rule, returning whether it existed.
+    pub fn unregister_fixed_rule(&self, name: &str) -> PyResult<bool> {
+        match &self.db {
+            Some(db) => db.unregister_fixed_rule(name).map_err(report2py),
+            None => Ok(false),
+        }
+    }
+    /// Export the named relations, as a dict from relation name to rows.
+    pub fn export_relations(&self, py: Python<'_>, relations: Vec<String>) -> PyResult<PyObject> {
+        let db = self.db()?;
+        let res = py
+            .allow_threads(|| db.export_relations(relations.iter()))
+            .map_err(report2py)?;
+        let ret = PyDict::new(py);
+        for (k, v) in res {
+            ret.set_item(k, named_rows_to_py(v, py))?;
+        }
+        Ok(ret.into())
+    }
+    /// Import rows into stored relations, from a dict of relation name to rows.
+    pub fn import_relations(&self, py: Python<'_>, data: &PyDict) -> PyResult<()> {
+        let db = self.db()?;
+        let mut arg = BTreeMap::new();
+        for (k, v) in data.iter() {
+            let k = k.extract::<String>()?;
+            let vals = py_to_named_rows(v)?;
+            arg.insert(k, vals);
+        }
+        py.allow_threads(|| db.import_relations(arg))
+            .map_err(report2py)
+    }
+    /// Back up the database to a SQLite file at `path`.
+    pub fn backup(&self, py: Python<'_>, path: &str) -> PyResult<()> {
+        let db = self.db()?;
+        py.allow_threads(|| db.backup_db(path)).map_err(report2py)
+    }
+    /// Restore the database from a backup file at `path`.
+    pub fn restore(&self, py: Python<'_>, path: &str) -> PyResult<()> {
+        let db = self.db()?;
+        py.allow_threads(|| db.restore_backup(path))
+            .map_err(report2py)
+    }
+    /// Import the named relations from a backup file.
+    pub fn import_from_backup(
+        &self,
+        py: Python<'_>,
+        in_file: &str,
+        relations: Vec<String>,
+    ) -> PyResult<()> {
+        let db = self.db()?;
+        py.allow_threads(|| db.import_from_backup(in_file, &relations))
+            .map_err(report2py)
+    }
+    /// Close the database, returning whether it was open.
+    pub fn close(&mut self) -> bool {
+        self.db.take().is_some()
+    }
+    /// Start a multi-statement transaction.
+    pub fn multi_transact(&self, write: bool) -> PyResult<CozoDbMulTx> {
+        let db = self.db()?;
+        Ok(CozoDbMulTx {
+            tx: db.multi_transaction(write),
+        })
+    }
+}
+
+impl CozoDbPy {
+    fn db(&self) -> PyResult<&DbInstance> {
+        self.db
+            .as_ref()
+            .ok_or_else(|| PyException::new_err(DB_CLOSED_MSG))
+    }
+}
+
+#[pymethods]
+impl CozoDbMulTx {
+    /// Abort the transaction.
+    pub fn abort(&self) -> PyResult<()> {
+        self.tx.abort().map_err(report2py)
+    }
+    /// Commit the transaction.
+    pub fn commit(&self) -> PyResult<()> {
+        self.tx.commit().map_err(report2py)
+    }
+    /// Run a script in the transaction.
+    pub fn run_script(&self, py: Python<'_>, query: &str, params: &PyDict) -> PyResult<PyObject> {
+        let params = convert_params(params)?;
+        match py.allow_threads(|| self.tx.run_script(query, params)) {
+            Ok(rows) => Ok(named_rows_to_py(rows, py)),
+            Err(err) => Err(report_to_py_json(err, query, py)),
+        }
+    }
+}
+
+/// The result of running a script.
+///
+/// Iterating over it yields the rows as tuples, converted to Python values one at a time.
+/// The rows can also be extracted column-wise, in a form that `numpy.array` or
+/// `pandas.DataFrame` accept directly.
+#[pyclass(name = "Rows")]
+pub struct PyRows {
+    headers: Vec<String>,
+    rows: Vec<Tuple>,
+    cursor: usize,
+}
+
+#[pymethods]
+impl PyRows {
+    #[getter]
+    fn headers(&self) -> Vec<String> {
+        self.headers.clone()
+    }
+
+    fn __len__(&self) -> usize {
+        self.rows.len()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> Option<PyObject> {
+        let row = self.rows.get(self.cursor)?;
+        self.cursor += 1;
+        let row = row.iter().map(|v| value_to_py(v, py)).collect::<Vec<_>>();
+        Some(PyTuple::new(py, row).into())
+    }
+
+    /// The values of the column named `name`, as a list.
+    fn column(&self, py: Python<'_>, name: &str) -> PyResult<PyObject> {
+        let idx = self
+            .headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| PyException::new_err(format!("no column named '{name}'")))?;
+        Ok(self.column_at(py, idx))
+    }
+
+    /// All columns, as a dict from header to the list of values.
+    fn columns(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let ret = PyDict::new(py);
+        for (idx, name) in self.headers.iter().enumerate() {
+            ret.set_item(name, self.column_at(py, idx))?;
+        }
+        Ok(ret.into())
+    }
+}
+
+impl PyRows {
+    fn column_at(&self, py: Python<'_>, idx: usize) -> PyObject {
+        PyList::new(py, self.rows.iter().map(|row| value_to_py(&row[idx], py))).into()
+    }
+}
+
+fn report_to_py(err: Report, script: &str) -> PyErr {
+    let report = format_error_as_json(err, Some(script));
+    PyException::new_err(report.to_string())
+}
+
+/// The error report as a Python dict, parsed from its JSON form.
+fn report_to_py_json(err: Report, script: &str, py: Python<'_>) -> PyErr {
+    let report = format_error_as_json(err, Some(script)).to_string();
+    let parsed = py
+        .import("json")
+        .and_then(|json_mod| json_mod.getattr("loads")?.call1((report.as_str(),)));
+    match parsed {
+        Ok(msg) => PyException::new_err(PyObject::from(msg)),
+        Err(err) => err,
+    }
+}
+
+fn report2py(r: Report) -> PyErr {
+    PyException::new_err(r.to_string())
+}
+
+fn convert_params(ob: &PyDict) -> PyResult<BTreeMap<String, DataValue>> {
+    let mut ret = BTreeMap::new();
+    for (k, v) in ob {
+        let k: String = k.extract()?;
+        ret.insert(k, py_to_value(v)?);
+    }
+    Ok(ret)
+}
+
+fn py_to_value(ob: &PyAny) -> PyResult<DataValue> {
+    Ok(if ob.is_none() {
+        DataValue::Null
+    } else if let Ok(b) = ob.downcast::<pyo3::types::PyBool>() {
+        DataValue::Bool(b.is_true())
+    } else if let Ok(i) = ob.extract::<i64>() {
+        DataValue::from(i)
+    } else if let Ok(f) = ob.extract::<f64>() {
+        DataValue::from(f)
+    } else if let Ok(s) = ob.downcast::<PyString>() {
+        DataValue::from(s.to_str()?)
+    } else if let Ok(b) = ob.downcast::<PyBytes>() {
+        DataValue::Bytes(b.as_bytes().to_vec())
+    } else if let Ok(d) = ob.downcast::<PyDict>() {
+        let mut coll = Vec::with_capacity(d.len());
+        for (k, v) in d {
+            coll.push(DataValue::List(vec![py_to_value(k)?, py_to_value(v)?]))
+        }
+        DataValue::List(coll)
+    } else if let Ok(l) = ob.extract::<Vec<&PyAny>>() {
+        DataValue::List(l.into_iter().map(py_to_value).collect::<PyResult<_>>()?)
+    } else {
+        return Err(PyException::new_err(format!(
+            "Cannot convert {ob} into Cozo value"
+        )));
+    })
+}
+
+fn value_to_py(val: &DataValue, py: Python<'_>) -> PyObject {
+    match val {
+        DataValue::Null | DataValue::Bot => py.None(),
+        DataValue::Bool(b) => b.into_py(py),
+        DataValue::Num(Num::Int(i)) => i.into_py(py),
+        DataValue::Num(Num::Float(f)) => f.into_py(py),
+        DataValue::Str(s) => s.as_str().into_py(py),
+        DataValue::Bytes(b) => PyBytes::new(py, b).into(),
+        DataValue::Uuid(uuid) => uuid.0.to_string().into_py(py),
+        DataValue::Regex(rx) => rx.0.as_str().into_py(py),
+        DataValue::List(l) => PyList::new(py, l.iter().map(|v| value_to_py(v, py))).into(),
+        DataValue::Set(s) => PyList::new(py, s.iter().map(|v| value_to_py(v, py))).into(),
+        DataValue::Validity(vld) => (vld.timestamp.0 .0, vld.is_assert.0).into_py(py),
+        DataValue::BigInt(i) => i.0.to_string().into_py(py),
+        DataValue::Decimal(d) => d.to_string().into_py(py),
+    }
+}
+
+fn py_to_rows(ob: &PyAny) -> PyResult<Vec<Vec<DataValue>>> {
+    let rows = ob.extract::<Vec<Vec<&PyAny>>>()?;
+    rows.into_iter()
+        .map(|row| row.into_iter().map(py_to_value).collect::<PyResult<_>>())
+        .collect()
+}
+
+fn py_to_named_rows(ob: &PyAny) -> PyResult<NamedRows> {
+    let d = ob.downcast::<PyDict>()?;
+    let rows = d
+        .get_item("rows")
+        .ok_or_else(|| PyException::new_err("named rows must contain 'rows'"))?;
+    let rows = py_to_rows(rows)?;
+    let headers = d
+        .get_item("headers")
+        .ok_or_else(|| PyException::new_err("named rows must contain 'headers'"))?;
+    let headers = headers.extract::<Vec<String>>()?;
+    Ok(NamedRows::new(headers, rows))
+}
+
+fn options_to_py(opts: BTreeMap<String, DataValue>, py: Python<'_>) -> PyResult<PyObject> {
+    let ret = PyDict::new(py);
+    for (k, v) in opts {
+        ret.set_item(k, value_to_py(&v, py))?;
+    }
+    Ok(ret.into())
+}
+
+fn rows_to_py_rows(rows: &[Tuple], py: Python<'_>) -> PyObject {
+    PyList::new(
+        py,
+        rows.iter()
+            .map(|row| PyList::new(py, row.iter().map(|v| value_to_py(v, py)))),
+    )
+    .into()
+}
+
+fn named_rows_to_py(named_rows: NamedRows, py: Python<'_>) -> PyObject {
+    let rows = rows_to_py_rows(&named_rows.rows, py);
+    let headers = named_rows.headers.into_py(py);
+    let next = match named_rows.next {
+        None => py.None(),
+        Some(nxt) => named_rows_to_py(*nxt, py),
+    };
+    BTreeMap::from([("rows", rows), ("headers", headers), ("next", next)]).into_py(py)
+}
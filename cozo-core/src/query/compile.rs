@@ -15,14 +15,16 @@ use thiserror::Error;
 use crate::data::aggr::Aggregation;
 use crate::data::expr::Expr;
 use crate::data::program::{
-    MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRulesOrFixed, MagicSymbol,
-    StratifiedMagicProgram,
+    IndexHint, MagicAtom, MagicFixedRuleApply, MagicInlineRule, MagicRelationApplyAtom,
+    MagicRulesOrFixed, MagicSymbol, SortDir, StratifiedMagicProgram,
 };
 use crate::data::symb::Symbol;
 use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
 use crate::query::ra::RelAlgebra;
-use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel};
+use crate::runtime::relation::{
+    AccessLevel, BadIndexHint, InsufficientAccessLevel, RelationHandle,
+};
 use crate::runtime::transact::SessionTx;
 
 pub(crate) type CompiledProgram = BTreeMap<MagicSymbol, CompiledRuleSet>;
@@ -95,10 +97,120 @@ struct RuleNotFound(String, #[label] SourceSpan);
 #[diagnostic(help("Required arity: {1}, number of arguments given: {2}"))]
 struct ArityMismatch(String, usize, usize, #[label] SourceSpan);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation {name} has arity {arity}, but is applied to {given} arguments")]
+#[diagnostic(code(eval::rule_arity_mismatch))]
+#[diagnostic(help("The columns of {name} are: {columns}"))]
+struct StoredRelArityMismatch {
+    name: String,
+    arity: usize,
+    given: usize,
+    columns: String,
+    #[label]
+    span: SourceSpan,
+}
+
+impl StoredRelArityMismatch {
+    fn new(store: &RelationHandle, given: usize, span: SourceSpan) -> Self {
+        let columns = store
+            .metadata
+            .keys
+            .iter()
+            .chain(store.metadata.non_keys.iter())
+            .map(|col| format!("{}: {}", col.name, col.typing))
+            .join(", ");
+        Self {
+            name: store.name.to_string(),
+            arity: store.arity(),
+            given,
+            columns,
+            span,
+        }
+    }
+}
+
+/// Whether the entry rule produces its tuples already ordered by `sorters`, because it
+/// scans a single stored relation or index whose leading key columns are the sorted ones.
+pub(crate) fn entry_in_storage_order(
+    strata: &[CompiledProgram],
+    sorters: &[(Symbol, SortDir)],
+) -> bool {
+    if sorters.is_empty() || sorters.iter().any(|(_, dir)| *dir != SortDir::Asc) {
+        return false;
+    }
+    let rules = match strata
+        .iter()
+        .flat_map(|prog| prog.iter())
+        .find(|(name, _)| name.is_prog_entry())
+    {
+        Some((_, CompiledRuleSet::Rules(rules))) => rules,
+        _ => return false,
+    };
+    if rules.len() != 1 || rules[0].aggr.iter().any(|a| a.is_some()) {
+        return false;
+    }
+    match rules[0].relation.storage_order() {
+        None => false,
+        Some(order) => {
+            order.len() >= sorters.len()
+                && sorters
+                    .iter()
+                    .zip(order.iter())
+                    .all(|((sorter, _), key)| sorter == key)
+        }
+    }
+}
+
+/// The index to scan for a stored relation atom: the one named by its `@use_index` hint,
+/// none with `@no_index`, and otherwise the best one found by [RelationHandle::choose_index]
+fn index_for_atom(
+    store: &RelationHandle,
+    rel_app: &MagicRelationApplyAtom,
+    arg_uses: &[IndexPositionUse],
+    validity_query: bool,
+    facts: &BTreeSet<String>,
+) -> Result<Option<(RelationHandle, Vec<usize>, bool)>> {
+    let filter_holds = |filter: &Expr| {
+        let mut filter = filter.clone();
+        filter.rebind_by_position(&rel_app.args);
+        facts.contains(&filter.to_string())
+    };
+    // the expiry column of a relation with a TTL is read to skip expired rows,
+    // so an index without it is joined with the relation
+    // the same goes for the sequence column of rows skipped for `:since_last`
+    let mut arg_uses = arg_uses.to_vec();
+    for col in store.ttl_col.iter().chain(store.new_after.iter().map(|(col, _)| col)) {
+        if arg_uses[*col] == IndexPositionUse::Ignored {
+            arg_uses[*col] = IndexPositionUse::BindForLater;
+        }
+    }
+    let chosen = match &rel_app.hint {
+        None => store.choose_index(&arg_uses, validity_query, filter_holds),
+        Some(IndexHint::NoIndex) => None,
+        Some(IndexHint::UseIndex(idx)) => {
+            Some(store.hinted_index(idx, &arg_uses, validity_query, filter_holds)?)
+        }
+    };
+    Ok(chosen.map(|(mut index, mapper, need_join)| {
+        index.ttl_col = store
+            .ttl_col
+            .and_then(|col| mapper.iter().position(|i| *i == col));
+        index.new_after = store.new_after.and_then(|(col, last)| {
+            mapper
+                .iter()
+                .position(|i| *i == col)
+                .map(|pos| (pos, last))
+        });
+        (index, mapper, need_join)
+    }))
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum IndexPositionUse {
     Join,
     BindForLater,
+    /// Bound later, but restricted by a filter that can become scan bounds
+    Range,
     Ignored,
 }
 
@@ -170,6 +282,37 @@ impl<'a> SessionTx<'a> {
             serial_id += 1;
             ret
         };
+        // conditions holding for the whole body, for choosing partial indices
+        let facts: BTreeSet<String> = rule
+            .body
+            .iter()
+            .flat_map(|atom| match atom {
+                MagicAtom::Predicate(p) => p.to_conjunction(),
+                MagicAtom::Unification(u) if !u.one_many_unif => vec![Expr::build_equate(
+                    vec![
+                        Expr::Binding {
+                            var: u.binding.clone(),
+                            tuple_pos: None,
+                        },
+                        u.expr.clone(),
+                    ],
+                    u.span,
+                )],
+                _ => vec![],
+            })
+            .map(|e| e.to_string())
+            .collect();
+        // filters that can bound a scan on the variable they restrict, for choosing indices
+        let mut range_filters: BTreeMap<Symbol, Vec<Expr>> = BTreeMap::new();
+        for atom in &rule.body {
+            if let MagicAtom::Predicate(p) = atom {
+                for conj in p.to_conjunction() {
+                    if let Some(target) = conj.range_target() {
+                        range_filters.entry(target.clone()).or_default().push(conj);
+                    }
+                }
+            }
+        }
         for atom in &rule.body {
             match atom {
                 MagicAtom::Rule(rule_app) => {
@@ -211,7 +354,7 @@ impl<'a> SessionTx<'a> {
                     ret = ret.join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
                 }
                 MagicAtom::Relation(rel_app) => {
-                    let store = self.get_relation(&rel_app.name, false)?;
+                    let mut store = self.get_relation(&rel_app.name, false)?;
                     if store.access_level < AccessLevel::ReadOnly {
                         bail!(InsufficientAccessLevel(
                             store.name.to_string(),
@@ -219,14 +362,10 @@ impl<'a> SessionTx<'a> {
                             store.access_level
                         ));
                     }
+                    self.skip_seen_rows(&mut store)?;
                     ensure!(
                         store.arity() == rel_app.args.len(),
-                        ArityMismatch(
-                            rel_app.name.to_string(),
-                            store.arity(),
-                            rel_app.args.len(),
-                            rel_app.span
-                        )
+                        StoredRelArityMismatch::new(&store, rel_app.args.len(), rel_app.span)
                     );
                     // already existing vars
                     let mut prev_joiner_vars = vec![];
@@ -252,24 +391,26 @@ impl<'a> SessionTx<'a> {
                             right_vars.push(var.clone());
                             if var.is_generated_ignored_symbol() {
                                 join_indices.push(IndexPositionUse::Ignored)
+                            } else if range_filters.contains_key(var) {
+                                join_indices.push(IndexPositionUse::Range)
                             } else {
                                 join_indices.push(IndexPositionUse::BindForLater)
                             }
                         }
                     }
 
+                    let valid_at = rel_app
+                        .valid_at
+                        .or_else(|| store.default_valid_at(self.default_validity));
                     let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                        index_for_atom(&store, rel_app, &join_indices, valid_at.is_some(), &facts)?;
 
                     match chosen_index {
                         None => {
                             // scan original relation
-                            let right = RelAlgebra::relation(
-                                right_vars,
-                                store,
-                                rel_app.span,
-                                rel_app.valid_at,
-                            )?;
+                            let right =
+                                RelAlgebra::relation(right_vars, store, rel_app.span, valid_at)?
+                                    .with_hint(rel_app.hint.clone());
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret =
                                 ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
@@ -284,8 +425,9 @@ impl<'a> SessionTx<'a> {
                                 new_right_vars,
                                 chosen_index,
                                 rel_app.span,
-                                rel_app.valid_at,
-                            )?;
+                                valid_at,
+                            )?
+                            .with_hint(rel_app.hint.clone());
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret =
                                 ret.join(right, prev_joiner_vars, right_joiner_vars, rel_app.span);
@@ -295,12 +437,22 @@ impl<'a> SessionTx<'a> {
                             let mut prev_joiner_first_vars = vec![];
                             let mut middle_joiner_left_vars = vec![];
                             let mut middle_vars = vec![];
+                            // copies of the range filters for scanning the index
+                            let mut middle_filters = vec![];
                             for i in mapper.iter() {
                                 let tv = gen_symb(right_vars[*i].span);
                                 if let Some(j) = right_joiner_vars_pos.iter().position(|el| el == i)
                                 {
                                     prev_joiner_first_vars.push(prev_joiner_vars[j].clone());
                                     middle_joiner_left_vars.push(tv.clone());
+                                } else if let Some(filters) = range_filters.get(&right_vars[*i]) {
+                                    let renames =
+                                        BTreeMap::from([(right_vars[*i].clone(), tv.clone())]);
+                                    for filter in filters {
+                                        let mut filter = filter.clone();
+                                        filter.rename_bindings(&renames);
+                                        middle_filters.push(filter);
+                                    }
                                 }
                                 middle_vars.push(tv);
                             }
@@ -316,29 +468,33 @@ impl<'a> SessionTx<'a> {
                                 })
                                 .collect_vec();
 
+                            // the key columns of the original relation, in the same order
+                            // as `middle_joiner_right_vars`
                             let mut final_joiner_vars = vec![];
                             for idx in mapper.iter() {
-                                final_joiner_vars.push(right_vars[*idx].clone());
+                                if *idx < store.metadata.keys.len() {
+                                    final_joiner_vars.push(right_vars[*idx].clone());
+                                }
                             }
 
-                            let middle = RelAlgebra::relation(
+                            let mut middle = RelAlgebra::relation(
                                 middle_vars,
                                 chosen_index,
                                 rel_app.span,
-                                rel_app.valid_at,
-                            )?;
+                                valid_at,
+                            )?
+                            .with_hint(rel_app.hint.clone());
+                            for filter in middle_filters {
+                                middle = middle.filter(filter);
+                            }
                             ret = ret.join(
                                 middle,
                                 prev_joiner_first_vars,
                                 middle_joiner_left_vars,
                                 rel_app.span,
                             );
-                            let final_alg = RelAlgebra::relation(
-                                right_vars,
-                                store,
-                                rel_app.span,
-                                rel_app.valid_at,
-                            )?;
+                            let final_alg =
+                                RelAlgebra::relation(right_vars, store, rel_app.span, valid_at)?;
                             ret = ret.join(
                                 final_alg,
                                 middle_joiner_right_vars,
@@ -348,7 +504,8 @@ impl<'a> SessionTx<'a> {
                         }
                     }
                 }
-                MagicAtom::NegatedRule(rule_app) => {
+                MagicAtom::NegatedRule(rule_app) | MagicAtom::ExistsRule(rule_app) => {
+                    let semi = matches!(atom, MagicAtom::ExistsRule(_));
                     let store_arity = store_arities.get(&rule_app.name).ok_or_else(|| {
                         RuleNotFound(
                             rule_app.name.symbol().to_string(),
@@ -383,18 +540,20 @@ impl<'a> SessionTx<'a> {
                     let right =
                         RelAlgebra::derived(right_vars, rule_app.name.clone(), rule_app.span);
                     debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
-                    ret = ret.neg_join(right, prev_joiner_vars, right_joiner_vars, rule_app.span);
+                    ret = ret.neg_join(
+                        right,
+                        prev_joiner_vars,
+                        right_joiner_vars,
+                        semi,
+                        rule_app.span,
+                    );
                 }
-                MagicAtom::NegatedRelation(rel_app) => {
+                MagicAtom::NegatedRelation(rel_app) | MagicAtom::ExistsRelation(rel_app) => {
+                    let semi = matches!(atom, MagicAtom::ExistsRelation(_));
                     let store = self.get_relation(&rel_app.name, false)?;
                     ensure!(
                         store.arity() == rel_app.args.len(),
-                        ArityMismatch(
-                            rel_app.name.to_string(),
-                            store.arity(),
-                            rel_app.args.len(),
-                            rel_app.span
-                        )
+                        StoredRelArityMismatch::new(&store, rel_app.args.len(), rel_app.span)
                     );
 
                     // already existing vars
@@ -417,32 +576,48 @@ impl<'a> SessionTx<'a> {
                             right_joiner_vars_pos.push(i);
                             join_indices.push(IndexPositionUse::Join)
                         } else {
-                            seen_variables.insert(var.clone());
+                            // variables only appearing under `exists` are not bound by it
+                            if !semi {
+                                seen_variables.insert(var.clone());
+                            }
                             right_vars.push(var.clone());
                             if var.is_generated_ignored_symbol() {
                                 join_indices.push(IndexPositionUse::Ignored)
+                            } else if range_filters.contains_key(var) {
+                                join_indices.push(IndexPositionUse::Range)
                             } else {
                                 join_indices.push(IndexPositionUse::BindForLater)
                             }
                         }
                     }
 
+                    let valid_at = rel_app
+                        .valid_at
+                        .or_else(|| store.default_valid_at(self.default_validity));
                     let chosen_index =
-                        store.choose_index(&join_indices, rel_app.valid_at.is_some());
+                        index_for_atom(&store, rel_app, &join_indices, valid_at.is_some(), &facts)?;
+                    if let (Some(IndexHint::UseIndex(idx)), Some((_, _, true))) =
+                        (&rel_app.hint, &chosen_index)
+                    {
+                        bail!(BadIndexHint(
+                            idx.name.to_string(),
+                            store.name.to_string(),
+                            "the index does not hold all the columns of the atom".to_string(),
+                            idx.span
+                        ));
+                    }
 
                     match chosen_index {
                         None | Some((_, _, true)) => {
-                            let right = RelAlgebra::relation(
-                                right_vars,
-                                store,
-                                rel_app.span,
-                                rel_app.valid_at,
-                            )?;
+                            let right =
+                                RelAlgebra::relation(right_vars, store, rel_app.span, valid_at)?
+                                    .with_hint(rel_app.hint.clone());
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret = ret.neg_join(
                                 right,
                                 prev_joiner_vars,
                                 right_joiner_vars,
+                                semi,
                                 rel_app.span,
                             );
                         }
@@ -456,13 +631,15 @@ impl<'a> SessionTx<'a> {
                                 new_right_vars,
                                 chosen_index,
                                 rel_app.span,
-                                rel_app.valid_at,
-                            )?;
+                                valid_at,
+                            )?
+                            .with_hint(rel_app.hint.clone());
                             debug_assert_eq!(prev_joiner_vars.len(), right_joiner_vars.len());
                             ret = ret.neg_join(
                                 right,
                                 prev_joiner_vars,
                                 right_joiner_vars,
+                                semi,
                                 rel_app.span,
                             );
                         }
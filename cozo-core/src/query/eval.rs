@@ -7,14 +7,15 @@
  */
 
 use std::collections::btree_map::Entry;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use itertools::Itertools;
 use log::{debug, trace};
-use miette::Result;
+use miette::{bail, Diagnostic, Result};
 #[cfg(not(target_arch = "wasm32"))]
 use rayon::prelude::*;
+use thiserror::Error;
 
 use crate::data::aggr::Aggregation;
 use crate::data::program::{MagicSymbol, NoEntryError};
@@ -28,10 +29,34 @@ use crate::runtime::db::Poison;
 use crate::runtime::temp_store::{EpochStore, MeetAggrStore, RegularTempStore};
 use crate::runtime::transact::SessionTx;
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Rule {0} is still deriving new tuples after {1} iterations")]
+#[diagnostic(code(eval::max_depth_exceeded))]
+#[diagnostic(help(
+    "Raise the depth of @strict_max_depth, or use @max_depth to stop at the given depth and mark the result as truncated"
+))]
+struct MaxDepthExceeded(String, usize, #[label] SourceSpan);
+
+/// The maximum depth given to `rule_symb` by its annotation, and whether exceeding it is an error.
+/// Only the rule itself is limited, not the magic sets feeding bindings into it.
+fn max_depth_of(
+    max_depths: &BTreeMap<Symbol, (usize, bool)>,
+    rule_symb: &MagicSymbol,
+) -> Option<(usize, bool)> {
+    match rule_symb {
+        MagicSymbol::Muggle { inner } | MagicSymbol::Magic { inner, .. } => {
+            max_depths.get(inner).copied()
+        }
+        MagicSymbol::Input { .. } | MagicSymbol::Sup { .. } => None,
+    }
+}
+
 pub(crate) struct QueryLimiter {
     total: Option<usize>,
     skip: Option<usize>,
     counter: AtomicUsize,
+    /// set by `:partial_on_timeout`, the entry stops deriving tuples once the query times out
+    partial_on_timeout: bool,
 }
 
 impl QueryLimiter {
@@ -60,16 +85,26 @@ impl QueryLimiter {
 }
 
 impl<'a> SessionTx<'a> {
+    /// Returns the results of the entry and of the rules in `output_rules`,
+    /// whether the evaluation returned early, and whether it was cut short by a timeout,
+    /// which with `partial_on_timeout` leaves the entry with the tuples derived so far
     pub(crate) fn stratified_magic_evaluate(
         &self,
         strata: &[CompiledProgram],
         store_lifetimes: BTreeMap<MagicSymbol, usize>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
+        max_depths: &BTreeMap<Symbol, (usize, bool)>,
+        output_rules: &BTreeSet<Symbol>,
+        partial_on_timeout: bool,
         poison: Poison,
-    ) -> Result<(EpochStore, bool)> {
+    ) -> Result<(EpochStore, BTreeMap<Symbol, EpochStore>, bool, bool)> {
+        let entry_symbol = MagicSymbol::Muggle {
+            inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+        };
         let mut stores: BTreeMap<MagicSymbol, EpochStore> = BTreeMap::new();
         let mut early_return = false;
+        let mut truncated = false;
         for (stratum, cur_prog) in strata.iter().enumerate() {
             if stratum > 0 {
                 // remove stores that have outlived their usefulness!
@@ -93,41 +128,107 @@ impl<'a> SessionTx<'a> {
                 stores.insert(rule_name.clone(), store);
             }
             debug!("stratum {}", stratum);
-            early_return = self.semi_naive_magic_evaluate(
+            trace_span!("stratum", stratum, rules = cur_prog.len());
+            let res = self.semi_naive_magic_evaluate(
                 cur_prog,
                 &mut stores,
                 total_num_to_take,
                 num_to_skip,
+                max_depths,
+                partial_on_timeout,
                 poison.clone(),
-            )?;
+            );
+            if partial_on_timeout && poison.timed_out() {
+                // tuples derived from meet aggregations not yet at their final values
+                // may not be in the full result
+                if cur_prog
+                    .values()
+                    .any(|rule_set| matches!(rule_set.aggr_kind(), AggrKind::Meet))
+                {
+                    stores.remove(&entry_symbol);
+                }
+                truncated = true;
+                break;
+            }
+            let (stratum_early_return, depth_truncated) = res?;
+            early_return = stratum_early_return;
+            truncated |= depth_truncated;
+            trace_event!(
+                tuples = cur_prog
+                    .keys()
+                    .filter_map(|k| stores.get(k))
+                    .map(|s| s.all_iter().count())
+                    .sum::<usize>(),
+                "stratum evaluated"
+            );
         }
-        let entry_symbol = MagicSymbol::Muggle {
-            inner: Symbol::new(PROG_ENTRY, SourceSpan(0, 0)),
+        let ret_area = match stores.remove(&entry_symbol) {
+            Some(store) => store,
+            None if truncated => {
+                let arity = strata
+                    .iter()
+                    .find_map(|prog| prog.get(&entry_symbol))
+                    .ok_or(NoEntryError)?
+                    .arity();
+                EpochStore::new_normal(arity)
+            }
+            None => bail!(NoEntryError),
         };
-        let ret_area = stores.remove(&entry_symbol).ok_or(NoEntryError)?;
-        Ok((ret_area, early_return))
+        let mut outputs = BTreeMap::new();
+        for rule in output_rules {
+            let symb = MagicSymbol::Muggle {
+                inner: rule.clone(),
+            };
+            if let Some(store) = stores.remove(&symb) {
+                outputs.insert(rule.clone(), store);
+            }
+        }
+        Ok((ret_area, outputs, early_return, truncated))
     }
-    /// returns true if early return is activated
+    /// returns whether early return is activated, and whether a rule was stopped at its maximum depth
+    ///
+    /// Rules with a maximum depth stop once they have derived new tuples in that many iterations.
+    /// They are still evaluated afterwards, and if they would derive more, their results
+    /// are discarded and the query is marked as truncated, or if the depth is strict, it fails.
     fn semi_naive_magic_evaluate(
         &self,
         prog: &CompiledProgram,
         stores: &mut BTreeMap<MagicSymbol, EpochStore>,
         total_num_to_take: Option<usize>,
         num_to_skip: Option<usize>,
+        max_depths: &BTreeMap<Symbol, (usize, bool)>,
+        partial_on_timeout: bool,
         poison: Poison,
-    ) -> Result<bool> {
+    ) -> Result<(bool, bool)> {
         let limiter = QueryLimiter {
             total: total_num_to_take,
             skip: num_to_skip,
             counter: 0.into(),
+            partial_on_timeout,
         };
+        // rules past their maximum depth must not count towards the limit
+        let unlimited = QueryLimiter {
+            total: None,
+            skip: None,
+            counter: 0.into(),
+            partial_on_timeout,
+        };
+        let mut truncated = false;
 
         let used_limiter: AtomicBool = false.into();
+        let mut depths: BTreeMap<&MagicSymbol, usize> = BTreeMap::new();
 
         for epoch in 0u32.. {
             debug!("epoch {}", epoch);
             let mut to_merge = BTreeMap::new();
             let borrowed_stores = stores as &BTreeMap<_, _>;
+            let exhausted: BTreeSet<&MagicSymbol> = depths
+                .iter()
+                .filter(|(k, depth)| {
+                    matches!(max_depth_of(max_depths, k), Some((max, false)) if **depth >= max)
+                })
+                .map(|(k, _)| *k)
+                .collect();
             if epoch == 0 {
                 #[allow(clippy::needless_borrow)]
                 let execution = |(k, compiled_ruleset): (_, &CompiledRuleSet)| -> Result<_> {
@@ -223,7 +324,11 @@ impl<'a> SessionTx<'a> {
                                         &ruleset,
                                         epoch,
                                         borrowed_stores,
-                                        &limiter,
+                                        if exhausted.contains(k) {
+                                            &unlimited
+                                        } else {
+                                            &limiter
+                                        },
                                         poison.clone(),
                                     )?;
                                     used_limiter.fetch_or(res.0, Ordering::Relaxed);
@@ -288,15 +393,35 @@ impl<'a> SessionTx<'a> {
             let mut changed = false;
             for (k, new_store) in to_merge {
                 let old_store = stores.get_mut(k).unwrap();
+                if exhausted.contains(k) {
+                    trace!("{:?} has reached its maximum depth", k);
+                    truncated |= old_store.would_change(&new_store)?;
+                    old_store.clear_delta();
+                    continue;
+                }
                 old_store.merge_in(new_store)?;
                 trace!("delta for {}: {}", k, old_store.has_delta());
-                changed |= old_store.has_delta();
+                if !old_store.has_delta() {
+                    continue;
+                }
+                changed = true;
+                if let Some((max, _)) = max_depth_of(max_depths, k) {
+                    let depth = depths.entry(k).or_default();
+                    if *depth >= max {
+                        bail!(MaxDepthExceeded(
+                            k.symbol().to_string(),
+                            max,
+                            k.symbol().span
+                        ))
+                    }
+                    *depth += 1;
+                }
             }
-            if !changed {
+            if !changed || (partial_on_timeout && poison.timed_out()) {
                 break;
             }
         }
-        Ok(used_limiter.load(Ordering::Acquire))
+        Ok((used_limiter.load(Ordering::Acquire), truncated))
     }
     /// returns true is early return is activated
     fn initial_rule_non_aggr_eval(
@@ -309,6 +434,7 @@ impl<'a> SessionTx<'a> {
     ) -> Result<(bool, RegularTempStore)> {
         let mut out_store = RegularTempStore::default();
         let should_check_limit = limiter.total.is_some() && rule_symb.is_prog_entry();
+        let stop_on_timeout = limiter.partial_on_timeout && rule_symb.is_prog_entry();
 
         for (rule_n, rule) in ruleset.iter().enumerate() {
             debug!("initial calculation for rule {:?}.{}", rule_symb, rule_n);
@@ -330,6 +456,10 @@ impl<'a> SessionTx<'a> {
                 } else {
                     out_store.put(item);
                 }
+                if stop_on_timeout && poison.timed_out() {
+                    trace!("early stopping due to timeout");
+                    return Ok((should_check_limit, out_store));
+                }
             }
             poison.check()?;
         }
@@ -513,6 +643,7 @@ impl<'a> SessionTx<'a> {
         let prev_store = stores.get(rule_symb).unwrap();
         let mut out_store = RegularTempStore::default();
         let should_check_limit = limiter.total.is_some() && rule_symb.is_prog_entry();
+        let stop_on_timeout = limiter.partial_on_timeout && rule_symb.is_prog_entry();
         for (rule_n, rule) in ruleset.iter().enumerate() {
             let dependencies_changed = rule
                 .contained_rules
@@ -560,6 +691,10 @@ impl<'a> SessionTx<'a> {
                             return Ok((true, out_store));
                         }
                     }
+                    if stop_on_timeout && poison.timed_out() {
+                        trace!("early stopping due to timeout");
+                        return Ok((should_check_limit, out_store));
+                    }
                 }
                 poison.check()?;
             }
@@ -0,0 +1,156 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use miette::Result;
+
+use crate::data::program::{
+    NormalFormAtom, NormalFormInlineRule, NormalFormProgram, NormalFormRulesOrFixed,
+};
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::parse::SourceSpan;
+
+impl NormalFormProgram {
+    /// Replace applications of simple rules in the body of the entry rule by the bodies of
+    /// those rules, so that a limit on the entry rule stops their evaluation early as well.
+    ///
+    /// A rule is simple if it consists of a single clause without aggregations whose body
+    /// applies no rules, i.e. it only scans stored relations, filters and projects. The entry
+    /// rule must also consist of a single clause without aggregations. Rules in `keep` are
+    /// never inlined.
+    pub(crate) fn inline_simple_rules_into_entry(&mut self, keep: &BTreeSet<Symbol>) -> Result<()> {
+        let entry_name = Symbol::new(PROG_ENTRY, SourceSpan(0, 0));
+        let entry = match self.prog.get(&entry_name) {
+            Some(NormalFormRulesOrFixed::Rules { rules }) if is_plain_single(rules) => &rules[0],
+            _ => return Ok(()),
+        };
+        let mut counter = 0;
+        let mut body = Vec::with_capacity(entry.body.len());
+        let mut inlined = false;
+        for atom in &entry.body {
+            let app = match atom {
+                NormalFormAtom::Rule(app)
+                    if app.name != entry_name && !keep.contains(&app.name) =>
+                {
+                    app
+                }
+                atom => {
+                    body.push(atom.clone());
+                    continue;
+                }
+            };
+            let rule = match self.prog.get(&app.name) {
+                Some(NormalFormRulesOrFixed::Rules { rules })
+                    if is_plain_single(rules) && !applies_rules(&rules[0]) =>
+                {
+                    &rules[0]
+                }
+                _ => {
+                    body.push(atom.clone());
+                    continue;
+                }
+            };
+            // head variables become the arguments of the application, the rest fresh variables
+            let mut renames: BTreeMap<Symbol, Symbol> = rule
+                .head
+                .iter()
+                .cloned()
+                .zip(app.args.iter().cloned())
+                .filter(|(_, arg)| !arg.is_generated_ignored_symbol())
+                .collect();
+            for atom in &rule.body {
+                for var in atom_vars(atom) {
+                    renames.entry(var.clone()).or_insert_with(|| {
+                        counter += 1;
+                        let prefix = if var.is_generated_ignored_symbol() {
+                            '~'
+                        } else {
+                            '*'
+                        };
+                        Symbol::new(&format!("{prefix}i{counter}") as &str, var.span)
+                    });
+                }
+            }
+            for atom in &rule.body {
+                body.push(rename_atom(atom, &renames));
+            }
+            inlined = true;
+        }
+        if inlined {
+            let new_entry = NormalFormInlineRule {
+                head: entry.head.clone(),
+                aggr: entry.aggr.clone(),
+                body,
+            }
+            .convert_to_well_ordered_rule()?;
+            self.prog.insert(
+                entry_name,
+                NormalFormRulesOrFixed::Rules {
+                    rules: vec![new_entry],
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+fn is_plain_single(rules: &[NormalFormInlineRule]) -> bool {
+    rules.len() == 1 && rules[0].aggr.iter().all(|a| a.is_none())
+}
+
+fn applies_rules(rule: &NormalFormInlineRule) -> bool {
+    rule.body.iter().any(|atom| {
+        matches!(
+            atom,
+            NormalFormAtom::Rule(_)
+                | NormalFormAtom::NegatedRule(_)
+                | NormalFormAtom::ExistsRule(_)
+        )
+    })
+}
+
+fn atom_vars(atom: &NormalFormAtom) -> Vec<Symbol> {
+    match atom {
+        NormalFormAtom::Rule(a)
+        | NormalFormAtom::NegatedRule(a)
+        | NormalFormAtom::ExistsRule(a) => a.args.clone(),
+        NormalFormAtom::Relation(a)
+        | NormalFormAtom::NegatedRelation(a)
+        | NormalFormAtom::ExistsRelation(a) => a.args.clone(),
+        NormalFormAtom::Predicate(p) => p.bindings().into_iter().collect(),
+        NormalFormAtom::Unification(u) => {
+            let mut vars: Vec<_> = u.expr.bindings().into_iter().collect();
+            vars.push(u.binding.clone());
+            vars
+        }
+    }
+}
+
+fn rename_atom(atom: &NormalFormAtom, renames: &BTreeMap<Symbol, Symbol>) -> NormalFormAtom {
+    let rename = |var: &Symbol| renames.get(var).unwrap_or(var).clone();
+    let mut atom = atom.clone();
+    match &mut atom {
+        NormalFormAtom::Rule(a)
+        | NormalFormAtom::NegatedRule(a)
+        | NormalFormAtom::ExistsRule(a) => {
+            a.args = a.args.iter().map(rename).collect();
+        }
+        NormalFormAtom::Relation(a)
+        | NormalFormAtom::NegatedRelation(a)
+        | NormalFormAtom::ExistsRelation(a) => {
+            a.args = a.args.iter().map(rename).collect();
+        }
+        NormalFormAtom::Predicate(p) => p.rename_bindings(renames),
+        NormalFormAtom::Unification(u) => {
+            u.binding = rename(&u.binding);
+            u.expr.rename_bindings(renames);
+        }
+    }
+    atom
+}
@@ -6,17 +6,20 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use itertools::Itertools;
 use miette::{bail, ensure, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::expr::Expr;
+use crate::data::functions::{OP_AND, OP_IS_NULL, OP_LE, OP_LT, OP_OR};
 use crate::data::program::{
     InputAtom, InputNamedFieldRelationApplyAtom, InputRelationApplyAtom, InputRuleApplyAtom,
     NormalFormAtom, NormalFormRelationApplyAtom, NormalFormRuleApplyAtom, TempSymbGen, Unification,
 };
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
 use crate::parse::SourceSpan;
 use crate::query::reorder::UnsafeNegation;
 use crate::runtime::transact::SessionTx;
@@ -57,7 +60,154 @@ impl Disjunction {
 #[derive(Debug)]
 pub(crate) struct Conjunction(pub(crate) Vec<NormalFormAtom>);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Only applications of rules or stored relations can follow `exists`")]
+#[diagnostic(code(eval::bad_exists))]
+pub(crate) struct BadExistence(#[label] pub(crate) SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Only applications of rules or stored relations can be optional")]
+#[diagnostic(code(eval::bad_optional))]
+pub(crate) struct BadOptional(#[label] pub(crate) SourceSpan);
+
+/// How an application occurs in the body of a rule
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Occurrence {
+    Positive,
+    Negated,
+    Exists,
+}
+
 impl InputAtom {
+    /// Variables occurring in the atom, except under negations, which bind nothing
+    fn collect_mentioned_variables(&self, coll: &mut BTreeSet<Symbol>) {
+        match self {
+            InputAtom::Rule { inner } => inner.args.iter().for_each(|a| a.collect_bindings(coll)),
+            InputAtom::NamedFieldRelation { inner } => {
+                inner.args.values().for_each(|a| a.collect_bindings(coll))
+            }
+            InputAtom::Relation { inner } => {
+                inner.args.iter().for_each(|a| a.collect_bindings(coll))
+            }
+            InputAtom::Predicate { inner } => inner.collect_bindings(coll),
+            InputAtom::Unification { inner } => {
+                coll.insert(inner.binding.clone());
+                inner.expr.collect_bindings(coll);
+            }
+            InputAtom::Conjunction { inner, .. } | InputAtom::Disjunction { inner, .. } => inner
+                .iter()
+                .for_each(|a| a.collect_mentioned_variables(coll)),
+            InputAtom::Optional { inner, .. } => inner.collect_mentioned_variables(coll),
+            InputAtom::Negation { .. } | InputAtom::Existence { .. } => {}
+        }
+    }
+
+    /// Rewrite `optional(a)` into `a or (not a', v = null, ...)`, where the `v` are the
+    /// variables of `a` that occur neither in `outer` nor elsewhere in the enclosing
+    /// conjunctions, and are replaced by `_` in `a'`.
+    fn lower_optional(self, outer: &BTreeSet<Symbol>) -> Result<Self> {
+        Ok(match self {
+            InputAtom::Conjunction { inner: args, span } => {
+                let mut mentioned = Vec::with_capacity(args.len());
+                for arg in &args {
+                    let mut coll = BTreeSet::new();
+                    arg.collect_mentioned_variables(&mut coll);
+                    mentioned.push(coll);
+                }
+                let inner = args
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, a)| {
+                        let mut bound = outer.clone();
+                        for (j, vars) in mentioned.iter().enumerate() {
+                            if i != j {
+                                bound.extend(vars.iter().cloned());
+                            }
+                        }
+                        a.lower_optional(&bound)
+                    })
+                    .try_collect()?;
+                InputAtom::Conjunction { inner, span }
+            }
+            InputAtom::Disjunction { inner: args, span } => InputAtom::Disjunction {
+                inner: args
+                    .into_iter()
+                    .map(|a| a.lower_optional(outer))
+                    .try_collect()?,
+                span,
+            },
+            InputAtom::Negation { inner, span } => InputAtom::Negation {
+                inner: Box::new(inner.lower_optional(outer)?),
+                span,
+            },
+            InputAtom::Existence { inner, span } => InputAtom::Existence {
+                inner: Box::new(inner.lower_optional(outer)?),
+                span,
+            },
+            InputAtom::Optional { inner, span } => {
+                let mut padded = vec![];
+                let mut pad = |arg: &mut Expr| {
+                    if let Expr::Binding { var, .. } = arg {
+                        if !var.is_ignored_symbol() && !outer.contains(var) {
+                            padded.push(var.clone());
+                            *arg = Expr::Binding {
+                                var: Symbol::new("_", var.span),
+                                tuple_pos: None,
+                            };
+                        }
+                    }
+                };
+                let applied = match *inner {
+                    InputAtom::Conjunction { mut inner, .. } if inner.len() == 1 => {
+                        inner.pop().unwrap()
+                    }
+                    a => a,
+                };
+                let mut unmatched = applied.clone();
+                match &mut unmatched {
+                    InputAtom::Rule { inner } => inner.args.iter_mut().for_each(&mut pad),
+                    InputAtom::Relation { inner } => inner.args.iter_mut().for_each(&mut pad),
+                    InputAtom::NamedFieldRelation { inner } => {
+                        inner.args.values_mut().for_each(&mut pad)
+                    }
+                    _ => bail!(BadOptional(span)),
+                }
+                let mut unmatched_branch = vec![InputAtom::Negation {
+                    inner: Box::new(unmatched),
+                    span,
+                }];
+                for var in padded.into_iter().unique() {
+                    unmatched_branch.push(InputAtom::Unification {
+                        inner: Unification {
+                            binding: var,
+                            expr: Expr::Const {
+                                val: DataValue::Null,
+                                span,
+                            },
+                            one_many_unif: false,
+                            span,
+                        },
+                    });
+                }
+                InputAtom::Disjunction {
+                    inner: vec![
+                        applied,
+                        InputAtom::Conjunction {
+                            inner: unmatched_branch,
+                            span,
+                        },
+                    ],
+                    span,
+                }
+            }
+            a @ (InputAtom::Rule { .. }
+            | InputAtom::NamedFieldRelation { .. }
+            | InputAtom::Relation { .. }
+            | InputAtom::Predicate { .. }
+            | InputAtom::Unification { .. }) => a,
+        })
+    }
+
     pub(crate) fn negation_normal_form(self) -> Result<Self> {
         Ok(match self {
             a @ (InputAtom::Rule { .. }
@@ -79,6 +229,27 @@ impl InputAtom {
                 span,
             },
             InputAtom::Unification { inner: unif } => InputAtom::Unification { inner: unif },
+            // already lowered
+            InputAtom::Optional { .. } => unreachable!(),
+            InputAtom::Existence { inner: arg, span } => match *arg {
+                a @ (InputAtom::Rule { .. }
+                | InputAtom::NamedFieldRelation { .. }
+                | InputAtom::Relation { .. }) => InputAtom::Existence {
+                    inner: Box::new(a),
+                    span,
+                },
+                InputAtom::Existence { inner, .. } => {
+                    InputAtom::Existence { inner, span }.negation_normal_form()?
+                }
+                InputAtom::Conjunction { mut inner, .. } if inner.len() == 1 => {
+                    InputAtom::Existence {
+                        inner: Box::new(inner.pop().unwrap()),
+                        span,
+                    }
+                    .negation_normal_form()?
+                }
+                _ => bail!(BadExistence(span)),
+            },
             InputAtom::Negation { inner: arg, span } => match *arg {
                 a @ (InputAtom::Rule { .. }
                 | InputAtom::NamedFieldRelation { .. }
@@ -90,6 +261,12 @@ impl InputAtom {
                     inner: p.negate(span),
                 },
                 InputAtom::Negation { inner, .. } => inner.negation_normal_form()?,
+                // with its free variables unbound, a negated application already
+                // means that no match exists
+                a @ InputAtom::Existence { .. } => match a.negation_normal_form()? {
+                    InputAtom::Existence { inner, .. } => InputAtom::Negation { inner, span },
+                    _ => unreachable!(),
+                },
                 InputAtom::Conjunction { inner: args, .. } => InputAtom::Disjunction {
                     inner: args
                         .into_iter()
@@ -121,26 +298,33 @@ impl InputAtom {
                 InputAtom::Unification { inner } => {
                     bail!(UnsafeNegation(inner.span))
                 }
+                InputAtom::Optional { .. } => unreachable!(),
             },
         })
     }
 
     pub(crate) fn disjunctive_normal_form(self, tx: &SessionTx<'_>) -> Result<Disjunction> {
-        let neg_form = self.negation_normal_form()?;
+        let neg_form = self
+            .lower_optional(&BTreeSet::new())?
+            .negation_normal_form()?;
         let mut gen = TempSymbGen::default();
         neg_form.do_disjunctive_normal_form(&mut gen, tx)
     }
 
+    /// Convert an application of a stored relation with named fields to a positional one,
+    /// together with the atoms computing the derived attributes given as fields
     fn convert_named_field_relation(
         InputNamedFieldRelationApplyAtom {
             name,
             mut args,
             valid_at,
+            valid_time,
+            hint,
             span,
         }: InputNamedFieldRelationApplyAtom,
         gen: &mut TempSymbGen,
         tx: &SessionTx<'_>,
-    ) -> Result<InputRelationApplyAtom> {
+    ) -> Result<(InputRelationApplyAtom, Vec<NormalFormAtom>)> {
         let stored = tx.get_relation(&name, false)?;
         let fields: BTreeSet<_> = stored
             .metadata
@@ -148,6 +332,7 @@ impl InputAtom {
             .iter()
             .chain(stored.metadata.non_keys.iter())
             .map(|col| &col.name)
+            .chain(stored.derived.keys())
             .collect();
         for k in args.keys() {
             ensure!(
@@ -155,6 +340,51 @@ impl InputAtom {
                 NamedFieldNotFound(name.to_string(), k.to_string(), span)
             );
         }
+        let mut extra = vec![];
+        for (attr, def) in &stored.derived {
+            let target = match args.remove(attr) {
+                None => continue,
+                Some(Expr::Binding { var, .. }) if var.is_ignored_symbol() => continue,
+                Some(target) => target,
+            };
+            let mut renames = BTreeMap::new();
+            for col in def.bindings() {
+                let var = match args.remove(&col.name) {
+                    Some(Expr::Binding { var, .. }) if !var.is_ignored_symbol() => var,
+                    given => {
+                        let var = gen.next(span);
+                        if let Some(expr) = given.filter(|e| e.get_binding().is_none()) {
+                            extra.push(NormalFormAtom::Unification(Unification {
+                                binding: var.clone(),
+                                expr,
+                                one_many_unif: false,
+                                span,
+                            }))
+                        }
+                        var
+                    }
+                };
+                args.insert(
+                    col.name.clone(),
+                    Expr::Binding {
+                        var: var.clone(),
+                        tuple_pos: None,
+                    },
+                );
+                renames.insert(col, var);
+            }
+            let mut expr = def.clone();
+            expr.rename_bindings(&renames);
+            extra.push(match target {
+                Expr::Binding { var, .. } => NormalFormAtom::Unification(Unification {
+                    binding: var,
+                    expr,
+                    one_many_unif: false,
+                    span,
+                }),
+                target => NormalFormAtom::Predicate(Expr::build_equate(vec![target, expr], span)),
+            });
+        }
         let mut new_args = vec![];
         for col_def in stored
             .metadata
@@ -168,12 +398,90 @@ impl InputAtom {
             });
             new_args.push(arg)
         }
-        Ok(InputRelationApplyAtom {
-            name,
-            args: new_args,
+        Ok((
+            InputRelationApplyAtom {
+                name,
+                args: new_args,
+                span,
+                valid_at,
+                valid_time,
+                hint,
+            },
+            extra,
+        ))
+    }
+
+    /// For atoms of bi-temporal relations given `valid_at`, the predicate selecting the rows
+    /// whose valid time contains the time given. The columns holding the valid time are bound
+    /// to variables in `atom` if they are not already.
+    fn select_valid_time(
+        atom: &mut InputRelationApplyAtom,
+        gen: &mut TempSymbGen,
+        tx: &SessionTx<'_>,
+    ) -> Result<Vec<NormalFormAtom>> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Stored relation {0} has no valid time")]
+        #[diagnostic(code(eval::no_valid_time))]
+        #[diagnostic(help("Set the columns holding the valid time with `::valid_time`"))]
+        struct NoValidTime(String, #[label] SourceSpan);
+
+        let time = match atom.valid_time.take() {
+            None => return Ok(vec![]),
+            Some(time) => time,
+        };
+        let span = atom.span;
+        let stored = tx.get_relation(&atom.name, false)?;
+        let (from_idx, to_idx) = match stored.valid_time {
+            Some(cols) => cols,
+            None => bail!(NoValidTime(atom.name.to_string(), span)),
+        };
+        if atom.args.len() != stored.arity() {
+            // the arity is reported when the atom is compiled
+            return Ok(vec![]);
+        }
+        let mut ret = vec![];
+        let bound = [from_idx, to_idx].map(|idx| match &atom.args[idx] {
+            Expr::Binding { var, .. } if !var.is_ignored_symbol() => var.clone(),
+            given => {
+                let var = gen.next(span);
+                if given.get_binding().is_none() {
+                    ret.push(NormalFormAtom::Unification(Unification {
+                        binding: var.clone(),
+                        expr: given.clone(),
+                        one_many_unif: false,
+                        span,
+                    }))
+                }
+                atom.args[idx] = Expr::Binding {
+                    var: var.clone(),
+                    tuple_pos: None,
+                };
+                var
+            }
+        });
+        let [from, to] = bound.map(|var| Expr::Binding {
+            var,
+            tuple_pos: None,
+        });
+        let apply = |op, args: Vec<Expr>| Expr::Apply {
+            op,
+            args: args.into(),
             span,
-            valid_at,
-        })
+        };
+        ret.push(NormalFormAtom::Predicate(apply(
+            &OP_AND,
+            vec![
+                apply(&OP_LE, vec![from, time.clone()]),
+                apply(
+                    &OP_OR,
+                    vec![
+                        apply(&OP_IS_NULL, vec![to.clone()]),
+                        apply(&OP_LT, vec![time, to]),
+                    ],
+                ),
+            ],
+        )));
+        Ok(ret)
     }
 
     fn do_disjunctive_normal_form(
@@ -203,34 +511,72 @@ impl InputAtom {
                 }
                 result
             }
-            InputAtom::Rule { inner: r } => r.normalize(false, gen),
+            InputAtom::Rule { inner: r } => r.normalize(Occurrence::Positive, gen),
             InputAtom::NamedFieldRelation { inner } => {
-                let r = Self::convert_named_field_relation(inner, gen, tx)?;
-                r.normalize(false, gen)
+                let (mut r, mut extra) = Self::convert_named_field_relation(inner, gen, tx)?;
+                extra.extend(Self::select_valid_time(&mut r, gen, tx)?);
+                let mut ret = r.normalize(Occurrence::Positive, gen);
+                for conj in ret.inner.iter_mut() {
+                    conj.0.extend(extra.iter().cloned());
+                }
+                ret
+            }
+            InputAtom::Relation { inner: mut v } => {
+                let extra = Self::select_valid_time(&mut v, gen, tx)?;
+                let mut ret = v.normalize(Occurrence::Positive, gen);
+                for conj in ret.inner.iter_mut() {
+                    conj.0.extend(extra.iter().cloned());
+                }
+                ret
             }
-            InputAtom::Relation { inner: v } => v.normalize(false, gen),
             InputAtom::Predicate { inner: mut p } => {
                 p.partial_eval()?;
                 Disjunction::singlet(NormalFormAtom::Predicate(p))
             }
             InputAtom::Negation { inner: n, .. } => match *n {
-                InputAtom::Rule { inner: r } => r.normalize(true, gen),
-                InputAtom::Relation { inner: v } => v.normalize(true, gen),
+                InputAtom::Rule { inner: r } => r.normalize(Occurrence::Negated, gen),
+                InputAtom::Relation { inner: v } => {
+                    ensure!(v.valid_time.is_none(), DerivedAttrNotPositive(v.span));
+                    v.normalize(Occurrence::Negated, gen)
+                }
+                InputAtom::NamedFieldRelation { inner } => {
+                    let span = inner.span;
+                    let (r, extra) = Self::convert_named_field_relation(inner, gen, tx)?;
+                    ensure!(
+                        extra.is_empty() && r.valid_time.is_none(),
+                        DerivedAttrNotPositive(span)
+                    );
+                    r.normalize(Occurrence::Negated, gen)
+                }
+                _ => unreachable!(),
+            },
+            InputAtom::Existence { inner: n, .. } => match *n {
+                InputAtom::Rule { inner: r } => r.normalize(Occurrence::Exists, gen),
+                InputAtom::Relation { inner: v } => {
+                    ensure!(v.valid_time.is_none(), DerivedAttrNotPositive(v.span));
+                    v.normalize(Occurrence::Exists, gen)
+                }
                 InputAtom::NamedFieldRelation { inner } => {
-                    let r = Self::convert_named_field_relation(inner, gen, tx)?;
-                    r.normalize(true, gen)
+                    let span = inner.span;
+                    let (r, extra) = Self::convert_named_field_relation(inner, gen, tx)?;
+                    ensure!(
+                        extra.is_empty() && r.valid_time.is_none(),
+                        DerivedAttrNotPositive(span)
+                    );
+                    r.normalize(Occurrence::Exists, gen)
                 }
                 _ => unreachable!(),
             },
             InputAtom::Unification { inner: u } => {
                 Disjunction::singlet(NormalFormAtom::Unification(u))
             }
+            InputAtom::Optional { .. } => unreachable!(),
         })
     }
 }
 
 impl InputRuleApplyAtom {
-    fn normalize(self, is_negated: bool, gen: &mut TempSymbGen) -> Disjunction {
+    fn normalize(self, occurrence: Occurrence, gen: &mut TempSymbGen) -> Disjunction {
         let mut ret = Vec::with_capacity(self.args.len() + 1);
         let mut args = Vec::with_capacity(self.args.len());
         let mut seen_variables = BTreeSet::new();
@@ -272,25 +618,22 @@ impl InputRuleApplyAtom {
             }
         }
 
-        ret.push(if is_negated {
-            NormalFormAtom::NegatedRule(NormalFormRuleApplyAtom {
-                name: self.name,
-                args,
-                span: self.span,
-            })
-        } else {
-            NormalFormAtom::Rule(NormalFormRuleApplyAtom {
-                name: self.name,
-                args,
-                span: self.span,
-            })
+        let atom = NormalFormRuleApplyAtom {
+            name: self.name,
+            args,
+            span: self.span,
+        };
+        ret.push(match occurrence {
+            Occurrence::Positive => NormalFormAtom::Rule(atom),
+            Occurrence::Negated => NormalFormAtom::NegatedRule(atom),
+            Occurrence::Exists => NormalFormAtom::ExistsRule(atom),
         });
         Disjunction::conj(ret)
     }
 }
 
 impl InputRelationApplyAtom {
-    fn normalize(self, is_negated: bool, gen: &mut TempSymbGen) -> Disjunction {
+    fn normalize(self, occurrence: Occurrence, gen: &mut TempSymbGen) -> Disjunction {
         let mut ret = Vec::with_capacity(self.args.len() + 1);
         let mut args = Vec::with_capacity(self.args.len());
         let mut seen_variables = BTreeSet::new();
@@ -332,25 +675,28 @@ impl InputRelationApplyAtom {
             }
         }
 
-        ret.push(if is_negated {
-            NormalFormAtom::NegatedRelation(NormalFormRelationApplyAtom {
-                name: self.name,
-                args,
-                valid_at: self.valid_at,
-                span: self.span,
-            })
-        } else {
-            NormalFormAtom::Relation(NormalFormRelationApplyAtom {
-                name: self.name,
-                args,
-                valid_at: self.valid_at,
-                span: self.span,
-            })
+        let atom = NormalFormRelationApplyAtom {
+            name: self.name,
+            args,
+            valid_at: self.valid_at,
+            hint: self.hint,
+            span: self.span,
+        };
+        ret.push(match occurrence {
+            Occurrence::Positive => NormalFormAtom::Relation(atom),
+            Occurrence::Negated => NormalFormAtom::NegatedRelation(atom),
+            Occurrence::Exists => NormalFormAtom::ExistsRelation(atom),
         });
         Disjunction::conj(ret)
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Derived attributes and valid times cannot be used under negation or `exists`")]
+#[diagnostic(code(eval::derived_attr_not_positive))]
+#[diagnostic(help("Bind the attribute in a positive atom and use its variable instead"))]
+struct DerivedAttrNotPositive(#[label] SourceSpan);
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("stored relation '{0}' does not have field '{1}'")]
 #[diagnostic(code(eval::named_field_not_found))]
@@ -48,13 +48,23 @@ impl NormalFormProgram {
 }
 
 impl StratifiedNormalFormProgram {
-    pub(crate) fn magic_sets_rewrite(self, tx: &SessionTx<'_>) -> Result<StratifiedMagicProgram> {
+    /// Rewrite the program with magic sets, except for the rules in `cached_rules`
+    /// and `no_magic_rules`, the entry rule and rules with aggregations.
+    /// The rules in `no_magic_rules` in addition pass no bindings to the rules they apply.
+    pub(crate) fn magic_sets_rewrite(
+        self,
+        tx: &SessionTx<'_>,
+        cached_rules: &BTreeSet<Symbol>,
+        no_magic_rules: &BTreeSet<Symbol>,
+    ) -> Result<StratifiedMagicProgram> {
         let mut exempt_rules = BTreeSet::from([Symbol::new(PROG_ENTRY, SourceSpan(0, 0))]);
+        exempt_rules.extend(cached_rules.iter().cloned());
+        exempt_rules.extend(no_magic_rules.iter().cloned());
         let mut collected = vec![];
         for prog in self.0 {
             prog.exempt_aggr_rules_for_magic_sets(&mut exempt_rules);
             let down_stream_rules = prog.get_downstream_rules();
-            let adorned = prog.adorn(&exempt_rules, tx)?;
+            let adorned = prog.adorn(&exempt_rules, no_magic_rules, tx)?;
             collected.push(adorned.magic_rewrite());
             exempt_rules.extend(down_stream_rules);
         }
@@ -161,7 +171,9 @@ fn magic_rewrite_ruleset(
             match atom {
                 a @ (MagicAtom::Predicate(_)
                 | MagicAtom::NegatedRule(_)
-                | MagicAtom::NegatedRelation(_)) => {
+                | MagicAtom::NegatedRelation(_)
+                | MagicAtom::ExistsRule(_)
+                | MagicAtom::ExistsRelation(_)) => {
                     collected_atoms.push(a);
                 }
                 MagicAtom::Relation(v) => {
@@ -264,7 +276,8 @@ impl NormalFormProgram {
                         for atom in rule.body.iter() {
                             match atom {
                                 NormalFormAtom::Rule(r_app)
-                                | NormalFormAtom::NegatedRule(r_app) => {
+                                | NormalFormAtom::NegatedRule(r_app)
+                                | NormalFormAtom::ExistsRule(r_app) => {
                                     if !own_rules.contains(&r_app.name) {
                                         downstream_rules.insert(r_app.name.clone());
                                     }
@@ -285,7 +298,12 @@ impl NormalFormProgram {
         }
         downstream_rules
     }
-    fn adorn(self, upstream_rules: &BTreeSet<Symbol>, tx: &SessionTx<'_>) -> Result<MagicProgram> {
+    fn adorn(
+        self,
+        upstream_rules: &BTreeSet<Symbol>,
+        no_magic_rules: &BTreeSet<Symbol>,
+        tx: &SessionTx<'_>,
+    ) -> Result<MagicProgram> {
         let rules_to_rewrite: BTreeSet<_> = self
             .prog
             .keys()
@@ -444,6 +462,7 @@ impl NormalFormProgram {
                             &mut pending_adornment,
                             &rules_to_rewrite,
                             Default::default(),
+                            !no_magic_rules.contains(rule_name),
                         );
                         adorned_rules.push(adorned_rule);
                     }
@@ -478,8 +497,12 @@ impl NormalFormProgram {
                     .zip(adornment.iter())
                     .filter_map(|(kw, bound)| if *bound { Some(kw.clone()) } else { None })
                     .collect();
-                let adorned_rule =
-                    rule.adorn(&mut pending_adornment, &rules_to_rewrite, seen_bindings);
+                let adorned_rule = rule.adorn(
+                    &mut pending_adornment,
+                    &rules_to_rewrite,
+                    seen_bindings,
+                    true,
+                );
                 adorned_rules.push(adorned_rule);
             }
             adorned_prog.prog.insert(
@@ -494,11 +517,13 @@ impl NormalFormProgram {
 }
 
 impl NormalFormAtom {
+    /// With `propagate` unset, rules are applied as if none of their arguments were bound.
     fn adorn(
         &self,
         pending: &mut Vec<MagicSymbol>,
         seen_bindings: &mut BTreeSet<Symbol>,
         rules_to_rewrite: &BTreeSet<Symbol>,
+        propagate: bool,
     ) -> MagicAtom {
         match self {
             NormalFormAtom::Relation(v) => {
@@ -506,6 +531,7 @@ impl NormalFormAtom {
                     name: v.name.clone(),
                     args: v.args.clone(),
                     valid_at: v.valid_at,
+                    hint: v.hint.clone(),
                     span: v.span,
                 };
                 for arg in v.args.iter() {
@@ -525,7 +551,8 @@ impl NormalFormAtom {
                     // then
                     let mut adornment = SmallVec::new();
                     for arg in rule.args.iter() {
-                        adornment.push(!seen_bindings.insert(arg.clone()));
+                        let bound = !seen_bindings.insert(arg.clone());
+                        adornment.push(bound && propagate);
                     }
                     let name = MagicSymbol::Magic {
                         inner: rule.name.clone(),
@@ -561,9 +588,26 @@ impl NormalFormAtom {
                     name: nv.name.clone(),
                     args: nv.args.clone(),
                     valid_at: nv.valid_at,
+                    hint: nv.hint.clone(),
                     span: nv.span,
                 })
             }
+            NormalFormAtom::ExistsRule(er) => MagicAtom::ExistsRule(MagicRuleApplyAtom {
+                name: MagicSymbol::Muggle {
+                    inner: er.name.clone(),
+                },
+                args: er.args.clone(),
+                span: er.span,
+            }),
+            NormalFormAtom::ExistsRelation(ev) => {
+                MagicAtom::ExistsRelation(MagicRelationApplyAtom {
+                    name: ev.name.clone(),
+                    args: ev.args.clone(),
+                    valid_at: ev.valid_at,
+                    hint: ev.hint.clone(),
+                    span: ev.span,
+                })
+            }
             NormalFormAtom::Unification(u) => {
                 seen_bindings.insert(u.binding.clone());
                 MagicAtom::Unification(u.clone())
@@ -578,11 +622,12 @@ impl NormalFormInlineRule {
         pending: &mut Vec<MagicSymbol>,
         rules_to_rewrite: &BTreeSet<Symbol>,
         mut seen_bindings: BTreeSet<Symbol>,
+        propagate: bool,
     ) -> MagicInlineRule {
         let mut ret_body = Vec::with_capacity(self.body.len());
 
         for atom in &self.body {
-            let new_atom = atom.adorn(pending, &mut seen_bindings, rules_to_rewrite);
+            let new_atom = atom.adorn(pending, &mut seen_bindings, rules_to_rewrite, propagate);
             ret_body.push(new_atom);
         }
         MagicInlineRule {
@@ -9,6 +9,7 @@
 pub(crate) mod compile;
 pub(crate) mod eval;
 pub(crate) mod graph;
+pub(crate) mod inline;
 pub(crate) mod logical;
 pub(crate) mod magic;
 pub(crate) mod ra;
@@ -16,3 +17,4 @@ pub(crate) mod reorder;
 pub(crate) mod sort;
 pub(crate) mod stored;
 pub(crate) mod stratify;
+pub(crate) mod window;
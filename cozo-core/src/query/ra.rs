@@ -17,12 +17,13 @@ use miette::{bail, Diagnostic, Result};
 use thiserror::Error;
 
 use crate::data::expr::{compute_bounds, eval_bytecode, eval_bytecode_pred, Bytecode, Expr};
-use crate::data::program::MagicSymbol;
+use crate::data::program::{IndexHint, MagicSymbol};
 use crate::data::relation::{ColType, NullableColType};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{Tuple, TupleIter};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
+use crate::runtime::db::seconds_since_the_epoch;
 use crate::runtime::relation::RelationHandle;
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
@@ -369,6 +370,23 @@ impl RelAlgebra {
     pub(crate) fn unit(span: SourceSpan) -> Self {
         Self::Fixed(InlineFixedRA::unit(span))
     }
+    /// If the relation is a scan of a single stored relation, the bindings of its key
+    /// columns, in whose order the tuples are produced
+    pub(crate) fn storage_order(&self) -> Option<Vec<Symbol>> {
+        match self {
+            RelAlgebra::Stored(r) => Some(r.bindings[..r.storage.metadata.keys.len()].to_vec()),
+            RelAlgebra::StoredWithValidity(r) => {
+                Some(r.bindings[..r.storage.metadata.keys.len() - 1].to_vec())
+            }
+            RelAlgebra::Join(r) if r.left.is_unit() && r.joiner.left_keys.is_empty() => {
+                r.right.storage_order()
+            }
+            RelAlgebra::Reorder(r) => r.relation.storage_order(),
+            RelAlgebra::Filter(r) => r.parent.storage_order(),
+            RelAlgebra::Unification(r) => r.parent.storage_order(),
+            _ => None,
+        }
+    }
     pub(crate) fn is_unit(&self) -> bool {
         if let RelAlgebra::Fixed(r) = self {
             r.bindings.is_empty() && r.data.len() == 1
@@ -398,12 +416,14 @@ impl RelAlgebra {
         span: SourceSpan,
         validity: Option<ValidityTs>,
     ) -> Result<Self> {
+        let storage = storage.read_at(seconds_since_the_epoch()?);
         match validity {
             None => Ok(Self::Stored(StoredRA {
                 bindings,
                 storage,
                 filters: vec![],
                 filters_bytecodes: vec![],
+                hint: None,
                 span,
             })),
             Some(vld) => {
@@ -421,11 +441,21 @@ impl RelAlgebra {
                     filters: vec![],
                     filters_bytecodes: vec![],
                     valid_at: vld,
+                    hint: None,
                     span,
                 }))
             }
         }
     }
+    /// Record the hint that led to scanning a stored relation
+    pub(crate) fn with_hint(mut self, hint: Option<IndexHint>) -> Self {
+        match &mut self {
+            RelAlgebra::Stored(s) => s.hint = hint,
+            RelAlgebra::StoredWithValidity(s) => s.hint = hint,
+            _ => {}
+        }
+        self
+    }
     pub(crate) fn reorder(self, new_order: Vec<Symbol>) -> Self {
         Self::Reorder(ReorderRA {
             relation: Box::new(self),
@@ -484,6 +514,7 @@ impl RelAlgebra {
                 storage,
                 mut filters,
                 filters_bytecodes,
+                hint,
                 span,
             }) => {
                 filters.push(filter);
@@ -492,6 +523,7 @@ impl RelAlgebra {
                     storage,
                     filters,
                     filters_bytecodes,
+                    hint,
                     span,
                 })
             }
@@ -502,6 +534,7 @@ impl RelAlgebra {
                 filters_bytecodes: filter_bytecodes,
                 span,
                 valid_at,
+                hint,
             }) => {
                 filters.push(filter);
                 RelAlgebra::StoredWithValidity(StoredWithValidityRA {
@@ -511,6 +544,7 @@ impl RelAlgebra {
                     span,
                     valid_at,
                     filters_bytecodes: filter_bytecodes,
+                    hint,
                 })
             }
             RelAlgebra::Join(inner) => {
@@ -601,6 +635,7 @@ impl RelAlgebra {
         right: RelAlgebra,
         left_keys: Vec<Symbol>,
         right_keys: Vec<Symbol>,
+        semi: bool,
         span: SourceSpan,
     ) -> Self {
         RelAlgebra::NegJoin(Box::new(NegJoin {
@@ -611,6 +646,7 @@ impl RelAlgebra {
                 right_keys,
             },
             to_eliminate: Default::default(),
+            semi,
             span,
         }))
     }
@@ -818,6 +854,8 @@ pub(crate) struct StoredRA {
     pub(crate) storage: RelationHandle,
     pub(crate) filters: Vec<Expr>,
     pub(crate) filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
+    /// The hint that led to scanning this relation, reported by `::explain`
+    pub(crate) hint: Option<IndexHint>,
     pub(crate) span: SourceSpan,
 }
 
@@ -828,6 +866,7 @@ pub(crate) struct StoredWithValidityRA {
     pub(crate) filters: Vec<Expr>,
     pub(crate) filters_bytecodes: Vec<(Vec<Bytecode>, SourceSpan)>,
     pub(crate) valid_at: ValidityTs,
+    pub(crate) hint: Option<IndexHint>,
     pub(crate) span: SourceSpan,
 }
 
@@ -878,7 +917,11 @@ impl StoredWithValidityRA {
                     .collect_vec();
 
                 if !skip_range_check && !self.filters.is_empty() {
-                    let other_bindings = &self.bindings[right_join_indices.len()..];
+                    // only key columns, excluding the validity, can bound the scan
+                    let key_bindings = &self.bindings[..self.storage.metadata.keys.len() - 1];
+                    let other_bindings = key_bindings
+                        .get(right_join_indices.len()..)
+                        .unwrap_or_default();
                     let (l_bound, u_bound) = match compute_bounds(&self.filters, other_bindings) {
                         Ok(b) => b,
                         _ => (vec![], vec![]),
@@ -1070,7 +1113,9 @@ impl StoredRA {
                 let mut stack = vec![];
 
                 if !skip_range_check && !self.filters.is_empty() {
-                    let other_bindings = &self.bindings[right_join_indices.len()..];
+                    // only key columns can bound the scan
+                    let key_bindings = &self.bindings[..self.storage.metadata.keys.len()];
+                    let other_bindings = &key_bindings[right_join_indices.len()..];
                     let (l_bound, u_bound) = match compute_bounds(&self.filters, other_bindings) {
                         Ok(b) => b,
                         _ => (vec![], vec![]),
@@ -1129,6 +1174,7 @@ impl StoredRA {
         left_iter: TupleIter<'a>,
         (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
         eliminate_indices: BTreeSet<usize>,
+        semi: bool,
     ) -> Result<TupleIter<'a>> {
         debug_assert!(!right_join_indices.is_empty());
         let mut right_invert_indices = right_join_indices.iter().enumerate().collect_vec();
@@ -1150,6 +1196,7 @@ impl StoredRA {
                             .map(|i| tuple[*i].clone())
                             .collect_vec();
 
+                        let mut matched = false;
                         'outer: for found in self.storage.scan_prefix(tx, &prefix) {
                             let found = found?;
                             for (left_idx, right_idx) in
@@ -1159,6 +1206,10 @@ impl StoredRA {
                                     continue 'outer;
                                 }
                             }
+                            matched = true;
+                            break;
+                        }
+                        if matched != semi {
                             return Ok(None);
                         }
 
@@ -1199,7 +1250,7 @@ impl StoredRA {
                             .iter()
                             .map(|i| tuple[*i].clone())
                             .collect();
-                        if right_join_vals.contains(&left_join_vals) {
+                        if right_join_vals.contains(&left_join_vals) != semi {
                             return Ok(None);
                         }
 
@@ -1295,6 +1346,7 @@ impl TempStoreRA {
         (left_join_indices, right_join_indices): (Vec<usize>, Vec<usize>),
         eliminate_indices: BTreeSet<usize>,
         stores: &'a BTreeMap<MagicSymbol, EpochStore>,
+        semi: bool,
     ) -> Result<TupleIter<'a>> {
         let storage = stores.get(&self.storage_key).unwrap();
         debug_assert!(!right_join_indices.is_empty());
@@ -1316,6 +1368,7 @@ impl TempStoreRA {
                             .map(|i| tuple[*i].clone())
                             .collect_vec();
 
+                        let mut matched = false;
                         'outer: for found in storage.prefix_iter(&prefix) {
                             for (left_idx, right_idx) in
                                 left_join_indices.iter().zip(right_join_indices.iter())
@@ -1324,6 +1377,10 @@ impl TempStoreRA {
                                     continue 'outer;
                                 }
                             }
+                            matched = true;
+                            break;
+                        }
+                        if matched != semi {
                             return Ok(None);
                         }
 
@@ -1363,7 +1420,7 @@ impl TempStoreRA {
                             .iter()
                             .map(|i| tuple[*i].clone())
                             .collect();
-                        if right_join_vals.contains(&left_join_vals) {
+                        if right_join_vals.contains(&left_join_vals) != semi {
                             return Ok(None);
                         }
                         Ok(Some(if !eliminate_indices.is_empty() {
@@ -1627,6 +1684,8 @@ pub(crate) struct NegJoin {
     pub(crate) right: RelAlgebra,
     pub(crate) joiner: Joiner,
     pub(crate) to_eliminate: BTreeSet<Symbol>,
+    /// Keep the left tuples that have a match on the right instead of those that do not
+    pub(crate) semi: bool,
     pub(crate) span: SourceSpan,
 }
 
@@ -1654,10 +1713,11 @@ impl NegJoin {
                         &self.right.bindings_after_eliminate(),
                     )
                     .unwrap();
-                if join_is_prefix(&join_indices.1) {
-                    "mem_neg_prefix_join"
-                } else {
-                    "mem_neg_mat_join"
+                match (self.semi, join_is_prefix(&join_indices.1)) {
+                    (false, true) => "mem_neg_prefix_join",
+                    (false, false) => "mem_neg_mat_join",
+                    (true, true) => "mem_semi_prefix_join",
+                    (true, false) => "mem_semi_mat_join",
                 }
             }
             RelAlgebra::Stored(_) => {
@@ -1668,10 +1728,11 @@ impl NegJoin {
                         &self.right.bindings_after_eliminate(),
                     )
                     .unwrap();
-                if join_is_prefix(&join_indices.1) {
-                    "stored_neg_prefix_join"
-                } else {
-                    "stored_neg_mat_join"
+                match (self.semi, join_is_prefix(&join_indices.1)) {
+                    (false, true) => "stored_neg_prefix_join",
+                    (false, false) => "stored_neg_mat_join",
+                    (true, true) => "stored_semi_prefix_join",
+                    (true, false) => "stored_semi_mat_join",
                 }
             }
             _ => {
@@ -1702,6 +1763,7 @@ impl NegJoin {
                     join_indices,
                     eliminate_indices,
                     stores,
+                    self.semi,
                 )
             }
             RelAlgebra::Stored(v) => {
@@ -1717,6 +1779,7 @@ impl NegJoin {
                     self.left.iter(tx, delta_rule, stores)?,
                     join_indices,
                     eliminate_indices,
+                    self.semi,
                 )
             }
             _ => {
@@ -25,6 +25,15 @@ You may also encounter this error if your rule can never produce any rows."
 ))]
 pub(crate) struct UnsafeNegation(#[label] pub(crate) SourceSpan);
 
+#[derive(Diagnostic, Debug, Error)]
+#[error("Encountered `exists` with no variable bound elsewhere in the rule")]
+#[diagnostic(code(eval::unbound_exists))]
+#[diagnostic(help(
+    "`exists` filters the rows produced by the rest of the rule body, \
+so at least one of its variables must be bound by another atom"
+))]
+pub(crate) struct UnboundExistence(#[label] pub(crate) SourceSpan);
+
 #[derive(Diagnostic, Debug, Error)]
 #[error("Atom contains unbound variable, or rule contains no variable at all")]
 #[diagnostic(code(eval::unbound_variable))]
@@ -68,6 +77,10 @@ impl NormalFormInlineRule {
                 NormalFormAtom::NegatedRelation(v) => {
                     pending.push(NormalFormAtom::NegatedRelation(v))
                 }
+                NormalFormAtom::ExistsRule(r) => pending.push(NormalFormAtom::ExistsRule(r)),
+                NormalFormAtom::ExistsRelation(v) => {
+                    pending.push(NormalFormAtom::ExistsRelation(v))
+                }
                 NormalFormAtom::Predicate(p) => {
                     pending.push(NormalFormAtom::Predicate(p));
                 }
@@ -91,6 +104,8 @@ impl NormalFormInlineRule {
                 }
                 NormalFormAtom::NegatedRule(_)
                 | NormalFormAtom::NegatedRelation(_)
+                | NormalFormAtom::ExistsRule(_)
+                | NormalFormAtom::ExistsRelation(_)
                 | NormalFormAtom::Predicate(_) => {
                     unreachable!()
                 }
@@ -116,6 +131,20 @@ impl NormalFormInlineRule {
                             pending.push(NormalFormAtom::NegatedRelation(v.clone()));
                         }
                     }
+                    NormalFormAtom::ExistsRule(r) => {
+                        if r.args.iter().all(|a| seen_variables.contains(a)) {
+                            collected.push(NormalFormAtom::ExistsRule(r.clone()));
+                        } else {
+                            pending.push(NormalFormAtom::ExistsRule(r.clone()));
+                        }
+                    }
+                    NormalFormAtom::ExistsRelation(v) => {
+                        if v.args.iter().all(|a| seen_variables.contains(a)) {
+                            collected.push(NormalFormAtom::ExistsRelation(v.clone()));
+                        } else {
+                            pending.push(NormalFormAtom::ExistsRelation(v.clone()));
+                        }
+                    }
                     NormalFormAtom::Predicate(p) => {
                         if p.bindings().is_subset(&seen_variables) {
                             collected.push(NormalFormAtom::Predicate(p.clone()));
@@ -152,6 +181,20 @@ impl NormalFormInlineRule {
                             bail!(UnsafeNegation(v.span));
                         }
                     }
+                    NormalFormAtom::ExistsRule(r) => {
+                        if r.args.iter().any(|a| seen_variables.contains(a)) {
+                            collected.push(NormalFormAtom::ExistsRule(r.clone()));
+                        } else {
+                            bail!(UnboundExistence(r.span));
+                        }
+                    }
+                    NormalFormAtom::ExistsRelation(v) => {
+                        if v.args.iter().any(|a| seen_variables.contains(a)) {
+                            collected.push(NormalFormAtom::ExistsRelation(v.clone()));
+                        } else {
+                            bail!(UnboundExistence(v.span));
+                        }
+                    }
                     NormalFormAtom::Predicate(p) => {
                         bail!(UnboundVariable(p.span()))
                     }
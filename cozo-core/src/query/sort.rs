@@ -8,45 +8,111 @@
 
 use std::cmp::Ordering;
 use std::collections::BTreeMap;
+use std::mem;
 
 use itertools::Itertools;
 use miette::Result;
 
+use crate::data::expr::Expr;
 use crate::data::program::SortDir;
+use crate::data::relation::Collation;
 use crate::data::symb::Symbol;
 use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
 use crate::runtime::temp_store::EpochStore;
 use crate::runtime::transact::SessionTx;
 
 impl<'a> SessionTx<'a> {
+    /// Collect the tuples of `original` sorted by `sorters`. The values of `sort_exprs`
+    /// are computed once for each tuple and appended to it, so `head` names the columns
+    /// of the tuples followed by the symbols standing for the expressions, in order.
     pub(crate) fn sort_and_collect(
         &mut self,
         original: EpochStore,
         sorters: &[(Symbol, SortDir)],
+        collations: &BTreeMap<Symbol, Collation>,
+        sort_exprs: &BTreeMap<Symbol, Expr>,
         head: &[Symbol],
     ) -> Result<Vec<Tuple>> {
-        let head_indices: BTreeMap<_, _> = head.iter().enumerate().map(|(i, k)| (k, i)).collect();
-        let idx_sorters = sorters
-            .iter()
-            .map(|(k, dir)| (head_indices[k], *dir))
-            .collect_vec();
-
+        trace_span!("sort", sorters = sorters.len());
         let mut all_data: Vec<_> = original.all_iter().map(|v| v.into_tuple()).collect_vec();
-        all_data.sort_by(|a, b| {
-            for (idx, dir) in &idx_sorters {
-                match a[*idx].cmp(&b[*idx]) {
-                    Ordering::Equal => {}
-                    o => {
-                        return match dir {
-                            SortDir::Asc => o,
-                            SortDir::Dsc => o.reverse(),
-                        }
-                    }
+        if !sort_exprs.is_empty() {
+            let head_indices: BTreeMap<_, _> = head
+                .iter()
+                .enumerate()
+                .map(|(i, k)| (k.clone(), i))
+                .collect();
+            let mut exprs = sort_exprs.values().cloned().collect_vec();
+            for expr in exprs.iter_mut() {
+                expr.fill_binding_indices(&head_indices)?;
+            }
+            for tuple in all_data.iter_mut() {
+                let vals: Vec<_> = exprs.iter().map(|expr| expr.eval(&*tuple)).try_collect()?;
+                tuple.extend(vals);
+            }
+        }
+        sort_tuples(&mut all_data, sorters, collations, head);
+        Ok(all_data)
+    }
+}
+
+fn compare_by(a: &[DataValue], b: &[DataValue], sorters: &[(usize, SortDir)]) -> Ordering {
+    for (idx, dir) in sorters {
+        match a[*idx].cmp(&b[*idx]) {
+            Ordering::Equal => {}
+            o => {
+                return match dir {
+                    SortDir::Asc => o,
+                    SortDir::Dsc => o.reverse(),
                 }
             }
-            Ordering::Equal
-        });
+        }
+    }
+    Ordering::Equal
+}
 
-        Ok(all_data)
+/// Sort `data` by `sorters`, comparing the strings held by the sort keys given `collations`
+/// under their collations
+pub(crate) fn sort_tuples(
+    data: &mut [Tuple],
+    sorters: &[(Symbol, SortDir)],
+    collations: &BTreeMap<Symbol, Collation>,
+    head: &[Symbol],
+) {
+    let head_indices: BTreeMap<_, _> = head.iter().enumerate().map(|(i, k)| (k, i)).collect();
+    let idx_sorters = sorters
+        .iter()
+        .map(|(k, dir)| (head_indices[k], *dir))
+        .collect_vec();
+
+    if collations.is_empty() {
+        data.sort_by(|a, b| compare_by(a, b, &idx_sorters));
+        return;
+    }
+
+    // the collated sort keys are computed once for each tuple, and compared in place of the tuples
+    let key_collations = sorters.iter().map(|(k, _)| collations.get(k)).collect_vec();
+    let key_sorters = idx_sorters
+        .iter()
+        .enumerate()
+        .map(|(i, (_, dir))| (i, *dir))
+        .collect_vec();
+    let mut keyed = data
+        .iter_mut()
+        .map(|tuple| {
+            let key = idx_sorters
+                .iter()
+                .zip(key_collations.iter())
+                .map(|((idx, _), collation)| match collation {
+                    Some(c) => c.apply(tuple[*idx].clone()),
+                    None => tuple[*idx].clone(),
+                })
+                .collect_vec();
+            (key, mem::take(tuple))
+        })
+        .collect_vec();
+    keyed.sort_by(|(a, _), (b, _)| compare_by(a, b, &key_sorters));
+    for (slot, (_, tuple)) in data.iter_mut().zip(keyed) {
+        *slot = tuple;
     }
 }
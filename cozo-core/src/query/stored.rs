@@ -10,7 +10,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::sync::Arc;
 
 use itertools::Itertools;
-use miette::{bail, Diagnostic, Result, WrapErr};
+use miette::{bail, miette, Diagnostic, Result, WrapErr};
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
@@ -22,7 +22,7 @@ use crate::data::tuple::Tuple;
 use crate::data::value::{DataValue, ValidityTs};
 use crate::fixed_rule::utilities::constant::Constant;
 use crate::fixed_rule::FixedRuleHandle;
-use crate::parse::parse_script;
+use crate::parse::{parse_script, SourceSpan};
 use crate::runtime::callback::{CallbackCollector, CallbackOp};
 use crate::runtime::relation::{
     extend_tuple_from_v, AccessLevel, InputRelationHandle, InsufficientAccessLevel,
@@ -36,6 +36,14 @@ use crate::{Db, NamedRows, StoreTx};
 #[diagnostic(code(eval::relation_arity_mismatch))]
 struct RelationArityMismatch(String, usize, usize);
 
+/// Numbers of rows affected by writing into a stored relation, collected for `:counts`
+#[derive(Debug, Default)]
+pub(crate) struct WriteCounts {
+    pub(crate) inserted: usize,
+    pub(crate) overwritten: usize,
+    pub(crate) retracted: usize,
+}
+
 impl<'a> SessionTx<'a> {
     pub(crate) fn execute_relation<'s, S: Storage<'s>>(
         &mut self,
@@ -48,7 +56,9 @@ impl<'a> SessionTx<'a> {
         callback_targets: &BTreeSet<SmartString<LazyCompact>>,
         callback_collector: &mut CallbackCollector,
         propagate_triggers: bool,
+        mut counts: Option<&mut WriteCounts>,
     ) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        trace_span!("write", relation = %meta.name, op = ?op);
         let mut to_clear = vec![];
         let mut replaced_old_triggers = None;
         if op == RelationOp::Replace {
@@ -127,6 +137,30 @@ impl<'a> SessionTx<'a> {
 
         let is_callback_target = callback_targets.contains(&relation_store.name);
 
+        if op == RelationOp::Put {
+            let deprecated = metadata
+                .non_keys
+                .iter()
+                .find_map(|def| Some((&def.name, relation_store.deprecated.get(&def.name)?)));
+            if let Some((col, replacement)) = deprecated {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Column {1} of stored relation {0} is deprecated")]
+                #[diagnostic(code(eval::deprecated_column))]
+                struct DeprecatedColumn(String, String, #[help] String, #[label] SourceSpan);
+
+                let help = match replacement {
+                    Some(r) => format!("Write to column {r} instead"),
+                    None => "The column can still be read, but no longer written".to_string(),
+                };
+                bail!(DeprecatedColumn(
+                    relation_store.name.to_string(),
+                    col.to_string(),
+                    help,
+                    *span
+                ))
+            }
+        }
+
         match op {
             RelationOp::Rm => {
                 if relation_store.access_level < AccessLevel::Protected {
@@ -155,15 +189,28 @@ impl<'a> SessionTx<'a> {
                         .iter()
                         .map(|ex| ex.extract_data(&tuple, cur_vld))
                         .try_collect()?;
+                    relation_store.check_tx_time(&extracted, cur_vld, *span)?;
                     let key = relation_store.encode_key_for_store(&extracted, *span)?;
+                    if !relation_store.is_temp {
+                        self.record_write()?;
+                    }
+                    if let Some(counts) = &mut counts {
+                        let existed = if relation_store.is_temp {
+                            self.temp_store_tx.exists(&key, false)?
+                        } else {
+                            self.store_tx.exists(&key, false)?
+                        };
+                        if existed {
+                            counts.retracted += 1;
+                        }
+                    }
                     if need_to_collect || has_indices {
                         if let Some(existing) = self.store_tx.get(&key, false)? {
                             let mut tup = extracted.clone();
                             extend_tuple_from_v(&mut tup, &existing);
                             if has_indices {
                                 for (idx_rel, extractor) in relation_store.indices.values() {
-                                    let idx_tup =
-                                        extractor.iter().map(|i| tup[*i].clone()).collect_vec();
+                                    let idx_tup = idx_rel.index_key_from(extractor, &tup);
                                     let encoded = idx_rel
                                         .encode_key_for_store(&idx_tup, Default::default())?;
                                     self.store_tx.del(&encoded)?;
@@ -386,12 +433,54 @@ impl<'a> SessionTx<'a> {
                     ));
                 }
 
+                // for append-only relations the last key is filled from a sequence,
+                // and since the resulting keys are new, nothing needs to be read before writing
+                let append_only = relation_store.append_only && op == RelationOp::Put;
+                let n_keys = relation_store.metadata.keys.len();
+                if append_only {
+                    let seq_col = &relation_store.metadata.keys[n_keys - 1].name;
+                    if metadata
+                        .keys
+                        .iter()
+                        .chain(metadata.non_keys.iter())
+                        .any(|col| col.name == *seq_col)
+                    {
+                        #[derive(Debug, Error, Diagnostic)]
+                        #[error("Cannot put a value for the sequence column {1} of the append-only relation {0}")]
+                        #[diagnostic(code(eval::append_only_seq_given))]
+                        #[diagnostic(help(
+                            "The sequence column is filled in on :put, leave it out"
+                        ))]
+                        struct AppendOnlySeqGiven(String, String, #[label] SourceSpan);
+
+                        bail!(AppendOnlySeqGiven(
+                            relation_store.name.to_string(),
+                            seq_col.to_string(),
+                            *span
+                        ))
+                    }
+                }
                 let mut key_extractors = make_extractors(
-                    &relation_store.metadata.keys,
+                    if append_only {
+                        &relation_store.metadata.keys[..n_keys - 1]
+                    } else {
+                        &relation_store.metadata.keys
+                    },
                     &metadata.keys,
                     key_bindings,
                     headers,
                 )?;
+                let seq_key = relation_store.append_seq_key();
+                let mut last_seq = if append_only {
+                    match self.store_tx.get(&seq_key, true)? {
+                        None => 0,
+                        Some(v) => i64::from_be_bytes(v.as_slice().try_into().map_err(|_| {
+                            miette!("corrupt sequence for relation {}", relation_store.name)
+                        })?),
+                    }
+                } else {
+                    0
+                };
 
                 let need_to_collect = !relation_store.is_temp
                     && (is_callback_target
@@ -409,30 +498,57 @@ impl<'a> SessionTx<'a> {
                 key_extractors.extend(val_extractors);
 
                 for tuple in res_iter {
-                    let extracted = key_extractors
+                    let mut extracted: Vec<DataValue> = key_extractors
                         .iter()
                         .map(|ex| ex.extract_data(&tuple, cur_vld))
                         .try_collect()?;
+                    if append_only {
+                        last_seq += 1;
+                        extracted.insert(n_keys - 1, DataValue::from(last_seq));
+                    }
+                    relation_store.check_tx_time(&extracted, cur_vld, *span)?;
 
                     let key = relation_store.encode_key_for_store(&extracted, *span)?;
                     let val = relation_store.encode_val_for_store(&extracted, *span)?;
+                    if !relation_store.is_temp {
+                        self.record_write()?;
+                    }
+
+                    if let Some(counts) = &mut counts {
+                        let existed = if append_only {
+                            false
+                        } else if relation_store.is_temp {
+                            self.temp_store_tx.exists(&key, false)?
+                        } else {
+                            self.store_tx.exists(&key, false)?
+                        };
+                        if existed {
+                            counts.overwritten += 1;
+                        } else {
+                            counts.inserted += 1;
+                        }
+                    }
 
                     if need_to_collect || has_indices {
-                        if let Some(existing) = self.store_tx.get(&key, false)? {
+                        let existing = if append_only {
+                            None
+                        } else {
+                            self.store_tx.get(&key, false)?
+                        };
+                        if let Some(existing) = existing {
                             let mut tup = extracted[0..relation_store.metadata.keys.len()].to_vec();
                             extend_tuple_from_v(&mut tup, &existing);
                             if has_indices && extracted != tup {
                                 for (idx_rel, extractor) in relation_store.indices.values() {
-                                    let idx_tup_old =
-                                        extractor.iter().map(|i| tup[*i].clone()).collect_vec();
+                                    let idx_tup_old = idx_rel.index_key_from(extractor, &tup);
                                     let encoded_old = idx_rel
                                         .encode_key_for_store(&idx_tup_old, Default::default())?;
                                     self.store_tx.del(&encoded_old)?;
 
-                                    let idx_tup_new = extractor
-                                        .iter()
-                                        .map(|i| extracted[*i].clone())
-                                        .collect_vec();
+                                    if !idx_rel.index_filter_holds(&extracted)? {
+                                        continue;
+                                    }
+                                    let idx_tup_new = idx_rel.index_key_from(extractor, &extracted);
                                     let encoded_new = idx_rel
                                         .encode_key_for_store(&idx_tup_new, Default::default())?;
                                     self.store_tx.put(&encoded_new, &[])?;
@@ -444,10 +560,10 @@ impl<'a> SessionTx<'a> {
                             }
                         } else if has_indices {
                             for (idx_rel, extractor) in relation_store.indices.values() {
-                                let idx_tup_new = extractor
-                                    .iter()
-                                    .map(|i| extracted[*i].clone())
-                                    .collect_vec();
+                                if !idx_rel.index_filter_holds(&extracted)? {
+                                    continue;
+                                }
+                                let idx_tup_new = idx_rel.index_key_from(extractor, &extracted);
                                 let encoded_new = idx_rel
                                     .encode_key_for_store(&idx_tup_new, Default::default())?;
                                 self.store_tx.put(&encoded_new, &[])?;
@@ -465,6 +581,9 @@ impl<'a> SessionTx<'a> {
                         self.store_tx.put(&key, &val)?;
                     }
                 }
+                if append_only {
+                    self.store_tx.put(&seq_key, &last_seq.to_be_bytes())?;
+                }
 
                 if need_to_collect && !new_tuples.is_empty() {
                     let mut bindings = relation_store
@@ -8,6 +8,7 @@
 
 use std::collections::btree_map::Entry;
 use std::collections::{BTreeMap, BTreeSet};
+use std::iter;
 
 use itertools::Itertools;
 use miette::{ensure, Diagnostic, Result};
@@ -28,10 +29,14 @@ impl NormalFormAtom {
         match self {
             NormalFormAtom::Relation(_)
             | NormalFormAtom::NegatedRelation(_)
+            | NormalFormAtom::ExistsRelation(_)
             | NormalFormAtom::Predicate(_)
             | NormalFormAtom::Unification(_) => Default::default(),
             NormalFormAtom::Rule(r) => BTreeMap::from([(&r.name, false)]),
-            NormalFormAtom::NegatedRule(r) => BTreeMap::from([(&r.name, true)]),
+            // like negation, `exists` needs the rule to be complete
+            NormalFormAtom::NegatedRule(r) | NormalFormAtom::ExistsRule(r) => {
+                BTreeMap::from([(&r.name, true)])
+            }
         }
     }
 }
@@ -219,8 +224,11 @@ fn make_scc_reduced_graph(
 
 impl NormalFormProgram {
     /// returns the stratified program and the store lifetimes of the intermediate relations
+    /// Stratify the rules reachable from the entry and from the rules in `output_rules`,
+    /// whose stores are kept until the end of the evaluation.
     pub(crate) fn into_stratified_program(
         self,
+        output_rules: &BTreeSet<Symbol>,
     ) -> Result<(StratifiedNormalFormProgram, BTreeMap<MagicSymbol, usize>)> {
         // prerequisite: the program is already in disjunctive normal form
         // 0. build a graph of the program
@@ -228,11 +236,15 @@ impl NormalFormProgram {
         let stratified_graph = convert_normal_form_program_to_graph(&self);
         let graph = reduce_to_graph(&stratified_graph);
 
-        // 1. find reachable clauses starting from the query
-        let reachable: BTreeSet<_> = reachable_components(&graph, &prog_entry)
-            .into_iter()
-            .map(|k| (*k).clone())
-            .collect();
+        // 1. find reachable clauses starting from the query and the output rules
+        let mut reachable = BTreeSet::new();
+        for root in iter::once(prog_entry).chain(output_rules.iter()) {
+            reachable.extend(
+                reachable_components(&graph, &root)
+                    .into_iter()
+                    .map(|k| (*k).clone()),
+            );
+        }
         // 2. prune the graph of unreachable clauses
         let stratified_graph: StratifiedGraph<_> = stratified_graph
             .into_iter()
@@ -288,6 +300,16 @@ impl NormalFormProgram {
             }
         }
 
+        // the results of the output rules are read after the last stratum
+        for rule in output_rules {
+            store_lifetimes.insert(
+                MagicSymbol::Muggle {
+                    inner: rule.clone(),
+                },
+                n_strata,
+            );
+        }
+
         for (name, ruleset) in self.prog {
             if let Some(scc_idx) = invert_indices.get(&name) {
                 if let Some(rev_stratum_idx) = invert_sort_result.get(scc_idx) {
@@ -0,0 +1,81 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::functions::op_add;
+use crate::data::program::{SortDir, WindowDef, WindowFn};
+use crate::data::symb::Symbol;
+use crate::data::tuple::Tuple;
+use crate::data::value::DataValue;
+
+/// Append the window columns to each of the `rows`, which are already in output order.
+/// Ranks are computed over the sort keys given by `sorters`.
+pub(crate) fn apply_windows(
+    rows: &mut [Tuple],
+    windows: &[WindowDef],
+    sorters: &[(Symbol, SortDir)],
+    head: &[Symbol],
+) -> Result<()> {
+    let head_indices: BTreeMap<_, _> = head.iter().enumerate().map(|(i, k)| (k, i)).collect();
+    let sort_indices = sorters.iter().map(|(k, _)| head_indices[k]).collect_vec();
+    let same_sort_keys = |a: &Tuple, b: &Tuple| sort_indices.iter().all(|i| a[*i] == b[*i]);
+
+    for window in windows {
+        let col: Vec<DataValue> = match &window.func {
+            WindowFn::RowNumber => (1..=rows.len() as i64).map(DataValue::from).collect(),
+            WindowFn::Rank | WindowFn::DenseRank => {
+                let dense = window.func == WindowFn::DenseRank;
+                let mut ret = Vec::with_capacity(rows.len());
+                let mut rank = 0;
+                for (i, row) in rows.iter().enumerate() {
+                    if i == 0 || !same_sort_keys(&rows[i - 1], row) {
+                        rank = if dense { rank + 1 } else { i as i64 + 1 };
+                    }
+                    ret.push(DataValue::from(rank));
+                }
+                ret
+            }
+            WindowFn::CumSum(col) => {
+                let idx = head_indices[col];
+                let mut sum = DataValue::from(0);
+                let mut ret = Vec::with_capacity(rows.len());
+                for row in rows.iter() {
+                    sum = op_add(&[sum, row[idx].clone()])?;
+                    ret.push(sum.clone());
+                }
+                ret
+            }
+            WindowFn::Lag(col, n) => {
+                let idx = head_indices[col];
+                (0..rows.len())
+                    .map(|i| match i.checked_sub(*n) {
+                        Some(j) => rows[j][idx].clone(),
+                        None => DataValue::Null,
+                    })
+                    .collect()
+            }
+            WindowFn::Lead(col, n) => {
+                let idx = head_indices[col];
+                (0..rows.len())
+                    .map(|i| match rows.get(i + *n) {
+                        Some(row) => row[idx].clone(),
+                        None => DataValue::Null,
+                    })
+                    .collect()
+            }
+        };
+        for (row, val) in rows.iter_mut().zip(col) {
+            row.push(val);
+        }
+    }
+    Ok(())
+}
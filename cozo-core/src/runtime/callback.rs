@@ -61,13 +61,16 @@ impl<'s, S: Storage<'s>> Db<S> {
     pub(crate) fn current_callback_targets(&self) -> BTreeSet<SmartString<LazyCompact>> {
         #[cfg(not(target_arch = "wasm32"))]
         {
-            self.event_callbacks
+            let mut targets: BTreeSet<_> = self
+                .event_callbacks
                 .read()
                 .unwrap()
                 .1
                 .keys()
                 .cloned()
-                .collect()
+                .collect();
+            targets.extend(self.sync_hook_targets());
+            targets
         }
 
         #[cfg(target_arch = "wasm32")]
@@ -0,0 +1,204 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::relation::{ColType, ColumnDef, NullableColType};
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::{Db, Storage, StoreTx};
+
+/// Identifiers that cannot name a field or a module in the generated code
+const RUST_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if", "impl", "in",
+    "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// The stored relations sharing a namespace, i.e. a prefix of their names up to a dot
+#[derive(Default)]
+struct Namespace {
+    relations: Vec<RelationHandle>,
+    children: BTreeMap<String, Namespace>,
+}
+
+fn rust_ident(name: &str) -> String {
+    if RUST_KEYWORDS.contains(&name) {
+        format!("{name}_")
+    } else {
+        name.to_string()
+    }
+}
+
+fn struct_name(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            let first = chars.next().unwrap();
+            first.to_uppercase().chain(chars).collect::<String>()
+        })
+        .collect()
+}
+
+/// The Rust type into which values of the column type are deserialized from their JSON form
+fn rust_type(typing: &NullableColType) -> String {
+    let ty = match &typing.coltype {
+        ColType::Any => "serde_json::Value".to_string(),
+        ColType::Bool => "bool".to_string(),
+        ColType::Int => "i64".to_string(),
+        ColType::Float => "f64".to_string(),
+        // bytes are encoded as base64 strings, and big integers and decimals as decimal strings
+        ColType::String | ColType::Bytes | ColType::Uuid | ColType::BigInt | ColType::Decimal => {
+            "String".to_string()
+        }
+        ColType::List { eltype, .. } => format!("Vec<{}>", rust_type(eltype)),
+        ColType::Tuple(els) if els.len() == 1 => format!("({},)", rust_type(&els[0])),
+        ColType::Tuple(els) => format!("({})", els.iter().map(rust_type).join(", ")),
+        ColType::Validity => "(i64, bool)".to_string(),
+    };
+    if typing.nullable {
+        format!("Option<{ty}>")
+    } else {
+        ty
+    }
+}
+
+impl Namespace {
+    fn insert(&mut self, handle: RelationHandle) {
+        let mut parts = handle.name.split('.').map(|s| s.to_string()).collect_vec();
+        parts.pop();
+        let mut cur = self;
+        for part in parts {
+            cur = cur.children.entry(part).or_default();
+        }
+        cur.relations.push(handle);
+    }
+    fn write(&self, out: &mut String, depth: usize) {
+        let indent = "    ".repeat(depth);
+        for handle in &self.relations {
+            let short_name = handle.name.rsplit('.').next().unwrap();
+            let ty_name = struct_name(short_name);
+            let cols = handle
+                .metadata
+                .keys
+                .iter()
+                .map(|col| (col, true))
+                .chain(handle.metadata.non_keys.iter().map(|col| (col, false)))
+                .collect_vec();
+            let col_doc = |col: &ColumnDef, is_key: bool| {
+                format!(
+                    "{}`{}: {}`",
+                    if is_key { "Key column " } else { "Column " },
+                    col.name,
+                    col.typing
+                )
+            };
+
+            writeln!(
+                out,
+                "{indent}/// Row of the stored relation `{}`",
+                handle.name
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "{indent}#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]"
+            )
+            .unwrap();
+            writeln!(out, "{indent}pub struct {ty_name} {{").unwrap();
+            for (col, is_key) in &cols {
+                writeln!(out, "{indent}    /// {}", col_doc(col, *is_key)).unwrap();
+                let field = rust_ident(&col.name);
+                if field != col.name {
+                    writeln!(out, "{indent}    #[serde(rename = \"{}\")]", col.name).unwrap();
+                }
+                if col.typing.nullable {
+                    writeln!(out, "{indent}    #[serde(default)]").unwrap();
+                }
+                writeln!(out, "{indent}    pub {field}: {},", rust_type(&col.typing)).unwrap();
+            }
+            writeln!(out, "{indent}}}\n").unwrap();
+
+            writeln!(out, "{indent}impl {ty_name} {{").unwrap();
+            writeln!(out, "{indent}    /// Name of the stored relation").unwrap();
+            writeln!(
+                out,
+                "{indent}    pub const RELATION: &'static str = \"{}\";",
+                handle.name
+            )
+            .unwrap();
+            for (col, is_key) in &cols {
+                writeln!(out, "{indent}    /// Name of the {}", col_doc(col, *is_key)).unwrap();
+                writeln!(
+                    out,
+                    "{indent}    pub const {}: &'static str = \"{}\";",
+                    col.name.to_uppercase(),
+                    col.name
+                )
+                .unwrap();
+            }
+            writeln!(out, "{indent}}}\n").unwrap();
+        }
+        for (name, child) in &self.children {
+            writeln!(
+                out,
+                "{indent}/// Stored relations in the namespace `{name}`"
+            )
+            .unwrap();
+            writeln!(out, "{indent}pub mod {} {{", rust_ident(name)).unwrap();
+            child.write(out, depth + 1);
+            writeln!(out, "{indent}}}\n").unwrap();
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Generate Rust source declaring a struct for each stored relation, for embedding
+    /// applications to work with typed rows and checked column names.
+    ///
+    /// Relations whose names contain dots are placed in nested modules, so that
+    /// `shop.order_line` becomes `shop::OrderLine`. Each struct has a field for every column,
+    /// with the type into which the column's values are deserialized by
+    /// [NamedRows::deserialize_rows](crate::NamedRows::deserialize_rows), and constants
+    /// holding the names of the relation and of its columns. Nullable columns may be missing
+    /// from the rows, as when only some columns are queried. Indices are not included.
+    ///
+    /// The generated code needs the `serde` (with the `derive` feature)
+    /// and `serde_json` crates.
+    pub fn generate_rust_types(&'s self) -> Result<String> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let tx = self.db.transact(false)?;
+        let mut root = Namespace::default();
+        for kv_res in tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            let handle = RelationHandle::decode(&v_slice)?;
+            if !handle.name.contains(':') {
+                root.insert(handle);
+            }
+        }
+        let mut out = "// Generated from the schema of a CozoDB database.\n\n".to_string();
+        root.write(&mut out, 0);
+        while out.ends_with("\n\n") {
+            out.pop();
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeSet;
+
+use miette::Result;
+
+use crate::data::aggr::AGGR_NAMES;
+use crate::data::expr::FUNCTION_NAMES;
+use crate::data::tuple::TupleT;
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::{Db, Storage, StoreTx};
+
+const SYS_OPS: &[&str] = &[
+    "relations",
+    "columns",
+    "relation_scan",
+    "sample_entities",
+    "analyze",
+    "stats",
+    "storage_stats",
+    "history",
+    "export_relation",
+    "import_relation",
+    "push",
+    "remove",
+    "remove_partition",
+    "rename",
+    "running",
+    "schedules",
+    "kill",
+    "explain",
+    "estimate",
+    "access_level",
+    "relation_kind",
+    "set_ttl",
+    "sweep_expired",
+    "deprecate",
+    "undeprecate",
+    "derive",
+    "underive",
+    "schedule_visibility",
+    "unschedule_visibility",
+    "valid_time",
+    "index",
+    "compact",
+    "vacuum",
+    "integrity_check",
+    "fixed_rules",
+    "show_triggers",
+    "set_triggers",
+    "save_query",
+    "remove_query",
+    "saved_queries",
+    "call",
+];
+
+const QUERY_OPTIONS: &[&str] = &[
+    "limit",
+    "offset",
+    "sort",
+    "order",
+    "window",
+    "create",
+    "replace",
+    "put",
+    "rm",
+    "ensure",
+    "ensure_not",
+    "timeout",
+    "partial_on_timeout",
+    "sleep",
+    "assert",
+    "set_var",
+    "counts",
+    "profile",
+    "import",
+    "at",
+    "since_last",
+];
+
+const IMPERATIVE_KEYWORDS: &[&str] = &[
+    "if",
+    "if_not",
+    "then",
+    "else",
+    "end",
+    "loop",
+    "mark",
+    "break",
+    "continue",
+    "return",
+    "debug",
+    "swap",
+    "ignore_error",
+];
+
+/// Keywords after which the name of a stored relation is expected
+const RELATION_TAKING: &[&str] = &[
+    ":create",
+    ":replace",
+    ":put",
+    ":rm",
+    ":ensure",
+    ":ensure_not",
+    "::columns",
+    "::relation_scan",
+    "::analyze",
+    "::stats",
+    "::remove",
+    "::remove_partition",
+    "::rename",
+    "::show_triggers",
+    "::set_triggers",
+    "::access_level",
+    "::relation_kind",
+    "::set_ttl",
+    "::sweep_expired",
+    "::deprecate",
+    "::undeprecate",
+    "::derive",
+    "::underive",
+    "::schedule_visibility",
+    "::unschedule_visibility",
+    "::valid_time",
+    "append_only",
+    "normal",
+    "protected",
+    "read_only",
+    "hidden",
+];
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Completion candidates for the partial token `prefix`, with `context` being the
+    /// script text before it. Candidates are drawn from the stored relations and fixed rules
+    /// currently known to the database, and from the builtin system ops, query options,
+    /// imperative keywords, functions and aggregations.
+    ///
+    /// The candidates returned are full tokens including any sigil, e.g. `*friends`,
+    /// `::relations` or `:limit`, sorted and deduplicated.
+    pub fn completions(&'s self, prefix: &str, context: &str) -> Result<Vec<String>> {
+        let last_word = context.split_whitespace().last().unwrap_or("");
+        let candidates: BTreeSet<String> = if let Some(p) = prefix.strip_prefix("::") {
+            with_sigil("::", SYS_OPS.iter().copied(), p)
+        } else if let Some(p) = prefix.strip_prefix('*') {
+            with_sigil("*", self.relation_names()?.iter().map(|s| s.as_str()), p)
+        } else if let Some(p) = prefix.strip_prefix(':') {
+            with_sigil(":", QUERY_OPTIONS.iter().copied(), p)
+        } else if let Some(p) = prefix.strip_prefix('%') {
+            with_sigil("%", IMPERATIVE_KEYWORDS.iter().copied(), p)
+        } else if last_word.ends_with("<~") {
+            let fixed_rules = self.fixed_rules.read().unwrap();
+            with_sigil("", fixed_rules.keys().map(|s| s.as_str()), prefix)
+        } else if RELATION_TAKING.contains(&last_word) {
+            with_sigil(
+                "",
+                self.relation_names()?.iter().map(|s| s.as_str()),
+                prefix,
+            )
+        } else {
+            with_sigil(
+                "",
+                FUNCTION_NAMES.iter().chain(AGGR_NAMES.iter()).copied(),
+                prefix,
+            )
+        };
+        Ok(candidates.into_iter().collect())
+    }
+
+    fn relation_names(&'s self) -> Result<Vec<String>> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let tx = self.db.transact(false)?;
+        let mut ret = vec![];
+        for kv_res in tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            let meta = RelationHandle::decode(&v_slice)?;
+            if !meta.name.contains(':') {
+                ret.push(meta.name.to_string());
+            }
+        }
+        Ok(ret)
+    }
+}
+
+fn with_sigil<'a>(
+    sigil: &str,
+    names: impl Iterator<Item = &'a str>,
+    prefix: &str,
+) -> BTreeSet<String> {
+    names
+        .filter(|name| name.starts_with(prefix))
+        .map(|name| format!("{sigil}{name}"))
+        .collect()
+}
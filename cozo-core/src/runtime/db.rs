@@ -14,11 +14,11 @@ use std::iter;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
 #[allow(unused_imports)]
-use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
 #[allow(unused_imports)]
 use std::thread;
 #[allow(unused_imports)]
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 #[allow(unused_imports)]
 use crossbeam::channel::{bounded, Receiver, Sender, unbounded};
@@ -35,27 +35,40 @@ use thiserror::Error;
 use crate::{decode_tuple_from_kv, FixedRule};
 use crate::data::functions::current_validity;
 use crate::data::json::JsonValue;
+use crate::data::msgpack::MsgpackValue;
 use crate::data::program::{InputProgram, QueryAssertion, RelationOp};
 use crate::data::relation::ColumnDef;
 use crate::data::tuple::{Tuple, TupleT};
 use crate::data::value::{DataValue, LARGEST_UTF_CHAR, ValidityTs};
 use crate::fixed_rule::DEFAULT_FIXED_RULES;
 use crate::parse::{CozoScript, parse_script, SourceSpan};
+use crate::parse::query::LiteralWrite;
 use crate::parse::sys::SysOp;
-use crate::query::compile::{CompiledProgram, CompiledRule, CompiledRuleSet};
+use crate::query::compile::{
+    entry_in_storage_order, CompiledProgram, CompiledRule, CompiledRuleSet,
+};
 use crate::query::ra::{
     FilteredRA, InnerJoin, NegJoin, RelAlgebra, ReorderRA, StoredRA, StoredWithValidityRA,
     TempStoreRA, UnificationRA,
 };
+use crate::query::sort::sort_tuples;
+use crate::query::stored::WriteCounts;
+use crate::query::window::apply_windows;
 #[allow(unused_imports)]
 use crate::runtime::callback::{
     CallbackCollector, CallbackDeclaration, CallbackOp, EventCallbackRegistry,
 };
+use crate::runtime::metrics::{LatencyHistogram, OpenGuard};
 use crate::runtime::relation::{
     AccessLevel, extend_tuple_from_v, InsufficientAccessLevel, RelationHandle, RelationId,
 };
+use crate::runtime::schedule::ScheduleRegistry;
+use crate::runtime::session::Session;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::runtime::sync_hook::SyncHookRegistry;
 use crate::runtime::transact::SessionTx;
 use crate::storage::{Storage, StoreTx};
+use crate::storage::profiled::{ProfiledTx, StorageCounters, StorageCounts};
 use crate::storage::temp::TempStorage;
 
 pub(crate) struct RunningQueryHandle {
@@ -66,13 +79,20 @@ pub(crate) struct RunningQueryHandle {
 pub(crate) struct RunningQueryCleanup {
     pub(crate) id: u64,
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
+    pub(crate) query_latency: Arc<Mutex<LatencyHistogram>>,
 }
 
 impl Drop for RunningQueryCleanup {
     fn drop(&mut self) {
-        let mut map = self.running_queries.lock().unwrap();
-        if let Some(handle) = map.remove(&self.id) {
-            handle.poison.0.store(true, Ordering::Relaxed);
+        let handle = self.running_queries.lock().unwrap().remove(&self.id);
+        if let Some(handle) = handle {
+            handle.poison.killed.store(true, Ordering::Relaxed);
+            if let Ok(now) = seconds_since_the_epoch() {
+                self.query_latency
+                    .lock()
+                    .unwrap()
+                    .record(now - handle.started_at);
+            }
         }
     }
 }
@@ -87,15 +107,29 @@ pub struct DbManifest {
 pub struct Db<S> {
     pub(crate) db: S,
     temp_db: TempStorage,
-    relation_store_id: Arc<AtomicU64>,
+    pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) queries_count: Arc<AtomicU64>,
     pub(crate) running_queries: Arc<Mutex<BTreeMap<u64, RunningQueryHandle>>>,
+    pub(crate) query_latency: Arc<Mutex<LatencyHistogram>>,
+    pub(crate) open_sessions: Arc<AtomicUsize>,
+    pub(crate) open_transactions: Arc<AtomicUsize>,
     pub(crate) fixed_rules: Arc<ShardedLock<BTreeMap<String, Arc<Box<dyn FixedRule>>>>>,
     #[cfg(not(target_arch = "wasm32"))]
     callback_count: Arc<AtomicU32>,
     #[cfg(not(target_arch = "wasm32"))]
     pub(crate) event_callbacks: Arc<ShardedLock<EventCallbackRegistry>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) sync_hooks: Arc<ShardedLock<SyncHookRegistry>>,
     relation_locks: Arc<ShardedLock<BTreeMap<SmartString<LazyCompact>, Arc<ShardedLock<()>>>>>,
+    pub(crate) schedules: Arc<Mutex<ScheduleRegistry>>,
+    /// Zero means unlimited
+    const_rule_row_limit: Arc<AtomicUsize>,
+    /// Zero means unlimited
+    tx_write_limit: Arc<AtomicUsize>,
+    /// Set by [Db::set_script_file_access]
+    pub(crate) script_file_access: Arc<AtomicBool>,
+    last_tx_id: Arc<AtomicU64>,
+    closed: Arc<AtomicBool>,
 }
 
 impl<S> Debug for Db<S> {
@@ -109,13 +143,48 @@ impl<S> Debug for Db<S> {
 #[diagnostic(code(db::init))]
 pub(crate) struct BadDbInit(#[help] pub(crate) String);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The database is closed")]
+#[diagnostic(code(db::closed))]
+pub(crate) struct DbClosed;
+
 #[derive(Debug, Error, Diagnostic)]
 #[error("Cannot import data into relation {0} as it is an index")]
 #[diagnostic(code(tx::import_into_index))]
 pub(crate) struct ImportIntoIndex(pub(crate) String);
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Required header {0} not found in the data for relation {1}")]
+#[diagnostic(code(tx::import_header_not_found))]
+pub(crate) struct ImportHeaderNotFound(pub(crate) String, pub(crate) String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Row {0:?} to import is too short")]
+#[diagnostic(code(tx::import_row_too_short))]
+pub(crate) struct ImportRowTooShort(pub(crate) Vec<DataValue>);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation {0} has no validity column and hence no history")]
+#[diagnostic(code(eval::history_without_validity))]
+#[diagnostic(help("The last key column of the relation must be of type `Validity`"))]
+pub(crate) struct NoValidityForHistory(pub(crate) String, #[label] pub(crate) SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Expected {0} key values for the history, got {1}")]
+#[diagnostic(code(eval::history_key_length))]
+#[diagnostic(help("Give the values of all key columns except the validity"))]
+pub(crate) struct HistoryKeyLength(
+    pub(crate) usize,
+    pub(crate) usize,
+    #[label] pub(crate) SourceSpan,
+);
+
 #[derive(serde_derive::Serialize, serde_derive::Deserialize, Debug, Clone, Default)]
 /// Rows in a relation, together with headers for the fields.
+///
+/// Fields may be added in minor versions, so outside this crate it is built with
+/// [NamedRows::new] rather than a struct expression.
+#[non_exhaustive]
 pub struct NamedRows {
     /// The headers
     pub headers: Vec<String>,
@@ -123,6 +192,15 @@ pub struct NamedRows {
     pub rows: Vec<Tuple>,
     /// Contains the next named rows, if exists
     pub next: Option<Box<NamedRows>>,
+    /// For the result of a script, the id of the last write transaction visible to it,
+    /// which is the id of the script's own transaction if it writes. See [Db::current_tx_id].
+    #[serde(default)]
+    pub tx_id: Option<u64>,
+    /// Whether the result is incomplete: either the query given `:partial_on_timeout` exceeded
+    /// its timeout, in which case the rows are those derived before it did, or a rule annotated
+    /// with `@max_depth` was stopped at its depth while it could still derive new rows
+    #[serde(default)]
+    pub truncated: bool,
 }
 
 impl NamedRows {
@@ -132,6 +210,8 @@ impl NamedRows {
             headers,
             rows,
             next: None,
+            tx_id: None,
+            truncated: false,
         }
     }
 
@@ -156,7 +236,11 @@ impl NamedRows {
         collected
     }
 
-    /// Convert to a JSON object
+    /// Convert to a JSON object.
+    ///
+    /// Each row is emitted as an array in the order of `headers`, and the keys of
+    /// the returned object are always in sorted order, so that the output for the same
+    /// rows is identical across runs and platforms.
     pub fn into_json(self) -> JsonValue {
         let nxt = match self.next {
             None => json!(null),
@@ -167,11 +251,70 @@ impl NamedRows {
             .into_iter()
             .map(|row| row.into_iter().map(JsonValue::from).collect::<JsonValue>())
             .collect::<JsonValue>();
-        json!({
+        let mut ret = json!({
             "headers": self.headers,
             "rows": rows,
             "next": nxt,
-        })
+        });
+        if let Some(tx_id) = self.tx_id {
+            ret.as_object_mut()
+                .unwrap()
+                .insert("tx_id".to_string(), json!(tx_id));
+        }
+        if self.truncated {
+            ret.as_object_mut()
+                .unwrap()
+                .insert("truncated".to_string(), json!(true));
+        }
+        ret
+    }
+    /// Convert to a msgpack map with the same keys as [NamedRows::into_json]. Unlike JSON,
+    /// integers and floats stay distinct, bytes are sent as binary and non-finite floats
+    /// are kept as they are.
+    pub(crate) fn into_msgpack(self) -> MsgpackValue {
+        let nxt = match self.next {
+            None => MsgpackValue::Nil,
+            Some(more) => more.into_msgpack(),
+        };
+        let headers = self
+            .headers
+            .into_iter()
+            .map(MsgpackValue::from)
+            .collect_vec();
+        let rows = self
+            .rows
+            .into_iter()
+            .map(|row| MsgpackValue::Array(row.into_iter().map(MsgpackValue::from).collect()))
+            .collect_vec();
+        let mut ret = vec![
+            (MsgpackValue::from("headers"), MsgpackValue::Array(headers)),
+            (MsgpackValue::from("next"), nxt),
+            (MsgpackValue::from("rows"), MsgpackValue::Array(rows)),
+        ];
+        if let Some(tx_id) = self.tx_id {
+            ret.push((MsgpackValue::from("tx_id"), MsgpackValue::from(tx_id)));
+        }
+        if self.truncated {
+            ret.push((MsgpackValue::from("truncated"), MsgpackValue::from(true)));
+        }
+        MsgpackValue::Map(ret)
+    }
+    /// Deserialize each row into a `T` having fields named after the headers, such as
+    /// the structs generated by [Db::generate_rust_types]. Values are first converted
+    /// to JSON as in [NamedRows::into_json].
+    pub fn deserialize_rows<T: serde::de::DeserializeOwned>(&self) -> Result<Vec<T>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                let obj: serde_json::Map<String, JsonValue> = self
+                    .headers
+                    .iter()
+                    .cloned()
+                    .zip(row.iter().map(|v| JsonValue::from(v.clone())))
+                    .collect();
+                serde_json::from_value(JsonValue::Object(obj)).into_diagnostic()
+            })
+            .collect()
     }
     /// Make named rows from JSON
     pub fn from_json(value: &JsonValue) -> Result<Self> {
@@ -204,12 +347,14 @@ impl NamedRows {
             headers,
             rows,
             next: None,
+            tx_id: None,
+            truncated: false,
         })
     }
 }
 
-const STATUS_STR: &str = "status";
-const OK_STR: &str = "OK";
+pub(crate) const STATUS_STR: &str = "status";
+pub(crate) const OK_STR: &str = "OK";
 
 /// Commands to be sent to a multi-transaction
 #[derive(Eq, PartialEq, Debug)]
@@ -233,13 +378,24 @@ impl<'s, S: Storage<'s>> Db<S> {
             relation_store_id: Default::default(),
             queries_count: Default::default(),
             running_queries: Default::default(),
+            query_latency: Default::default(),
+            open_sessions: Default::default(),
+            open_transactions: Default::default(),
             fixed_rules: Arc::new(ShardedLock::new(DEFAULT_FIXED_RULES.clone())),
             #[cfg(not(target_arch = "wasm32"))]
             callback_count: Default::default(),
             // callback_receiver: Arc::new(receiver),
             #[cfg(not(target_arch = "wasm32"))]
             event_callbacks: Default::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            sync_hooks: Default::default(),
             relation_locks: Default::default(),
+            schedules: Default::default(),
+            const_rule_row_limit: Default::default(),
+            tx_write_limit: Default::default(),
+            script_file_access: Default::default(),
+            last_tx_id: Default::default(),
+            closed: Default::default(),
         };
         Ok(ret)
     }
@@ -250,6 +406,89 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(())
     }
 
+    /// Limit the number of rows a constant rule may hold, counted after parameters are
+    /// substituted. Queries exceeding the limit fail before they are compiled.
+    /// `None` removes the limit, which is the default.
+    ///
+    /// The rows of constant rules are always held in memory while the query runs: they are
+    /// neither streamed nor spilled to disk. Larger inputs should be loaded with
+    /// [`import_relations`](Self::import_relations) instead, which writes them in batches.
+    pub fn set_const_rule_row_limit(&self, limit: Option<usize>) {
+        self.const_rule_row_limit.store(limit.unwrap_or(0), Ordering::Release);
+    }
+
+    /// Limit the number of rows a single write transaction may write to or remove from
+    /// stored relations. Transactions exceeding the limit fail and are rolled back.
+    /// `None` removes the limit, which is the default.
+    pub fn set_tx_write_limit(&self, limit: Option<usize>) {
+        self.tx_write_limit.store(limit.unwrap_or(0), Ordering::Release);
+    }
+
+    /// Allow scripts to read and write files of the host, with `::export_relation`
+    /// and `::import_relation`, and to open other databases with `::push`. This is off
+    /// by default, since anyone able to run scripts, e.g. through a server, would otherwise
+    /// have the file access of the process.
+    pub fn set_script_file_access(&self, allow: bool) {
+        self.script_file_access.store(allow, Ordering::Release);
+    }
+
+    /// Id of the last committed write transaction. Ids count up from zero each time the database
+    /// is opened, so this serves as a version of the data, e.g. for validating caches.
+    pub fn current_tx_id(&self) -> u64 {
+        self.last_tx_id.load(Ordering::Acquire)
+    }
+
+    /// Close the database. From now on no transaction can be started, scheduled scripts,
+    /// sync hooks and event callbacks are stopped, and running queries are given `grace`
+    /// to finish before they are killed. Finally the storage engine persists buffered writes.
+    ///
+    /// The storage engine, together with the files it has open, is released once all clones
+    /// of the database are dropped, which no longer includes those held by background threads.
+    /// Closing a closed database only waits for the queries still running.
+    pub fn close(&self, grace: Duration) -> Result<()> {
+        self.closed.store(true, Ordering::Release);
+        for (_, schedule) in std::mem::take(&mut *self.schedules.lock().unwrap()) {
+            schedule.cancelled.store(true, Ordering::Relaxed);
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            // dropping the senders stops the delivery threads
+            self.sync_hooks.write().unwrap().clear();
+            *self.event_callbacks.write().unwrap() = Default::default();
+        }
+        if !self.wait_for_running_queries(grace) {
+            for handle in self.running_queries.lock().unwrap().values() {
+                handle.poison.killed.store(true, Ordering::Relaxed);
+            }
+            if !self.wait_for_running_queries(grace) {
+                bail!("Queries are still running after being killed");
+            }
+        }
+        self.db.flush()
+    }
+
+    /// Whether the running queries finish within `timeout`
+    fn wait_for_running_queries(&self, timeout: Duration) -> bool {
+        if self.running_queries.lock().unwrap().is_empty() {
+            return true;
+        }
+        let deadline = Instant::now() + timeout;
+        while !self.running_queries.lock().unwrap().is_empty() {
+            match deadline.checked_duration_since(Instant::now()) {
+                None => return false,
+                Some(remaining) => thread::sleep(remaining.min(Duration::from_millis(10))),
+            }
+        }
+        true
+    }
+
+    pub(crate) fn ensure_open(&self) -> Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            bail!(DbClosed)
+        }
+        Ok(())
+    }
+
     /// Run a multi-transaction. A command should be sent to `payloads`, and the result should be
     /// retrieved from `results`. A transaction ends when it receives a `Commit` or `Abort`,
     /// or when a query is not successful. After a transaction ends, sending / receiving from
@@ -276,6 +515,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 return;
             }
         };
+        let _open = OpenGuard::new(&self.open_transactions);
 
         let ts = current_validity();
         let callback_targets = self.current_callback_targets();
@@ -285,9 +525,22 @@ impl<'s, S: Storage<'s>> Db<S> {
         for payload in payloads {
             match payload {
                 TransactionPayload::Commit => {
-                    let _ = results.send(tx.commit_tx().map(|_| NamedRows::default()));
+                    #[cfg(not(target_arch = "wasm32"))]
+                    let res = self.commit_with_sync_journals(
+                        &mut tx,
+                        &callback_collector,
+                        &Default::default(),
+                    );
+                    #[cfg(target_arch = "wasm32")]
+                    let res = tx.commit_tx();
+                    let failed = res.is_err();
+                    let _ = results.send(res.map(|_| NamedRows::default()));
+                    if failed {
+                        break;
+                    }
                     #[cfg(not(target_arch = "wasm32"))]
                     if !callback_collector.is_empty() {
+                        self.notify_sync_hooks(&callback_collector);
                         self.send_callbacks(callback_collector)
                     }
 
@@ -327,7 +580,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                             }
                         }
                     };
-                    if let Some(write_lock_name) = p.needs_write_lock() {
+                    for write_lock_name in p.needs_write_lock() {
                         match write_locks.entry(write_lock_name) {
                             Entry::Vacant(e) => {
                                 let lock = self
@@ -363,7 +616,182 @@ impl<'s, S: Storage<'s>> Db<S> {
         params: BTreeMap<String, DataValue>,
     ) -> Result<NamedRows> {
         let cur_vld = current_validity();
-        self.do_run_script(payload, &params, cur_vld)
+        self.do_run_script(payload, &params, cur_vld, None, None)
+    }
+    /// Run the CozoScript passed in like [Db::run_script], but kill the queries it runs
+    /// once `cancel_token` is cancelled, as `::kill` does. Tie the token to the lifecycle
+    /// of the connection the script comes from, so that queries are not left running
+    /// after the client has gone away.
+    pub fn run_script_cancellable(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+        cancel_token: &CancelToken,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        self.do_run_script(payload, &params, cur_vld, None, Some(cancel_token))
+    }
+    /// Run several scripts with all-or-nothing semantics. All scripts are parsed before any is
+    /// executed, then they are executed in order within a single write transaction, which is
+    /// committed only if all of them succeed. Returns the result of each script.
+    ///
+    /// Each script must be a single query: imperative scripts and system ops are rejected.
+    /// The `params` are shared by all scripts.
+    ///
+    /// The queries are run as by [Db::run_script], so each is listed by `::running` while it
+    /// runs. Killing one with `::kill` rolls back the whole batch.
+    ///
+    /// Preconditions can be checked atomically with the writes by putting queries with
+    /// `:assert some` or `:assert none` before them: as these are evaluated in the same
+    /// transaction, no concurrent write can invalidate them before the writes are committed.
+    pub fn run_scripts(
+        &'s self,
+        payloads: &[&str],
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<Vec<NamedRows>> {
+        let cur_vld = current_validity();
+        let progs: Vec<_> = payloads
+            .iter()
+            .enumerate()
+            .map(|(i, payload)| -> Result<InputProgram> {
+                parse_script(payload, &params, &self.fixed_rules.read().unwrap(), cur_vld)
+                    .and_then(|script| match script {
+                        CozoScript::LiteralWrite(w) => self.resolve_literal_write(*w),
+                        script => script.get_single_program(),
+                    })
+                    .map_err(|err| {
+                        err.with_source_code(payload.to_string())
+                            .wrap_err(format!("when parsing script {i}"))
+                    })
+            })
+            .try_collect()?;
+
+        let write_lock_names: BTreeSet<_> =
+            progs.iter().flat_map(|p| p.needs_write_lock()).collect();
+        let write_locks = self.obtain_relation_locks(write_lock_names.iter());
+        let _write_lock_guards = write_locks.iter().map(|l| l.read().unwrap()).collect_vec();
+        let callback_targets = self.current_callback_targets();
+        let mut callback_collector = BTreeMap::new();
+        let mut cleanups = vec![];
+        let mut ret = Vec::with_capacity(progs.len());
+        {
+            let mut tx = self.transact_write()?;
+            for (i, p) in progs.into_iter().enumerate() {
+                let res = self
+                    .execute_single_program(
+                        p,
+                        &mut tx,
+                        &mut cleanups,
+                        cur_vld,
+                        &callback_targets,
+                        &mut callback_collector,
+                    )
+                    .map_err(|err| {
+                        err.with_source_code(payloads[i].to_string())
+                            .wrap_err(format!("when executing script {i}"))
+                    })?;
+                ret.push(res);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            self.commit_with_sync_journals(&mut tx, &callback_collector, &write_lock_names)?;
+            #[cfg(target_arch = "wasm32")]
+            tx.commit_tx()?;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if !callback_collector.is_empty() {
+            self.notify_sync_hooks(&callback_collector);
+            self.send_callbacks(callback_collector)
+        }
+        for (lower, upper) in cleanups {
+            self.db.del_range(&lower, &upper)?;
+        }
+        Ok(ret)
+    }
+    /// Parse the CozoScript passed in without executing it and without touching storage.
+    ///
+    /// Returns a single row summarizing the script: its kind (`query`, `imperative`, `sys` or `write`),
+    /// the names of the rules defined, the output columns of the entry rule, and the stored
+    /// relations that would be written to. Parse errors are returned as usual, carrying spans
+    /// into `payload`.
+    pub fn parse_only(
+        &'s self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let script = parse_script(
+            payload,
+            &params,
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        )?;
+        let row = match script {
+            CozoScript::Single(p) => {
+                let rules = p
+                    .prog
+                    .keys()
+                    .map(|k| DataValue::from(k.name.as_str()))
+                    .collect_vec();
+                let output = p
+                    .get_entry_out_head_or_default()?
+                    .into_iter()
+                    .map(|s| DataValue::from(s.name.as_str()))
+                    .collect_vec();
+                let writes = p
+                    .needs_write_lock()
+                    .into_iter()
+                    .map(|s| DataValue::from(s.as_str()))
+                    .collect_vec();
+                vec![
+                    DataValue::from("query"),
+                    DataValue::List(rules),
+                    DataValue::List(output),
+                    DataValue::List(writes),
+                ]
+            }
+            CozoScript::Imperative(ps) => {
+                let mut writes = BTreeSet::new();
+                for p in ps.iter() {
+                    p.needs_write_locks(&mut writes);
+                }
+                vec![
+                    DataValue::from("imperative"),
+                    DataValue::Null,
+                    DataValue::Null,
+                    DataValue::List(
+                        writes
+                            .iter()
+                            .map(|s| DataValue::from(s.as_str()))
+                            .collect_vec(),
+                    ),
+                ]
+            }
+            CozoScript::Sys(_) => vec![
+                DataValue::from("sys"),
+                DataValue::Null,
+                DataValue::Null,
+                DataValue::Null,
+            ],
+            CozoScript::LiteralWrite(w) => vec![
+                DataValue::from("write"),
+                DataValue::Null,
+                DataValue::Null,
+                DataValue::List(vec![DataValue::from(w.name.name.as_str())]),
+            ],
+        };
+        Ok(NamedRows::new(
+            vec![
+                "kind".to_string(),
+                "rules".to_string(),
+                "output".to_string(),
+                "writes".to_string(),
+            ],
+            vec![row],
+        ))
+    }
+    /// Create a new session on this database. A session carries settings, such as the
+    /// default validity, that apply to every script run through it.
+    pub fn new_session(&'s self) -> Session<'s, S> {
+        Session::new(self)
     }
     /// Export relations to JSON data.
     ///
@@ -475,11 +903,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 .iter()
                 .map(|col| -> Result<(usize, &ColumnDef)> {
                     let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
-                        miette!(
-                            "required header {} not found for relation {}",
-                            col.name,
-                            relation
-                        )
+                        ImportHeaderNotFound(col.name.to_string(), relation.to_string())
                     })?;
                     Ok((*idx, col))
                 })
@@ -494,11 +918,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                     .iter()
                     .map(|col| -> Result<(usize, &ColumnDef)> {
                         let idx = header2idx.get(&col.name as &str).ok_or_else(|| {
-                            miette!(
-                                "required header {} not found for relation {}",
-                                col.name,
-                                relation
-                            )
+                            ImportHeaderNotFound(col.name.to_string(), relation.to_string())
                         })?;
                         Ok((*idx, col))
                     })
@@ -506,12 +926,13 @@ impl<'s, S: Storage<'s>> Db<S> {
             };
 
             for row in in_data.rows {
+                tx.record_write()?;
                 let keys: Vec<_> = key_indices
                     .iter()
                     .map(|(i, col)| -> Result<DataValue> {
                         let v = row
                             .get(*i)
-                            .ok_or_else(|| miette!("row too short: {:?}", row))?;
+                            .ok_or_else(|| ImportRowTooShort(row.clone()))?;
                         col.typing.coerce(v.clone(), cur_vld)
                     })
                     .try_collect()?;
@@ -522,8 +943,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         extend_tuple_from_v(&mut old, &existing);
                         if is_delete || old != row {
                             for (idx_rel, extractor) in handle.indices.values() {
-                                let idx_tup =
-                                    extractor.iter().map(|i| old[*i].clone()).collect_vec();
+                                let idx_tup = idx_rel.index_key_from(extractor, &old);
                                 let encoded =
                                     idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
                                 tx.store_tx.del(&encoded)?;
@@ -539,7 +959,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         .map(|(i, col)| -> Result<DataValue> {
                             let v = row
                                 .get(*i)
-                                .ok_or_else(|| miette!("row too short: {:?}", row))?;
+                                .ok_or_else(|| ImportRowTooShort(row.clone()))?;
                             col.typing.coerce(v.clone(), cur_vld)
                         })
                         .try_collect()?;
@@ -549,7 +969,10 @@ impl<'s, S: Storage<'s>> Db<S> {
                         let mut kv = keys;
                         kv.extend(vals);
                         for (idx_rel, extractor) in handle.indices.values() {
-                            let idx_tup = extractor.iter().map(|i| kv[*i].clone()).collect_vec();
+                            if !idx_rel.index_filter_holds(&kv)? {
+                                continue;
+                            }
+                            let idx_tup = idx_rel.index_key_from(extractor, &kv);
                             let encoded =
                                 idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
                             tx.store_tx.put(&encoded, &[])?;
@@ -561,6 +984,36 @@ impl<'s, S: Storage<'s>> Db<S> {
         tx.commit_tx()?;
         Ok(())
     }
+    /// Import relations like [`import_relations`](Self::import_relations), but in transactions
+    /// of at most `chunk_size` rows each, so that imports of any size stay within
+    /// the limits of a single transaction.
+    ///
+    /// The import is _not_ atomic: if it fails, the chunks committed so far are kept.
+    /// After each chunk `progress` is called with the name of the relation, the number of
+    /// its rows imported so far and its total number of rows.
+    pub fn import_relations_chunked(
+        &'s self,
+        data: BTreeMap<String, NamedRows>,
+        chunk_size: usize,
+        mut progress: impl FnMut(&str, usize, usize),
+    ) -> Result<()> {
+        ensure!(chunk_size > 0, "chunk size for import must be positive");
+        for (relation, in_data) in data {
+            let total = in_data.rows.len();
+            let mut done = 0;
+            let mut rows = in_data.rows.into_iter().peekable();
+            while rows.peek().is_some() {
+                let chunk = rows.by_ref().take(chunk_size).collect_vec();
+                done += chunk.len();
+                self.import_relations(BTreeMap::from([(
+                    relation.clone(),
+                    NamedRows::new(in_data.headers.clone(), chunk),
+                )]))?;
+                progress(&relation, done, total);
+            }
+        }
+        Ok(())
+    }
     /// Backup the running database into an Sqlite file
     #[allow(unused_variables)]
     pub fn backup_db(&'s self, out_file: impl AsRef<Path>) -> Result<()> {
@@ -579,6 +1032,14 @@ impl<'s, S: Storage<'s>> Db<S> {
         #[cfg(not(feature = "storage-sqlite"))]
         bail!("backup requires the 'storage-sqlite' feature to be enabled")
     }
+    /// Copy the database to `path`, which must not exist yet, without taking it offline.
+    /// The copy is consistent as of the moment it is taken, independent of the original,
+    /// and can be opened with the same storage engine, e.g. to seed a test environment.
+    /// Supported by the RocksDB and Sqlite engines.
+    pub fn clone_to(&self, path: impl AsRef<Path>) -> Result<()> {
+        self.ensure_open()?;
+        self.db.checkpoint(path.as_ref())
+    }
     /// Restore from an Sqlite backup
     #[allow(unused_variables)]
     pub fn restore_backup(&'s self, in_file: impl AsRef<Path>) -> Result<()> {
@@ -678,6 +1139,44 @@ impl<'s, S: Storage<'s>> Db<S> {
             dst_tx.commit_tx()
         }
     }
+    /// Get the next value of the named persistent sequence. Sequences start at 1 and are
+    /// created on first use. Values are never handed out twice, even across processes
+    /// sharing the same storage.
+    pub fn next_in_sequence(&'s self, name: &str) -> Result<i64> {
+        self.reserve_in_sequence(name, 1)
+    }
+    /// Atomically reserve `count` consecutive values of the named persistent sequence,
+    /// returning the first of them. Useful to amortize the cost of a write transaction
+    /// when many values are needed.
+    pub fn reserve_in_sequence(&'s self, name: &str, count: u32) -> Result<i64> {
+        ensure!(count > 0, "must reserve at least one value from sequence {}", name);
+        let lock_name = SmartString::from(format!("*sequence*{name}"));
+        let lock = self.obtain_relation_locks(iter::once(&lock_name)).pop().unwrap();
+        let _guard = lock.write().unwrap();
+
+        let key = vec![
+            DataValue::Null,
+            DataValue::from("SEQUENCE"),
+            DataValue::from(name),
+        ]
+        .encode_as_key(RelationId::SYSTEM);
+        let mut tx = self.transact_write()?;
+        let last = match tx.store_tx.get(&key, true)? {
+            None => 0,
+            Some(v) => i64::from_be_bytes(
+                v.as_slice()
+                    .try_into()
+                    .map_err(|_| miette!("corrupt value for sequence {}", name))?,
+            ),
+        };
+        let next = last + 1;
+        let new_last = last
+            .checked_add(count as i64)
+            .ok_or_else(|| miette!("sequence {} overflowed", name))?;
+        tx.store_tx.put(&key, &new_last.to_be_bytes())?;
+        tx.commit_tx()?;
+        Ok(next)
+    }
     /// Register a custom fixed rule implementation.
     pub fn register_fixed_rule<R>(&self, name: String, rule_impl: R) -> Result<()>
     where
@@ -784,7 +1283,80 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(())
     }
 
-    fn load_last_ids(&'s self) -> Result<()> {
+    /// Find data left behind in storage by relations and indices that no longer exist,
+    /// and unless `dry_run` is set, delete and compact it.
+    fn vacuum(&'s self, dry_run: bool) -> Result<NamedRows> {
+        // no relation is created, written or removed while the locks are held,
+        // and the scans and deletes below are in the same write transaction
+        let locks = self
+            .relation_locks
+            .read()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect_vec();
+        let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+        let mut tx = self.transact_write()?;
+        // relations created from now on have larger ids, and are never taken as orphans
+        let max_id = self.relation_store_id.load(Ordering::Acquire);
+        let mut live_ids = BTreeSet::from([RelationId::SYSTEM.0]);
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            live_ids.insert(RelationHandle::decode(&v_slice)?.id.0);
+        }
+        let mut orphans: BTreeMap<u64, (i64, i64)> = BTreeMap::new();
+        let mut orphan_keys = vec![];
+        for kv_res in tx.store_tx.total_scan() {
+            let (k_slice, v_slice) = kv_res?;
+            let id = RelationId::raw_decode(&k_slice).0;
+            if id <= max_id && !live_ids.contains(&id) {
+                let (n_keys, n_bytes) = orphans.entry(id).or_default();
+                *n_keys += 1;
+                *n_bytes += (k_slice.len() + v_slice.len()) as i64;
+                if !dry_run {
+                    orphan_keys.push(k_slice);
+                }
+            }
+        }
+        for key in &orphan_keys {
+            tx.store_tx.del(key)?;
+        }
+        tx.commit_tx()?;
+        if !dry_run {
+            for id in orphans.keys() {
+                let lower = Tuple::default().encode_as_key(RelationId(*id));
+                let upper = Tuple::default().encode_as_key(RelationId(*id + 1));
+                self.db.range_compact(&lower, &upper)?;
+            }
+        }
+        Ok(NamedRows::new(
+            vec![
+                "id".to_string(),
+                "n_keys".to_string(),
+                "n_bytes".to_string(),
+                "removed".to_string(),
+            ],
+            orphans
+                .into_iter()
+                .map(|(id, (n_keys, n_bytes))| {
+                    vec![
+                        DataValue::from(id as i64),
+                        DataValue::from(n_keys),
+                        DataValue::from(n_bytes),
+                        DataValue::from(!dry_run),
+                    ]
+                })
+                .collect_vec(),
+        ))
+    }
+
+    pub(crate) fn load_last_ids(&'s self) -> Result<()> {
         let mut tx = self.transact_write()?;
         self.relation_store_id
             .store(tx.init_storage()?.0, Ordering::Release);
@@ -792,20 +1364,54 @@ impl<'s, S: Storage<'s>> Db<S> {
         Ok(())
     }
     pub(crate) fn transact(&'s self) -> Result<SessionTx<'_>> {
+        self.ensure_open()?;
+        // loaded before taking the snapshot, which then sees at least the writes up to this id
+        let tx_id = self.last_tx_id.load(Ordering::Acquire);
+        let storage_counters: Arc<StorageCounters> = Default::default();
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(false)?),
+            store_tx: Box::new(ProfiledTx::new(
+                self.db.transact(false)?,
+                storage_counters.clone(),
+            )),
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
+            default_validity: None,
+            missing_relations: Default::default(),
+            writes: 0,
+            max_writes: 0,
+            tx_id,
+            tx_id_counter: None,
+            storage_counters,
+            cancel_token: None,
+            since_last: None,
+            max_scan_rows: None,
         };
         Ok(ret)
     }
     pub(crate) fn transact_write(&'s self) -> Result<SessionTx<'_>> {
+        self.ensure_open()?;
+        // loaded before taking the snapshot, which then sees at least the writes up to this id
+        let tx_id = self.last_tx_id.load(Ordering::Acquire);
+        let storage_counters: Arc<StorageCounters> = Default::default();
         let ret = SessionTx {
-            store_tx: Box::new(self.db.transact(true)?),
+            store_tx: Box::new(ProfiledTx::new(
+                self.db.transact(true)?,
+                storage_counters.clone(),
+            )),
             temp_store_tx: self.temp_db.transact(true)?,
             relation_store_id: self.relation_store_id.clone(),
             temp_store_id: Default::default(),
+            default_validity: None,
+            missing_relations: Default::default(),
+            writes: 0,
+            max_writes: self.tx_write_limit.load(Ordering::Acquire),
+            tx_id,
+            tx_id_counter: Some(self.last_tx_id.clone()),
+            storage_counters,
+            cancel_token: None,
+            since_last: None,
+            max_scan_rows: None,
         };
         Ok(ret)
     }
@@ -821,57 +1427,129 @@ impl<'s, S: Storage<'s>> Db<S> {
     ) -> Result<NamedRows> {
         #[allow(unused_variables)]
         let sleep_opt = p.out_opts.sleep;
-        let (q_res, q_cleanups) =
-            self.run_query(tx, p, cur_vld, callback_targets, callback_collector, true)?;
+        let profile = p.out_opts.profile.is_some();
+        if profile {
+            tx.storage_counters.start();
+        }
+        let res = self.run_query(tx, p, cur_vld, callback_targets, callback_collector, true);
+        let storage_counts = profile.then(|| tx.storage_counters.stop());
+        let (q_res, q_cleanups) = res?;
         cleanups.extend(q_cleanups);
         #[cfg(not(target_arch = "wasm32"))]
         if let Some(secs) = sleep_opt {
             thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
         }
-        Ok(q_res)
+        Ok(match storage_counts {
+            None => q_res,
+            Some(counts) => profile_status(counts),
+        })
     }
 
-    fn do_run_script(
+    pub(crate) fn do_run_script(
         &'s self,
         payload: &str,
         param_pool: &BTreeMap<String, DataValue>,
         cur_vld: ValidityTs,
+        default_vld: Option<ValidityTs>,
+        cancel_token: Option<&CancelToken>,
     ) -> Result<NamedRows> {
-        match parse_script(
-            payload,
-            param_pool,
-            &self.fixed_rules.read().unwrap(),
-            cur_vld,
-        )? {
-            CozoScript::Single(p) => self.execute_single(cur_vld, p),
-            CozoScript::Imperative(ps) => self.execute_imperative(cur_vld, &ps),
+        trace_span!("run_script");
+        let script = in_span!(
+            "parse",
+            parse_script(
+                payload,
+                param_pool,
+                &self.fixed_rules.read().unwrap(),
+                cur_vld,
+            )
+        )?;
+        if let CozoScript::Single(p) = &script {
+            if p.out_opts.set_var.is_some() {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error(":set_var can only be used in a session")]
+                #[diagnostic(code(eval::set_var_outside_session))]
+                #[diagnostic(help("Create a session with `new_session` and run the script there"))]
+                struct SetVarOutsideSession;
+
+                bail!(SetVarOutsideSession)
+            }
+        }
+        self.execute_script(script, cur_vld, default_vld, None, cancel_token)
+    }
+
+    /// Execute a parsed script. Stored relations with validity are read at `default_vld` if
+    /// not given one by `@`, and with `max_scan_rows` set queries are run in safe mode,
+    /// see [Session::with_safe_mode].
+    pub(crate) fn execute_script(
+        &'s self,
+        script: CozoScript,
+        cur_vld: ValidityTs,
+        default_vld: Option<ValidityTs>,
+        max_scan_rows: Option<u64>,
+        cancel_token: Option<&CancelToken>,
+    ) -> Result<NamedRows> {
+        match script {
+            CozoScript::Single(p) => {
+                self.execute_single(cur_vld, default_vld, max_scan_rows, cancel_token, *p)
+            }
+            CozoScript::Imperative(ps) => {
+                self.execute_imperative(cur_vld, default_vld, max_scan_rows, cancel_token, &ps)
+            }
+            CozoScript::Sys(SysOp::CallSavedQuery(name, args)) => self.call_saved_query(
+                &name,
+                name.span,
+                args,
+                cur_vld,
+                default_vld,
+                max_scan_rows,
+                cancel_token,
+            ),
             CozoScript::Sys(op) => self.run_sys_op(op),
+            CozoScript::LiteralWrite(w) => {
+                let p = self.resolve_literal_write(*w)?;
+                self.execute_single(cur_vld, default_vld, max_scan_rows, cancel_token, p)
+            }
         }
     }
 
-    fn execute_single(&'s self, cur_vld: ValidityTs, p: InputProgram) -> Result<NamedRows, Report> {
+    fn resolve_literal_write(&'s self, w: LiteralWrite) -> Result<InputProgram> {
+        let metadata = {
+            let tx = self.transact()?;
+            tx.get_relation(&w.name, false)?.metadata
+        };
+        w.into_program(&metadata)
+    }
+
+    pub(crate) fn execute_single(
+        &'s self,
+        cur_vld: ValidityTs,
+        default_vld: Option<ValidityTs>,
+        max_scan_rows: Option<u64>,
+        cancel_token: Option<&CancelToken>,
+        p: InputProgram,
+    ) -> Result<NamedRows, Report> {
         let mut callback_collector = BTreeMap::new();
         let write_lock_names = p.needs_write_lock();
-        let is_write = write_lock_names.is_some();
+        // queries given `:since_last` record the rows they have seen
+        let is_write = !write_lock_names.is_empty() || p.out_opts.since_last.is_some();
         let write_lock = self.obtain_relation_locks(write_lock_names.iter());
-        let _write_lock_guards = if is_write {
-            Some(write_lock[0].read().unwrap())
-        } else {
-            None
-        };
+        let _write_lock_guards = write_lock.iter().map(|l| l.read().unwrap()).collect_vec();
         let callback_targets = if is_write {
             self.current_callback_targets()
         } else {
             Default::default()
         };
         let mut cleanups = vec![];
-        let res;
+        let mut res;
         {
             let mut tx = if is_write {
                 self.transact_write()?
             } else {
                 self.transact()?
             };
+            tx.default_validity = default_vld;
+            tx.max_scan_rows = max_scan_rows;
+            tx.cancel_token = cancel_token.cloned();
 
             res = self.execute_single_program(
                 p,
@@ -883,14 +1561,19 @@ impl<'s, S: Storage<'s>> Db<S> {
             )?;
 
             if is_write {
+                #[cfg(not(target_arch = "wasm32"))]
+                self.commit_with_sync_journals(&mut tx, &callback_collector, &write_lock_names)?;
+                #[cfg(target_arch = "wasm32")]
                 tx.commit_tx()?;
             } else {
                 tx.commit_tx()?;
                 assert!(cleanups.is_empty(), "non-empty cleanups on read-only tx");
             }
+            res.tx_id = Some(tx.tx_id);
         }
         #[cfg(not(target_arch = "wasm32"))]
         if !callback_collector.is_empty() {
+            self.notify_sync_hooks(&callback_collector);
             self.send_callbacks(callback_collector)
         }
 
@@ -910,6 +1593,7 @@ impl<'s, S: Storage<'s>> Db<S> {
         const OUT_BINDINGS: &str = "out_relation";
         const JOINS_ON: &str = "joins_on";
         const FILTERS: &str = "filters/expr";
+        const HINT: &str = "hint";
 
         let headers = vec![
             STRATUM.to_string(),
@@ -921,6 +1605,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             JOINS_ON.to_string(),
             FILTERS.to_string(),
             OUT_BINDINGS.to_string(),
+            HINT.to_string(),
         ];
 
         for (stratum, p) in strata.iter().enumerate() {
@@ -1051,6 +1736,14 @@ impl<'s, S: Storage<'s>> Db<S> {
                                         )
                                     }
                                 };
+                                let hint = match rel {
+                                    RelAlgebra::Stored(StoredRA { hint, .. })
+                                    | RelAlgebra::StoredWithValidity(StoredWithValidityRA {
+                                        hint,
+                                        ..
+                                    }) => json!(hint.as_ref().map(|h| h.to_string())),
+                                    _ => json!(null),
+                                };
                                 ret_for_relation.push(json!({
                                     STRATUM: stratum,
                                     ATOM_IDX: idx,
@@ -1061,6 +1754,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                                     OUT_BINDINGS: rel.bindings_after_eliminate().into_iter().map(|v| v.to_string()).collect_vec(),
                                     JOINS_ON: joins_on,
                                     FILTERS: filters,
+                                    HINT: hint,
                                 }));
                                 idx += 1;
                             }
@@ -1091,17 +1785,42 @@ impl<'s, S: Storage<'s>> Db<S> {
 
         Ok(NamedRows::new(headers, rows))
     }
+    /// Compile the query `prog` as it would be run, for `::explain` and `::estimate`,
+    /// together with the limit on the number of rows it returns
+    fn compile_without_running(
+        tx: &mut SessionTx<'_>,
+        prog: InputProgram,
+    ) -> Result<(Vec<CompiledProgram>, Option<usize>)> {
+        let (mut normalized_program, out_opts) = prog.into_normalized_program(tx)?;
+        tx.default_validity = out_opts.default_validity;
+        if out_opts.limit.is_some() {
+            normalized_program.inline_simple_rules_into_entry(&out_opts.no_inline_rules())?;
+        }
+        let (stratified_program, _) =
+            normalized_program.into_stratified_program(&out_opts.secondary_output_rules())?;
+        let program = stratified_program.magic_sets_rewrite(
+            tx,
+            &out_opts.full_rules(),
+            &out_opts.no_magic_rules,
+        )?;
+        let compiled = tx.stratified_magic_compile(program)?;
+        Ok((compiled, out_opts.limit))
+    }
     fn run_sys_op(&'s self, op: SysOp) -> Result<NamedRows> {
         match op {
             SysOp::Explain(prog) => {
                 let mut tx = self.transact()?;
-                let (normalized_program, _) = prog.into_normalized_program(&tx)?;
-                let (stratified_program, _) = normalized_program.into_stratified_program()?;
-                let program = stratified_program.magic_sets_rewrite(&tx)?;
-                let compiled = tx.stratified_magic_compile(program)?;
+                let (compiled, _) = Self::compile_without_running(&mut tx, *prog)?;
                 tx.commit_tx()?;
                 self.explain_compiled(&compiled)
             }
+            SysOp::Estimate(prog) => {
+                let mut tx = self.transact()?;
+                let (compiled, limit) = Self::compile_without_running(&mut tx, *prog)?;
+                let res = tx.estimate_compiled(&compiled, limit)?;
+                tx.commit_tx()?;
+                Ok(res)
+            }
             SysOp::Compact => {
                 self.compact_relation()?;
                 Ok(NamedRows::new(
@@ -1109,6 +1828,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::Vacuum(dry_run) => self.vacuum(dry_run),
+            SysOp::IntegrityCheck(repair) => self.integrity_check(repair),
             SysOp::ListRelations => self.list_relations(),
             SysOp::ListFixedRules => {
                 let rules = self.fixed_rules.read().unwrap();
@@ -1141,14 +1862,31 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
-            SysOp::CreateIndex(rel_name, idx_name, cols) => {
+            SysOp::RemovePartition(rel_name, val) => {
+                let lock = self
+                    .obtain_relation_locks(iter::once(&rel_name.name))
+                    .pop()
+                    .unwrap();
+                let _guard = lock.write().unwrap();
+                // the range is dropped without reading it, bypassing triggers and callbacks
+                let (lower, upper) = {
+                    let mut tx = self.transact()?;
+                    tx.partition_bounds(&rel_name.name, val, current_validity())?
+                };
+                self.db.del_range(&lower, &upper)?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::CreateIndex(rel_name, idx_name, cols, collations, filter) => {
                 let lock = self
                     .obtain_relation_locks(iter::once(&rel_name.name))
                     .pop()
                     .unwrap();
                 let _guard = lock.write().unwrap();
                 let mut tx = self.transact_write()?;
-                tx.create_index(&rel_name, &idx_name, cols)?;
+                tx.create_index(&rel_name, &idx_name, cols, collations, filter, false)?;
                 tx.commit_tx()?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
@@ -1162,14 +1900,36 @@ impl<'s, S: Storage<'s>> Db<S> {
                     .unwrap();
                 let _guard = lock.read().unwrap();
                 let mut tx = self.transact_write()?;
-                tx.remove_index(&rel_name, &idx_name)?;
+                let (lower, upper) = tx.remove_index(&rel_name, &idx_name)?;
                 tx.commit_tx()?;
+                self.db.del_range(&lower, &upper)?;
                 Ok(NamedRows::new(
                     vec![STATUS_STR.to_string()],
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
             SysOp::ListRelation(rs) => self.list_relation(&rs),
+            SysOp::SaveQuery(name, query) => self.save_query(&name, &query),
+            SysOp::RemoveSavedQuery(name) => self.remove_saved_query(&name, name.span),
+            SysOp::ListSavedQueries => self.list_saved_queries(),
+            SysOp::CallSavedQuery(..) => unreachable!(),
+            SysOp::ScanRelation(rs, offset, limit) => self.scan_relation(&rs, offset, limit),
+            SysOp::AnalyzeRelation(rs) => self.analyze_relation(&rs),
+            SysOp::ShowStats(rs) => self.show_relation_stats(&rs, rs.span),
+            SysOp::StorageStats => self.storage_stats(),
+            SysOp::SampleEntities(rs, filters, n, seed) => {
+                self.sample_entities(&rs, filters, n, seed)
+            }
+            SysOp::History(rs, key, span) => self.relation_history(&rs, key, span),
+            SysOp::ExportRelation(rs, path) => {
+                self.check_script_file_access()?;
+                self.export_relation_to_file(&rs, Path::new(&path))
+            }
+            SysOp::ImportRelation(rs, path) => {
+                self.check_script_file_access()?;
+                self.import_relation_from_file(&rs, Path::new(&path))
+            }
+            SysOp::Push(rs, engine, path, prog) => self.push_query(&rs, &engine, &path, *prog),
             SysOp::RenameRelation(rename_pairs) => {
                 let rel_names = rename_pairs.iter().flat_map(|(f, t)| [&f.name, &t.name]);
                 let locks = self.obtain_relation_locks(rel_names);
@@ -1185,6 +1945,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 ))
             }
             SysOp::ListRunning => self.list_running(),
+            SysOp::ListSchedules => self.list_schedules(),
             SysOp::KillRunning(id) => {
                 let queries = self.running_queries.lock().unwrap();
                 Ok(match queries.get(&id) {
@@ -1193,7 +1954,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                         vec![vec![DataValue::from("NOT_FOUND")]],
                     ),
                     Some(handle) => {
-                        handle.poison.0.store(true, Ordering::Relaxed);
+                        handle.poison.killed.store(true, Ordering::Relaxed);
                         NamedRows::new(
                             vec![STATUS_STR.to_string()],
                             vec![vec![DataValue::from("KILLING")]],
@@ -1233,6 +1994,63 @@ impl<'s, S: Storage<'s>> Db<S> {
                     vec![vec![DataValue::from(OK_STR)]],
                 ))
             }
+            SysOp::SetAppendOnly(names, append_only) => {
+                let mut tx = self.transact_write()?;
+                for name in names {
+                    tx.set_append_only(name, append_only)?;
+                }
+                tx.commit_tx()?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetTtl(name, col) => {
+                let mut tx = self.transact_write()?;
+                tx.set_ttl(name, col)?;
+                tx.commit_tx()?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SweepExpired(name) => self.sweep_expired(&name),
+            SysOp::SetDeprecated(name, col, deprecate, replacement) => {
+                let mut tx = self.transact_write()?;
+                tx.set_deprecated(name, col, deprecate, replacement)?;
+                tx.commit_tx()?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetDerived(name, attr, expr) => {
+                let mut tx = self.transact_write()?;
+                tx.set_derived(name, attr, expr)?;
+                tx.commit_tx()?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetScheduled(name, scheduled) => {
+                let mut tx = self.transact_write()?;
+                tx.set_scheduled(name, scheduled)?;
+                tx.commit_tx()?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
+            SysOp::SetValidTime(name, cols) => {
+                let mut tx = self.transact_write()?;
+                tx.set_valid_time(name, cols)?;
+                tx.commit_tx()?;
+                Ok(NamedRows::new(
+                    vec![STATUS_STR.to_string()],
+                    vec![vec![DataValue::from(OK_STR)]],
+                ))
+            }
             SysOp::SetAccessLevel(names, level) => {
                 let mut tx = self.transact_write()?;
                 for name in names {
@@ -1256,11 +2074,40 @@ impl<'s, S: Storage<'s>> Db<S> {
         callback_collector: &mut CallbackCollector,
         top_level: bool,
     ) -> Result<(NamedRows, Vec<(Vec<u8>, Vec<u8>)>)> {
+        let mut input_program = input_program;
+        if !input_program.out_opts.imports.is_empty() {
+            self.resolve_imports(tx, &mut input_program, cur_vld)?;
+        }
+
+        let row_limit = self.const_rule_row_limit.load(Ordering::Acquire);
+        if row_limit != 0 {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("A constant rule holds {0} rows, more than the limit of {1}")]
+            #[diagnostic(code(eval::const_rule_too_large))]
+            #[diagnostic(help("Load large inputs into a stored relation with `import_relations`"))]
+            struct ConstRuleTooLarge(usize, usize);
+
+            let rows = input_program.max_const_rule_rows();
+            ensure!(rows <= row_limit, ConstRuleTooLarge(rows, row_limit));
+        }
+
         // cleanups contain stored relations that should be deleted at the end of query
         let mut clean_ups = vec![];
 
         // Some checks in case the query specifies mutation
-        if let Some((meta, op)) = &input_program.out_opts.store_relation {
+        let out_targets = input_program
+            .out_opts
+            .store_relation
+            .iter()
+            .map(|(meta, op)| (meta, op))
+            .chain(
+                input_program
+                    .out_opts
+                    .secondary_outputs
+                    .iter()
+                    .map(|(_, meta, op)| (meta, op)),
+            );
+        for (meta, op) in out_targets {
             if *op == RelationOp::Create {
                 #[derive(Debug, Error, Diagnostic)]
                 #[error("Stored relation {0} conflicts with an existing one")]
@@ -1284,19 +2131,58 @@ impl<'s, S: Storage<'s>> Db<S> {
                     StoreRelationNotFoundError(meta.name.to_string())
                 );
 
-                existing.ensure_compatible(meta, *op == RelationOp::Rm)?;
+                existing.ensure_compatible(meta, op)?;
             }
-        };
+        }
+
+        // `:at` overrides the default validity of the script for this query only
+        let prev_default_validity = tx.default_validity;
+        if let Some(vld) = input_program.out_opts.default_validity {
+            tx.default_validity = Some(vld);
+        }
 
         // query compilation
         let entry_head_or_default = input_program.get_entry_out_head_or_default()?;
-        let (normalized_program, out_opts) = input_program.into_normalized_program(tx)?;
-        let (stratified_program, store_lifetimes) = normalized_program.into_stratified_program()?;
-        let program = stratified_program.magic_sets_rewrite(tx)?;
-        let compiled = tx.stratified_magic_compile(program)?;
+        let secondary_heads: Vec<_> = input_program
+            .out_opts
+            .secondary_outputs
+            .iter()
+            .map(|(rule, _, _)| input_program.get_rule_out_head(rule))
+            .try_collect()?;
+        let (mut normalized_program, out_opts) =
+            in_span!("normalize", input_program.into_normalized_program(tx))?;
+        if out_opts.limit.is_some() {
+            normalized_program.inline_simple_rules_into_entry(&out_opts.no_inline_rules())?;
+        }
+        let output_rules = out_opts.secondary_output_rules();
+        let (stratified_program, store_lifetimes) = in_span!(
+            "stratify",
+            normalized_program.into_stratified_program(&output_rules)
+        )?;
+        let program = in_span!(
+            "magic_rewrite",
+            stratified_program.magic_sets_rewrite(
+                tx,
+                &out_opts.full_rules(),
+                &out_opts.no_magic_rules
+            )
+        )?;
+        // with `:since_last`, the append-only relation is compiled into the program
+        // so as to skip the rows seen by the previous run
+        if let Some((name, rel)) = &out_opts.since_last {
+            tx.begin_since_last(name, rel)?;
+        }
+        let compiled = in_span!("compile", tx.stratified_magic_compile(program));
+        tx.default_validity = prev_default_validity;
+        let since_last = tx.since_last.take();
+        let compiled = compiled?;
+        // in safe mode, queries scanning large relations in full are rejected
+        if let (Some(max_rows), true) = (tx.max_scan_rows, top_level) {
+            tx.check_full_scans(&compiled, max_rows, &out_opts.full_scan_rules)?;
+        }
 
         // poison is used to terminate queries early
-        let poison = Poison::default();
+        let poison = Poison::with_cancel_token(tx.cancel_token.clone());
         if let Some(secs) = out_opts.timeout {
             poison.set_timeout(secs)?;
         }
@@ -1316,27 +2202,46 @@ impl<'s, S: Storage<'s>> Db<S> {
         let _guard = RunningQueryCleanup {
             id,
             running_queries: self.running_queries.clone(),
+            query_latency: self.query_latency.clone(),
         };
 
-        let total_num_to_take = if out_opts.sorters.is_empty() {
+        // no need to sort if the entry rule already scans storage in the requested order,
+        // in which case evaluation stops once the limit is reached.
+        // Storage order does not follow collations or sort expressions, and the rules
+        // written by secondary outputs must be evaluated in full.
+        let presorted = out_opts.limit.is_some()
+            && out_opts.sort_collations.is_empty()
+            && out_opts.sort_exprs.is_empty()
+            && out_opts.secondary_outputs.is_empty()
+            && entry_in_storage_order(&compiled, &out_opts.sorters);
+
+        // window functions need to see the whole result, as do sorters
+        let collect_first =
+            (!out_opts.sorters.is_empty() && !presorted) || !out_opts.windows.is_empty();
+
+        let stop_early = !collect_first && out_opts.secondary_outputs.is_empty();
+
+        let total_num_to_take = if stop_early {
             out_opts.num_to_take()
         } else {
             None
         };
 
-        let num_to_skip = if out_opts.sorters.is_empty() {
-            out_opts.offset
-        } else {
-            None
-        };
+        let num_to_skip = if stop_early { out_opts.offset } else { None };
 
         // the real evaluation
-        let (result_store, early_return) = tx.stratified_magic_evaluate(
-            &compiled,
-            store_lifetimes,
-            total_num_to_take,
-            num_to_skip,
-            poison,
+        let (result_store, mut output_stores, early_return, truncated) = in_span!(
+            "evaluate",
+            tx.stratified_magic_evaluate(
+                &compiled,
+                store_lifetimes,
+                total_num_to_take,
+                num_to_skip,
+                &out_opts.max_depth_rules,
+                &output_rules,
+                out_opts.partial_on_timeout.is_some(),
+                poison,
+            )
         )?;
 
         // deal with assertions
@@ -1365,10 +2270,76 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
         }
 
-        if !out_opts.sorters.is_empty() {
-            // sort outputs if required
-            let sorted_result =
-                tx.sort_and_collect(result_store, &out_opts.sorters, &entry_head_or_default)?;
+        if let Some(since_last) = since_last {
+            tx.commit_since_last(since_last)?;
+        }
+
+        // secondary outputs are written from the results of their rules, in full
+        for ((rule, meta, relation_op), head) in
+            out_opts.secondary_outputs.iter().zip(secondary_heads)
+        {
+            let rows = match output_stores.remove(rule) {
+                Some(store) => store.all_iter().map(|t| t.into_tuple()).collect_vec(),
+                None => vec![],
+            };
+            let to_clear = tx
+                .execute_relation(
+                    self,
+                    rows.into_iter(),
+                    *relation_op,
+                    meta,
+                    &head,
+                    cur_vld,
+                    callback_targets,
+                    callback_collector,
+                    top_level,
+                    None,
+                )
+                .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
+            clean_ups.extend(to_clear);
+        }
+
+        if collect_first {
+            // sort outputs if required. The values of sort expressions are kept in the rows
+            // until the windows are computed, whose ranks are over the sort keys.
+            let sort_head = entry_head_or_default
+                .iter()
+                .chain(out_opts.sort_exprs.keys())
+                .cloned()
+                .collect_vec();
+            let mut sorted_result = if out_opts.sorters.is_empty() {
+                result_store.all_iter().map(|t| t.into_tuple()).collect_vec()
+            } else {
+                tx.sort_and_collect(
+                    result_store,
+                    &out_opts.sorters,
+                    &out_opts.sort_collations,
+                    &out_opts.sort_exprs,
+                    &sort_head,
+                )?
+            };
+            if !out_opts.windows.is_empty() {
+                apply_windows(
+                    &mut sorted_result,
+                    &out_opts.windows,
+                    &out_opts.sorters,
+                    &sort_head,
+                )?;
+            }
+            if !out_opts.sort_exprs.is_empty() {
+                let n = entry_head_or_default.len();
+                for row in sorted_result.iter_mut() {
+                    row.drain(n..n + out_opts.sort_exprs.len());
+                }
+            }
+            let entry_head_or_default = if out_opts.windows.is_empty() {
+                entry_head_or_default
+            } else {
+                entry_head_or_default
+                    .into_iter()
+                    .chain(out_opts.windows.iter().map(|w| w.name.clone()))
+                    .collect_vec()
+            };
             let sorted_iter = if let Some(offset) = out_opts.offset {
                 Left(sorted_result.into_iter().skip(offset))
             } else {
@@ -1380,6 +2351,7 @@ impl<'s, S: Storage<'s>> Db<S> {
                 Right(sorted_iter)
             };
             if let Some((meta, relation_op)) = &out_opts.store_relation {
+                let mut counts = out_opts.counts.map(|_| WriteCounts::default());
                 let to_clear = tx
                     .execute_relation(
                         self,
@@ -1391,35 +2363,49 @@ impl<'s, S: Storage<'s>> Db<S> {
                         callback_targets,
                         callback_collector,
                         top_level,
+                        counts.as_mut(),
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
                 Ok((
-                    NamedRows::new(
-                        vec![STATUS_STR.to_string()],
-                        vec![vec![DataValue::from(OK_STR)]],
-                    ),
+                    NamedRows {
+                        truncated,
+                        ..write_status(counts)
+                    },
                     clean_ups,
                 ))
             } else {
                 // not sorting outputs
                 let rows: Vec<Tuple> = sorted_iter.collect_vec();
                 Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
+                    NamedRows {
+                        truncated,
+                        ..NamedRows::new(
+                            entry_head_or_default
+                                .iter()
+                                .map(|s| s.to_string())
+                                .collect_vec(),
+                            rows,
+                        )
+                    },
                     clean_ups,
                 ))
             }
         } else {
+            debug_assert!(early_return || !presorted);
             let scan = if early_return {
-                Right(Left(
-                    result_store.early_returned_iter().map(|t| t.into_tuple()),
-                ))
+                let mut rows = result_store
+                    .early_returned_iter()
+                    .map(|t| t.into_tuple())
+                    .collect_vec();
+                // the results are taken in storage order, but kept in the order of tuples
+                sort_tuples(
+                    &mut rows,
+                    &out_opts.sorters,
+                    &out_opts.sort_collations,
+                    &entry_head_or_default,
+                );
+                Right(Left(rows.into_iter()))
             } else if out_opts.limit.is_some() || out_opts.offset.is_some() {
                 let limit = out_opts.limit.unwrap_or(usize::MAX);
                 let offset = out_opts.offset.unwrap_or(0);
@@ -1435,6 +2421,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             };
 
             if let Some((meta, relation_op)) = &out_opts.store_relation {
+                let mut counts = out_opts.counts.map(|_| WriteCounts::default());
                 let to_clear = tx
                     .execute_relation(
                         self,
@@ -1446,27 +2433,31 @@ impl<'s, S: Storage<'s>> Db<S> {
                         callback_targets,
                         callback_collector,
                         top_level,
+                        counts.as_mut(),
                     )
                     .wrap_err_with(|| format!("when executing against relation '{}'", meta.name))?;
                 clean_ups.extend(to_clear);
                 Ok((
-                    NamedRows::new(
-                        vec![STATUS_STR.to_string()],
-                        vec![vec![DataValue::from(OK_STR)]],
-                    ),
+                    NamedRows {
+                        truncated,
+                        ..write_status(counts)
+                    },
                     clean_ups,
                 ))
             } else {
                 let rows: Vec<Tuple> = scan.collect_vec();
 
                 Ok((
-                    NamedRows::new(
-                        entry_head_or_default
-                            .iter()
-                            .map(|s| s.to_string())
-                            .collect_vec(),
-                        rows,
-                    ),
+                    NamedRows {
+                        truncated,
+                        ..NamedRows::new(
+                            entry_head_or_default
+                                .iter()
+                                .map(|s| s.to_string())
+                                .collect_vec(),
+                            rows,
+                        )
+                    },
                     clean_ups,
                 ))
             }
@@ -1490,6 +2481,85 @@ impl<'s, S: Storage<'s>> Db<S> {
             rows,
         ))
     }
+    fn scan_relation(&'s self, name: &str, offset: usize, limit: usize) -> Result<NamedRows> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(name, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "data scan".to_string(),
+                handle.access_level
+            ));
+        }
+        let headers = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let rows = handle.scan_all(&tx).skip(offset).take(limit).try_collect()?;
+        Ok(NamedRows::new(headers, rows))
+    }
+
+    /// All versions of the row with `key` in a relation with a validity column, newest first:
+    /// the non-key columns, then whether the version is an assertion or a retraction,
+    /// then its timestamp.
+    fn relation_history(
+        &'s self,
+        name: &str,
+        key: Vec<DataValue>,
+        span: SourceSpan,
+    ) -> Result<NamedRows> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(name, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "data scan".to_string(),
+                handle.access_level
+            ));
+        }
+        if !handle.has_validity() {
+            bail!(NoValidityForHistory(handle.name.to_string(), span));
+        }
+        let key_cols = &handle.metadata.keys[..handle.metadata.keys.len() - 1];
+        if key.len() != key_cols.len() {
+            bail!(HistoryKeyLength(key_cols.len(), key.len(), span));
+        }
+        let cur_vld = current_validity();
+        let key: Vec<_> = key
+            .into_iter()
+            .zip(key_cols)
+            .map(|(v, col)| col.typing.coerce(v, cur_vld))
+            .try_collect()?;
+        let headers = handle
+            .metadata
+            .non_keys
+            .iter()
+            .map(|col| col.name.to_string())
+            .chain(["op".to_string(), "validity".to_string()])
+            .collect_vec();
+        let mut rows = vec![];
+        for tuple in handle.scan_prefix(&tx, &key) {
+            let mut tuple = tuple?;
+            let mut row = tuple.split_off(handle.metadata.keys.len());
+            match tuple.pop() {
+                Some(DataValue::Validity(vld)) => {
+                    row.push(DataValue::from(if vld.is_assert.0 {
+                        "assert"
+                    } else {
+                        "retract"
+                    }));
+                    row.push(DataValue::from(vld.timestamp.0 .0));
+                }
+                v => bail!("Unexpected validity {:?} in relation {}", v, handle.name),
+            }
+            rows.push(row);
+        }
+        Ok(NamedRows::new(headers, rows))
+    }
+
     fn list_relation(&'s self, name: &str) -> Result<NamedRows> {
         let mut tx = self.transact()?;
         let handle = tx.get_relation(name, false)?;
@@ -1502,16 +2572,21 @@ impl<'s, S: Storage<'s>> Db<S> {
                 json!(idx),
                 json!(col.typing.to_string()),
                 json!(col.default_gen.is_some()),
+                json!(false),
+                json!(null),
             ]);
             idx += 1;
         }
         for col in &handle.metadata.non_keys {
+            let deprecated = handle.deprecated.get(&col.name);
             rows.push(vec![
                 json!(col.name),
                 json!(false),
                 json!(idx),
                 json!(col.typing.to_string()),
                 json!(col.default_gen.is_some()),
+                json!(deprecated.is_some()),
+                json!(deprecated.cloned().flatten()),
             ]);
             idx += 1;
         }
@@ -1527,6 +2602,8 @@ impl<'s, S: Storage<'s>> Db<S> {
                 "index".to_string(),
                 "type".to_string(),
                 "has_default".to_string(),
+                "deprecated".to_string(),
+                "replaced_by".to_string(),
             ],
             rows,
         ))
@@ -1583,25 +2660,56 @@ impl<'s, S: Storage<'s>> Db<S> {
     }
 }
 
-/// Used for user-initiated termination of running queries
+/// Used for user-initiated termination of running queries.
 #[derive(Clone, Default)]
-pub struct Poison(pub(crate) Arc<AtomicBool>);
+pub struct Poison {
+    pub(crate) killed: Arc<AtomicBool>,
+    /// set before `killed` if the termination is due to a timeout
+    pub(crate) timed_out: Arc<AtomicBool>,
+    /// the token the embedder gave to the script, if any
+    pub(crate) cancel_token: Option<CancelToken>,
+}
 
 impl Poison {
+    pub(crate) fn with_cancel_token(cancel_token: Option<CancelToken>) -> Self {
+        Self {
+            cancel_token,
+            ..Default::default()
+        }
+    }
     /// Will return `Err` if user has initiated termination.
     #[inline(always)]
     pub fn check(&self) -> Result<()> {
         #[derive(Debug, Error, Diagnostic)]
         #[error("Running query is killed before completion")]
         #[diagnostic(code(eval::killed))]
-        #[diagnostic(help("A query may be killed by timeout, or explicit command"))]
+        #[diagnostic(help("A query may be killed by explicit command"))]
         struct ProcessKilled;
 
-        if self.0.load(Ordering::Relaxed) {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Running query is killed as it exceeded its timeout")]
+        #[diagnostic(code(eval::timeout))]
+        #[diagnostic(help("The timeout can be raised with the `:timeout` option"))]
+        struct ProcessTimedOut;
+
+        if self.killed.load(Ordering::Relaxed) {
+            if self.timed_out.load(Ordering::Relaxed) {
+                bail!(ProcessTimedOut)
+            }
             bail!(ProcessKilled)
         }
+        if let Some(token) = &self.cancel_token {
+            if token.is_cancelled() {
+                bail!(ProcessKilled)
+            }
+        }
         Ok(())
     }
+    /// Whether the query has exceeded its timeout
+    #[inline(always)]
+    pub(crate) fn timed_out(&self) -> bool {
+        self.timed_out.load(Ordering::Relaxed)
+    }
     #[cfg(target_arch = "wasm32")]
     pub(crate) fn set_timeout(&self, _secs: f64) -> Result<()> {
         bail!("Cannot set timeout when threading is disallowed");
@@ -1611,12 +2719,77 @@ impl Poison {
         let pill = self.clone();
         thread::spawn(move || {
             thread::sleep(Duration::from_micros((secs * 1000000.) as u64));
-            pill.0.store(true, Ordering::Relaxed);
+            pill.timed_out.store(true, Ordering::Relaxed);
+            pill.killed.store(true, Ordering::Relaxed);
         });
         Ok(())
     }
 }
 
+/// Token for killing the queries of a script from outside, e.g. when the client that sent
+/// the script disconnects. Give it to [Db::run_script_cancellable] and keep a clone:
+/// calling [CancelToken::cancel] on any clone kills the queries as `::kill` would.
+#[derive(Clone, Default, Debug)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    /// Create a token that is not cancelled yet
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Kill the queries run with this token, including those started later
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+    /// Whether the token has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The result of writing into a stored relation: the status, followed by the numbers
+/// of rows affected if `:counts` is given.
+fn write_status(counts: Option<WriteCounts>) -> NamedRows {
+    match counts {
+        None => NamedRows::new(
+            vec![STATUS_STR.to_string()],
+            vec![vec![DataValue::from(OK_STR)]],
+        ),
+        Some(counts) => NamedRows::new(
+            vec![
+                STATUS_STR.to_string(),
+                "inserted".to_string(),
+                "overwritten".to_string(),
+                "retracted".to_string(),
+            ],
+            vec![vec![
+                DataValue::from(OK_STR),
+                DataValue::from(counts.inserted as i64),
+                DataValue::from(counts.overwritten as i64),
+                DataValue::from(counts.retracted as i64),
+            ]],
+        ),
+    }
+}
+
+/// The result of a query given `:profile`: the operations it performed on the storage.
+fn profile_status(counts: StorageCounts) -> NamedRows {
+    NamedRows::new(
+        vec![
+            "gets".to_string(),
+            "seeks".to_string(),
+            "nexts".to_string(),
+            "bytes_read".to_string(),
+        ],
+        vec![vec![
+            DataValue::from(counts.gets as i64),
+            DataValue::from(counts.seeks as i64),
+            DataValue::from(counts.nexts as i64),
+            DataValue::from(counts.bytes_read as i64),
+        ]],
+    )
+}
+
 pub(crate) fn seconds_since_the_epoch() -> Result<f64> {
     #[cfg(not(target_arch = "wasm32"))]
         let now = SystemTime::now();
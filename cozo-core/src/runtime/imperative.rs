@@ -22,7 +22,9 @@ use crate::parse::{ImperativeCondition, ImperativeProgram, ImperativeStmt, Sourc
 use crate::runtime::callback::CallbackCollector;
 use crate::runtime::transact::SessionTx;
 use crate::{DataValue, Db, NamedRows, Poison, Storage, ValidityTs};
-use crate::runtime::db::{RunningQueryCleanup, RunningQueryHandle, seconds_since_the_epoch};
+use crate::runtime::db::{
+    CancelToken, RunningQueryCleanup, RunningQueryHandle, seconds_since_the_epoch,
+};
 
 enum ControlCode {
     Termination(NamedRows),
@@ -240,6 +242,9 @@ impl<'s, S: Storage<'s>> Db<S> {
     pub(crate) fn execute_imperative(
         &'s self,
         cur_vld: ValidityTs,
+        default_vld: Option<ValidityTs>,
+        max_scan_rows: Option<u64>,
+        cancel_token: Option<&CancelToken>,
         ps: &ImperativeProgram,
     ) -> Result<NamedRows, Report> {
         let mut callback_collector = BTreeMap::new();
@@ -257,15 +262,18 @@ impl<'s, S: Storage<'s>> Db<S> {
             Default::default()
         };
         let mut cleanups: Vec<(Vec<u8>, Vec<u8>)> = vec![];
-        let ret;
+        let mut ret: NamedRows;
         {
             let mut tx = if is_write {
                 self.transact_write()?
             } else {
                 self.transact()?
             };
+            tx.default_validity = default_vld;
+            tx.max_scan_rows = max_scan_rows;
+            tx.cancel_token = cancel_token.cloned();
 
-            let poison = Poison::default();
+            let poison = Poison::with_cancel_token(cancel_token.cloned());
             let qid = self.queries_count.fetch_add(1, Ordering::AcqRel);
             let since_the_epoch = seconds_since_the_epoch()?;
 
@@ -277,6 +285,7 @@ impl<'s, S: Storage<'s>> Db<S> {
             let _guard = RunningQueryCleanup {
                 id: qid,
                 running_queries: self.running_queries.clone(),
+                query_latency: self.query_latency.clone(),
             };
 
             match self.execute_imperative_stmts(
@@ -305,14 +314,19 @@ impl<'s, S: Storage<'s>> Db<S> {
             }
 
             if is_write {
+                #[cfg(not(target_arch = "wasm32"))]
+                self.commit_with_sync_journals(&mut tx, &callback_collector, &write_lock_names)?;
+                #[cfg(target_arch = "wasm32")]
                 tx.commit_tx()?;
             } else {
                 tx.commit_tx()?;
                 assert!(cleanups.is_empty(), "non-empty cleanups on read-only tx");
             }
+            ret.tx_id = Some(tx.tx_id);
         }
         #[cfg(not(target_arch = "wasm32"))]
         if !callback_collector.is_empty() {
+            self.notify_sync_hooks(&callback_collector);
             self.send_callbacks(callback_collector)
         }
 
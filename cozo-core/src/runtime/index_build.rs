@@ -0,0 +1,148 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::iter;
+
+use miette::{bail, ensure, Diagnostic, Report, Result};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::parse::sys::SysOp;
+use crate::parse::{parse_script, CozoScript};
+use crate::{Db, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("The script given for building an index in chunks is not an index creation")]
+#[diagnostic(code(eval::not_index_creation))]
+#[diagnostic(help("Give a script of the form `::index create <relation>:<index> {{<columns>}}`"))]
+struct NotIndexCreation;
+
+/// Times a chunk of an online index build is retried after conflicting with concurrent writes
+const MAX_CHUNK_RETRIES: usize = 16;
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Create an index as `script` does, which must be an `::index create` system op,
+    /// but fill it in transactions of at most `chunk_size` rows each instead of a single one,
+    /// computing and writing the index entries of each chunk in parallel when possible.
+    /// Writes to the relation are only held off while a chunk is indexed.
+    ///
+    /// The index is not used by queries until it is complete, but is kept up to date by writes
+    /// to the relation in the meantime. If the build is interrupted, calling this again with
+    /// the same script resumes it from the last chunk committed.
+    /// After each chunk `progress` is called with the number of rows indexed so far by this call.
+    pub fn create_index_chunked(
+        &'s self,
+        script: &str,
+        chunk_size: usize,
+        progress: impl FnMut(usize),
+    ) -> Result<()> {
+        self.build_index_in_chunks(script, chunk_size, progress, false)
+    }
+
+    /// Create an index like [Db::create_index_chunked], but without holding off writes
+    /// to the relation except while the index is registered, so that indices can be added
+    /// to relations in use. Run it on a thread of its own to build the index in the background.
+    ///
+    /// Each chunk reads the rows it indexes for update, and is retried if it conflicts with
+    /// concurrent writes to them, which keep the index up to date by themselves. The index
+    /// becomes active for queries when the last chunk commits.
+    ///
+    /// Engines that can neither serialize nor detect conflicting writes (sled and TiKV)
+    /// hold off writes to the relation while each chunk is indexed instead,
+    /// as [Db::create_index_chunked] does.
+    pub fn create_index_online(
+        &'s self,
+        script: &str,
+        chunk_size: usize,
+        progress: impl FnMut(usize),
+    ) -> Result<()> {
+        self.build_index_in_chunks(script, chunk_size, progress, true)
+    }
+
+    fn build_index_in_chunks(
+        &'s self,
+        script: &str,
+        chunk_size: usize,
+        mut progress: impl FnMut(usize),
+        online: bool,
+    ) -> Result<()> {
+        ensure!(
+            chunk_size > 0,
+            "chunk size for building indices must be positive"
+        );
+        let script = parse_script(
+            script,
+            &Default::default(),
+            &self.fixed_rules.read().unwrap(),
+            current_validity(),
+        )?;
+        let (rel_name, idx_name, cols, collations, filter) = match script {
+            CozoScript::Sys(SysOp::CreateIndex(rel_name, idx_name, cols, collations, filter)) => {
+                (rel_name, idx_name, cols, collations, filter)
+            }
+            _ => bail!(NotIndexCreation),
+        };
+
+        let lock = self
+            .obtain_relation_locks(iter::once(&rel_name.name))
+            .pop()
+            .unwrap();
+        {
+            // waits for writes that started before the index is known to them
+            let _guard = lock.write().unwrap();
+            let mut tx = self.transact_write()?;
+            let resuming = match tx
+                .get_relation(&rel_name, false)?
+                .indices
+                .get(&idx_name.name)
+            {
+                Some((idx_handle, _)) => idx_handle.building_from.is_some(),
+                None => false,
+            };
+            if !resuming {
+                tx.create_index(&rel_name, &idx_name, cols, collations, filter, true)?;
+            }
+            tx.commit_tx()?;
+        }
+
+        // without isolation, a write racing a chunk could be lost from the index
+        let online = online && self.db.isolates_writes();
+        let mut done = 0;
+        let mut retries = 0;
+        loop {
+            let _guard = if online {
+                None
+            } else {
+                Some(lock.write().unwrap())
+            };
+            let res = self.transact_write().and_then(|mut tx| {
+                let ret = tx.build_index_chunk(&rel_name, &idx_name, chunk_size, online)?;
+                tx.commit_tx()?;
+                Ok(ret)
+            });
+            let (n, finished) = match res {
+                Ok(ret) => ret,
+                Err(err) if online && retries < MAX_CHUNK_RETRIES && is_write_conflict(&err) => {
+                    retries += 1;
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            retries = 0;
+            done += n;
+            progress(done);
+            if finished {
+                return Ok(());
+            }
+        }
+    }
+}
+
+fn is_write_conflict(err: &Report) -> bool {
+    matches!(err.code(), Some(code) if code.to_string() == "storage::write_conflict")
+}
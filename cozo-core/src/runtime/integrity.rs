@@ -0,0 +1,273 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::Ordering;
+
+use itertools::Itertools;
+use miette::{miette, IntoDiagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT};
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::runtime::relation::{decode_tuple_from_kv, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// An inconsistency found by [Db::integrity_check]
+struct Problem {
+    check: &'static str,
+    relation: String,
+    count: usize,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Look for inconsistencies in the stored data, as may be left behind by unclean shutdowns
+    /// or bugs, and with `repair` set, fix them:
+    ///
+    /// * `stale_index_rows`: rows of an index without a corresponding row in the indexed relation,
+    ///   which are removed,
+    /// * `missing_index_rows`: rows of a relation not reflected in one of its indices,
+    ///   which are added to the index,
+    /// * `dangling_index`: metadata of an index that its relation does not list,
+    ///   which is removed together with its data,
+    /// * `missing_index_metadata`: indices listed by their relation without metadata of their own,
+    ///   which is restored,
+    /// * `id_counter`: the counter of relation ids lagging behind the ids in use,
+    ///   which is moved forward.
+    ///
+    /// Index rows are checked by looking up the rows they correspond to, without holding the
+    /// rows of an index in memory. Repairs hold the locks of all relations while they run.
+    ///
+    /// Returns a row for each problem found, with the number of offending items.
+    pub(crate) fn integrity_check(&'s self, repair: bool) -> Result<NamedRows> {
+        // repairs hold off other access to the relations existing when they start
+        let locked_names = if repair {
+            all_relation_handles(&self.transact()?)?
+                .into_keys()
+                .filter(|name| !name.contains(':'))
+                .collect()
+        } else {
+            BTreeSet::new()
+        };
+        let locks = self.obtain_relation_locks(locked_names.iter());
+        let _guards = locks.iter().map(|l| l.write().unwrap()).collect_vec();
+        let mut tx = if repair {
+            self.transact_write()?
+        } else {
+            self.transact()?
+        };
+        let handles = all_relation_handles(&tx)?;
+        let mut problems = vec![];
+        let mut dangling = vec![];
+
+        for handle in handles.values() {
+            if let Some((base, idx)) = handle.name.split_once(':') {
+                let listed = match handles.get(base) {
+                    None => false,
+                    Some(base) => base.indices.contains_key(idx),
+                };
+                if !listed {
+                    problems.push(Problem {
+                        check: "dangling_index",
+                        relation: handle.name.to_string(),
+                        count: 1,
+                    });
+                    dangling.push(handle.clone());
+                }
+                continue;
+            }
+            for (idx_name, (idx_handle, extractor)) in &handle.indices {
+                let idx_full_name = format!("{}:{}", handle.name, idx_name);
+                if !handles.contains_key(&idx_full_name as &str) {
+                    problems.push(Problem {
+                        check: "missing_index_metadata",
+                        relation: idx_full_name.clone(),
+                        count: 1,
+                    });
+                    if repair {
+                        let key = vec![DataValue::from(&idx_full_name as &str)]
+                            .encode_as_key(RelationId::SYSTEM);
+                        let meta = rmp_serde::to_vec_named(idx_handle).into_diagnostic()?;
+                        tx.store_tx.put(&key, &meta)?;
+                    }
+                }
+
+                // rows of the relation whose index rows are missing, found by looking up
+                // the index row of each row of the relation in turn
+                let mut missing = vec![];
+                for tuple in handle.scan_all(&tx) {
+                    let tuple = tuple?;
+                    if !idx_handle.index_filter_holds(&tuple)? {
+                        continue;
+                    }
+                    // for indices still being built, rows not reached by the build may or may
+                    // not be indexed, depending on whether they have been written since it started
+                    if let Some(from) = &idx_handle.building_from {
+                        if handle.encode_key_for_store(&tuple, Default::default())? >= *from {
+                            continue;
+                        }
+                    }
+                    let extracted = idx_handle.index_key_from(extractor, &tuple);
+                    let idx_key =
+                        idx_handle.encode_key_for_store(&extracted, Default::default())?;
+                    if !tx.store_tx.exists(&idx_key, false)? {
+                        missing.push(idx_key);
+                    }
+                }
+                // rows of the index not derived from a row of the relation, found by looking up
+                // the row of the relation each row of the index points to in turn
+                let key_positions: Vec<_> = (0..handle.metadata.keys.len())
+                    .map(|i| {
+                        extractor.iter().position(|j| *j == i).ok_or_else(|| {
+                            miette!("index {} lacks the keys of its relation", idx_full_name)
+                        })
+                    })
+                    .try_collect()?;
+                let mut stale = vec![];
+                let (lower, upper) = id_range(idx_handle.id);
+                for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+                    let (k, _) = kv_res?;
+                    let idx_tuple = decode_tuple_from_key(&k);
+                    let key_tuple = key_positions
+                        .iter()
+                        .map(|i| idx_tuple[*i].clone())
+                        .collect_vec();
+                    let key = handle.encode_key_for_store(&key_tuple, Default::default())?;
+                    let derived = match tx.store_tx.get(&key, false)? {
+                        None => false,
+                        Some(val) => {
+                            let tuple = decode_tuple_from_kv(&key, &val);
+                            idx_handle.index_filter_holds(&tuple)?
+                                && idx_handle.encode_key_for_store(
+                                    &idx_handle.index_key_from(extractor, &tuple),
+                                    Default::default(),
+                                )? == k
+                        }
+                    };
+                    if !derived {
+                        stale.push(k);
+                    }
+                }
+                if !stale.is_empty() {
+                    problems.push(Problem {
+                        check: "stale_index_rows",
+                        relation: idx_full_name.clone(),
+                        count: stale.len(),
+                    });
+                }
+                if !missing.is_empty() {
+                    problems.push(Problem {
+                        check: "missing_index_rows",
+                        relation: idx_full_name,
+                        count: missing.len(),
+                    });
+                }
+                if repair {
+                    for k in stale {
+                        tx.store_tx.del(&k)?;
+                    }
+                    for k in missing {
+                        tx.store_tx.put(&k, &[])?;
+                    }
+                }
+            }
+        }
+
+        let counter_key = vec![DataValue::Null].encode_as_key(RelationId::SYSTEM);
+        let counter = match tx.store_tx.get(&counter_key, false)? {
+            None => 0,
+            Some(v) => RelationId::raw_decode(&v).0,
+        };
+        let max_id = handles
+            .values()
+            .flat_map(|h| {
+                std::iter::once(h.id.0).chain(h.indices.values().map(|(idx, _)| idx.id.0))
+            })
+            .max()
+            .unwrap_or(0);
+        if counter < max_id {
+            problems.push(Problem {
+                check: "id_counter",
+                relation: String::new(),
+                count: (max_id - counter) as usize,
+            });
+            if repair {
+                tx.store_tx
+                    .put(&counter_key, &RelationId::new(max_id).raw_encode())?;
+            }
+        }
+
+        if repair {
+            for handle in &dangling {
+                let key =
+                    vec![DataValue::from(&handle.name as &str)].encode_as_key(RelationId::SYSTEM);
+                tx.store_tx.del(&key)?;
+            }
+            tx.commit_tx()?;
+            self.relation_store_id.fetch_max(max_id, Ordering::AcqRel);
+            for handle in &dangling {
+                // the id may be shared with a live relation if the counter was behind
+                if handles
+                    .values()
+                    .any(|h| h.id == handle.id && h.name != handle.name)
+                {
+                    continue;
+                }
+                let (lower, upper) = id_range(handle.id);
+                self.db.del_range(&lower, &upper)?;
+            }
+        }
+
+        Ok(NamedRows::new(
+            vec![
+                "check".to_string(),
+                "relation".to_string(),
+                "count".to_string(),
+                "repaired".to_string(),
+            ],
+            problems
+                .into_iter()
+                .map(|p| {
+                    vec![
+                        DataValue::from(p.check),
+                        DataValue::from(p.relation),
+                        DataValue::from(p.count as i64),
+                        DataValue::from(repair),
+                    ]
+                })
+                .collect(),
+        ))
+    }
+}
+
+/// The metadata of all stored relations, indices included, by name
+fn all_relation_handles(
+    tx: &SessionTx<'_>,
+) -> Result<BTreeMap<SmartString<LazyCompact>, RelationHandle>> {
+    let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+    let upper =
+        vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+    let mut ret = BTreeMap::new();
+    for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+        let (k_slice, v_slice) = kv_res?;
+        if upper <= k_slice {
+            break;
+        }
+        let handle = RelationHandle::decode(&v_slice)?;
+        ret.insert(handle.name.clone(), handle);
+    }
+    Ok(ret)
+}
+
+fn id_range(id: RelationId) -> (Vec<u8>, Vec<u8>) {
+    (
+        Tuple::default().encode_as_key(id),
+        Tuple::default().encode_as_key(RelationId(id.0 + 1)),
+    )
+}
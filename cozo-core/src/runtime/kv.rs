@@ -0,0 +1,102 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use miette::{IntoDiagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::tuple::{decode_tuple_from_key, TupleT};
+use crate::data::value::DataValue;
+use crate::runtime::relation::RelationId;
+use crate::{Db, Storage, StoreTx};
+
+/// A namespace of the key-value store embedded in the database, obtained by [Db::kv].
+///
+/// Keys and values are arbitrary [DataValue]s, with keys ordered as in stored relations.
+/// The store lives in the system keyspace, separate from all stored relations, and is meant
+/// for small amounts of data such as application configuration.
+/// Each operation runs in its own transaction.
+pub struct KvNamespace<'s, S> {
+    db: &'s Db<S>,
+    namespace: SmartString<LazyCompact>,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// The namespace `namespace` of the embedded key-value store.
+    pub fn kv(&'s self, namespace: &str) -> KvNamespace<'s, S> {
+        KvNamespace {
+            db: self,
+            namespace: SmartString::from(namespace),
+        }
+    }
+}
+
+impl<'s, S: Storage<'s>> KvNamespace<'s, S> {
+    fn encode_key(&self, key: &DataValue) -> Vec<u8> {
+        vec![
+            DataValue::Null,
+            DataValue::from("KV"),
+            DataValue::from(self.namespace.as_str()),
+            key.clone(),
+        ]
+        .encode_as_key(RelationId::SYSTEM)
+    }
+
+    /// The value stored under `key`.
+    pub fn get(&self, key: &DataValue) -> Result<Option<DataValue>> {
+        self.db.ensure_open()?;
+        let tx = self.db.db.transact(false)?;
+        match tx.get(&self.encode_key(key), false)? {
+            None => Ok(None),
+            Some(v) => Ok(Some(rmp_serde::from_slice(&v).into_diagnostic()?)),
+        }
+    }
+
+    /// Store `val` under `key`, replacing any existing value.
+    pub fn put(&self, key: &DataValue, val: &DataValue) -> Result<()> {
+        self.db.ensure_open()?;
+        let mut tx = self.db.db.transact(true)?;
+        tx.put(
+            &self.encode_key(key),
+            &rmp_serde::to_vec(val).into_diagnostic()?,
+        )?;
+        tx.commit()
+    }
+
+    /// Remove `key`. Returns whether it existed.
+    pub fn remove(&self, key: &DataValue) -> Result<bool> {
+        self.db.ensure_open()?;
+        let mut tx = self.db.db.transact(true)?;
+        let encoded = self.encode_key(key);
+        let existed = tx.exists(&encoded, true)?;
+        if existed {
+            tx.del(&encoded)?;
+        }
+        tx.commit()?;
+        Ok(existed)
+    }
+
+    /// All entries with keys in the range from `lower` (inclusive) to `upper` (exclusive), in order.
+    /// Use [DataValue::Null] and [DataValue::Bot] as bounds to scan the whole namespace.
+    pub fn range(
+        &self,
+        lower: &DataValue,
+        upper: &DataValue,
+    ) -> Result<Vec<(DataValue, DataValue)>> {
+        self.db.ensure_open()?;
+        let tx = self.db.db.transact(false)?;
+        let lower = self.encode_key(lower);
+        let upper = self.encode_key(upper);
+        let mut ret = vec![];
+        for kv_res in tx.range_scan(&lower, &upper) {
+            let (k, v) = kv_res?;
+            let key = decode_tuple_from_key(&k).pop().unwrap();
+            ret.push((key, rmp_serde::from_slice(&v).into_diagnostic()?));
+        }
+        Ok(ret)
+    }
+}
@@ -0,0 +1,107 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use serde_derive::Serialize;
+
+use crate::{Db, Storage};
+
+/// Upper bounds in seconds of the buckets of [LatencyHistogram]
+const LATENCY_BOUNDS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5, 10.,
+];
+
+/// A histogram of query latencies, in the form used by Prometheus.
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyHistogram {
+    /// Pairs of the upper bound of a bucket in seconds and the number of queries
+    /// that took at most that long. The counts are cumulative, and queries longer than
+    /// the last bound are only counted in `count`.
+    pub buckets: Vec<(f64, u64)>,
+    /// Total number of queries
+    pub count: u64,
+    /// Total time taken by all queries, in seconds
+    pub sum: f64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            buckets: LATENCY_BOUNDS.iter().map(|b| (*b, 0)).collect(),
+            count: 0,
+            sum: 0.,
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&mut self, secs: f64) {
+        for (bound, n) in self.buckets.iter_mut() {
+            if secs <= *bound {
+                *n += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+/// Counts an open session or transaction in a gauge of [DbMetrics] while it is alive.
+pub(crate) struct OpenGuard<'a>(&'a AtomicUsize);
+
+impl<'a> OpenGuard<'a> {
+    pub(crate) fn new(gauge: &'a AtomicUsize) -> Self {
+        gauge.fetch_add(1, Ordering::AcqRel);
+        Self(gauge)
+    }
+}
+
+impl Drop for OpenGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Metrics of a database, as returned by [Db::metrics].
+#[derive(Debug, Clone, Serialize)]
+pub struct DbMetrics {
+    /// Kind of the storage engine
+    pub storage_kind: &'static str,
+    /// Number of sessions currently open
+    pub open_sessions: usize,
+    /// Number of multi-statement transactions currently open
+    pub open_transactions: usize,
+    /// Number of queries currently running
+    pub running_queries: usize,
+    /// Number of queries started since the database was opened
+    pub queries_started: u64,
+    /// Number of scripts currently scheduled
+    pub scheduled_scripts: usize,
+    /// Latencies of the queries completed since the database was opened
+    pub query_latency: LatencyHistogram,
+    /// Statistics reported by the storage engine, e.g. RocksDB properties
+    pub storage: BTreeMap<String, u64>,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Snapshot of the metrics of the database, suitable for exporting to monitoring systems.
+    pub fn metrics(&self) -> DbMetrics {
+        DbMetrics {
+            storage_kind: self.db.storage_kind(),
+            open_sessions: self.open_sessions.load(Ordering::Acquire),
+            open_transactions: self.open_transactions.load(Ordering::Acquire),
+            running_queries: self.running_queries.lock().unwrap().len(),
+            queries_started: self.queries_count.load(Ordering::Acquire),
+            scheduled_scripts: self.schedules.lock().unwrap().len(),
+            query_latency: self.query_latency.lock().unwrap().clone(),
+            storage: self.db.metrics(),
+        }
+    }
+}
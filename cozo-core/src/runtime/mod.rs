@@ -7,10 +7,27 @@
  */
 
 pub(crate) mod callback;
+pub(crate) mod codegen;
+pub(crate) mod completion;
 pub(crate) mod db;
 pub(crate) mod imperative;
+pub(crate) mod index_build;
+pub(crate) mod integrity;
+pub(crate) mod kv;
+pub(crate) mod metrics;
+pub(crate) mod push;
 pub(crate) mod relation;
+pub(crate) mod relation_file;
+pub(crate) mod sampling;
+pub(crate) mod saved_query;
+pub(crate) mod schedule;
+pub(crate) mod session;
+pub(crate) mod since_last;
+pub(crate) mod stats;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod sync_hook;
 pub(crate) mod temp_store;
 #[cfg(test)]
 mod tests;
 pub(crate) mod transact;
+pub(crate) mod ttl;
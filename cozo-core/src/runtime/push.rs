@@ -0,0 +1,61 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::program::InputProgram;
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::{OK_STR, STATUS_STR};
+use crate::{Db, DbInstance, NamedRows, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("A query pushed into another database cannot write to stored relations itself")]
+#[diagnostic(code(eval::push_with_write))]
+#[diagnostic(help("Its rows are written into the relation named after `::push`"))]
+struct PushWithWrite(#[label] SourceSpan);
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Run the query `prog` and write its rows into the stored relation `rel` of the database
+    /// opened with `engine` and `path`, as [DbInstance::push_query_result] does.
+    /// Only allowed if scripts may access files, see [Db::set_script_file_access].
+    pub(crate) fn push_query(
+        &'s self,
+        rel: &Symbol,
+        engine: &str,
+        path: &str,
+        prog: InputProgram,
+    ) -> Result<NamedRows> {
+        self.check_script_file_access()?;
+        if prog.out_opts.store_relation.is_some() || !prog.out_opts.secondary_outputs.is_empty() {
+            bail!(PushWithWrite(rel.span));
+        }
+        let rows = self.execute_single(current_validity(), None, None, None, prog)?;
+        let target = DbInstance::new(engine, path, "")?;
+        let pushed = push_rows(rows, &target, &rel.name)?;
+        Ok(NamedRows::new(
+            vec![STATUS_STR.to_string(), "pushed".to_string()],
+            vec![vec![
+                DataValue::from(OK_STR),
+                DataValue::from(pushed as i64),
+            ]],
+        ))
+    }
+}
+
+/// Write `rows` into the stored relation `relation` of `target`, returning how many were written.
+pub(crate) fn push_rows(rows: NamedRows, target: &DbInstance, relation: &str) -> Result<usize> {
+    let pushed = rows.rows.len();
+    target.import_relations(BTreeMap::from([(relation.to_string(), rows)]))?;
+    Ok(pushed)
+}
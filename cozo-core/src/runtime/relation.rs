@@ -8,23 +8,31 @@
 
 use std::collections::BTreeMap;
 use std::fmt::{Debug, Display, Formatter};
+use std::mem;
 use std::sync::atomic::Ordering;
 
 use itertools::Itertools;
 use log::error;
 use miette::{bail, ensure, Diagnostic, Result};
+use ordered_float::OrderedFloat;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 use rmp_serde::Serializer;
 use serde::Serialize;
 use smartstring::{LazyCompact, SmartString};
 use thiserror::Error;
 
+use crate::data::expr::Expr;
+use crate::data::functions::current_validity;
 use crate::data::memcmp::MemCmpEncoder;
-use crate::data::relation::StoredRelationMetadata;
+use crate::data::program::RelationOp;
+use crate::data::relation::{Collation, ColType, NullableColType, StoredRelationMetadata};
 use crate::data::symb::Symbol;
 use crate::data::tuple::{decode_tuple_from_key, Tuple, TupleT, ENCODED_KEY_MIN_LEN};
 use crate::data::value::{DataValue, ValidityTs};
 use crate::parse::SourceSpan;
 use crate::query::compile::IndexPositionUse;
+use crate::runtime::stats::stats_key;
 use crate::runtime::transact::SessionTx;
 use crate::{NamedRows, StoreTx};
 
@@ -77,6 +85,54 @@ pub(crate) struct RelationHandle {
     pub(crate) is_temp: bool,
     #[serde(default)]
     pub(crate) indices: BTreeMap<SmartString<LazyCompact>, (RelationHandle, Vec<usize>)>,
+    /// For append-only relations, the last key column is filled by `:put` from
+    /// a monotonic sequence, so that puts never overwrite existing rows.
+    #[serde(default)]
+    pub(crate) append_only: bool,
+    /// For partial indices, the condition on the rows of the indexed relation
+    /// that are included in the index
+    #[serde(default)]
+    pub(crate) index_filter: Option<Expr>,
+    /// For indices, the key positions holding strings stored under a collation
+    #[serde(default)]
+    pub(crate) index_collations: Vec<(usize, Collation)>,
+    /// The position of the column holding the time, in seconds since the epoch,
+    /// at which each row expires, as set by `::set_ttl`
+    #[serde(default)]
+    pub(crate) ttl_col: Option<usize>,
+    /// Non-key columns deprecated by `::deprecate`, with the columns replacing them if any.
+    /// They can still be read, but writes giving values for them are rejected.
+    #[serde(default)]
+    pub(crate) deprecated: BTreeMap<SmartString<LazyCompact>, Option<SmartString<LazyCompact>>>,
+    /// Attributes defined by `::derive`, computed on read from the columns of the row
+    /// by their expressions. Queries can bind them by name like columns, but they are not stored.
+    #[serde(default)]
+    pub(crate) derived: BTreeMap<SmartString<LazyCompact>, Expr>,
+    /// For relations with validity given scheduled visibility by `::schedule_visibility`,
+    /// queries not given a validity read them at the current time, so that rows asserted
+    /// with a future validity only become visible once it is reached.
+    #[serde(default)]
+    pub(crate) scheduled: bool,
+    /// For bi-temporal relations set up by `::valid_time`, the positions of the columns holding
+    /// the start and the end, if not null, of the valid time of each row, in microseconds
+    /// since the epoch. The validity column then holds the transaction time, which can only be
+    /// that of the writing transaction.
+    #[serde(default)]
+    pub(crate) valid_time: Option<(usize, usize)>,
+    /// For indices being built by [Db::create_index_chunked](crate::Db::create_index_chunked),
+    /// the storage key of the first row of the indexed relation not indexed yet.
+    /// Queries do not use such indices, whereas writes keep them up to date.
+    #[serde(default)]
+    pub(crate) building_from: Option<Vec<u8>>,
+    /// For handles read by queries, the position of the expiry column and the time of
+    /// the query: rows expired by then are skipped by scans and lookups through the handle
+    #[serde(skip)]
+    pub(crate) live_at: Option<(usize, OrderedFloat<f64>)>,
+    /// For handles of append-only relations read by queries given `:since_last`, the position
+    /// of the sequence column and the last sequence number seen by the previous run:
+    /// rows up to it are skipped by scans and lookups through the handle
+    #[serde(skip)]
+    pub(crate) new_after: Option<(usize, i64)>,
 }
 
 #[derive(
@@ -121,10 +177,88 @@ struct StoredRelArityMismatch {
     span: SourceSpan,
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot use index {0} of stored relation {1} as hinted: {2}")]
+#[diagnostic(code(eval::bad_index_hint))]
+pub(crate) struct BadIndexHint(
+    pub(crate) String,
+    pub(crate) String,
+    pub(crate) String,
+    #[label] pub(crate) SourceSpan,
+);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("index {0} for relation {1} not found")]
+#[diagnostic(code(tx::idx_not_found))]
+pub(crate) struct IndexNotFound(pub(crate) String, pub(crate) String);
+
+/// Whether the row `tuple` has expired at the time of `live_at`, see [RelationHandle::live_at]
+pub(crate) fn is_expired(live_at: Option<(usize, OrderedFloat<f64>)>, tuple: &[DataValue]) -> bool {
+    match live_at {
+        None => false,
+        Some((col, now)) => {
+            matches!(tuple.get(col), Some(DataValue::Num(expiry)) if expiry.get_float() <= now.0)
+        }
+    }
+}
+
+/// Whether the row `tuple` was already seen by the previous run, see [RelationHandle::new_after]
+fn is_seen(new_after: Option<(usize, i64)>, tuple: &[DataValue]) -> bool {
+    match new_after {
+        None => false,
+        Some((col, last)) => {
+            matches!(tuple.get(col).and_then(|v| v.get_int()), Some(seq) if seq <= last)
+        }
+    }
+}
+
+/// The rows of `it` that are visible through `handle`
+fn skip_hidden<'a>(
+    handle: &RelationHandle,
+    it: impl Iterator<Item = Result<Tuple>> + 'a,
+) -> impl Iterator<Item = Result<Tuple>> + 'a {
+    let live_at = handle.live_at;
+    let new_after = handle.new_after;
+    it.filter(move |row| {
+        !matches!(row, Ok(tuple) if is_expired(live_at, tuple) || is_seen(new_after, tuple))
+    })
+}
+
+/// Whether scanning an index with the columns at positions `mapper` of the relation
+/// binds all the arguments in use, so that the relation itself need not be joined
+fn index_covers(mapper: &[usize], arg_uses: &[IndexPositionUse]) -> bool {
+    arg_uses
+        .iter()
+        .enumerate()
+        .all(|(i, pos_use)| *pos_use == IndexPositionUse::Ignored || mapper.contains(&i))
+}
+
 impl RelationHandle {
     pub(crate) fn has_triggers(&self) -> bool {
         !self.put_triggers.is_empty() || !self.rm_triggers.is_empty()
     }
+    /// The key in the system keyspace holding the last sequence number used for
+    /// the auto-filled key column of an append-only relation.
+    pub(crate) fn append_seq_key(&self) -> Vec<u8> {
+        vec![
+            DataValue::Null,
+            DataValue::from("APPEND_SEQ"),
+            DataValue::from(self.id.0 as i64),
+        ]
+        .encode_as_key(RelationId::SYSTEM)
+    }
+    pub(crate) fn has_validity(&self) -> bool {
+        match self.metadata.keys.last() {
+            None => false,
+            Some(col) => {
+                col.typing
+                    == NullableColType {
+                        coltype: ColType::Validity,
+                        nullable: false,
+                    }
+            }
+        }
+    }
     fn encode_key_prefix(&self, len: usize) -> Vec<u8> {
         let mut ret = Vec::with_capacity(4 + 4 * len + 10 * len);
         let prefix_bytes = self.id.0.to_be_bytes();
@@ -152,10 +286,79 @@ impl RelationHandle {
         let prefix_bytes = self.id.0.to_be_bytes();
         data[0..8].copy_from_slice(&prefix_bytes);
     }
+    /// Whether the row `tuple` of the indexed relation belongs in this index,
+    /// which is always the case unless the index is partial.
+    pub(crate) fn index_filter_holds(&self, tuple: &[DataValue]) -> Result<bool> {
+        match &self.index_filter {
+            None => Ok(true),
+            Some(filter) => match filter.eval(tuple)? {
+                DataValue::Bool(b) => Ok(b),
+                v => bail!(
+                    "Condition of partial index {} evaluates to {}, not a boolean",
+                    self.name,
+                    v
+                ),
+            },
+        }
+    }
+    /// The key of this index for the row `tuple` of the indexed relation, given the
+    /// positions in `tuple` of the columns of the index.
+    pub(crate) fn index_key_from(&self, extractor: &[usize], tuple: &[DataValue]) -> Tuple {
+        let mut ret = extractor.iter().map(|i| tuple[*i].clone()).collect_vec();
+        for (pos, collation) in &self.index_collations {
+            let val = mem::replace(&mut ret[*pos], DataValue::Null);
+            ret[*pos] = collation.apply(val);
+        }
+        ret
+    }
+    /// The validity at which a query reads this relation when not given one by `@`:
+    /// `default` for relations with validity, or the current time for scheduled ones.
+    pub(crate) fn default_valid_at(&self, default: Option<ValidityTs>) -> Option<ValidityTs> {
+        if !self.has_validity() {
+            None
+        } else if default.is_none() && self.scheduled {
+            Some(current_validity())
+        } else {
+            default
+        }
+    }
+    /// For bi-temporal relations, check that the row `tuple` is written at the transaction
+    /// time `cur_vld`, as its history would otherwise be rewritten.
+    pub(crate) fn check_tx_time(
+        &self,
+        tuple: &Tuple,
+        cur_vld: ValidityTs,
+        span: SourceSpan,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Rows of bi-temporal relation {0} must be written at the time of the transaction")]
+        #[diagnostic(code(eval::explicit_tx_time))]
+        #[diagnostic(help(
+            "Give the validity column as 'ASSERT' or 'RETRACT', and the valid time in its columns"
+        ))]
+        struct ExplicitTxTime(String, #[label] SourceSpan);
+
+        if self.valid_time.is_none() {
+            return Ok(());
+        }
+        match tuple.get(self.metadata.keys.len() - 1) {
+            Some(DataValue::Validity(vld)) if vld.timestamp == cur_vld => Ok(()),
+            _ => bail!(ExplicitTxTime(self.name.to_string(), span)),
+        }
+    }
+    /// This handle, for reading by a query at `now` in seconds since the epoch. If rows expire
+    /// by the column given by [ttl_col](Self::ttl_col), those expired by then are skipped.
+    pub(crate) fn read_at(mut self, now: f64) -> Self {
+        self.live_at = self.ttl_col.map(|col| (col, OrderedFloat(now)));
+        self
+    }
+    /// Partial indices are only chosen if `filter_holds` is true for their conditions.
+    /// Collated indices are never chosen, as they do not hold the original values.
     pub(crate) fn choose_index(
         &self,
         arg_uses: &[IndexPositionUse],
         validity_query: bool,
+        filter_holds: impl Fn(&Expr) -> bool,
     ) -> Option<(RelationHandle, Vec<usize>, bool)> {
         if self.indices.is_empty() {
             return None;
@@ -163,46 +366,80 @@ impl RelationHandle {
         if *arg_uses.first().unwrap() == IndexPositionUse::Join {
             return None;
         }
-        let mut max_prefix_len = 0;
-        let required_positions = arg_uses
-            .iter()
-            .enumerate()
-            .filter_map(|(i, pos_use)| {
-                if *pos_use != IndexPositionUse::Ignored {
-                    Some(i)
-                } else {
-                    None
-                }
-            })
-            .collect_vec();
+        // an index is better if it is joined on a longer prefix, or on a prefix as long
+        // followed by a column restricted to a range, e.g. by `starts_with`
+        let mut best = (0, *arg_uses.first().unwrap() == IndexPositionUse::Range);
         let mut chosen = None;
         for (manifest, mapper) in self.indices.values() {
             if validity_query && *mapper.last().unwrap() != self.metadata.keys.len() - 1 {
                 continue;
             }
-
-            let mut cur_prefix_len = 0;
-            for i in mapper {
-                if arg_uses[*i] == IndexPositionUse::Join {
-                    cur_prefix_len += 1;
-                } else {
-                    break;
-                }
+            if !manifest.index_collations.is_empty() || manifest.building_from.is_some() {
+                continue;
             }
-            if cur_prefix_len > max_prefix_len {
-                max_prefix_len = cur_prefix_len;
-                let mut need_join = false;
-                for need_pos in required_positions.iter() {
-                    if !mapper.contains(need_pos) {
-                        need_join = true;
-                        break;
-                    }
+            if let Some(filter) = &manifest.index_filter {
+                if !filter_holds(filter) {
+                    continue;
                 }
+            }
+
+            let cur_prefix_len = mapper
+                .iter()
+                .take_while(|i| arg_uses[**i] == IndexPositionUse::Join)
+                .count();
+            let ranged = matches!(
+                mapper.get(cur_prefix_len),
+                Some(i) if arg_uses[*i] == IndexPositionUse::Range
+            );
+            if (cur_prefix_len, ranged) > best {
+                best = (cur_prefix_len, ranged);
+                let need_join = !index_covers(mapper, arg_uses);
                 chosen = Some((manifest.clone(), mapper.clone(), need_join))
             }
         }
         chosen
     }
+    /// The index named by a `@use_index` hint, checked to be usable in the way
+    /// [choose_index](Self::choose_index) requires, whether or not it is better than scanning
+    /// the relation itself.
+    pub(crate) fn hinted_index(
+        &self,
+        idx_name: &Symbol,
+        arg_uses: &[IndexPositionUse],
+        validity_query: bool,
+        filter_holds: impl Fn(&Expr) -> bool,
+    ) -> Result<(RelationHandle, Vec<usize>, bool)> {
+        let bad_hint = |reason: &str| {
+            BadIndexHint(
+                idx_name.name.to_string(),
+                self.name.to_string(),
+                reason.to_string(),
+                idx_name.span,
+            )
+        };
+        let (manifest, mapper) = match self.indices.get(&idx_name.name) {
+            None => bail!(bad_hint("the relation has no such index")),
+            Some(found) => found,
+        };
+        if validity_query && *mapper.last().unwrap() != self.metadata.keys.len() - 1 {
+            bail!(bad_hint("the index does not end with the validity column"));
+        }
+        if !manifest.index_collations.is_empty() {
+            bail!(bad_hint("the index holds collated values"));
+        }
+        if manifest.building_from.is_some() {
+            bail!(bad_hint("the index is still being built"));
+        }
+        if let Some(filter) = &manifest.index_filter {
+            if !filter_holds(filter) {
+                bail!(bad_hint(
+                    "the condition of the partial index does not hold for the atom"
+                ));
+            }
+        }
+        let need_join = !index_covers(mapper, arg_uses);
+        Ok((manifest.clone(), mapper.clone(), need_join))
+    }
     pub(crate) fn encode_key_for_store(&self, tuple: &Tuple, span: SourceSpan) -> Result<Vec<u8>> {
         let len = self.metadata.keys.len();
         ensure!(
@@ -241,8 +478,14 @@ impl RelationHandle {
     pub(crate) fn ensure_compatible(
         &self,
         inp: &InputRelationHandle,
-        is_remove: bool,
+        op: &RelationOp,
     ) -> Result<()> {
+        let is_remove = *op == RelationOp::Rm || *op == RelationOp::EnsureNot;
+        let n_required_keys = if self.append_only && *op == RelationOp::Put {
+            self.metadata.keys.len() - 1
+        } else {
+            self.metadata.keys.len()
+        };
         let InputRelationHandle { metadata, .. } = inp;
         // check that every given key is found and compatible
         for col in &metadata.keys {
@@ -252,7 +495,7 @@ impl RelationHandle {
             self.metadata.compatible_with_col(col, false)?
         }
         // check that every key is provided or has default
-        for col in &self.metadata.keys {
+        for col in &self.metadata.keys[..n_required_keys] {
             metadata.satisfied_by_required_col(col, true)?;
         }
         if !is_remove {
@@ -307,11 +550,14 @@ impl RelationHandle {
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
         let lower = Tuple::default().encode_as_key(self.id);
         let upper = Tuple::default().encode_as_key(self.id.next());
-        if self.is_temp {
-            tx.temp_store_tx.range_scan_tuple(&lower, &upper)
-        } else {
-            tx.store_tx.range_scan_tuple(&lower, &upper)
-        }
+        skip_hidden(
+            self,
+            if self.is_temp {
+                tx.temp_store_tx.range_scan_tuple(&lower, &upper)
+            } else {
+                tx.store_tx.range_scan_tuple(&lower, &upper)
+            },
+        )
     }
 
     pub(crate) fn skip_scan_all<'a>(
@@ -321,30 +567,36 @@ impl RelationHandle {
     ) -> impl Iterator<Item = Result<Tuple>> + 'a {
         let lower = Tuple::default().encode_as_key(self.id);
         let upper = Tuple::default().encode_as_key(self.id.next());
-        if self.is_temp {
-            tx.temp_store_tx
-                .range_skip_scan_tuple(&lower, &upper, valid_at)
-        } else {
-            tx.store_tx.range_skip_scan_tuple(&lower, &upper, valid_at)
-        }
+        skip_hidden(
+            self,
+            if self.is_temp {
+                tx.temp_store_tx
+                    .range_skip_scan_tuple(&lower, &upper, valid_at)
+            } else {
+                tx.store_tx.range_skip_scan_tuple(&lower, &upper, valid_at)
+            },
+        )
     }
 
     pub(crate) fn get(&self, tx: &SessionTx<'_>, key: &[DataValue]) -> Result<Option<Tuple>> {
         let key_data = key.encode_as_key(self.id);
-        if self.is_temp {
-            Ok(tx
-                .temp_store_tx
+        let found = if self.is_temp {
+            tx.temp_store_tx
                 .get(&key_data, false)?
-                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data)))
+                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data))
         } else {
-            Ok(tx
-                .store_tx
+            tx.store_tx
                 .get(&key_data, false)?
-                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data)))
-        }
+                .map(|val_data| decode_tuple_from_kv(&key_data, &val_data))
+        };
+        Ok(found
+            .filter(|tuple| !is_expired(self.live_at, tuple) && !is_seen(self.new_after, tuple)))
     }
 
     pub(crate) fn exists(&self, tx: &SessionTx<'_>, key: &[DataValue]) -> Result<bool> {
+        if self.live_at.is_some() || self.new_after.is_some() {
+            return Ok(self.get(tx, key)?.is_some());
+        }
         let key_data = key.encode_as_key(self.id);
         if self.is_temp {
             tx.temp_store_tx.exists(&key_data, false)
@@ -364,13 +616,16 @@ impl RelationHandle {
         upper.push(DataValue::Bot);
         let prefix_encoded = lower.encode_as_key(self.id);
         let upper_encoded = upper.encode_as_key(self.id);
-        if self.is_temp {
-            tx.temp_store_tx
-                .range_scan_tuple(&prefix_encoded, &upper_encoded)
-        } else {
-            tx.store_tx
-                .range_scan_tuple(&prefix_encoded, &upper_encoded)
-        }
+        skip_hidden(
+            self,
+            if self.is_temp {
+                tx.temp_store_tx
+                    .range_scan_tuple(&prefix_encoded, &upper_encoded)
+            } else {
+                tx.store_tx
+                    .range_scan_tuple(&prefix_encoded, &upper_encoded)
+            },
+        )
     }
 
     pub(crate) fn skip_scan_prefix<'a>(
@@ -385,13 +640,16 @@ impl RelationHandle {
         upper.push(DataValue::Bot);
         let prefix_encoded = lower.encode_as_key(self.id);
         let upper_encoded = upper.encode_as_key(self.id);
-        if self.is_temp {
-            tx.temp_store_tx
-                .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
-        } else {
-            tx.store_tx
-                .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
-        }
+        skip_hidden(
+            self,
+            if self.is_temp {
+                tx.temp_store_tx
+                    .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
+            } else {
+                tx.store_tx
+                    .range_skip_scan_tuple(&prefix_encoded, &upper_encoded, valid_at)
+            },
+        )
     }
 
     pub(crate) fn scan_bounded_prefix<'a>(
@@ -408,12 +666,15 @@ impl RelationHandle {
         upper_t.push(DataValue::Bot);
         let lower_encoded = lower_t.encode_as_key(self.id);
         let upper_encoded = upper_t.encode_as_key(self.id);
-        if self.is_temp {
-            tx.temp_store_tx
-                .range_scan_tuple(&lower_encoded, &upper_encoded)
-        } else {
-            tx.store_tx.range_scan_tuple(&lower_encoded, &upper_encoded)
-        }
+        skip_hidden(
+            self,
+            if self.is_temp {
+                tx.temp_store_tx
+                    .range_scan_tuple(&lower_encoded, &upper_encoded)
+            } else {
+                tx.store_tx.range_scan_tuple(&lower_encoded, &upper_encoded)
+            },
+        )
     }
     pub(crate) fn skip_scan_bounded_prefix<'a>(
         &self,
@@ -430,13 +691,16 @@ impl RelationHandle {
         upper_t.push(DataValue::Bot);
         let lower_encoded = lower_t.encode_as_key(self.id);
         let upper_encoded = upper_t.encode_as_key(self.id);
-        if self.is_temp {
-            tx.temp_store_tx
-                .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
-        } else {
-            tx.store_tx
-                .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
-        }
+        skip_hidden(
+            self,
+            if self.is_temp {
+                tx.temp_store_tx
+                    .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
+            } else {
+                tx.store_tx
+                    .range_skip_scan_tuple(&lower_encoded, &upper_encoded, valid_at)
+            },
+        )
     }
 }
 
@@ -463,13 +727,23 @@ struct RelNameConflictError(String);
 
 impl<'a> SessionTx<'a> {
     pub(crate) fn relation_exists(&self, name: &str) -> Result<bool> {
+        if self.missing_relations.lock().unwrap().contains(name) {
+            return Ok(false);
+        }
         let key = DataValue::from(name);
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
-        if name.starts_with('_') {
-            self.temp_store_tx.exists(&encoded, false)
+        let found = if name.starts_with('_') {
+            self.temp_store_tx.exists(&encoded, false)?
         } else {
-            self.store_tx.exists(&encoded, false)
+            self.store_tx.exists(&encoded, false)?
+        };
+        if !found {
+            self.missing_relations
+                .lock()
+                .unwrap()
+                .insert(SmartString::from(name));
         }
+        Ok(found)
     }
     pub(crate) fn set_relation_triggers(
         &mut self,
@@ -521,6 +795,11 @@ impl<'a> SessionTx<'a> {
             bail!(RelNameConflictError(input_meta.name.to_string()))
         }
 
+        self.missing_relations
+            .lock()
+            .unwrap()
+            .remove(&input_meta.name.name);
+
         let metadata = input_meta.metadata.clone();
         let last_id = if is_temp {
             self.temp_store_id.fetch_add(1, Ordering::Relaxed) as u64
@@ -537,6 +816,17 @@ impl<'a> SessionTx<'a> {
             access_level: AccessLevel::Normal,
             is_temp,
             indices: Default::default(),
+            append_only: false,
+            index_filter: None,
+            index_collations: vec![],
+            ttl_col: None,
+            deprecated: Default::default(),
+            derived: Default::default(),
+            scheduled: false,
+            valid_time: None,
+            building_from: None,
+            live_at: None,
+            new_after: None,
         };
 
         let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
@@ -564,20 +854,28 @@ impl<'a> SessionTx<'a> {
         #[diagnostic(code(query::relation_not_found))]
         struct StoredRelationNotFoundError(String);
 
+        if !lock && self.missing_relations.lock().unwrap().contains(name) {
+            bail!(StoredRelationNotFoundError(name.to_string()))
+        }
+
         let key = DataValue::from(name);
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
 
         let found = if name.starts_with('_') {
-            self.temp_store_tx
-                .get(&encoded, lock)?
-                .ok_or_else(|| StoredRelationNotFoundError(name.to_string()))?
+            self.temp_store_tx.get(&encoded, lock)?
         } else {
-            self.store_tx
-                .get(&encoded, lock)?
-                .ok_or_else(|| StoredRelationNotFoundError(name.to_string()))?
+            self.store_tx.get(&encoded, lock)?
         };
-        let metadata = RelationHandle::decode(&found)?;
-        Ok(metadata)
+        match found {
+            None => {
+                self.missing_relations
+                    .lock()
+                    .unwrap()
+                    .insert(SmartString::from(name));
+                bail!(StoredRelationNotFoundError(name.to_string()))
+            }
+            Some(found) => RelationHandle::decode(&found),
+        }
     }
     pub(crate) fn destroy_relation(&mut self, name: &str) -> Result<(Vec<u8>, Vec<u8>)> {
         if name.starts_with('_') {
@@ -602,10 +900,353 @@ impl<'a> SessionTx<'a> {
         let key = DataValue::from(name);
         let encoded = vec![key].encode_as_key(RelationId::SYSTEM);
         self.store_tx.del(&encoded)?;
+        self.store_tx.del(&stats_key(name))?;
         let lower_bound = Tuple::default().encode_as_key(store.id);
         let upper_bound = Tuple::default().encode_as_key(store.id.next());
         Ok((lower_bound, upper_bound))
     }
+    /// The key range holding all rows of a stored relation whose first key column
+    /// equals `val`. Rows are stored ordered by their keys, so such a partition is contiguous.
+    pub(crate) fn partition_bounds(
+        &mut self,
+        name: &str,
+        val: DataValue,
+        cur_vld: ValidityTs,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
+        let store = self.get_relation(name, true)?;
+        if name.starts_with('_') || name.contains(':') {
+            bail!("Cannot remove partitions of `{}`", name);
+        }
+        if !store.indices.is_empty() {
+            bail!(
+                "Cannot remove partitions of stored relation `{}` with indices attached.",
+                name
+            );
+        }
+        if store.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                store.name.to_string(),
+                "partition removal".to_string(),
+                store.access_level
+            ))
+        }
+        let val = match store.metadata.keys.first() {
+            Some(col) => col.typing.coerce(val, cur_vld)?,
+            None => bail!("Stored relation `{}` has no keys to partition by", name),
+        };
+        let lower_bound = vec![val.clone()].encode_as_key(store.id);
+        let upper_bound = vec![val, DataValue::Bot].encode_as_key(store.id);
+        Ok((lower_bound, upper_bound))
+    }
+    pub(crate) fn set_append_only(&mut self, rel: Symbol, append_only: bool) -> Result<()> {
+        let mut meta = self.get_relation(&rel, true)?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "changing relation kind".to_string(),
+                meta.access_level
+            ))
+        }
+        if append_only && !meta.append_only {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Cannot make relation {0} append-only")]
+            #[diagnostic(code(eval::bad_append_only_relation))]
+            struct BadAppendOnlyRelation(String, #[help] String);
+
+            if meta.is_temp || meta.name.contains(':') {
+                bail!(BadAppendOnlyRelation(
+                    meta.name.to_string(),
+                    "Only stored relations can be append-only".to_string()
+                ))
+            }
+            let seq_col_ok = match meta.metadata.keys.last() {
+                None => false,
+                Some(col) => matches!(col.typing.coltype, ColType::Int | ColType::Any),
+            };
+            if !seq_col_ok {
+                bail!(BadAppendOnlyRelation(
+                    meta.name.to_string(),
+                    "The last key column must be of type Int or Any, to hold the sequence number"
+                        .to_string()
+                ))
+            }
+            let lower = Tuple::default().encode_as_key(meta.id);
+            let upper = Tuple::default().encode_as_key(meta.id.next());
+            if self.store_tx.range_scan(&lower, &upper).next().is_some() {
+                bail!(BadAppendOnlyRelation(
+                    meta.name.to_string(),
+                    "The relation must be empty".to_string()
+                ))
+            }
+        }
+        meta.append_only = append_only;
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+
+    /// Turn the scheduled visibility of a stored relation with validity on or off,
+    /// see [RelationHandle::scheduled].
+    pub(crate) fn set_scheduled(&mut self, rel: Symbol, scheduled: bool) -> Result<()> {
+        let mut meta = self.get_relation(&rel, true)?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "scheduling".to_string(),
+                meta.access_level
+            ))
+        }
+        if scheduled && !meta.has_validity() {
+            #[derive(Debug, Error, Diagnostic)]
+            #[error("Cannot schedule the visibility of relation {0}")]
+            #[diagnostic(code(eval::bad_scheduled_relation))]
+            #[diagnostic(help("The last key column must be of type Validity"))]
+            struct BadScheduledRelation(String, #[label] SourceSpan);
+
+            bail!(BadScheduledRelation(meta.name.to_string(), rel.span))
+        }
+        meta.scheduled = scheduled;
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+
+    /// Make a stored relation with validity bi-temporal, with the valid time of each row
+    /// held by the columns `cols`, or make it an ordinary relation with validity if `None`.
+    pub(crate) fn set_valid_time(
+        &mut self,
+        rel: Symbol,
+        cols: Option<(Symbol, Symbol)>,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot set the valid time of relation {0}")]
+        #[diagnostic(code(eval::bad_valid_time))]
+        struct BadValidTime(String, #[help] String, #[label] SourceSpan);
+
+        let mut meta = self.get_relation(&rel, true)?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "setting valid time".to_string(),
+                meta.access_level
+            ))
+        }
+        meta.valid_time = match cols {
+            None => None,
+            Some((from, to)) => {
+                if meta.is_temp || meta.name.contains(':') || !meta.has_validity() {
+                    bail!(BadValidTime(
+                        meta.name.to_string(),
+                        "Only stored relations whose last key column is of type Validity, \
+                         holding the transaction time, can have a valid time"
+                            .to_string(),
+                        rel.span
+                    ))
+                }
+                let n_keys = meta.metadata.keys.len();
+                let mut positions = vec![];
+                for col in [&from, &to] {
+                    let found = meta
+                        .metadata
+                        .keys
+                        .iter()
+                        .chain(meta.metadata.non_keys.iter())
+                        .find_position(|def| def.name == col.name);
+                    match found {
+                        Some((idx, def))
+                            if idx != n_keys - 1 && def.typing.coltype == ColType::Int =>
+                        {
+                            positions.push(idx)
+                        }
+                        Some(_) => bail!(BadValidTime(
+                            meta.name.to_string(),
+                            "The column must be of type Int, \
+                             to hold the time in microseconds since the epoch"
+                                .to_string(),
+                            col.span
+                        )),
+                        None => bail!(BadValidTime(
+                            meta.name.to_string(),
+                            format!("The relation has no column {}", col.name),
+                            col.span
+                        )),
+                    }
+                }
+                Some((positions[0], positions[1]))
+            }
+        };
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+
+    /// Define the attribute `attr` of a stored relation as computed from the columns of each row
+    /// by `expr`, or remove the definition if `expr` is `None`.
+    pub(crate) fn set_derived(
+        &mut self,
+        rel: Symbol,
+        attr: Symbol,
+        expr: Option<Expr>,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot derive attribute {1} of relation {0}")]
+        #[diagnostic(code(eval::bad_derived_attr))]
+        struct BadDerivedAttr(String, String, #[help] String, #[label] SourceSpan);
+
+        let mut meta = self.get_relation(&rel, true)?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "deriving attributes".to_string(),
+                meta.access_level
+            ))
+        }
+        let bad = |help: String, span: SourceSpan| {
+            BadDerivedAttr(meta.name.to_string(), attr.name.to_string(), help, span)
+        };
+        let is_col = |name: &str| {
+            meta.metadata
+                .keys
+                .iter()
+                .chain(meta.metadata.non_keys.iter())
+                .any(|def| def.name == name)
+        };
+        match expr {
+            None => {
+                if meta.derived.remove(&attr.name).is_none() {
+                    bail!(bad(
+                        "The relation has no such derived attribute".to_string(),
+                        attr.span
+                    ))
+                }
+            }
+            Some(mut expr) => {
+                if is_col(&attr.name) {
+                    bail!(bad(
+                        "The relation already has a column of the same name".to_string(),
+                        attr.span
+                    ))
+                }
+                for var in expr.bindings() {
+                    if !is_col(&var.name) {
+                        bail!(bad(
+                            format!(
+                                "The expression can only refer to the columns of the relation, \
+                                 but {} is not one",
+                                var.name
+                            ),
+                            var.span
+                        ))
+                    }
+                }
+                expr.partial_eval()?;
+                meta.derived.insert(attr.name.clone(), expr);
+            }
+        }
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
+
+    /// Deprecate the column `col` of a stored relation, or lift the deprecation if
+    /// `deprecate` is false. Only non-key columns having a default can be deprecated,
+    /// so that rows can still be put without them.
+    pub(crate) fn set_deprecated(
+        &mut self,
+        rel: Symbol,
+        col: Symbol,
+        deprecate: bool,
+        replacement: Option<Symbol>,
+    ) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot deprecate column {1} of relation {0}")]
+        #[diagnostic(code(eval::bad_deprecation))]
+        struct BadDeprecation(String, String, #[help] String, #[label] SourceSpan);
+
+        let mut meta = self.get_relation(&rel, true)?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "deprecating columns".to_string(),
+                meta.access_level
+            ))
+        }
+        let bad = |help: String, span: SourceSpan| {
+            BadDeprecation(meta.name.to_string(), col.name.to_string(), help, span)
+        };
+        let def = match meta
+            .metadata
+            .non_keys
+            .iter()
+            .find(|def| def.name == col.name)
+        {
+            Some(def) => def,
+            None => {
+                let help = if meta.metadata.keys.iter().any(|def| def.name == col.name) {
+                    "Key columns cannot be deprecated".to_string()
+                } else {
+                    format!("The relation has no column {}", col.name)
+                };
+                bail!(bad(help, col.span))
+            }
+        };
+        if !deprecate {
+            meta.deprecated.remove(&col.name);
+        } else {
+            if def.default_gen.is_none() {
+                bail!(bad(
+                    "Only columns having a default can be deprecated, \
+                     so that rows can still be put without them"
+                        .to_string(),
+                    col.span
+                ))
+            }
+            if let Some(r) = &replacement {
+                let exists = meta
+                    .metadata
+                    .keys
+                    .iter()
+                    .chain(meta.metadata.non_keys.iter())
+                    .any(|def| def.name == r.name);
+                if !exists || r.name == col.name {
+                    bail!(bad(
+                        format!("{} is not another column of the relation", r.name),
+                        r.span
+                    ))
+                }
+            }
+            meta.deprecated
+                .insert(col.name.clone(), replacement.map(|r| r.name));
+        }
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
     pub(crate) fn set_access_level(&mut self, rel: Symbol, level: AccessLevel) -> Result<()> {
         let mut meta = self.get_relation(&rel, true)?;
         meta.access_level = level;
@@ -619,12 +1260,79 @@ impl<'a> SessionTx<'a> {
 
         Ok(())
     }
+    /// Make the rows of a stored relation expire at the time held by the column `col`,
+    /// or never expire if `col` is `None`.
+    pub(crate) fn set_ttl(&mut self, rel: Symbol, col: Option<Symbol>) -> Result<()> {
+        #[derive(Debug, Error, Diagnostic)]
+        #[error("Cannot set the TTL of relation {0}")]
+        #[diagnostic(code(eval::bad_ttl))]
+        struct BadTtl(String, #[help] String, #[label] SourceSpan);
+
+        let mut meta = self.get_relation(&rel, true)?;
+        if meta.access_level < AccessLevel::Normal {
+            bail!(InsufficientAccessLevel(
+                meta.name.to_string(),
+                "setting TTL".to_string(),
+                meta.access_level
+            ))
+        }
+        if meta.is_temp || meta.name.contains(':') {
+            bail!(BadTtl(
+                meta.name.to_string(),
+                "Only stored relations can have a TTL".to_string(),
+                rel.span
+            ))
+        }
+        meta.ttl_col = match col {
+            None => None,
+            Some(col) => {
+                let found = meta
+                    .metadata
+                    .keys
+                    .iter()
+                    .chain(meta.metadata.non_keys.iter())
+                    .find_position(|def| def.name == col.name);
+                match found {
+                    None => bail!(BadTtl(
+                        meta.name.to_string(),
+                        format!("The relation has no column {}", col.name),
+                        col.span
+                    )),
+                    Some((idx, def)) => {
+                        if !matches!(def.typing.coltype, ColType::Int | ColType::Float) {
+                            bail!(BadTtl(
+                                meta.name.to_string(),
+                                "The column must be of type Int or Float, \
+                                 to hold the expiry time in seconds since the epoch"
+                                    .to_string(),
+                                col.span
+                            ))
+                        }
+                        Some(idx)
+                    }
+                }
+            }
+        };
+
+        let name_key = vec![DataValue::Str(meta.name.clone())].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        meta.serialize(&mut Serializer::new(&mut meta_val).with_struct_map())
+            .unwrap();
+        self.store_tx.put(&name_key, &meta_val)?;
+
+        Ok(())
+    }
 
+    /// Create an index and fill it with the rows of the relation, unless `deferred` is true,
+    /// in which case the index is filled afterwards by [SessionTx::build_index_chunk].
     pub(crate) fn create_index(
         &mut self,
         rel_name: &Symbol,
         idx_name: &Symbol,
         cols: Vec<Symbol>,
+        collations: Vec<(usize, Collation)>,
+        filter: Option<Expr>,
+        deferred: bool,
     ) -> Result<()> {
         let mut rel_handle = self.get_relation(rel_name, true)?;
         if rel_handle.indices.contains_key(&idx_name.name) {
@@ -665,6 +1373,26 @@ impl<'a> SessionTx<'a> {
             ));
         }
 
+        // the keys of the relation keep rows apart in the index only if stored as they are
+        for (pos, _) in collations.iter() {
+            let col = &col_defs[*pos];
+            if rel_handle.metadata.keys.iter().any(|k| k.name == col.name) {
+                #[derive(Debug, Error, Diagnostic)]
+                #[error("Key column {0} of relation {1} cannot be collated in index {2}")]
+                #[diagnostic(code(tx::collated_key_in_index))]
+                #[diagnostic(help(
+                    "Rows whose keys only differ under the collation would share index entries"
+                ))]
+                pub(crate) struct CollatedKeyInIndex(String, String, String);
+
+                bail!(CollatedKeyInIndex(
+                    col.name.to_string(),
+                    rel_name.name.to_string(),
+                    idx_name.name.to_string()
+                ));
+            }
+        }
+
         'outer: for key in rel_handle.metadata.keys.iter() {
             for col in cols.iter() {
                 if col.name == key.name {
@@ -694,7 +1422,21 @@ impl<'a> SessionTx<'a> {
             span: Default::default(),
         };
 
-        let idx_handle = self.create_relation(idx_handle)?;
+        let mut idx_handle = self.create_relation(idx_handle)?;
+        idx_handle.index_collations = collations;
+        if let Some(mut filter) = filter {
+            let binding_map = rel_handle
+                .metadata
+                .keys
+                .iter()
+                .chain(rel_handle.metadata.non_keys.iter())
+                .enumerate()
+                .map(|(i, col)| (Symbol::new(col.name.clone(), Default::default()), i))
+                .collect();
+            filter.partial_eval()?;
+            filter.fill_binding_indices(&binding_map)?;
+            idx_handle.index_filter = Some(filter);
+        }
 
         // populate index
         let extraction_indices = idx_handle
@@ -716,23 +1458,25 @@ impl<'a> SessionTx<'a> {
             })
             .collect_vec();
 
-        if self.store_tx.supports_par_put() {
+        if deferred {
+            idx_handle.building_from = Some(Tuple::default().encode_as_key(rel_handle.id));
+        } else if self.store_tx.supports_par_put() {
             for tuple in rel_handle.scan_all(self) {
                 let tuple = tuple?;
-                let extracted = extraction_indices
-                    .iter()
-                    .map(|idx| tuple[*idx].clone())
-                    .collect_vec();
+                if !idx_handle.index_filter_holds(&tuple)? {
+                    continue;
+                }
+                let extracted = idx_handle.index_key_from(&extraction_indices, &tuple);
                 let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
                 self.store_tx.par_put(&key, &[])?;
             }
         } else {
             for tuple in rel_handle.scan_all(self).collect_vec() {
                 let tuple = tuple?;
-                let extracted = extraction_indices
-                    .iter()
-                    .map(|idx| tuple[*idx].clone())
-                    .collect_vec();
+                if !idx_handle.index_filter_holds(&tuple)? {
+                    continue;
+                }
+                let extracted = idx_handle.index_key_from(&extraction_indices, &tuple);
                 let key = idx_handle.encode_key_for_store(&extracted, Default::default())?;
                 self.store_tx.put(&key, &[])?;
             }
@@ -753,18 +1497,104 @@ impl<'a> SessionTx<'a> {
         Ok(())
     }
 
-    pub(crate) fn remove_index(&mut self, rel_name: &Symbol, idx_name: &Symbol) -> Result<()> {
+    /// Index up to `chunk_size` rows of the relation for an index created with `deferred` set,
+    /// starting from the first row not indexed yet. The index keys are computed and written
+    /// in parallel when possible. Returns the number of rows looked at, and whether the index
+    /// is complete, after which queries can use it.
+    ///
+    /// With `online` set, the rows are read again for update, so that the transaction conflicts
+    /// with concurrent writes to them instead of indexing rows changed in the meantime.
+    pub(crate) fn build_index_chunk(
+        &mut self,
+        rel_name: &Symbol,
+        idx_name: &Symbol,
+        chunk_size: usize,
+        online: bool,
+    ) -> Result<(usize, bool)> {
+        let mut rel_handle = self.get_relation(rel_name, true)?;
+        let from = match rel_handle.indices.get(&idx_name.name) {
+            None => bail!(IndexNotFound(idx_name.to_string(), rel_name.to_string())),
+            Some((idx_handle, _)) => match &idx_handle.building_from {
+                None => return Ok((0, true)),
+                Some(from) => from.clone(),
+            },
+        };
+        let upper = Tuple::default().encode_as_key(rel_handle.id.next());
+        let mut rows: Vec<Tuple> = self
+            .store_tx
+            .range_scan_tuple(&from, &upper)
+            .take(chunk_size + 1)
+            .try_collect()?;
+        let next_from = if rows.len() > chunk_size {
+            let next = rows.pop().unwrap();
+            Some(rel_handle.encode_key_for_store(&next, Default::default())?)
+        } else {
+            None
+        };
+        if online {
+            for tuple in &rows {
+                let key = rel_handle.encode_key_for_store(tuple, Default::default())?;
+                self.store_tx.get(&key, true)?;
+            }
+        }
+
+        let (idx_handle, extractor) = rel_handle.indices.get_mut(&idx_name.name).unwrap();
+        let idx_keys = {
+            let idx_handle = &*idx_handle;
+            let extractor = &*extractor;
+            #[cfg(not(feature = "rayon"))]
+            let it = rows.iter();
+            #[cfg(feature = "rayon")]
+            let it = rows.par_iter();
+            it.map(|tuple| -> Result<Option<Vec<u8>>> {
+                if !idx_handle.index_filter_holds(tuple)? {
+                    return Ok(None);
+                }
+                let extracted = idx_handle.index_key_from(extractor, tuple);
+                Ok(Some(
+                    idx_handle.encode_key_for_store(&extracted, Default::default())?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?
+        };
+        if self.store_tx.supports_par_put() {
+            let store_tx = &self.store_tx;
+            #[cfg(not(feature = "rayon"))]
+            let it = idx_keys.iter();
+            #[cfg(feature = "rayon")]
+            let it = idx_keys.par_iter();
+            it.filter_map(|key| key.as_ref())
+                .try_for_each(|key| store_tx.par_put(key, &[]))?;
+        } else {
+            for key in idx_keys.iter().flatten() {
+                self.store_tx.put(key, &[])?;
+            }
+        }
+
+        let finished = next_from.is_none();
+        idx_handle.building_from = next_from;
+        let new_encoded =
+            vec![DataValue::from(&rel_name.name as &str)].encode_as_key(RelationId::SYSTEM);
+        let mut meta_val = vec![];
+        rel_handle
+            .serialize(&mut Serializer::new(&mut meta_val))
+            .unwrap();
+        self.store_tx.put(&new_encoded, &meta_val)?;
+
+        Ok((rows.len(), finished))
+    }
+
+    pub(crate) fn remove_index(
+        &mut self,
+        rel_name: &Symbol,
+        idx_name: &Symbol,
+    ) -> Result<(Vec<u8>, Vec<u8>)> {
         let mut rel = self.get_relation(rel_name, true)?;
         if rel.indices.remove(&idx_name.name).is_none() {
-            #[derive(Debug, Error, Diagnostic)]
-            #[error("index {0} for relation {1} not found")]
-            #[diagnostic(code(tx::idx_not_found))]
-            pub(crate) struct IndexNotFound(String, String);
-
             bail!(IndexNotFound(idx_name.to_string(), rel_name.to_string()));
         }
 
-        self.destroy_relation(&format!("{}:{}", rel_name.name, idx_name.name))?;
+        let bounds = self.destroy_relation(&format!("{}:{}", rel_name.name, idx_name.name))?;
 
         let new_encoded =
             vec![DataValue::from(&rel_name.name as &str)].encode_as_key(RelationId::SYSTEM);
@@ -772,7 +1602,7 @@ impl<'a> SessionTx<'a> {
         rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
         self.store_tx.put(&new_encoded, &meta_val)?;
 
-        Ok(())
+        Ok(bounds)
     }
 
     pub(crate) fn rename_relation(&mut self, old: Symbol, new: Symbol) -> Result<()> {
@@ -797,12 +1627,17 @@ impl<'a> SessionTx<'a> {
                 rel.access_level
             ));
         }
+        self.missing_relations.lock().unwrap().remove(&new.name);
         rel.name = new.name;
 
         let mut meta_val = vec![];
         rel.serialize(&mut Serializer::new(&mut meta_val)).unwrap();
         self.store_tx.del(&old_encoded)?;
         self.store_tx.put(&new_encoded, &meta_val)?;
+        if let Some(stats) = self.store_tx.get(&stats_key(&old.name), false)? {
+            self.store_tx.del(&stats_key(&old.name))?;
+            self.store_tx.put(&stats_key(&rel.name), &stats)?;
+        }
 
         Ok(())
     }
@@ -818,6 +1653,7 @@ impl<'a> SessionTx<'a> {
         let old_encoded = vec![old_key].encode_as_key(RelationId::SYSTEM);
 
         let mut rel = self.get_relation(&old, true)?;
+        self.missing_relations.lock().unwrap().remove(&new.name);
         rel.name = new.name;
 
         let mut meta_val = vec![];
@@ -0,0 +1,253 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::iter;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use crc32fast::Hasher;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::data::json::JsonValue;
+use crate::data::value::DataValue;
+use crate::runtime::db::{OK_STR, STATUS_STR};
+use crate::{Db, NamedRows, Storage};
+
+/// The value of the `format` field in the header of relation files
+const RELATION_FILE_FORMAT: &str = "cozo_relation";
+/// The version of the layout of relation files written by this version of Cozo
+const RELATION_FILE_VERSION: u32 = 1;
+
+/// The first record of a relation file
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct FileHeader {
+    format: String,
+    version: u32,
+    headers: Vec<String>,
+    /// The number of rows following the header
+    rows: usize,
+}
+
+/// The last record of a relation file
+#[derive(serde_derive::Serialize, serde_derive::Deserialize)]
+struct FileTrailer {
+    /// CRC-32 of all bytes preceding the trailer
+    checksum: u32,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Malformed relation file {0}")]
+#[diagnostic(code(import::bad_relation_file))]
+struct BadRelationFile(String, #[help] String);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation file {0} has version {1}, which this version of Cozo cannot read")]
+#[diagnostic(code(import::unsupported_file_version))]
+#[diagnostic(help("Versions up to {RELATION_FILE_VERSION} are supported"))]
+struct UnsupportedFileVersion(String, u32);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Checksum mismatch in relation file {0}: expected {1:08x}, found {2:08x}")]
+#[diagnostic(code(import::checksum_mismatch))]
+#[diagnostic(help("The file has been corrupted or modified since it was exported"))]
+struct ChecksumMismatch(String, u32, u32);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Scripts are not allowed to access files")]
+#[diagnostic(code(eval::file_access_denied))]
+#[diagnostic(help("File access must be enabled with `Db::set_script_file_access`"))]
+struct FileAccessDenied;
+
+/// Files ending in `.msgpack` or `.mpk` hold msgpack, all others JSON lines.
+fn is_msgpack(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|ext| ext.to_str()),
+        Some("msgpack" | "mpk")
+    )
+}
+
+/// Writes records in the format of a relation file, keeping the checksum of the bytes written.
+struct RecordWriter<W> {
+    inner: W,
+    hasher: Hasher,
+    msgpack: bool,
+}
+
+impl<W: Write> RecordWriter<W> {
+    fn write<T: Serialize>(&mut self, record: &T) -> Result<()> {
+        let mut buf = if self.msgpack {
+            rmp_serde::to_vec_named(record).into_diagnostic()?
+        } else {
+            serde_json::to_vec(record).into_diagnostic()?
+        };
+        if !self.msgpack {
+            buf.push(b'\n');
+        }
+        self.hasher.update(&buf);
+        self.inner.write_all(&buf).into_diagnostic()
+    }
+    fn finish(mut self) -> Result<()> {
+        let checksum = self.hasher.clone().finalize();
+        self.write(&FileTrailer { checksum })?;
+        self.inner.flush().into_diagnostic()
+    }
+}
+
+/// Reads records in the format of a relation file, keeping the checksum of the bytes read.
+struct RecordReader<'a, R> {
+    inner: R,
+    hasher: Hasher,
+    msgpack: bool,
+    path: &'a Path,
+}
+
+impl<'a, R: BufRead> Read for RecordReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<'a, R: BufRead> RecordReader<'a, R> {
+    fn bad_file(&self, msg: impl ToString) -> BadRelationFile {
+        BadRelationFile(self.path.display().to_string(), msg.to_string())
+    }
+    fn read<T: DeserializeOwned>(&mut self) -> Result<T> {
+        if self.msgpack {
+            return rmp_serde::decode::from_read(&mut *self)
+                .map_err(|err| self.bad_file(err).into());
+        }
+        let mut line = String::new();
+        if self.inner.read_line(&mut line).into_diagnostic()? == 0 {
+            bail!(self.bad_file("the file ends prematurely"));
+        }
+        self.hasher.update(line.as_bytes());
+        serde_json::from_str(&line).map_err(|err| self.bad_file(err).into())
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Fail unless scripts are allowed to access files, see [Db::set_script_file_access]
+    pub(crate) fn check_script_file_access(&self) -> Result<()> {
+        if !self.script_file_access.load(Ordering::Acquire) {
+            bail!(FileAccessDenied)
+        }
+        Ok(())
+    }
+
+    /// Write the rows of the stored relation `name` into the file at `path`: a header
+    /// with the format version and the column names, one row after another, and finally
+    /// a checksum of everything before it. The file must not exist yet.
+    ///
+    /// In JSON lines, values that JSON cannot represent are converted as in query results,
+    /// and are turned back when imported into columns of the corresponding types.
+    /// Msgpack preserves all values exactly.
+    pub(crate) fn export_relation_to_file(&'s self, name: &str, path: &Path) -> Result<NamedRows> {
+        let data = self
+            .export_relations(iter::once(name))?
+            .remove(name)
+            .unwrap();
+        let mut writer = RecordWriter {
+            inner: BufWriter::new(
+                OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("when creating {}", path.display()))?,
+            ),
+            hasher: Hasher::new(),
+            msgpack: is_msgpack(path),
+        };
+        writer.write(&FileHeader {
+            format: RELATION_FILE_FORMAT.to_string(),
+            version: RELATION_FILE_VERSION,
+            headers: data.headers,
+            rows: data.rows.len(),
+        })?;
+        for row in data.rows {
+            if writer.msgpack {
+                writer.write(&row)?;
+            } else {
+                writer.write(&row.into_iter().map(JsonValue::from).collect::<Vec<_>>())?;
+            }
+        }
+        writer.finish()?;
+        Ok(NamedRows::new(
+            vec![STATUS_STR.to_string()],
+            vec![vec![DataValue::from(OK_STR)]],
+        ))
+    }
+
+    /// Import the rows in the file at `path`, as written by
+    /// [`export_relation_to_file`](Self::export_relation_to_file), into the existing
+    /// stored relation `name`, like [`import_relations`](Self::import_relations) does.
+    /// Nothing is imported unless the checksum of the file matches.
+    pub(crate) fn import_relation_from_file(
+        &'s self,
+        name: &str,
+        path: &Path,
+    ) -> Result<NamedRows> {
+        let mut reader = RecordReader {
+            inner: BufReader::new(
+                File::open(path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("when opening {}", path.display()))?,
+            ),
+            hasher: Hasher::new(),
+            msgpack: is_msgpack(path),
+            path,
+        };
+        let header: FileHeader = reader.read()?;
+        if header.format != RELATION_FILE_FORMAT {
+            bail!(reader.bad_file(format!("unknown format {:?}", header.format)));
+        }
+        if header.version > RELATION_FILE_VERSION {
+            bail!(UnsupportedFileVersion(
+                path.display().to_string(),
+                header.version
+            ));
+        }
+        let mut rows = vec![];
+        for _ in 0..header.rows {
+            if reader.msgpack {
+                rows.push(reader.read()?);
+            } else {
+                let row: Vec<JsonValue> = reader.read()?;
+                rows.push(row.into_iter().map(DataValue::from).collect());
+            }
+        }
+        let computed = reader.hasher.clone().finalize();
+        let trailer: FileTrailer = reader.read()?;
+        if trailer.checksum != computed {
+            bail!(ChecksumMismatch(
+                path.display().to_string(),
+                trailer.checksum,
+                computed
+            ));
+        }
+        if !reader.inner.fill_buf().into_diagnostic()?.is_empty() {
+            bail!(reader.bad_file("unexpected data after the checksum"));
+        }
+        self.import_relations(BTreeMap::from([(
+            name.to_string(),
+            NamedRows::new(header.headers, rows),
+        )]))?;
+        Ok(NamedRows::new(
+            vec![STATUS_STR.to_string()],
+            vec![vec![DataValue::from(OK_STR)]],
+        ))
+    }
+}
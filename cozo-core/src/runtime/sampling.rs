@@ -0,0 +1,185 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use rand::prelude::*;
+use thiserror::Error;
+
+use crate::data::functions::current_validity;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage, StoreTx};
+
+/// How many probes are made at most for each entity to sample
+const PROBES_PER_SAMPLE: usize = 16;
+/// How many rows following a probe are examined for one matching the filters
+const ROWS_PER_PROBE: usize = 64;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation {0} has no column {1} to filter samples by")]
+#[diagnostic(code(eval::sample_column_not_found))]
+struct SampleColumnNotFound(String, String, #[label] SourceSpan);
+
+/// Positions in the key range of a relation, or of the part of it sharing a prefix:
+/// the 16 bytes following the encoded prefix, read as a big-endian integer.
+/// Sampling positions between those of the first and the last row spreads
+/// the probes over the keys actually present.
+struct KeySpace<'a, 'b> {
+    tx: &'a SessionTx<'b>,
+    id: RelationId,
+    n_keys: usize,
+    is_temp: bool,
+    lower: Vec<u8>,
+    upper: Vec<u8>,
+}
+
+impl<'a, 'b> KeySpace<'a, 'b> {
+    /// The rows at or after `pos`, in key order. Position 0 is the start of the key space,
+    /// which also holds the keys shorter than a position.
+    fn rows_from(&self, pos: u128) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        let mut from = self.lower.clone();
+        if pos != 0 {
+            from.extend_from_slice(&pos.to_be_bytes());
+        }
+        if self.is_temp {
+            self.tx.temp_store_tx.range_scan_tuple(&from, &self.upper)
+        } else {
+            self.tx.store_tx.range_scan_tuple(&from, &self.upper)
+        }
+    }
+    /// A position from which the first row is found, or `None` if the key space is empty.
+    /// This is one less than the bytes of its key, as keys shorter than a position
+    /// sort before their zero-padded positions.
+    fn first_position(&self) -> Result<Option<u128>> {
+        let first = match self.rows_from(0).next().transpose()? {
+            None => return Ok(None),
+            Some(tuple) => (&tuple[..self.n_keys]).encode_as_key(self.id),
+        };
+        let mut pos = [0u8; 16];
+        for (p, b) in pos.iter_mut().zip(&first[self.lower.len()..]) {
+            *p = *b;
+        }
+        Ok(Some(u128::from_be_bytes(pos).saturating_sub(1)))
+    }
+    /// The largest position at or before which some row lies, found by bisection
+    /// starting from the position of the first row
+    fn last_position(&self, first: u128) -> Result<u128> {
+        let (mut lo, mut hi) = (first, u128::MAX);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2 + 1;
+            if self.rows_from(mid).next().transpose()?.is_some() {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        Ok(lo)
+    }
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Sample up to `n` distinct keys of the rows of a stored relation whose columns equal
+    /// the given values, reproducibly for the same `seed` and data.
+    ///
+    /// Instead of scanning the whole relation, random positions in its key range are probed,
+    /// each yielding the first matching row at or after it not already sampled. Filters on the
+    /// leading key columns confine the probes to the rows having those keys. Rows following
+    /// large gaps in the encoded keys are more likely to be chosen, so the sample is closest
+    /// to uniform for randomly generated keys such as UUIDs. Fewer than `n` keys are returned
+    /// if matching rows are too sparse to be found by the probes.
+    pub(crate) fn sample_entities(
+        &'s self,
+        name: &str,
+        filters: Vec<(Symbol, DataValue)>,
+        n: usize,
+        seed: u64,
+    ) -> Result<NamedRows> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(name, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "data scan".to_string(),
+                handle.access_level
+            ));
+        }
+        let cur_vld = current_validity();
+        let mut conds = BTreeMap::new();
+        for (col, val) in filters {
+            let found = handle
+                .metadata
+                .keys
+                .iter()
+                .chain(handle.metadata.non_keys.iter())
+                .find_position(|def| def.name == col.name);
+            match found {
+                Some((idx, def)) => {
+                    conds.insert(idx, def.typing.coerce(val, cur_vld)?);
+                }
+                None => bail!(SampleColumnNotFound(
+                    handle.name.to_string(),
+                    col.name.to_string(),
+                    col.span
+                )),
+            }
+        }
+        let n_keys = handle.metadata.keys.len();
+        let prefix = (0..n_keys)
+            .map_while(|i| conds.get(&i).cloned())
+            .collect_vec();
+        let mut upper = prefix.clone();
+        upper.push(DataValue::Bot);
+        let space = KeySpace {
+            tx: &tx,
+            id: handle.id,
+            n_keys,
+            is_temp: handle.is_temp,
+            lower: prefix.encode_as_key(handle.id),
+            upper: upper.encode_as_key(handle.id),
+        };
+
+        let headers = handle
+            .metadata
+            .keys
+            .iter()
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let mut rows = vec![];
+        let first = match space.first_position()? {
+            None => return Ok(NamedRows::new(headers, rows)),
+            Some(first) => first,
+        };
+        let last = space.last_position(first)?;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut seen = BTreeSet::new();
+        for _ in 0..n.saturating_mul(PROBES_PER_SAMPLE) {
+            if rows.len() >= n {
+                break;
+            }
+            let pos = rng.gen_range(first..=last);
+            for tuple in space.rows_from(pos).take(ROWS_PER_PROBE) {
+                let mut tuple = tuple?;
+                if conds.iter().all(|(i, v)| tuple[*i] == *v) {
+                    tuple.truncate(n_keys);
+                    if seen.insert(tuple.encode_as_key(space.id)) {
+                        rows.push(tuple);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(NamedRows::new(headers, rows))
+    }
+}
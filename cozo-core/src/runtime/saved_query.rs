@@ -0,0 +1,225 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result, WrapErr};
+use thiserror::Error;
+
+use crate::data::program::InputProgram;
+use crate::data::symb::{Symbol, PROG_ENTRY};
+use crate::data::tuple::{decode_tuple_from_key, TupleT};
+use crate::data::value::{DataValue, ValidityTs};
+use crate::parse::{parse_script, CozoScript, SourceSpan};
+use crate::runtime::db::{CancelToken, OK_STR, STATUS_STR};
+use crate::runtime::relation::RelationId;
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// A script saved in the database under a name, to be run with `::call`.
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct SavedQuery {
+    pub(crate) script: String,
+    pub(crate) description: String,
+    /// Names of the parameters the script takes, all of which must be given when calling it
+    pub(crate) params: Vec<String>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Saved query {0} not found")]
+#[diagnostic(code(eval::saved_query_not_found))]
+struct SavedQueryNotFound(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Wrong parameters for saved query {0}")]
+#[diagnostic(code(eval::saved_query_bad_params))]
+#[diagnostic(help("The query takes the parameters: {1:?}"))]
+struct SavedQueryBadParams(String, Vec<String>, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot import saved query {0}")]
+#[diagnostic(code(eval::bad_import))]
+struct BadImport(String, #[help] String, #[label] SourceSpan);
+
+fn get_saved_query(tx: &SessionTx<'_>, name: &str, span: SourceSpan) -> Result<SavedQuery> {
+    match tx.store_tx.get(&saved_query_key(name), false)? {
+        None => bail!(SavedQueryNotFound(name.to_string(), span)),
+        Some(v) => rmp_serde::from_slice(&v).into_diagnostic(),
+    }
+}
+
+fn saved_query_key(name: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from("SAVED_QUERY"),
+        DataValue::from(name),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    pub(crate) fn save_query(&'s self, name: &str, query: &SavedQuery) -> Result<NamedRows> {
+        let mut tx = self.transact_write()?;
+        tx.store_tx.put(
+            &saved_query_key(name),
+            &rmp_serde::to_vec(query).into_diagnostic()?,
+        )?;
+        tx.commit_tx()?;
+        Ok(NamedRows::new(
+            vec![STATUS_STR.to_string()],
+            vec![vec![DataValue::from(OK_STR)]],
+        ))
+    }
+
+    pub(crate) fn remove_saved_query(&'s self, name: &str, span: SourceSpan) -> Result<NamedRows> {
+        let key = saved_query_key(name);
+        let mut tx = self.transact_write()?;
+        if !tx.store_tx.exists(&key, true)? {
+            bail!(SavedQueryNotFound(name.to_string(), span));
+        }
+        tx.store_tx.del(&key)?;
+        tx.commit_tx()?;
+        Ok(NamedRows::new(
+            vec![STATUS_STR.to_string()],
+            vec![vec![DataValue::from(OK_STR)]],
+        ))
+    }
+
+    pub(crate) fn list_saved_queries(&'s self) -> Result<NamedRows> {
+        let lower = vec![
+            DataValue::Null,
+            DataValue::from("SAVED_QUERY"),
+            DataValue::Null,
+        ]
+        .encode_as_key(RelationId::SYSTEM);
+        let upper = vec![
+            DataValue::Null,
+            DataValue::from("SAVED_QUERY"),
+            DataValue::Bot,
+        ]
+        .encode_as_key(RelationId::SYSTEM);
+        let tx = self.transact()?;
+        let mut rows = vec![];
+        for kv_res in tx.store_tx.range_scan(&lower, &upper) {
+            let (k, v) = kv_res?;
+            let name = decode_tuple_from_key(&k).pop().unwrap();
+            let query: SavedQuery = rmp_serde::from_slice(&v).into_diagnostic()?;
+            rows.push(vec![
+                name,
+                DataValue::List(
+                    query
+                        .params
+                        .iter()
+                        .map(|p| DataValue::from(p.as_str()))
+                        .collect_vec(),
+                ),
+                DataValue::from(query.description),
+                DataValue::from(query.script),
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec![
+                "name".to_string(),
+                "params".to_string(),
+                "description".to_string(),
+                "script".to_string(),
+            ],
+            rows,
+        ))
+    }
+
+    /// Run the saved query `name` with `args`, which must name exactly its declared parameters.
+    pub(crate) fn call_saved_query(
+        &'s self,
+        name: &str,
+        span: SourceSpan,
+        args: BTreeMap<String, DataValue>,
+        cur_vld: ValidityTs,
+        default_vld: Option<ValidityTs>,
+        max_scan_rows: Option<u64>,
+        cancel_token: Option<&CancelToken>,
+    ) -> Result<NamedRows> {
+        let query = get_saved_query(&self.transact()?, name, span)?;
+        if args.len() != query.params.len() || !query.params.iter().all(|p| args.contains_key(p)) {
+            bail!(SavedQueryBadParams(name.to_string(), query.params, span));
+        }
+        let script = parse_script(
+            &query.script,
+            &args,
+            &self.fixed_rules.read().unwrap(),
+            cur_vld,
+        )
+        .map_err(|err| err.with_source_code(query.script.clone()))
+        .wrap_err_with(|| format!("when parsing saved query {name}"))?;
+        if let CozoScript::Sys(_) = script {
+            bail!("Saved query {} cannot run system ops", name);
+        }
+        self.execute_script(script, cur_vld, default_vld, max_scan_rows, cancel_token)
+            .map_err(|err| err.with_source_code(query.script))
+    }
+
+    /// Add the rules of the saved queries named by `:import` to `prog`, following the
+    /// imports of imported queries in turn. The entry rules and options of imported
+    /// queries are ignored, and an imported rule may not share its name with another rule.
+    pub(crate) fn resolve_imports(
+        &self,
+        tx: &SessionTx<'_>,
+        prog: &mut InputProgram,
+        cur_vld: ValidityTs,
+    ) -> Result<()> {
+        let entry = Symbol::new(PROG_ENTRY, SourceSpan(0, 0));
+        let mut pending = mem::take(&mut prog.out_opts.imports);
+        let mut seen = BTreeSet::new();
+        while let Some(import) = pending.pop() {
+            if !seen.insert(import.name.clone()) {
+                continue;
+            }
+            let query = get_saved_query(tx, &import.name, import.span)?;
+            if !query.params.is_empty() {
+                bail!(BadImport(
+                    import.to_string(),
+                    "Only saved queries without parameters can be imported".to_string(),
+                    import.span
+                ));
+            }
+            let imported = match parse_script(
+                &query.script,
+                &Default::default(),
+                &self.fixed_rules.read().unwrap(),
+                cur_vld,
+            )
+            .map_err(|err| err.with_source_code(query.script.clone()))
+            .wrap_err_with(|| format!("when parsing saved query {}", import.name))?
+            {
+                CozoScript::Single(p) => *p,
+                _ => bail!(BadImport(
+                    import.to_string(),
+                    "Only saved queries consisting of a single query can be imported".to_string(),
+                    import.span
+                )),
+            };
+            pending.extend(imported.out_opts.imports);
+            for (name, rules) in imported.prog {
+                if name == entry {
+                    continue;
+                }
+                if prog.prog.contains_key(&name) {
+                    bail!(BadImport(
+                        import.to_string(),
+                        format!("It defines the rule {name}, which is already defined"),
+                        import.span
+                    ));
+                }
+                prog.prog.insert(name, rules);
+            }
+        }
+        Ok(())
+    }
+}
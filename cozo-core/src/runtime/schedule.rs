@@ -0,0 +1,127 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use itertools::Itertools;
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::value::DataValue;
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::{Db, NamedRows, Storage};
+
+pub(crate) type ScheduleRegistry = BTreeMap<SmartString<LazyCompact>, ScheduledScript>;
+
+/// A script registered to run periodically, together with the outcome of its last run.
+pub(crate) struct ScheduledScript {
+    pub(crate) script: String,
+    pub(crate) interval: Duration,
+    pub(crate) runs: u64,
+    pub(crate) last_run: Option<f64>,
+    /// `None` if the last run succeeded
+    pub(crate) last_error: Option<String>,
+    pub(crate) cancelled: Arc<AtomicBool>,
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Record a new scheduled script, returning the flag that stops it when set.
+    pub(crate) fn register_schedule(
+        &self,
+        name: &str,
+        script: &str,
+        interval: Duration,
+    ) -> Result<Arc<AtomicBool>> {
+        if interval.is_zero() {
+            bail!("The interval of scheduled script {} must be positive", name);
+        }
+        let mut schedules = self.schedules.lock().unwrap();
+        if schedules.contains_key(name) {
+            bail!("A script named {} is already scheduled", name);
+        }
+        let cancelled: Arc<AtomicBool> = Default::default();
+        schedules.insert(
+            SmartString::from(name),
+            ScheduledScript {
+                script: script.to_string(),
+                interval,
+                runs: 0,
+                last_run: None,
+                last_error: None,
+                cancelled: cancelled.clone(),
+            },
+        );
+        Ok(cancelled)
+    }
+
+    pub(crate) fn record_schedule_run(&self, name: &str, error: Option<String>) {
+        if let Some(entry) = self.schedules.lock().unwrap().get_mut(name) {
+            entry.runs += 1;
+            entry.last_run = seconds_since_the_epoch().ok();
+            entry.last_error = error;
+        }
+    }
+
+    /// Stop running the scheduled script `name`. A run already in progress is completed.
+    /// Returns `false` if no such script is scheduled.
+    pub fn unschedule_script(&self, name: &str) -> bool {
+        match self.schedules.lock().unwrap().remove(name) {
+            None => false,
+            Some(entry) => {
+                entry.cancelled.store(true, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+
+    pub(crate) fn list_schedules(&self) -> Result<NamedRows> {
+        let rows = self
+            .schedules
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, entry)| {
+                vec![
+                    DataValue::from(name.as_str()),
+                    DataValue::from(entry.script.as_str()),
+                    DataValue::from(entry.interval.as_secs_f64()),
+                    DataValue::from(entry.runs as i64),
+                    entry
+                        .last_run
+                        .map(DataValue::from)
+                        .unwrap_or(DataValue::Null),
+                    DataValue::from(match entry.last_run {
+                        None => "PENDING",
+                        Some(_) if entry.last_error.is_none() => "OK",
+                        Some(_) => "ERROR",
+                    }),
+                    entry
+                        .last_error
+                        .as_deref()
+                        .map(DataValue::from)
+                        .unwrap_or(DataValue::Null),
+                ]
+            })
+            .collect_vec();
+        Ok(NamedRows::new(
+            vec![
+                "name".to_string(),
+                "script".to_string(),
+                "interval".to_string(),
+                "runs".to_string(),
+                "last_run".to_string(),
+                "last_status".to_string(),
+                "last_error".to_string(),
+            ],
+            rows,
+        ))
+    }
+}
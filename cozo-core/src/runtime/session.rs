@@ -0,0 +1,111 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use miette::Result;
+
+use crate::data::functions::current_validity;
+use crate::parse::{parse_script, CozoScript};
+use crate::runtime::metrics::OpenGuard;
+use crate::{DataValue, Db, NamedRows, Storage, ValidityTs};
+
+/// A session on a database, obtained by [Db::new_session].
+///
+/// Settings made on the session apply to all scripts run through it.
+/// A session also holds variables, which are passed to every script run in it as parameters.
+pub struct Session<'s, S> {
+    db: &'s Db<S>,
+    _open: OpenGuard<'s>,
+    default_validity: Option<ValidityTs>,
+    max_scan_rows: Option<u64>,
+    variables: Mutex<BTreeMap<String, DataValue>>,
+}
+
+impl<'s, S: Storage<'s>> Session<'s, S> {
+    pub(crate) fn new(db: &'s Db<S>) -> Self {
+        Self {
+            db,
+            _open: OpenGuard::new(&db.open_sessions),
+            default_validity: None,
+            max_scan_rows: None,
+            variables: Default::default(),
+        }
+    }
+    /// Make stored relations with validity that are not given an explicit `@` specification
+    /// be queried at the given validity, instead of returning all historical rows.
+    pub fn with_default_validity(mut self, vld: ValidityTs) -> Self {
+        self.default_validity = Some(vld);
+        self
+    }
+    /// The default validity of this session, if set.
+    pub fn default_validity(&self) -> Option<ValidityTs> {
+        self.default_validity
+    }
+    /// Run scripts in safe mode: queries are rejected if they scan in full a stored relation
+    /// that has more than `max_scan_rows` rows, or that has not been analyzed, or if they join
+    /// rules without shared variables, unless the rules doing so are annotated
+    /// with `@allow_full_scan`.
+    pub fn with_safe_mode(mut self, max_scan_rows: u64) -> Self {
+        self.max_scan_rows = Some(max_scan_rows);
+        self
+    }
+    /// Set a session variable, available as the parameter `$name` in subsequent scripts.
+    pub fn set_var(&self, name: &str, value: DataValue) {
+        self.variables
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), value);
+    }
+    /// Get the value of a session variable.
+    pub fn get_var(&self, name: &str) -> Option<DataValue> {
+        self.variables.lock().unwrap().get(name).cloned()
+    }
+    /// Remove a session variable, returning its value if it was set.
+    pub fn remove_var(&self, name: &str) -> Option<DataValue> {
+        self.variables.lock().unwrap().remove(name)
+    }
+    /// Run the CozoScript passed in within this session. See [Db::run_script].
+    ///
+    /// Session variables are available as parameters, but are shadowed by
+    /// parameters of the same name in `params`. A query with the option `:set_var <name>`
+    /// stores its resulting rows, as a list of lists, into the session variable `name`.
+    /// The option cannot be used in imperative scripts.
+    pub fn run_script(
+        &self,
+        payload: &str,
+        params: BTreeMap<String, DataValue>,
+    ) -> Result<NamedRows> {
+        let cur_vld = current_validity();
+        let mut param_pool = self.variables.lock().unwrap().clone();
+        param_pool.extend(params);
+        let script = parse_script(
+            payload,
+            &param_pool,
+            &self.db.fixed_rules.read().unwrap(),
+            cur_vld,
+        )?;
+        let set_var = match &script {
+            CozoScript::Single(p) => p.out_opts.set_var.clone(),
+            _ => None,
+        };
+        let res = self.db.execute_script(
+            script,
+            cur_vld,
+            self.default_validity,
+            self.max_scan_rows,
+            None,
+        )?;
+        if let Some(name) = set_var {
+            let rows = res.rows.iter().cloned().map(DataValue::List).collect();
+            self.set_var(&name, DataValue::List(rows));
+        }
+        Ok(res)
+    }
+}
@@ -0,0 +1,149 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::BTreeMap;
+
+use miette::{bail, miette, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
+
+use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::relation::{RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Query {0} given `:since_last` runs in a read-only transaction")]
+#[diagnostic(code(eval::since_last_read_only))]
+#[diagnostic(help(
+    "The last rows seen are recorded by the query, which is possible when run on its own, \
+    or in an imperative script that also writes to stored relations"
+))]
+struct SinceLastReadOnly(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Relation {0} given to `:since_last` is not append-only")]
+#[diagnostic(code(eval::since_last_not_append_only))]
+#[diagnostic(help(
+    "Only append-only relations number their rows, make it one with `::relation_kind append_only`"
+))]
+struct SinceLastNotAppendOnly(String, #[label] SourceSpan);
+
+/// The state of a query given `:since_last <name> <relation>` while it is compiled:
+/// the append-only relation, with the last sequence number seen by the previous run
+pub(crate) struct SinceLast {
+    name: SmartString<LazyCompact>,
+    relation: SmartString<LazyCompact>,
+    /// keyed by relation id, so that the rows of a relation created again are all new
+    marks: BTreeMap<RelationId, (i64, Vec<u8>)>,
+}
+
+fn since_last_key(name: &str, rel_id: RelationId) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from("SINCE_LAST"),
+        DataValue::from(name),
+        DataValue::from(rel_id.0 as i64),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+fn decode_seq(v: &[u8]) -> Result<i64> {
+    Ok(i64::from_be_bytes(v.try_into().map_err(|_| {
+        miette!("corrupt sequence number {:x?}", v)
+    })?))
+}
+
+impl<'a> SessionTx<'a> {
+    /// Start compiling a query given `:since_last <name> <relation>`
+    pub(crate) fn begin_since_last(&mut self, name: &Symbol, relation: &Symbol) -> Result<()> {
+        if self.tx_id_counter.is_none() {
+            bail!(SinceLastReadOnly(name.name.to_string(), name.span));
+        }
+        if !self.get_relation(relation, false)?.append_only {
+            bail!(SinceLastNotAppendOnly(
+                relation.name.to_string(),
+                relation.span
+            ));
+        }
+        self.since_last = Some(SinceLast {
+            name: name.name.clone(),
+            relation: relation.name.clone(),
+            marks: Default::default(),
+        });
+        Ok(())
+    }
+    /// For queries given `:since_last`, make scans through `store` skip the rows seen
+    /// by the previous run of the query, if it is the relation given to `:since_last`
+    /// or an index of it read by name. Only positive atoms are filtered: negated atoms
+    /// and the other relations joined see all rows.
+    pub(crate) fn skip_seen_rows(&mut self, store: &mut RelationHandle) -> Result<()> {
+        let relation = match &self.since_last {
+            None => return Ok(()),
+            Some(since_last) => &since_last.relation,
+        };
+        let rel_name = match store.name.split_once(':') {
+            None => store.name.as_str(),
+            Some((rel_name, _)) => rel_name,
+        };
+        if rel_name != relation.as_str() {
+            return Ok(());
+        }
+        let (rel_id, seq_key, seq_col) = match store.name.split_once(':') {
+            None if store.append_only => (
+                store.id,
+                store.append_seq_key(),
+                store.metadata.keys.len() - 1,
+            ),
+            // indices hold the sequence column of the relation, and share its marks
+            Some((rel_name, idx_name)) => {
+                let rel = self.get_relation(rel_name, false)?;
+                let rel_seq_col = rel.metadata.keys.len() - 1;
+                let seq_col = rel
+                    .indices
+                    .get(idx_name)
+                    .and_then(|(_, mapper)| mapper.iter().position(|i| *i == rel_seq_col));
+                match seq_col {
+                    Some(seq_col) if rel.append_only => (rel.id, rel.append_seq_key(), seq_col),
+                    _ => return Ok(()),
+                }
+            }
+            None => return Ok(()),
+        };
+        let since_last = self.since_last.as_mut().unwrap();
+        let last = match since_last.marks.get(&rel_id) {
+            Some((last, _)) => *last,
+            None => {
+                let last = match self
+                    .store_tx
+                    .get(&since_last_key(&since_last.name, rel_id), false)?
+                {
+                    None => 0,
+                    Some(v) => decode_seq(&v)?,
+                };
+                since_last.marks.insert(rel_id, (last, seq_key));
+                last
+            }
+        };
+        store.new_after = Some((seq_col, last));
+        Ok(())
+    }
+    /// Record the last rows of the relations read by the query as seen, so that they are
+    /// skipped by the next run
+    pub(crate) fn commit_since_last(&mut self, since_last: SinceLast) -> Result<()> {
+        for (rel_id, (_, seq_key)) in since_last.marks {
+            if let Some(v) = self.store_tx.get(&seq_key, false)? {
+                self.store_tx
+                    .put(&since_last_key(&since_last.name, rel_id), &v)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, IntoDiagnostic, Result};
+use rand::prelude::*;
+use thiserror::Error;
+
+use crate::data::aggr::{AggrApproxCountDistinct, NormalAggrObj};
+use crate::data::program::MagicSymbol;
+use crate::data::symb::Symbol;
+use crate::data::tuple::{Tuple, TupleT};
+use crate::data::value::{DataValue, LARGEST_UTF_CHAR};
+use crate::parse::SourceSpan;
+use crate::query::compile::{CompiledProgram, CompiledRuleSet};
+use crate::query::ra::RelAlgebra;
+use crate::runtime::relation::{AccessLevel, InsufficientAccessLevel, RelationHandle, RelationId};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage, StoreTx};
+
+/// Number of buckets of the histogram collected for each column
+const HISTOGRAM_BUCKETS: usize = 10;
+/// Number of values of each column sampled to compute its histogram
+const RESERVOIR_SIZE: usize = 1024;
+/// Number of rows assumed by `::estimate` for stored relations that have not been analyzed,
+/// for fixed rules, and for rules whose sizes are not yet known where they are used
+const DEFAULT_ROWS: f64 = 1000.;
+/// Fraction of the rows assumed by `::estimate` to pass each filter
+const FILTER_SELECTIVITY: f64 = 1. / 3.;
+/// Number of rows assumed by `::estimate` to be produced by each multi-unification
+const MULTI_UNIFY_FANOUT: f64 = 10.;
+
+/// Statistics of the data in a stored relation, as collected by `::analyze`
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct RelationStats {
+    pub(crate) n_rows: u64,
+    /// For the key columns followed by the non-key columns
+    pub(crate) columns: Vec<ColumnStats>,
+}
+
+#[derive(Debug, serde_derive::Serialize, serde_derive::Deserialize)]
+pub(crate) struct ColumnStats {
+    pub(crate) name: String,
+    pub(crate) n_nulls: u64,
+    /// Estimated number of distinct non-null values
+    pub(crate) n_distinct: u64,
+    /// Bounds of buckets holding roughly equal numbers of non-null values,
+    /// starting with the smallest value and ending with the largest
+    pub(crate) histogram: Vec<DataValue>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Rule {0} scans stored relation {1} in full, which is not allowed in safe mode")]
+#[diagnostic(code(eval::unsafe_full_scan))]
+#[diagnostic(help(
+    "Bind the first key columns of the relation, analyze the relation if it is small, \
+    or annotate the rule with @allow_full_scan"
+))]
+struct UnsafeFullScan(String, String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Rule {0} joins atoms without shared variables, which is not allowed in safe mode")]
+#[diagnostic(code(eval::unsafe_cross_join))]
+#[diagnostic(help("Join the atoms on some variable, or annotate the rule with @allow_full_scan"))]
+struct UnsafeCrossJoin(String, #[label] SourceSpan);
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation {0} has not been analyzed")]
+#[diagnostic(code(eval::stats_not_found))]
+#[diagnostic(help("Statistics are collected by running `::analyze {0}`"))]
+struct StatsNotFound(String, #[label] SourceSpan);
+
+pub(crate) fn stats_key(name: &str) -> Vec<u8> {
+    vec![
+        DataValue::Null,
+        DataValue::from("STATS"),
+        DataValue::from(name),
+    ]
+    .encode_as_key(RelationId::SYSTEM)
+}
+
+/// Collects the statistics of one column from the values fed to it in turn
+struct ColumnCollector {
+    n_seen: u64,
+    n_nulls: u64,
+    distinct: AggrApproxCountDistinct,
+    reservoir: Vec<DataValue>,
+}
+
+impl ColumnCollector {
+    fn new() -> Self {
+        Self {
+            n_seen: 0,
+            n_nulls: 0,
+            distinct: AggrApproxCountDistinct::default(),
+            reservoir: vec![],
+        }
+    }
+    fn add(&mut self, val: &DataValue, rng: &mut StdRng) -> Result<()> {
+        if *val == DataValue::Null {
+            self.n_nulls += 1;
+            return Ok(());
+        }
+        self.distinct.set(val)?;
+        self.n_seen += 1;
+        if self.reservoir.len() < RESERVOIR_SIZE {
+            self.reservoir.push(val.clone());
+        } else {
+            let idx = rng.gen_range(0..self.n_seen) as usize;
+            if idx < RESERVOIR_SIZE {
+                self.reservoir[idx] = val.clone();
+            }
+        }
+        Ok(())
+    }
+    fn finish(mut self, name: String) -> Result<ColumnStats> {
+        let n_distinct = match self.distinct.get()? {
+            DataValue::Num(n) => (n.get_int().unwrap_or(0).max(0) as u64).min(self.n_seen),
+            _ => unreachable!(),
+        };
+        self.reservoir.sort();
+        let mut histogram = vec![];
+        if let Some(last) = self.reservoir.len().checked_sub(1) {
+            histogram = (0..=HISTOGRAM_BUCKETS)
+                .map(|i| self.reservoir[i * last / HISTOGRAM_BUCKETS].clone())
+                .dedup()
+                .collect();
+        }
+        Ok(ColumnStats {
+            name,
+            n_nulls: self.n_nulls,
+            n_distinct,
+            histogram,
+        })
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// The statistics last collected for the stored relation `name`, if it has been analyzed
+    pub(crate) fn relation_stats(&self, name: &str) -> Result<Option<RelationStats>> {
+        match self.store_tx.get(&stats_key(name), false)? {
+            None => Ok(None),
+            Some(v) => Ok(Some(rmp_serde::from_slice(&v).into_diagnostic()?)),
+        }
+    }
+}
+
+impl<'a> SessionTx<'a> {
+    /// The estimated numbers of rows produced by each rule of the compiled program `strata`,
+    /// followed by that of the result given the `limit`, for `::estimate`. Stored relations
+    /// are assumed to hold the numbers of rows and distinct values of their last statistics.
+    pub(crate) fn estimate_compiled(
+        &self,
+        strata: &[CompiledProgram],
+        limit: Option<usize>,
+    ) -> Result<NamedRows> {
+        let mut rule_sizes: BTreeMap<&MagicSymbol, f64> = BTreeMap::new();
+        let mut rows = vec![];
+        for (stratum, program) in strata.iter().enumerate() {
+            for (name, rule_set) in program {
+                let mut total = 0.;
+                match rule_set {
+                    CompiledRuleSet::Rules(rules) => {
+                        for (idx, rule) in rules.iter().enumerate() {
+                            let est = self.estimate_rows(&rule.relation, &rule_sizes)?;
+                            total += est;
+                            rows.push(vec![
+                                DataValue::from(stratum as i64),
+                                DataValue::from(idx as i64),
+                                DataValue::from(name.to_string()),
+                                DataValue::from("rule"),
+                                DataValue::from(est.round() as i64),
+                            ]);
+                        }
+                    }
+                    CompiledRuleSet::Fixed(_) => {
+                        total = DEFAULT_ROWS;
+                        rows.push(vec![
+                            DataValue::from(stratum as i64),
+                            DataValue::from(0),
+                            DataValue::from(name.to_string()),
+                            DataValue::from("fixed"),
+                            DataValue::from(total as i64),
+                        ]);
+                    }
+                }
+                rule_sizes.insert(name, total);
+            }
+        }
+        let mut result = rule_sizes
+            .iter()
+            .find(|(name, _)| name.is_prog_entry())
+            .map(|(_, est)| *est)
+            .unwrap_or(0.);
+        if let Some(limit) = limit {
+            result = result.min(limit as f64);
+        }
+        rows.push(vec![
+            DataValue::Null,
+            DataValue::Null,
+            DataValue::from("?"),
+            DataValue::from("result"),
+            DataValue::from(result.round() as i64),
+        ]);
+        Ok(NamedRows::new(
+            vec![
+                "stratum".to_string(),
+                "rule_idx".to_string(),
+                "rule".to_string(),
+                "op".to_string(),
+                "est_rows".to_string(),
+            ],
+            rows,
+        ))
+    }
+
+    fn estimate_rows(
+        &self,
+        ra: &RelAlgebra,
+        rule_sizes: &BTreeMap<&MagicSymbol, f64>,
+    ) -> Result<f64> {
+        let filtered =
+            |rows: f64, n_filters: usize| rows * FILTER_SELECTIVITY.powi(n_filters as i32);
+        Ok(match ra {
+            RelAlgebra::Fixed(r) => r.data.len() as f64,
+            RelAlgebra::TempStore(r) => filtered(
+                rule_sizes
+                    .get(&r.storage_key)
+                    .copied()
+                    .unwrap_or(DEFAULT_ROWS),
+                r.filters.len(),
+            ),
+            RelAlgebra::Stored(r) => filtered(self.stored_rows(&r.storage)?.0, r.filters.len()),
+            RelAlgebra::StoredWithValidity(r) => {
+                filtered(self.stored_rows(&r.storage)?.0, r.filters.len())
+            }
+            RelAlgebra::Join(j) => {
+                let left = self.estimate_rows(&j.left, rule_sizes)?;
+                let right = self.estimate_rows(&j.right, rule_sizes)?;
+                if j.joiner.right_keys.is_empty() {
+                    left * right
+                } else {
+                    // each row on the left is assumed to match the rows on the right
+                    // sharing one of the distinct values of the join keys
+                    let n_distinct = self.distinct_keys(&j.right, &j.joiner.right_keys)?;
+                    left * right / n_distinct.unwrap_or(right).clamp(1., right.max(1.))
+                }
+            }
+            RelAlgebra::NegJoin(j) => self.estimate_rows(&j.left, rule_sizes)?,
+            RelAlgebra::Reorder(r) => self.estimate_rows(&r.relation, rule_sizes)?,
+            RelAlgebra::Filter(r) => {
+                filtered(self.estimate_rows(&r.parent, rule_sizes)?, r.filters.len())
+            }
+            RelAlgebra::Unification(r) => {
+                let parent = self.estimate_rows(&r.parent, rule_sizes)?;
+                if r.is_multi {
+                    parent * MULTI_UNIFY_FANOUT
+                } else {
+                    parent
+                }
+            }
+        })
+    }
+
+    /// The number of rows of a stored relation or index as of its last statistics,
+    /// and the numbers of distinct values of its columns, if known
+    fn stored_rows(&self, handle: &RelationHandle) -> Result<(f64, Vec<Option<f64>>)> {
+        // indices are not analyzed, but hold the columns of their relations
+        let rel_name = handle.name.split(':').next().unwrap_or_default();
+        let stats = match self.relation_stats(rel_name)? {
+            None => return Ok((DEFAULT_ROWS, vec![])),
+            Some(stats) => stats,
+        };
+        let n_distinct = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .map(|col| {
+                stats
+                    .columns
+                    .iter()
+                    .find(|c| c.name == col.name)
+                    .map(|c| c.n_distinct as f64)
+            })
+            .collect();
+        Ok((stats.n_rows as f64, n_distinct))
+    }
+
+    /// In safe mode, reject the compiled program `strata` if one of its rules, unless it is
+    /// in `allowed`, scans in full a stored relation that has more than `max_rows` rows
+    /// or that has not been analyzed, or joins atoms without shared variables.
+    pub(crate) fn check_full_scans(
+        &self,
+        strata: &[CompiledProgram],
+        max_rows: u64,
+        allowed: &BTreeSet<Symbol>,
+    ) -> Result<()> {
+        for program in strata {
+            for (name, rule_set) in program {
+                if allowed.contains(name.as_plain_symbol()) {
+                    continue;
+                }
+                if let CompiledRuleSet::Rules(rules) = rule_set {
+                    for rule in rules {
+                        self.check_rule_scans(name.as_plain_symbol(), &rule.relation, max_rows)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_rule_scans(&self, rule: &Symbol, ra: &RelAlgebra, max_rows: u64) -> Result<()> {
+        match ra {
+            RelAlgebra::Join(j) => {
+                let scanned = match &j.right {
+                    RelAlgebra::Stored(r) => Some(&r.storage),
+                    RelAlgebra::StoredWithValidity(r) => Some(&r.storage),
+                    _ => None,
+                };
+                if let Some(handle) = scanned {
+                    if j.joiner.right_keys.is_empty() || j.join_type() == "stored_mat_join" {
+                        let rel_name = handle.name.split(':').next().unwrap_or_default();
+                        let n_rows = self.relation_stats(rel_name)?.map(|stats| stats.n_rows);
+                        if !matches!(n_rows, Some(n) if n <= max_rows) {
+                            bail!(UnsafeFullScan(
+                                rule.to_string(),
+                                handle.name.to_string(),
+                                j.span
+                            ))
+                        }
+                    }
+                }
+                if j.joiner.right_keys.is_empty()
+                    && !j.left.is_unit()
+                    && !matches!(j.right, RelAlgebra::Fixed(_))
+                {
+                    bail!(UnsafeCrossJoin(rule.to_string(), j.span))
+                }
+                self.check_rule_scans(rule, &j.left, max_rows)?;
+                self.check_rule_scans(rule, &j.right, max_rows)
+            }
+            RelAlgebra::NegJoin(j) => {
+                self.check_rule_scans(rule, &j.left, max_rows)?;
+                self.check_rule_scans(rule, &j.right, max_rows)
+            }
+            RelAlgebra::Reorder(r) => self.check_rule_scans(rule, &r.relation, max_rows),
+            RelAlgebra::Filter(r) => self.check_rule_scans(rule, &r.parent, max_rows),
+            RelAlgebra::Unification(r) => self.check_rule_scans(rule, &r.parent, max_rows),
+            RelAlgebra::Fixed(_)
+            | RelAlgebra::TempStore(_)
+            | RelAlgebra::Stored(_)
+            | RelAlgebra::StoredWithValidity(_) => Ok(()),
+        }
+    }
+
+    /// For stored relations, the estimated number of distinct combinations
+    /// of the values bound to `keys`
+    fn distinct_keys(&self, ra: &RelAlgebra, keys: &[Symbol]) -> Result<Option<f64>> {
+        let (bindings, handle) = match ra {
+            RelAlgebra::Stored(r) => (&r.bindings, &r.storage),
+            RelAlgebra::StoredWithValidity(r) => (&r.bindings, &r.storage),
+            _ => return Ok(None),
+        };
+        let (_, n_distinct) = self.stored_rows(handle)?;
+        let mut ret = 1.;
+        for key in keys {
+            let pos = bindings.iter().position(|b| b == key);
+            match pos.and_then(|i| n_distinct.get(i).copied().flatten()) {
+                None => return Ok(None),
+                Some(n) => ret *= n,
+            }
+        }
+        Ok(Some(ret))
+    }
+}
+
+fn stats_to_rows(stats: RelationStats) -> NamedRows {
+    let rows = stats
+        .columns
+        .into_iter()
+        .map(|col| {
+            vec![
+                DataValue::from(col.name),
+                DataValue::from(stats.n_rows as i64),
+                DataValue::from(col.n_distinct as i64),
+                DataValue::from(col.n_nulls as i64),
+                col.histogram.first().cloned().unwrap_or(DataValue::Null),
+                col.histogram.last().cloned().unwrap_or(DataValue::Null),
+                DataValue::List(col.histogram),
+            ]
+        })
+        .collect_vec();
+    NamedRows::new(
+        vec![
+            "column".to_string(),
+            "rows".to_string(),
+            "distinct".to_string(),
+            "nulls".to_string(),
+            "min".to_string(),
+            "max".to_string(),
+            "histogram".to_string(),
+        ],
+        rows,
+    )
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Scan the stored relation `name` to collect the number of rows, and for each column
+    /// the number of nulls, an estimate of the number of distinct values by HyperLogLog, and
+    /// an equi-depth histogram of the values. The statistics replace those stored before
+    /// for the relation, and are returned with a row for each column.
+    pub(crate) fn analyze_relation(&'s self, name: &str) -> Result<NamedRows> {
+        let mut tx = self.transact_write()?;
+        let handle = tx.get_relation(name, false)?;
+        if handle.access_level < AccessLevel::ReadOnly {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "data scan".to_string(),
+                handle.access_level
+            ));
+        }
+        let names = handle
+            .metadata
+            .keys
+            .iter()
+            .chain(handle.metadata.non_keys.iter())
+            .map(|col| col.name.to_string())
+            .collect_vec();
+        let mut collectors = names.iter().map(|_| ColumnCollector::new()).collect_vec();
+        // a fixed seed makes the histograms the same for the same data
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut n_rows = 0;
+        for tuple in handle.scan_all(&tx) {
+            let tuple = tuple?;
+            for (collector, val) in collectors.iter_mut().zip(tuple.iter()) {
+                collector.add(val, &mut rng)?;
+            }
+            n_rows += 1;
+        }
+        let stats = RelationStats {
+            n_rows,
+            columns: collectors
+                .into_iter()
+                .zip(names)
+                .map(|(collector, name)| collector.finish(name))
+                .try_collect()?,
+        };
+        tx.store_tx.put(
+            &stats_key(&handle.name),
+            &rmp_serde::to_vec(&stats).into_diagnostic()?,
+        )?;
+        tx.commit_tx()?;
+        Ok(stats_to_rows(stats))
+    }
+
+    /// For each stored relation and index, the number of its keys and the bytes of its keys and
+    /// values, counted by a scan, together with the bytes it takes and the share of deletion
+    /// markers among the entries of the storage units holding it, where the storage engine
+    /// is able to estimate them, as RocksDB is.
+    pub(crate) fn storage_stats(&'s self) -> Result<NamedRows> {
+        let lower = vec![DataValue::from("")].encode_as_key(RelationId::SYSTEM);
+        let upper =
+            vec![DataValue::from(String::from(LARGEST_UTF_CHAR))].encode_as_key(RelationId::SYSTEM);
+        let tx = self.db.transact(false)?;
+        let mut handles = vec![];
+        for kv_res in tx.range_scan(&lower, &upper) {
+            let (k_slice, v_slice) = kv_res?;
+            if upper <= k_slice {
+                break;
+            }
+            handles.push(RelationHandle::decode(&v_slice)?);
+        }
+        let mut rows = vec![];
+        for handle in handles {
+            let lower = Tuple::default().encode_as_key(handle.id);
+            let upper = Tuple::default().encode_as_key(handle.id.next());
+            let (mut n_keys, mut n_bytes) = (0i64, 0i64);
+            for kv_res in tx.range_scan(&lower, &upper) {
+                let (k_slice, v_slice) = kv_res?;
+                n_keys += 1;
+                n_bytes += (k_slice.len() + v_slice.len()) as i64;
+            }
+            let (disk_bytes, tombstone_ratio) = match self.db.estimate_range(&lower, &upper) {
+                None => (DataValue::Null, DataValue::Null),
+                Some(est) => (
+                    DataValue::from(est.size as i64),
+                    DataValue::from(if est.entries == 0 {
+                        0.
+                    } else {
+                        est.deletions as f64 / est.entries as f64
+                    }),
+                ),
+            };
+            rows.push(vec![
+                DataValue::from(handle.name.as_str()),
+                DataValue::from(n_keys),
+                DataValue::from(n_bytes),
+                disk_bytes,
+                tombstone_ratio,
+            ]);
+        }
+        Ok(NamedRows::new(
+            vec![
+                "relation".to_string(),
+                "keys".to_string(),
+                "bytes".to_string(),
+                "disk_bytes".to_string(),
+                "tombstone_ratio".to_string(),
+            ],
+            rows,
+        ))
+    }
+
+    /// The statistics collected by the last `::analyze` of the stored relation `name`
+    pub(crate) fn show_relation_stats(&'s self, name: &str, span: SourceSpan) -> Result<NamedRows> {
+        let tx = self.transact()?;
+        let handle = tx.get_relation(name, false)?;
+        match tx.relation_stats(&handle.name)? {
+            None => bail!(StatsNotFound(handle.name.to_string(), span)),
+            Some(stats) => Ok(stats_to_rows(stats)),
+        }
+    }
+}
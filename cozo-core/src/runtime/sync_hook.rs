@@ -0,0 +1,260 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use crossbeam::channel::{bounded, Receiver, Sender};
+use itertools::Itertools;
+use miette::{bail, Result};
+use smartstring::{LazyCompact, SmartString};
+
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::callback::{CallbackCollector, CallbackOp};
+use crate::runtime::transact::SessionTx;
+use crate::{Db, NamedRows, Storage};
+
+/// A registered sync hook. Changes to its relations are journaled in the same transaction
+/// that makes them, and the journal is drained by the thread delivering them to the handler.
+pub(crate) struct SyncHook {
+    pub(crate) relations: BTreeSet<SmartString<LazyCompact>>,
+    /// Held from the assignment of sequence numbers until the transaction is committed,
+    /// so that they follow the commit order.
+    pub(crate) next_seq: Arc<Mutex<i64>>,
+    /// Wakes up the delivery thread. Dropping it stops the thread.
+    pub(crate) notify: Sender<()>,
+}
+
+pub(crate) type SyncHookRegistry = BTreeMap<SmartString<LazyCompact>, SyncHook>;
+
+/// A batch of changes waiting in the journal of a sync hook.
+pub(crate) struct SyncBatch {
+    pub(crate) seq: i64,
+    pub(crate) relation: String,
+    pub(crate) op: CallbackOp,
+    pub(crate) new: NamedRows,
+    pub(crate) old: NamedRows,
+}
+
+/// Name of the stored relation holding the undelivered changes of the sync hook `hook`
+pub(crate) fn journal_name(hook: &str) -> String {
+    format!("sync_journal.{hook}")
+}
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Register the sync hook `name` on `relations`, creating its journal if it does not exist.
+    /// Changes left in an existing journal are delivered first.
+    /// Returns the channel through which the delivery thread is woken up.
+    pub(crate) fn register_sync_hook_entry(
+        &'s self,
+        name: &str,
+        relations: &[&str],
+    ) -> Result<Receiver<()>> {
+        if self.sync_hooks.read().unwrap().contains_key(name) {
+            bail!("A sync hook named {} is already registered", name);
+        }
+        let journal = journal_name(name);
+        let exists = {
+            let tx = self.transact()?;
+            tx.relation_exists(&journal)?
+        };
+        if !exists {
+            self.run_script(
+                &format!(
+                    ":create {journal} {{seq: Int => relation: String, op: String, new: Any, old: Any}}"
+                ),
+                Default::default(),
+            )?;
+        }
+        let last = self.run_script(
+            &format!("?[seq] := *{journal}{{seq}} :order -seq :limit 1"),
+            Default::default(),
+        )?;
+        let next_seq = match last.rows.first() {
+            None => 0,
+            Some(row) => row[0].get_int().unwrap_or(0) + 1,
+        };
+        let (notify, receiver) = bounded(1);
+        let mut hooks = self.sync_hooks.write().unwrap();
+        if hooks.contains_key(name) {
+            bail!("A sync hook named {} is already registered", name);
+        }
+        hooks.insert(
+            SmartString::from(name),
+            SyncHook {
+                relations: relations.iter().map(|r| SmartString::from(*r)).collect(),
+                next_seq: Arc::new(Mutex::new(next_seq)),
+                notify,
+            },
+        );
+        Ok(receiver)
+    }
+
+    /// Unregister the sync hook `name`, stopping its delivery thread once the batch being
+    /// delivered, if any, is done. The journal is kept, so registering a hook with the same
+    /// name later resumes delivery where it stopped. Returns `false` if no such hook exists.
+    pub fn unregister_sync_hook(&self, name: &str) -> bool {
+        self.sync_hooks.write().unwrap().remove(name).is_some()
+    }
+
+    pub(crate) fn sync_hook_targets(&self) -> BTreeSet<SmartString<LazyCompact>> {
+        self.sync_hooks
+            .read()
+            .unwrap()
+            .values()
+            .flat_map(|hook| hook.relations.iter().cloned())
+            .collect()
+    }
+
+    /// Commit `tx` after writing the collected changes into the journals of the sync hooks
+    /// interested in them, under the locks of the journals like any other write.
+    /// `locked` are the relations whose locks the transaction already holds.
+    pub(crate) fn commit_with_sync_journals(
+        &'s self,
+        tx: &mut SessionTx<'_>,
+        collector: &CallbackCollector,
+        locked: &BTreeSet<SmartString<LazyCompact>>,
+    ) -> Result<()> {
+        let hooks = self
+            .sync_hooks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(_, hook)| collector.keys().any(|rel| hook.relations.contains(rel)))
+            .map(|(name, hook)| (name.clone(), hook.relations.clone(), hook.next_seq.clone()))
+            .collect_vec();
+        let journals: BTreeSet<SmartString<LazyCompact>> = hooks
+            .iter()
+            .map(|(name, _, _)| SmartString::from(journal_name(name)))
+            .filter(|journal| !locked.contains(journal))
+            .collect();
+        let journal_locks = self.obtain_relation_locks(journals.iter());
+        let _journal_guards = journal_locks
+            .iter()
+            .map(|l| l.read().unwrap())
+            .collect_vec();
+        let mut seq_guards = Vec::with_capacity(hooks.len());
+        for (name, relations, next_seq) in &hooks {
+            let mut next_seq = next_seq.lock().unwrap();
+            let handle = tx.get_relation(&journal_name(name), false)?;
+            for (relation, changes) in collector {
+                if !relations.contains(relation) {
+                    continue;
+                }
+                for (op, new, old) in changes {
+                    let tuple = vec![
+                        DataValue::from(*next_seq),
+                        DataValue::from(relation.as_str()),
+                        DataValue::from(op.as_str()),
+                        rows_to_value(new),
+                        rows_to_value(old),
+                    ];
+                    let key = handle.encode_key_for_store(&tuple, SourceSpan(0, 0))?;
+                    let val = handle.encode_val_for_store(&tuple, SourceSpan(0, 0))?;
+                    tx.store_tx.put(&key, &val)?;
+                    *next_seq += 1;
+                }
+            }
+            seq_guards.push(next_seq);
+        }
+        tx.commit_tx()
+    }
+
+    /// Wake up the delivery threads of the sync hooks interested in the collected changes.
+    pub(crate) fn notify_sync_hooks(&self, collector: &CallbackCollector) {
+        for hook in self.sync_hooks.read().unwrap().values() {
+            if collector.keys().any(|rel| hook.relations.contains(rel)) {
+                let _ = hook.notify.try_send(());
+            }
+        }
+    }
+
+    /// The oldest `limit` batches in the journal of the sync hook `name`.
+    pub(crate) fn pending_sync_batches(
+        &'s self,
+        name: &str,
+        limit: usize,
+    ) -> Result<Vec<SyncBatch>> {
+        let res = self.run_script(
+            &format!(
+                "?[seq, relation, op, new, old] := *{}{{seq, relation, op, new, old}} :order seq :limit {limit}",
+                journal_name(name)
+            ),
+            Default::default(),
+        )?;
+        Ok(res
+            .rows
+            .into_iter()
+            .map(|row| {
+                let mut row = row.into_iter();
+                SyncBatch {
+                    seq: row.next().unwrap().get_int().unwrap(),
+                    relation: row.next().unwrap().get_str().unwrap().to_string(),
+                    op: match row.next().unwrap().get_str() {
+                        Some("Rm") => CallbackOp::Rm,
+                        _ => CallbackOp::Put,
+                    },
+                    new: value_to_rows(row.next().unwrap()),
+                    old: value_to_rows(row.next().unwrap()),
+                }
+            })
+            .collect_vec())
+    }
+
+    /// Remove a delivered batch from the journal of the sync hook `name`.
+    pub(crate) fn ack_sync_batch(&'s self, name: &str, seq: i64) -> Result<()> {
+        self.run_script(
+            &format!("?[seq] <- [[{seq}]] :rm {} {{seq}}", journal_name(name)),
+            Default::default(),
+        )?;
+        Ok(())
+    }
+}
+
+fn rows_to_value(rows: &NamedRows) -> DataValue {
+    DataValue::List(vec![
+        DataValue::List(
+            rows.headers
+                .iter()
+                .map(|h| DataValue::from(h.as_str()))
+                .collect(),
+        ),
+        DataValue::List(
+            rows.rows
+                .iter()
+                .map(|r| DataValue::List(r.clone()))
+                .collect(),
+        ),
+    ])
+}
+
+fn value_to_rows(val: DataValue) -> NamedRows {
+    let mut parts = match val {
+        DataValue::List(l) => l.into_iter(),
+        _ => return NamedRows::default(),
+    };
+    let headers = match parts.next() {
+        Some(DataValue::List(l)) => l
+            .into_iter()
+            .map(|h| h.get_str().unwrap_or_default().to_string())
+            .collect(),
+        _ => vec![],
+    };
+    let rows = match parts.next() {
+        Some(DataValue::List(l)) => l
+            .into_iter()
+            .map(|r| match r {
+                DataValue::List(r) => r,
+                v => vec![v],
+            })
+            .collect(),
+        _ => vec![],
+    };
+    NamedRows::new(headers, rows)
+}
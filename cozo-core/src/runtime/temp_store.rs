@@ -177,6 +177,22 @@ impl MeetAggrStore {
                 }
             })
     }
+    /// Whether merging `new` in would add a group or improve an aggregated value.
+    fn would_change(&self, new: &Self) -> Result<bool> {
+        for (k, v) in new.inner.iter() {
+            let mut target = match self.inner.get(k) {
+                None => return Ok(true),
+                Some(target) => target.clone(),
+            };
+            for (i, (aggr_op, _)) in self.aggregations.iter().enumerate() {
+                let op = aggr_op.meet_op.as_ref().unwrap();
+                if op.update(&mut target[i], &v[i])? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
     /// returns true if prev is guaranteed to be the same as self after this function call,
     /// false if we are not sure.
     pub(crate) fn merge_in(&mut self, prev: &mut Self, mut new: Self) -> Result<bool> {
@@ -285,6 +301,24 @@ impl EpochStore {
         }
         Ok(())
     }
+    /// Whether merging `new` in would change the store.
+    pub(crate) fn would_change(&self, new: &TempStore) -> Result<bool> {
+        match (&self.total, new) {
+            (TempStore::Normal(total), TempStore::Normal(new)) => {
+                Ok(new.inner.keys().any(|k| !total.exists(k)))
+            }
+            (TempStore::MeetAggr(total), TempStore::MeetAggr(new)) => total.would_change(new),
+            _ => unreachable!(),
+        }
+    }
+    /// Empty the delta, as merging in an empty store does.
+    pub(crate) fn clear_delta(&mut self) {
+        match &mut self.delta {
+            TempStore::Normal(prev) => prev.inner.clear(),
+            TempStore::MeetAggr(prev) => prev.inner.clear(),
+        }
+        self.use_total_for_delta = false;
+    }
     pub(crate) fn has_delta(&self) -> bool {
         if self.use_total_for_delta {
             !self.total.is_empty()
@@ -7,8 +7,9 @@
  *
  */
 
-use std::collections::BTreeMap;
-use std::time::Duration;
+use std::collections::{BTreeMap, BTreeSet};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use itertools::Itertools;
 use log::debug;
@@ -17,12 +18,17 @@ use smartstring::{LazyCompact, SmartString};
 
 use crate::data::expr::Expr;
 use crate::data::symb::Symbol;
+use crate::data::tuple::TupleT;
 use crate::data::value::DataValue;
 use crate::fixed_rule::FixedRulePayload;
 use crate::parse::SourceSpan;
 use crate::runtime::callback::CallbackOp;
-use crate::runtime::db::Poison;
-use crate::{new_cozo_mem, DbInstance, FixedRule, RegularTempStore};
+use crate::runtime::db::{CancelToken, Poison};
+use crate::runtime::relation::RelationId;
+use crate::{
+    format_error_as_json, new_cozo_mem, DbInstance, FixedRule, NamedRows, RegularTempStore,
+    Storage, StoreTx,
+};
 
 #[test]
 fn test_limit_offset() {
@@ -738,3 +744,3762 @@ fn test_multi_tx() {
     tx.abort().unwrap();
     assert!(db.run_script("?[a] := *a[a]", Default::default()).is_err());
 }
+
+#[test]
+fn test_unknown_column_near_misses() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create person {id => name, email}", Default::default())
+        .unwrap();
+    let err = db
+        .run_script(
+            "?[id, name, emial] <- [[1, 'a', 'b']] :put person {id => name, emial}",
+            Default::default(),
+        )
+        .unwrap_err();
+    let help = err.help().unwrap().to_string();
+    assert!(help.contains("email"));
+    assert!(!help.contains("name"));
+}
+
+#[test]
+fn test_missing_relation_cache_in_tx() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let tx = db.multi_transaction(true);
+    assert!(tx.run_script("?[a] := *a[a]", Default::default()).is_err());
+    tx.run_script(":create a {a}", Default::default()).unwrap();
+    tx.run_script("?[a] <- [[1]] :put a {a}", Default::default())
+        .unwrap();
+    assert_eq!(
+        tx.run_script("?[a] := *a[a]", Default::default())
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1]])
+    );
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_sequences() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    assert_eq!(db.next_in_sequence("invoice_no").unwrap(), 1);
+    assert_eq!(db.next_in_sequence("invoice_no").unwrap(), 2);
+    assert_eq!(db.next_in_sequence("other").unwrap(), 1);
+    assert_eq!(db.reserve_in_sequence("invoice_no", 10).unwrap(), 3);
+    assert_eq!(db.next_in_sequence("invoice_no").unwrap(), 13);
+    assert!(db.reserve_in_sequence("invoice_no", 0).is_err());
+    db.run_script(":create a {a}", Default::default()).unwrap();
+    assert_eq!(
+        db.run_script("::relations", Default::default())
+            .unwrap()
+            .rows
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn test_compare_and_swap_with_ensure() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create counter {k => v}", Default::default())
+        .unwrap();
+    db.run_script(
+        "?[k, v] <- [['a', 1]] :put counter {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+    let cas = r#"
+        {?[k, v] <- [['a', $expected]] :ensure counter {k => v}}
+        {?[k, v] <- [['a', $new]] :put counter {k => v}}
+    "#;
+    let params = |expected: i64, new: i64| {
+        BTreeMap::from([
+            ("expected".to_string(), DataValue::from(expected)),
+            ("new".to_string(), DataValue::from(new)),
+        ])
+    };
+    db.run_script(cas, params(1, 2)).unwrap();
+    assert!(db.run_script(cas, params(1, 3)).is_err());
+    assert_eq!(
+        db.run_script("?[v] := *counter{k: 'a', v}", Default::default())
+            .unwrap()
+            .into_json()["rows"],
+        json!([[2]])
+    );
+    let insert_if_absent = r#"
+        {?[k] <- [['b']] :ensure_not counter {k}}
+        {?[k, v] <- [['b', 1]] :put counter {k => v}}
+    "#;
+    db.run_script(insert_if_absent, Default::default()).unwrap();
+    assert!(db.run_script(insert_if_absent, Default::default()).is_err());
+}
+
+#[test]
+fn test_session_variables() {
+    let db = new_cozo_mem().unwrap();
+    let session = db.new_session();
+    session
+        .run_script(
+            "?[a, b] := a in [1, 2], b = a * 10 :set_var pairs",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        session.get_var("pairs").unwrap(),
+        DataValue::List(vec![
+            DataValue::List(vec![DataValue::from(1), DataValue::from(10)]),
+            DataValue::List(vec![DataValue::from(2), DataValue::from(20)]),
+        ])
+    );
+    let res = session
+        .run_script("?[a, b] <- $pairs :order -a", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, 20], [1, 10]]));
+
+    session.set_var("x", DataValue::from(5));
+    let res = session
+        .run_script("?[x] := x = $x", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[5]]));
+    let res = session
+        .run_script(
+            "?[x] := x = $x",
+            BTreeMap::from([("x".to_string(), DataValue::from(6))]),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[6]]));
+
+    assert!(db
+        .run_script("?[a] := a = 1 :set_var a", Default::default())
+        .is_err());
+
+    // imperative scripts cannot set variables
+    let err = session
+        .run_script(
+            "{?[a] := a = 1 :set_var a} {?[b] := b = 2}",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::set_var_in_imperative"
+    );
+    assert!(session.get_var("a").is_none());
+}
+
+#[test]
+fn test_completions() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_script(":create friends {a, b}", Default::default())
+        .unwrap();
+    db.run_script(":create foes {a, b}", Default::default())
+        .unwrap();
+    db.run_script("::index create friends:rev {b, a}", Default::default())
+        .unwrap();
+    assert_eq!(
+        db.completions("*f", "?[a] := ").unwrap(),
+        vec!["*foes", "*friends"]
+    );
+    assert_eq!(db.completions("fr", ":put").unwrap(), vec!["friends"]);
+    assert_eq!(
+        db.completions("::rel", "").unwrap(),
+        vec!["::relation_kind", "::relation_scan", "::relations"]
+    );
+    assert_eq!(
+        db.completions(":li", "?[a] := a = 1").unwrap(),
+        vec![":limit"]
+    );
+    assert_eq!(
+        db.completions("Const", "?[a] <~").unwrap(),
+        vec!["Constant"]
+    );
+    let fns = db.completions("str_", "?[a] := a = ").unwrap();
+    assert!(fns.contains(&"str_includes".to_string()));
+    assert!(db
+        .completions("co", "?[a] := a = ")
+        .unwrap()
+        .contains(&"count".to_string()));
+}
+
+#[test]
+fn test_parse_only() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let res = db
+        .parse_only(
+            "r[a] := a = 1; ?[x, y] := r[x], y = x + 1 :put nums {x => y}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["query", ["?", "r"], ["x", "y"], ["nums"]]])
+    );
+    // the target relation does not exist, but nothing is executed
+    assert!(db
+        .run_script("?[a] := *nums[a]", Default::default())
+        .is_err());
+
+    let res = db.parse_only("::relations", Default::default()).unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from("sys"));
+
+    let res = db.parse_only_str("?[a] := a = ", "");
+    let res: serde_json::Value = serde_json::from_str(&res).unwrap();
+    assert_eq!(res["ok"], json!(false));
+    assert!(!res["labels"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_parse_to_json() {
+    let src = "?[a, b] := a = 1, b = a + 1";
+    let ast = crate::parse_to_json(src).unwrap();
+    assert_eq!(ast["rule"], json!("query_script"));
+    assert_eq!(ast["span"], json!([0, src.len()]));
+    assert_eq!(ast["variables"]["a"].as_array().unwrap().len(), 3);
+    assert_eq!(ast["variables"]["b"], json!([[5, 6], [18, 19]]));
+    assert!(crate::parse_to_json("?[a] := a = ").is_err());
+}
+
+#[test]
+fn test_format_script() {
+    let src = r#"
+        # the answer
+        ?[a,b]:=a=1,b=-a+1;:limit 10
+        :order -a
+    "#;
+    let formatted = crate::format_script(src).unwrap();
+    assert_eq!(
+        formatted,
+        "# the answer\n?[a, b] := a = 1, b = -a + 1;\n:order -a\n:limit 10"
+    );
+    assert_eq!(crate::format_script(&formatted).unwrap(), formatted);
+
+    let src = "{:create rel{a:Int,b:[Int;2]?=>c default 'x'}} %if {?[x]:=*rel{a:x}} %then {?[]<~Constant(data:[[1]])} %end";
+    let formatted = crate::format_script(src).unwrap();
+    assert_eq!(
+        formatted,
+        r#"{
+    :create rel {a: Int, b: [Int; 2]? => c default 'x'}
+}
+%if {
+    ?[x] := *rel{a: x}
+} %then
+    {
+        ?[] <~ Constant(data: [[1]])
+    }
+%end"#
+    );
+    assert_eq!(crate::format_script(&formatted).unwrap(), formatted);
+
+    let src = "?[long_variable_one, long_variable_two] := long_variable_one = 1, long_variable_two = 2, long_variable_one < long_variable_two";
+    let formatted = crate::format_script(src).unwrap();
+    assert_eq!(
+        formatted,
+        "?[long_variable_one, long_variable_two] := long_variable_one = 1,\n    long_variable_two = 2,\n    long_variable_one < long_variable_two"
+    );
+    let db = new_cozo_mem().unwrap();
+    assert_eq!(
+        db.run_script(&formatted, Default::default())
+            .unwrap()
+            .into_json()["rows"],
+        json!([[1, 2]])
+    );
+}
+
+#[test]
+fn test_format_script_roundtrip() {
+    // the syntax tree without positions, which formatting changes
+    fn tree(src: &str) -> serde_json::Value {
+        fn strip(node: &mut serde_json::Value) {
+            let node = node.as_object_mut().unwrap();
+            node.remove("span");
+            node.remove("variables");
+            if let Some(serde_json::Value::String(text)) = node.get_mut("text") {
+                *text = text.trim().to_string();
+            }
+            for child in node
+                .get_mut("children")
+                .and_then(|c| c.as_array_mut())
+                .into_iter()
+                .flatten()
+            {
+                strip(child);
+            }
+        }
+        let mut ast = crate::parse_to_json(src).unwrap();
+        strip(&mut ast);
+        ast
+    }
+    let scripts = [
+        "a[x] := x = 1; b[x] <- [[2]]; ?[x] := a[x] or b[x]; :order -x; :limit 1;",
+        "?[x] <~ Constant(data: [[1]]); :timeout 1",
+        "{?[a] <- [[1]]; :create r {a}} {?[a] := *r[a]; :limit 1} %return _x",
+        "%loop {?[a] := a = 1; :put r {a}} %if_not _x %then %break %end %end",
+        "::index create r:idx {b, a}",
+    ];
+    for src in scripts {
+        let formatted = crate::format_script(src).unwrap();
+        assert_eq!(tree(&formatted), tree(src), "{formatted}");
+        assert_eq!(crate::format_script(&formatted).unwrap(), formatted);
+    }
+    assert_eq!(
+        crate::format_script(scripts[0]).unwrap(),
+        "a[x] := x = 1;\nb[x] <- [[2]];\n?[x] := a[x] or b[x];\n:order -x;\n:limit 1;"
+    );
+}
+
+#[test]
+fn test_vacuum() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r"
+        ?[a, b] <- [[1, 2], [3, 4]]
+        :create friends {a => b}
+    ",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::index create friends:rev {b, a}", Default::default())
+        .unwrap();
+    let res = db
+        .run_script("::vacuum dry_run", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+
+    // simulate data left behind by a relation that no longer exists
+    db.run_script(":create gone {x}", Default::default())
+        .unwrap();
+    let gone_id = db
+        .transact()
+        .unwrap()
+        .get_relation("gone", false)
+        .unwrap()
+        .id;
+    let orphan = vec![DataValue::from(1)].encode_as_key(gone_id);
+    {
+        let mut tx = db.db.transact(true).unwrap();
+        tx.del(&vec![DataValue::from("gone")].encode_as_key(RelationId::SYSTEM))
+            .unwrap();
+        tx.put(&orphan, &[]).unwrap();
+        tx.commit().unwrap();
+    }
+
+    let res = db
+        .run_script("::vacuum dry_run", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[gone_id.0, 1, orphan.len(), false]])
+    );
+    let res = db.run_script("::vacuum", Default::default()).unwrap();
+    assert_eq!(res.rows[0][3], DataValue::from(true));
+    let res = db
+        .run_script("::vacuum dry_run", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+    let res = db
+        .run_script("?[a, b] := *friends[a, b]", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+    let res = db
+        .run_script("?[a, b] := *friends:rev[b, a]", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+}
+
+#[test]
+fn test_remove_partition() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r"
+        ?[day, id, v] <- [[1, 1, 'a'], [1, 2, 'b'], [2, 1, 'c'], [3, 1, 'd']]
+        :create events {day: Int, id => v}
+    ",
+        Default::default(),
+    )
+    .unwrap();
+    assert!(db
+        .run_script("::remove_partition events 'x'", Default::default())
+        .is_err());
+
+    // removing a partition runs neither `on rm` triggers nor callbacks
+    db.run_script(":create removed {day: Int, id}", Default::default())
+        .unwrap();
+    db.run_script(
+        r"
+        ::set_triggers events
+        on rm {
+            ?[day, id] := _old[day, id, v]
+            :put removed {day, id}
+        }
+        ",
+        Default::default(),
+    )
+    .unwrap();
+    let (_id, receiver) = db.register_callback("events", None);
+    db.run_script("::remove_partition events 1", Default::default())
+        .unwrap();
+    db.db.join_cleanups();
+    let res = db
+        .run_script("?[day, v] := *events{day, v}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2, "c"], [3, "d"]]));
+    let res = db
+        .run_script("?[day, id] := *removed{day, id}", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+    assert!(receiver.try_recv().is_err());
+
+    db.run_script("::index create events:by_v {v}", Default::default())
+        .unwrap();
+    assert!(db
+        .run_script("::remove_partition events 2", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_append_only_relation() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create events {source, seq: Int => v}", Default::default())
+        .unwrap();
+    db.run_script("::index create events:by_v {v}", Default::default())
+        .unwrap();
+    db.run_script("::relation_kind append_only events", Default::default())
+        .unwrap();
+    db.run_script(
+        "?[source, v] <- [['a', 1], ['b', 2]] :put events {source => v}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[source, v] <- [['a', 1]] :put events {source => v}",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            "?[source, seq, v] := *events{source, seq, v}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["a", 1, 1], ["a", 3, 1], ["b", 2, 2]])
+    );
+    let res = db
+        .run_script("?[seq] := *events:by_v{v: 1, seq}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [3]]));
+
+    // the sequence column cannot be given
+    let err = db
+        .run_script(
+            "?[source, seq, v] <- [['a', 100, 1]] :put events {source, seq => v}",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::append_only_seq_given"
+    );
+
+    // rows can still be removed by their full key
+    db.run_script(
+        "?[source, seq] <- [['a', 1]] :rm events {source, seq}",
+        Default::default(),
+    )
+    .unwrap();
+
+    db.run_script(":create other {a => b}", Default::default())
+        .unwrap();
+    db.run_script(
+        "?[a, b] <- [[1, 2]] :put other {a => b}",
+        Default::default(),
+    )
+    .unwrap();
+    assert!(db
+        .run_script("::relation_kind append_only other", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_window_functions() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[t, v] <- [[3, 20], [1, 10], [2, 20], [4, 5]]
+            :order v, t
+            :window s = cumsum(v), p = lag(t), n = lead(t, 2), r = rank(), d = dense_rank(), i = row_number()
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.headers, vec!["t", "v", "s", "p", "n", "r", "d", "i"]);
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            [4, 5, 5, null, 2, 1, 1, 1],
+            [1, 10, 15, 4, 3, 2, 2, 2],
+            [2, 20, 35, 1, null, 3, 3, 3],
+            [3, 20, 55, 2, null, 4, 4, 4]
+        ])
+    );
+
+    // ranks tie on the sort keys only
+    let res = db
+        .run_script(
+            r#"
+            ?[t, v] <- [[3, 20], [1, 10], [2, 20], [4, 5]]
+            :order -v
+            :window r = rank(), d = dense_rank()
+            :limit 3
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[2, 20, 1, 1], [3, 20, 1, 1], [1, 10, 3, 2]])
+    );
+
+    db.run_script(
+        "?[t, v] <- [[1, 2], [2, 3]] :window s = cumsum(v) :create cum {t => v, s}",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[t, s] := *cum{t, s}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 2], [2, 5]]));
+
+    assert!(db
+        .run_script("?[t] <- [[1]] :window s = cumsum(x)", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("?[t] <- [[1]] :window t = row_number()", Default::default())
+        .is_err());
+    assert!(db
+        .run_script("?[t] <- [[1]] :window s = median(t)", Default::default())
+        .is_err());
+}
+
+#[cfg(feature = "capi")]
+#[test]
+fn test_c_api() {
+    use std::ffi::{CStr, CString};
+    use std::ptr::null_mut;
+
+    use crate::data::json::JsonValue;
+    use crate::ffi::*;
+
+    unsafe {
+        let engine = CString::new("mem").unwrap();
+        let empty = CString::new("").unwrap();
+        let options = CString::new("{}").unwrap();
+        let mut db_id = -1;
+        let status = cozo_db_create(
+            engine.as_ptr(),
+            empty.as_ptr(),
+            options.as_ptr(),
+            &mut db_id,
+            null_mut(),
+        );
+        assert_eq!(status, CozoStatus::Ok);
+
+        let script = CString::new("?[a] <- [[$x]]").unwrap();
+        let params = CString::new(r#"{"x": 1}"#).unwrap();
+        let mut out = null_mut();
+        let status = cozo_db_run_script(db_id, script.as_ptr(), params.as_ptr(), &mut out);
+        assert_eq!(status, CozoStatus::Ok);
+        let res: JsonValue = serde_json::from_str(CStr::from_ptr(out).to_str().unwrap()).unwrap();
+        assert_eq!(res["rows"], json!([[1]]));
+        cozo_string_free(out);
+
+        let bad = CString::new("?[a] <- [[$y]]").unwrap();
+        let mut out = null_mut();
+        let status = cozo_db_run_script(db_id, bad.as_ptr(), params.as_ptr(), &mut out);
+        assert_eq!(status, CozoStatus::ScriptFailed);
+        assert!(!out.is_null());
+        cozo_string_free(out);
+
+        assert_eq!(cozo_db_close(db_id), CozoStatus::Ok);
+        assert_eq!(cozo_db_close(db_id), CozoStatus::DbNotFound);
+        let mut out = null_mut();
+        let status = cozo_db_run_script(db_id, script.as_ptr(), null_mut(), &mut out);
+        assert_eq!(status, CozoStatus::DbNotFound);
+    }
+}
+
+#[test]
+fn test_error_codes() {
+    let db = new_cozo_mem().unwrap();
+    let code = |script: &str| {
+        let err = db.run_script(script, Default::default()).unwrap_err();
+        format_error_as_json(err, Some(script))["code"].clone()
+    };
+    db.run_script(":create rel {a => b}", Default::default())
+        .unwrap();
+
+    assert_eq!(code("?[a] := *nope[a]"), json!("query::relation_not_found"));
+    assert_eq!(code("?[a] := *rel[a]"), json!("eval::rule_arity_mismatch"));
+    let err = db
+        .run_script("?[a] := not *rel[a, 1, 2], a = 1", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Stored relation rel has arity 2, but is applied to 3 arguments"
+    );
+    assert_eq!(
+        err.help().unwrap().to_string(),
+        "The columns of rel are: a: Any?, b: Any?"
+    );
+    assert_eq!(
+        code(":create rel {a}"),
+        json!("eval::stored_relation_conflict")
+    );
+    assert_eq!(
+        code(
+            r#"
+            r[x] := x = 0
+            r[y] := r[x], y = x + 1
+            ?[x] := r[x]
+            :timeout 1
+            "#
+        ),
+        json!("eval::timeout")
+    );
+
+    let err = db
+        .import_relations(BTreeMap::from([(
+            "rel".to_string(),
+            NamedRows::new(vec!["a".to_string()], vec![vec![DataValue::from(1)]]),
+        )]))
+        .unwrap_err();
+    assert_eq!(
+        format_error_as_json(err, None)["code"],
+        json!("tx::import_header_not_found")
+    );
+}
+
+#[test]
+fn test_run_scripts() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_scripts(
+            &[
+                ":create a {x}",
+                "?[x] <- [[1], [2]] :put a {x}",
+                "?[count(x)] := *a{x}",
+            ],
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.len(), 3);
+    assert_eq!(res[2].rows, vec![vec![DataValue::from(2)]]);
+
+    // a failing script rolls back the whole batch
+    assert!(db
+        .run_scripts(
+            &[
+                ":create b {x}",
+                "?[x] <- [[3]] :put a {x}",
+                "?[x] <- [[1]] :put nope {x}",
+            ],
+            Default::default(),
+        )
+        .is_err());
+    assert!(db.run_script("?[x] := *b{x}", Default::default()).is_err());
+    let res = db
+        .run_script("?[count(x)] := *a{x}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+
+    // nothing runs if any script fails to parse
+    assert!(db
+        .run_scripts(
+            &["?[x] <- [[4]] :put a {x}", "?[x] <- "],
+            Default::default()
+        )
+        .is_err());
+    assert!(db
+        .run_scripts(
+            &["?[x] <- [[4]] :put a {x}", "::relations"],
+            Default::default()
+        )
+        .is_err());
+    let res = db
+        .run_script("?[count(x)] := *a{x}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(2)]]);
+}
+
+#[test]
+fn test_run_scripts_are_running_queries() {
+    let db = new_cozo_mem().unwrap();
+    thread::scope(|scope| {
+        let runner = scope.spawn(|| {
+            db.run_scripts(
+                &[
+                    "?[x] <- [[1]]",
+                    "r[x] := x = 0 r[y] := r[x], y = x + 1 ?[x] := r[x]",
+                ],
+                Default::default(),
+            )
+        });
+        // each query of the batch is listed while it runs, and killing one aborts the batch
+        while !runner.is_finished() {
+            let running = db.run_script("::running", Default::default()).unwrap();
+            for row in running.rows {
+                let params = BTreeMap::from([("id".to_string(), row[0].clone())]);
+                db.run_script("::kill $id", params).unwrap();
+            }
+            thread::yield_now();
+        }
+        let err = runner.join().unwrap().unwrap_err();
+        assert_eq!(
+            err.root_cause().to_string(),
+            "Running query is killed before completion"
+        );
+    });
+}
+
+#[test]
+fn test_run_scripts_with_guards() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        {:create parent {id}}
+        {:create child {id => parent}}
+        {?[id] <- [[1]] :put parent {id}}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    let insert_child = |parent: i64| {
+        db.run_scripts(
+            &[
+                "?[id] := *parent{id}, id = $parent :assert some",
+                "?[id, parent] <- [[$id, $parent]] :put child {id => parent}",
+            ],
+            BTreeMap::from([
+                ("id".to_string(), DataValue::from(parent * 10)),
+                ("parent".to_string(), DataValue::from(parent)),
+            ]),
+        )
+    };
+    assert!(insert_child(1).is_ok());
+    assert!(insert_child(2).is_err());
+    let res = db
+        .run_script("?[id, parent] := *child{id, parent}", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.rows,
+        vec![vec![DataValue::from(10), DataValue::from(1)]]
+    );
+}
+
+#[test]
+fn test_write_counts() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            "?[k, v] <- [[1, 'a'], [2, 'b']] :create rel {k => v} :counts",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.headers,
+        vec!["status", "inserted", "overwritten", "retracted"]
+    );
+    assert_eq!(res.into_json()["rows"], json!([["OK", 2, 0, 0]]));
+
+    let res = db
+        .run_script(
+            "?[k, v] <- [[2, 'c'], [3, 'd']] :put rel {k => v} :counts",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["OK", 1, 1, 0]]));
+
+    let res = db
+        .run_script("?[k] <- [[1], [4]] :rm rel {k} :counts", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["OK", 0, 0, 1]]));
+
+    // without :counts, only the status is returned
+    let res = db
+        .run_script(
+            "?[k, v] <- [[5, 'e']] :put rel {k => v}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["OK"]]));
+
+    assert!(db
+        .run_script("?[k] <- [[1]] :counts", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_profile() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_script(
+        "?[k, v] := k in [0, 1, 2, 3, 4, 5, 6, 7, 8, 9], v = k * 2 :create rel {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+
+    let res = db
+        .run_script("?[k, v] := *rel[k, v] :profile", Default::default())
+        .unwrap();
+    assert_eq!(res.headers, vec!["gets", "seeks", "nexts", "bytes_read"]);
+    let counts = res.rows[0]
+        .iter()
+        .map(|v| v.get_int().unwrap())
+        .collect_vec();
+    assert!(counts[0] >= 1);
+    assert!(counts[1] >= 1);
+    assert_eq!(counts[2], 10);
+    assert!(counts[3] > 0);
+
+    let res = db
+        .run_script("?[v] := *rel[5, v] :profile", Default::default())
+        .unwrap();
+    // looking up by the full key needs no scan
+    assert_eq!(res.rows[0][2], DataValue::from(0));
+    assert!(res.rows[0][0].get_int().unwrap() >= 2);
+
+    // counting stops with the profiled query
+    let res = db
+        .run_script(
+            "{?[v] := *rel[5, v] :profile} {?[k, v] := *rel[k, v]}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 10);
+}
+
+#[test]
+fn test_create_index_chunked() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[k, v] := k in [0, 1, 2, 3, 4, 5, 6, 7, 8, 9], v = k * 2 :create rel {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+
+    // interrupted after the first chunk
+    {
+        let mut tx = db.transact_write().unwrap();
+        let rel = Symbol::new("rel", Default::default());
+        let idx = Symbol::new("v", Default::default());
+        let cols = vec![Symbol::new("v", Default::default())];
+        tx.create_index(&rel, &idx, cols, vec![], None, true)
+            .unwrap();
+        assert_eq!(
+            tx.build_index_chunk(&rel, &idx, 4, false).unwrap(),
+            (4, false)
+        );
+        tx.commit_tx().unwrap();
+    }
+    // the partial index is not used, and is kept up to date by writes
+    let res = db
+        .run_script("?[k] := *rel{k, v: 12}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(6)]]);
+    db.run_script(
+        "?[k, v] <- [[10, 20]] :put rel {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+
+    // rows not reached by the build are not missing from the index
+    let res = db
+        .run_script("::integrity_check", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+
+    let mut reported = vec![];
+    db.create_index_chunked("::index create rel:v {v}", 3, |n| reported.push(n))
+        .unwrap();
+    assert_eq!(reported, vec![3, 6, 7]);
+    let res = db
+        .run_script("?[v, k] := *rel:v{v, k}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 11);
+    let res = db
+        .run_script("?[k] := *rel{k, v: 20}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(10)]]);
+
+    assert!(db
+        .create_index_chunked("::index create rel:v {v}", 3, |_| {})
+        .is_err());
+    assert!(db.create_index_chunked("?[a] <- [[1]]", 3, |_| {}).is_err());
+}
+
+#[test]
+fn test_create_index_online() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_script(":create rel {k: Int => v: Int}", Default::default())
+        .unwrap();
+    let rows = (0..200)
+        .map(|i| vec![DataValue::from(i), DataValue::from(i * 2)])
+        .collect_vec();
+    db.import_relations(BTreeMap::from([(
+        "rel".to_string(),
+        NamedRows::new(vec!["k".to_string(), "v".to_string()], rows),
+    )]))
+    .unwrap();
+
+    let builder = {
+        let db = db.clone();
+        thread::spawn(move || db.create_index_online("::index create rel:v {v}", 2, |_| {}))
+    };
+    // writes go on while the index is built
+    for i in 0..50 {
+        db.run_script(
+            "?[k, v] <- [[$k, $v]] :put rel {k => v}",
+            BTreeMap::from([
+                ("k".to_string(), DataValue::from(i * 4)),
+                ("v".to_string(), DataValue::from(-i)),
+            ]),
+        )
+        .unwrap();
+        db.run_script(
+            "?[k] <- [[$k]] :rm rel {k}",
+            BTreeMap::from([("k".to_string(), DataValue::from(i * 4 + 1))]),
+        )
+        .unwrap();
+    }
+    builder.join().unwrap().unwrap();
+
+    let res = db
+        .run_script("::integrity_check", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+    let res = db
+        .run_script("?[k] := *rel{k, v: -3}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(12)]]);
+    let res = db
+        .run_script("?[count(k)] := *rel:v{k}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(150)]]);
+}
+
+#[test]
+fn test_scheduled_scripts() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_script(":create counter {k: Int}", Default::default())
+        .unwrap();
+    db.schedule_script(
+        "tick",
+        "?[k] := k = 0; ?[k] := *counter[j], k = j + 1 :put counter {k}",
+        Default::default(),
+        Duration::from_millis(50),
+    )
+    .unwrap();
+    db.schedule_script(
+        "broken",
+        "?[x] := *nonexistent[x]",
+        Default::default(),
+        Duration::from_millis(50),
+    )
+    .unwrap();
+    assert!(db
+        .schedule_script(
+            "tick",
+            "?[a] <- [[1]]",
+            Default::default(),
+            Duration::from_secs(1)
+        )
+        .is_err());
+
+    thread::sleep(Duration::from_millis(300));
+    let res = db.run_script("::schedules", Default::default()).unwrap();
+    let json = res.into_json();
+    let rows = json["rows"].as_array().unwrap();
+    assert_eq!(rows.len(), 2);
+    assert_eq!(rows[0][0], json!("broken"));
+    assert_eq!(rows[0][5], json!("ERROR"));
+    assert_eq!(rows[1][0], json!("tick"));
+    assert_eq!(rows[1][5], json!("OK"));
+    assert!(rows[1][3].as_i64().unwrap() >= 1);
+
+    assert!(db.unschedule_script("tick"));
+    assert!(db.unschedule_script("broken"));
+    assert!(!db.unschedule_script("tick"));
+    thread::sleep(Duration::from_millis(150));
+    let n = db
+        .run_script("?[count(k)] := *counter[k]", Default::default())
+        .unwrap()
+        .rows[0][0]
+        .clone();
+    thread::sleep(Duration::from_millis(150));
+    let res = db
+        .run_script("?[count(k)] := *counter[k]", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], n);
+    assert!(db
+        .run_script("::schedules", Default::default())
+        .unwrap()
+        .rows
+        .is_empty());
+}
+
+#[test]
+fn test_metrics() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let before = db.metrics();
+    assert_eq!(before.storage_kind, "mem");
+    assert_eq!(before.running_queries, 0);
+    assert!(before.storage.is_empty());
+    for _ in 0..3 {
+        db.run_script("?[a] <- [[1]]", Default::default()).unwrap();
+    }
+    let after = db.metrics();
+    assert_eq!(after.queries_started, before.queries_started + 3);
+    assert_eq!(after.query_latency.count, before.query_latency.count + 3);
+    assert_eq!(after.running_queries, 0);
+    let (bound, n) = *after.query_latency.buckets.last().unwrap();
+    assert_eq!(bound, 10.);
+    assert_eq!(n, 3);
+    assert!(after
+        .query_latency
+        .buckets
+        .windows(2)
+        .all(|w| w[0].1 <= w[1].1));
+    assert_eq!(after.open_sessions, 0);
+    assert_eq!(after.open_transactions, 0);
+
+    let tx = db.multi_transaction(false);
+    tx.run_script("?[a] <- [[1]]", Default::default()).unwrap();
+    assert_eq!(db.metrics().open_transactions, 1);
+    tx.abort().unwrap();
+
+    let mem_db = new_cozo_mem().unwrap();
+    let session = mem_db.new_session();
+    let other = mem_db.new_session();
+    assert_eq!(mem_db.metrics().open_sessions, 2);
+    drop(session);
+    assert_eq!(mem_db.metrics().open_sessions, 1);
+    drop(other);
+    assert_eq!(mem_db.metrics().open_sessions, 0);
+}
+
+#[test]
+fn test_sync_hooks() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_script(":create docs {id: Int => body: String}", Default::default())
+        .unwrap();
+    db.run_script(":create other {id: Int}", Default::default())
+        .unwrap();
+
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    let (err_sender, err_receiver) = crossbeam::channel::unbounded();
+    let mut failures = 1;
+    db.register_sync_hook(
+        "search",
+        &["docs"],
+        move |rel, op, new, _old| {
+            // the first delivery fails, and must be retried
+            if failures > 0 {
+                failures -= 1;
+                miette::bail!("search engine unavailable");
+            }
+            sender.send((rel.to_string(), op, new.rows)).unwrap();
+            Ok(())
+        },
+        move |err| err_sender.send(err.to_string()).unwrap(),
+    )
+    .unwrap();
+    assert!(db
+        .register_sync_hook("search", &["docs"], |_, _, _, _| Ok(()), |_| {})
+        .is_err());
+
+    db.run_script(
+        "?[id, body] <- [[1, 'a'], [2, 'b']] :put docs {id => body}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("?[id] <- [[1]] :put other {id}", Default::default())
+        .unwrap();
+    db.run_script("?[id] <- [[1]] :rm docs {id}", Default::default())
+        .unwrap();
+
+    assert_eq!(
+        err_receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+        "search engine unavailable"
+    );
+    let (rel, op, rows) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(rel, "docs");
+    assert_eq!(op, CallbackOp::Put);
+    assert_eq!(rows.len(), 2);
+    let (rel, op, rows) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(rel, "docs");
+    assert_eq!(op, CallbackOp::Rm);
+    assert_eq!(rows, vec![vec![DataValue::from(1)]]);
+
+    // a change is only delivered once the ones before it are removed from the journal
+    db.run_script(
+        "?[id, body] <- [[4, 'd']] :put docs {id => body}",
+        Default::default(),
+    )
+    .unwrap();
+    let (_, _, rows) = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(rows, vec![vec![DataValue::from(4), DataValue::from("d")]]);
+    let res = db
+        .run_script(
+            "?[count(seq)] := *sync_journal.search{seq}, seq < 2",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(0));
+    assert!(err_receiver.is_empty());
+
+    // changes not yet acknowledged are delivered when the hook is registered again
+    assert!(db.unregister_sync_hook("search"));
+    db.register_sync_hook(
+        "search",
+        &["docs"],
+        |_, _, _, _| miette::bail!("search engine unavailable"),
+        |_| {},
+    )
+    .unwrap();
+    db.run_script(
+        "?[id, body] <- [[3, 'c']] :put docs {id => body}",
+        Default::default(),
+    )
+    .unwrap();
+    assert!(db.unregister_sync_hook("search"));
+    let (sender, receiver) = crossbeam::channel::unbounded();
+    db.register_sync_hook(
+        "search",
+        &["docs"],
+        move |_, _, new, _| {
+            sender.send(new.rows).unwrap();
+            Ok(())
+        },
+        |_| {},
+    )
+    .unwrap();
+    // the change delivered last before may not have been acknowledged, and come again first
+    let mut delivered = vec![];
+    while delivered.last() != Some(&vec![vec![DataValue::from(3), DataValue::from("c")]]) {
+        delivered.push(receiver.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+    assert!(db.unregister_sync_hook("search"));
+}
+
+#[test]
+fn test_kv_namespaces() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create rel {a: Int}", Default::default())
+        .unwrap();
+    let config = db.kv("config");
+    let other = db.kv("other");
+    assert_eq!(config.get(&DataValue::from("theme")).unwrap(), None);
+    config
+        .put(&DataValue::from("theme"), &DataValue::from("dark"))
+        .unwrap();
+    config
+        .put(&DataValue::from("retries"), &DataValue::from(3))
+        .unwrap();
+    config
+        .put(
+            &DataValue::from("zones"),
+            &DataValue::List(vec![DataValue::from("a"), DataValue::from("b")]),
+        )
+        .unwrap();
+    other
+        .put(&DataValue::from("theme"), &DataValue::from("light"))
+        .unwrap();
+    assert_eq!(
+        config.get(&DataValue::from("theme")).unwrap(),
+        Some(DataValue::from("dark"))
+    );
+    assert_eq!(
+        other.get(&DataValue::from("theme")).unwrap(),
+        Some(DataValue::from("light"))
+    );
+    let all = config.range(&DataValue::Null, &DataValue::Bot).unwrap();
+    assert_eq!(
+        all.iter().map(|(k, _)| k.clone()).collect_vec(),
+        vec![
+            DataValue::from("retries"),
+            DataValue::from("theme"),
+            DataValue::from("zones")
+        ]
+    );
+    let some = config
+        .range(&DataValue::from("s"), &DataValue::from("u"))
+        .unwrap();
+    assert_eq!(
+        some,
+        vec![(DataValue::from("theme"), DataValue::from("dark"))]
+    );
+    assert!(config.remove(&DataValue::from("theme")).unwrap());
+    assert!(!config.remove(&DataValue::from("theme")).unwrap());
+    assert_eq!(config.get(&DataValue::from("theme")).unwrap(), None);
+
+    // the store does not interfere with stored relations
+    let res = db.run_script("::relations", Default::default()).unwrap();
+    assert_eq!(res.rows.len(), 1);
+    db.run_script("?[a] <- [[1]] :put rel {a}", Default::default())
+        .unwrap();
+}
+
+#[test]
+fn test_literal_writes() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        ":create rel {k: Int => v: String default 'x'}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        ":put rel [[1, 'a'], [2, 'b'], [3, 'c']]",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(":rm rel [[1]]", Default::default()).unwrap();
+    db.run_script(":rm rel [[2, 'b']]", Default::default())
+        .unwrap();
+    let mut params = BTreeMap::new();
+    params.insert(
+        "rows".to_string(),
+        DataValue::List(vec![DataValue::List(vec![
+            DataValue::from(4),
+            DataValue::from("d"),
+        ])]),
+    );
+    db.run_script(":put rel $rows", params).unwrap();
+    let res = db
+        .run_script("?[k, v] := *rel{k, v}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3, "c"], [4, "d"]]));
+
+    let err = db
+        .run_script(":put rel [[5]]", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::literal_write_arity_mismatch"
+    );
+    assert!(db
+        .run_script(":put nonexistent [[1]]", Default::default())
+        .is_err());
+
+    // queries starting with an option are unaffected
+    db.run_script(
+        ":put rel {k => v} ?[k, v] <- [[6, 'f']]",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_scripts(&[":put rel [[7, 'g']]"], Default::default())
+        .unwrap();
+    assert_eq!(res.len(), 1);
+    let res = db
+        .run_script("?[count(k)] := *rel{k}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(4));
+}
+
+#[test]
+fn test_relation_scan() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[k, v] := k in [0, 1, 2, 3, 4, 5, 6, 7, 8, 9], v = k * k :create rel {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("::relation_scan rel 3 2", Default::default())
+        .unwrap();
+    assert_eq!(res.headers, vec!["k", "v"]);
+    assert_eq!(res.into_json()["rows"], json!([[3, 9], [4, 16]]));
+    let res = db
+        .run_script("::relation_scan rel 8 5", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+    let res = db
+        .run_script("::relation_scan rel 20 5", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+
+    let err = db
+        .run_script("::relation_scan rel -1 5", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::bad_scan_bound");
+    db.run_script("::access_level hidden rel", Default::default())
+        .unwrap();
+    assert!(db
+        .run_script("::relation_scan rel 0 5", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_const_rule_row_limit() {
+    let db = new_cozo_mem().unwrap();
+    let rows = DataValue::List(
+        (0..10)
+            .map(|i| DataValue::List(vec![DataValue::from(i)]))
+            .collect(),
+    );
+    let params = BTreeMap::from([("rows".to_string(), rows)]);
+    db.set_const_rule_row_limit(Some(5));
+    let err = db.run_script("?[a] <- $rows", params.clone()).unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::const_rule_too_large"
+    );
+    let res = db
+        .run_script("?[a] <- [[1], [2]]", Default::default())
+        .unwrap();
+    assert_eq!(res.rows.len(), 2);
+
+    db.set_const_rule_row_limit(None);
+    let res = db.run_script("?[a] <- $rows", params).unwrap();
+    assert_eq!(res.rows.len(), 10);
+}
+
+#[test]
+fn test_saved_queries() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[a, b] <- [[1, 'one'], [2, 'two'], [3, 'three']] :create rel {a => b}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::save_query above(min) '?[b] := *rel{a, b}, a > $min' 'Names above a minimum'",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::save_query all() '?[a] := *rel{a}'", Default::default())
+        .unwrap();
+
+    let res = db
+        .run_script("::call above {min: 1}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["three"], ["two"]]));
+    let params = BTreeMap::from([("x".to_string(), DataValue::from(2))]);
+    let res = db.run_script("::call above {min: $x}", params).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["three"]]));
+    let res = db.run_script("::call all", Default::default()).unwrap();
+    assert_eq!(res.rows.len(), 3);
+
+    let code = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap_err()
+            .code()
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(code("::call above"), "eval::saved_query_bad_params");
+    assert_eq!(
+        code("::call above {min: 1, max: 2}"),
+        "eval::saved_query_bad_params"
+    );
+    assert_eq!(code("::call nope"), "eval::saved_query_not_found");
+
+    let res = db
+        .run_script("::saved_queries", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            [
+                "above",
+                ["min"],
+                "Names above a minimum",
+                "?[b] := *rel{a, b}, a > $min"
+            ],
+            ["all", [], "", "?[a] := *rel{a}"]
+        ])
+    );
+    db.run_script("::remove_query above", Default::default())
+        .unwrap();
+    assert_eq!(code("::call above {min: 1}"), "eval::saved_query_not_found");
+    assert_eq!(code("::remove_query above"), "eval::saved_query_not_found");
+}
+
+#[test]
+fn test_import_saved_rules() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[a, b] <- [[1, 2], [2, 3], [3, 4]] :create follows {a, b}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::save_query social.friend() 'friend[a, b] := *follows{a, b}, *follows{a: b, b: a} \
+        friend[a, b] := *follows{a, b}, a == 1'",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::save_query social.reach() 'reach[a, b] := friend[a, b] \
+        reach[a, c] := reach[a, b], friend[b, c] :import social.friend'",
+        Default::default(),
+    )
+    .unwrap();
+
+    let res = db
+        .run_script(
+            "?[b] := friend[1, b] :import social.friend",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2]]));
+    let err = db
+        .run_script(
+            "friend[a, b] := a = 0, b = 1 ?[b] := reach[0, b] :import social.reach",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::bad_import");
+    let res = db
+        .run_script(
+            "?[b] := reach[1, b] :import social.reach",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2]]));
+    let err = db
+        .run_script("?[b] := friend[1, b] :import nope", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::saved_query_not_found"
+    );
+}
+
+#[test]
+fn test_tx_write_limit() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create rel {a => b}", Default::default())
+        .unwrap();
+    db.set_tx_write_limit(Some(3));
+    db.run_script(
+        "?[a, b] <- [[1, 1], [2, 2], [3, 3]] :put rel {a => b}",
+        Default::default(),
+    )
+    .unwrap();
+    let err = db
+        .run_script(
+            "?[a, b] <- [[4, 4], [5, 5], [6, 6], [7, 7]] :put rel {a => b}",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::too_many_writes");
+    // temp relations are not counted
+    db.run_script(
+        "{?[a] <- [[8], [9], [10], [11]] :create _t {a}} \
+         {?[a, b] := *_t{a}, a < 10, b = 0 :put rel {a => b}}",
+        Default::default(),
+    )
+    .unwrap();
+
+    let data = NamedRows::new(
+        vec!["a".to_string(), "b".to_string()],
+        (10..20)
+            .map(|i| vec![DataValue::from(i), DataValue::from(i)])
+            .collect(),
+    );
+    let err = db
+        .import_relations(BTreeMap::from([("rel".to_string(), data.clone())]))
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::too_many_writes");
+    let mut reports = vec![];
+    db.import_relations_chunked(
+        BTreeMap::from([("rel".to_string(), data)]),
+        3,
+        |rel, done, total| reports.push((rel.to_string(), done, total)),
+    )
+    .unwrap();
+    assert_eq!(
+        reports.iter().map(|(_, done, _)| *done).collect_vec(),
+        vec![3, 6, 9, 10]
+    );
+    assert!(reports
+        .iter()
+        .all(|(rel, _, total)| rel == "rel" && *total == 10));
+    let res = db
+        .run_script("?[count(a)] := *rel{a}", Default::default())
+        .unwrap();
+    assert_eq!(res.rows[0][0], DataValue::from(15));
+}
+
+#[test]
+fn test_index_join_back_to_relation() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"?[id, name, status] <- [[1, 'a', 'active'], [2, 'b', 'inactive'], [3, 'c', 'active']]
+        :create users {id => name, status}"#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::index create users:by_name {name}", Default::default())
+        .unwrap();
+    // the index holds name and id, so status is read from users by joining on id
+    let script = "n[name] <- [['b'], ['c']] ?[id, status] := n[name], *users{id, name, status}";
+    let res = db
+        .run_script(&format!("::explain {{ {script} }}"), Default::default())
+        .unwrap();
+    assert!(res.into_json().to_string().contains("users:by_name"));
+    let res = db.run_script(script, Default::default()).unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[2, "inactive"], [3, "active"]])
+    );
+}
+
+#[test]
+fn test_partial_index() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"?[id, name, status] <- [[1, 'a', 'active'], [2, 'b', 'inactive'], [3, 'c', 'active']]
+        :create users {id => name, status}"#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"::index create users:active {name} where status == "active""#,
+        Default::default(),
+    )
+    .unwrap();
+    let indexed = || {
+        db.run_script("?[name] := *users:active{name}", Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(indexed(), json!([["a"], ["c"]]));
+
+    db.run_script(
+        r#"?[id, name, status] <- [[1, 'a', 'inactive'], [4, 'd', 'active'], [5, 'e', 'new']]
+        :put users {id => name, status}"#,
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(indexed(), json!([["c"], ["d"]]));
+    db.import_relations(BTreeMap::from([(
+        "users".to_string(),
+        NamedRows::new(
+            vec!["id".to_string(), "name".to_string(), "status".to_string()],
+            vec![
+                vec![
+                    DataValue::from(6),
+                    DataValue::from("f"),
+                    DataValue::from("active"),
+                ],
+                vec![
+                    DataValue::from(7),
+                    DataValue::from("g"),
+                    DataValue::from("gone"),
+                ],
+            ],
+        ),
+    )]))
+    .unwrap();
+    assert_eq!(indexed(), json!([["c"], ["d"], ["f"]]));
+
+    let uses_index = |script: &str| {
+        db.run_script(&format!("::explain {{ {script} }}"), Default::default())
+            .unwrap()
+            .into_json()
+            .to_string()
+            .contains("users:active")
+    };
+    let with_cond = r#"n[name] <- [['c']]
+        ?[id] := n[name], *users{id, name, status}, status == "active""#;
+    assert!(uses_index(with_cond));
+    assert!(uses_index(
+        r#"n[name] <- [['c']] ?[id] := n[name], *users{id, name, status: "active"}"#
+    ));
+    assert!(!uses_index(
+        "n[name] <- [['c']] ?[id] := n[name], *users{id, name}"
+    ));
+    let res = db.run_script(with_cond, Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3]]));
+    let res = db
+        .run_script(
+            r#"n[name] <- [['g']] ?[id] := n[name], *users{id, name, status: "active"}"#,
+            Default::default(),
+        )
+        .unwrap();
+    assert!(res.rows.is_empty());
+}
+
+#[test]
+fn test_collated_index() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"?[id, email] <- [[1, 'Alice@Example.com'], [2, 'bob@example.com']]
+        :create users {id => email}"#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::index create users:email {email collate nocase}",
+        Default::default(),
+    )
+    .unwrap();
+    let lookup = |email: &str| {
+        db.run_script(
+            "?[id] := *users:email{email: collate($e, 'nocase'), id}",
+            BTreeMap::from([("e".to_string(), DataValue::from(email))]),
+        )
+        .unwrap()
+        .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(lookup("ALICE@example.COM"), json!([[1]]));
+    assert_eq!(lookup("Bob@Example.com"), json!([[2]]));
+
+    db.run_script(
+        r#"?[id, email] <- [[1, 'alice@other.org'], [3, 'Carol@Example.com']]
+        :put users {id => email}"#,
+        Default::default(),
+    )
+    .unwrap();
+    assert_eq!(lookup("alice@example.com"), json!([]));
+    assert_eq!(lookup("ALICE@OTHER.ORG"), json!([[1]]));
+    assert_eq!(lookup("carol@example.com"), json!([[3]]));
+
+    let res = db
+        .run_script("?[email] := *users{email}", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["Carol@Example.com"],
+            ["alice@other.org"],
+            ["bob@example.com"]
+        ])
+    );
+    assert!(db
+        .run_script(
+            "::index create users:bad {email collate klingon}",
+            Default::default()
+        )
+        .is_err());
+
+    // keys differing only in case would share their index entries
+    db.run_script(
+        "?[tag] <- [['Rust'], ['rust']] :create tags {tag}",
+        Default::default(),
+    )
+    .unwrap();
+    let err = db
+        .run_script(
+            "::index create tags:ci {tag collate nocase}",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "tx::collated_key_in_index");
+}
+
+#[test]
+fn test_prefix_search_with_index() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"?[id, name, age] <- [[1, 'alice', 30], [2, 'albert', 40], [3, 'bob', 50], [4, 'alfred', 60]]
+        :create users {id => name, age}"#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::index create users:name {name}", Default::default())
+        .unwrap();
+    let uses_index = |script: &str| {
+        db.run_script(&format!("::explain {{ {script} }}"), Default::default())
+            .unwrap()
+            .into_json()
+            .to_string()
+            .contains("users:name")
+    };
+    let index_only = "?[id, name] := *users{id, name}, starts_with(name, 'al')";
+    let with_join = "?[id, age] := *users{id, name, age}, starts_with(name, 'alf')";
+    assert!(uses_index(index_only));
+    assert!(uses_index(with_join));
+    assert!(!uses_index(
+        "?[id, name] := *users{id, name}, ends_with(name, 'ce')"
+    ));
+
+    let res = db.run_script(index_only, Default::default()).unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "alice"], [2, "albert"], [4, "alfred"]])
+    );
+    let res = db.run_script(with_join, Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[4, 60]]));
+    let res = db
+        .run_script(
+            "?[id] := *users{id, name}, starts_with(name, 'alb'), id > 1",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2]]));
+}
+
+#[test]
+fn test_range_filters_bound_scans() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"?[k, v] <- [[10, 'x'], [11, 'y'], [12, 'a']]
+        :create r {k => v}"#,
+        Default::default(),
+    )
+    .unwrap();
+    // bounds on non-key columns must not restrict the key range
+    let res = db
+        .run_script("?[k] := *r{k, v}, k >= 10, v >= 'x'", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[10], [11]]));
+
+    db.run_script(
+        r#"?[id, age] <- [[1, 30], [2, 40], [3, 50], [4, 60]]
+        :create users {id => age}"#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::index create users:age {age}", Default::default())
+        .unwrap();
+    let uses_index = |script: &str| {
+        db.run_script(&format!("::explain {{ {script} }}"), Default::default())
+            .unwrap()
+            .into_json()
+            .to_string()
+            .contains("users:age")
+    };
+    let between = "?[id] := *users{id, age}, age >= $lo, age < 55";
+    assert!(uses_index(&between.replace("$lo", "35")));
+    assert!(uses_index("?[id] := *users{id, age}, 45 > age"));
+    assert!(!uses_index("?[id] := *users{id, age}, age != 45"));
+    let res = db
+        .run_script(
+            between,
+            BTreeMap::from([("lo".to_string(), DataValue::from(35))]),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3]]));
+    let res = db
+        .run_script("?[id] := *users{id, age}, 45 > age", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2]]));
+}
+
+#[test]
+fn test_order_by_storage_key() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"?[ts, seq, v] <- [[3, 0, 'c'], [1, 1, 'b'], [1, 0, 'a'], [5, 0, 'e'], [4, 0, 'd']]
+        :create events {ts, seq => v}"#,
+        Default::default(),
+    )
+    .unwrap();
+    let rows = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        rows("?[v, ts] := *events{ts, v} :order ts :limit 3"),
+        json!([["a", 1], ["b", 1], ["c", 3]])
+    );
+    assert_eq!(
+        rows("?[v, ts] := *events{ts, v}, ts > 1 :order ts :limit 2 :offset 1"),
+        json!([["d", 4], ["e", 5]])
+    );
+    assert_eq!(
+        rows("?[v, seq, ts] := *events{ts, seq, v} :order ts, seq :limit 2"),
+        json!([["a", 0, 1], ["b", 1, 1]])
+    );
+    assert_eq!(
+        rows("?[v, ts] := *events{ts, v} :order v :limit 2"),
+        json!([["a", 1], ["b", 1]])
+    );
+    assert_eq!(
+        rows("?[v, ts] := *events{ts, v} :order -ts :limit 2"),
+        json!([["e", 5], ["d", 4]])
+    );
+}
+
+#[test]
+fn test_limit_through_simple_rules() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"?[a, b] <- [[1, 'x'], [6, 'y'], [7, 'z'], [8, 'w'], [9, 'v']]
+        :create r {a => b}"#,
+        Default::default(),
+    )
+    .unwrap();
+    let rows = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    let simple = "s[a, c] := *r{a, b}, a > 5, c = concat(b, '!')";
+    assert_eq!(
+        rows(&format!("{simple} ?[c] := s[_, c] :limit 2")),
+        json!([["y!"], ["z!"]])
+    );
+    assert_eq!(
+        rows(&format!(
+            "{simple} ?[a1, a2] := s[a1, _], s[a2, _], a2 == a1 + 2 :limit 2"
+        )),
+        json!([[6, 8], [7, 9]])
+    );
+    assert_eq!(
+        rows(&format!(
+            "{simple} ?[a] := s[a, c], c != 'y!' :limit 1 :offset 1"
+        )),
+        json!([[8]])
+    );
+
+    let explained = db
+        .run_script(
+            &format!("::explain {{ {simple} ?[c] := s[_, c] :limit 2 }}"),
+            Default::default(),
+        )
+        .unwrap()
+        .into_json();
+    // the rule `s` is inlined, so only the entry rule is left
+    assert!(explained["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|row| row[2] == json!("?")));
+}
+
+#[test]
+fn test_tx_id() {
+    let db = new_cozo_mem().unwrap();
+    let start = db.current_tx_id();
+    let res = db.run_script("?[x] <- [[1]]", Default::default()).unwrap();
+    assert_eq!(res.tx_id, Some(start));
+
+    let res = db.run_script(":create a {x}", Default::default()).unwrap();
+    assert_eq!(res.tx_id, Some(start + 1));
+    let res = db
+        .run_script("?[x] <- [[1]] :put a {x}", Default::default())
+        .unwrap();
+    assert_eq!(res.tx_id, Some(start + 2));
+    assert_eq!(db.current_tx_id(), start + 2);
+
+    let res = db.run_script("?[x] := *a{x}", Default::default()).unwrap();
+    assert_eq!(res.tx_id, Some(start + 2));
+    assert_eq!(res.into_json()["tx_id"], json!(start + 2));
+
+    let res = db
+        .run_script(
+            "{?[x] <- [[2]] :put a {x}} {?[x] := *a{x}}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.tx_id, Some(start + 3));
+}
+
+#[test]
+fn test_close() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_script(":create a {x}", Default::default()).unwrap();
+    db.schedule_script(
+        "tick",
+        "?[x] <- [[1]] :put a {x}",
+        Default::default(),
+        Duration::from_millis(20),
+    )
+    .unwrap();
+    let runner = db.clone();
+    let endless = thread::spawn(move || {
+        runner.run_script(
+            "r[x] := x = 0; r[y] := r[x], y = x + 1 ?[x] := r[x]",
+            Default::default(),
+        )
+    });
+    thread::sleep(Duration::from_millis(100));
+
+    db.close(Duration::from_millis(50)).unwrap();
+    assert!(endless.join().unwrap().is_err());
+    assert_eq!(db.metrics().scheduled_scripts, 0);
+    assert!(db.run_script("?[x] <- [[1]]", Default::default()).is_err());
+    assert!(db.run_script("?[x] := *a{x}", Default::default()).is_err());
+    db.close(Duration::ZERO).unwrap();
+}
+
+#[test]
+fn test_integrity_check() {
+    let db = new_cozo_mem().unwrap();
+    for script in [
+        "?[k, v] <- [[1, 10], [2, 20], [3, 30]] :create a {k => v}",
+        "::index create a:by_v {v}",
+        ":create b {k}",
+        "::index create b:by_k {k}",
+    ] {
+        db.run_script(script, Default::default()).unwrap();
+    }
+    let check = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(check("::integrity_check"), json!([]));
+
+    {
+        let mut tx = db.transact_write().unwrap();
+        let idx = tx.get_relation("a:by_v", false).unwrap();
+        let missing = vec![DataValue::from(20), DataValue::from(2)];
+        tx.store_tx
+            .del(
+                &idx.encode_key_for_store(&missing, Default::default())
+                    .unwrap(),
+            )
+            .unwrap();
+        // one without a row in the relation, and one not matching its row
+        for stale in [
+            vec![DataValue::from(40), DataValue::from(4)],
+            vec![DataValue::from(99), DataValue::from(3)],
+        ] {
+            tx.store_tx
+                .put(
+                    &idx.encode_key_for_store(&stale, Default::default())
+                        .unwrap(),
+                    &[],
+                )
+                .unwrap();
+        }
+        let mut b = tx.get_relation("b", false).unwrap();
+        b.indices.clear();
+        tx.store_tx
+            .put(
+                &vec![DataValue::from("b")].encode_as_key(RelationId::SYSTEM),
+                &rmp_serde::to_vec_named(&b).unwrap(),
+            )
+            .unwrap();
+        tx.store_tx
+            .put(
+                &vec![DataValue::Null].encode_as_key(RelationId::SYSTEM),
+                &RelationId::new(1).raw_encode(),
+            )
+            .unwrap();
+        tx.commit_tx().unwrap();
+    }
+
+    assert_eq!(
+        check("::integrity_check"),
+        json!([
+            ["stale_index_rows", "a:by_v", 2, false],
+            ["missing_index_rows", "a:by_v", 1, false],
+            ["dangling_index", "b:by_k", 1, false],
+            ["id_counter", "", 3, false]
+        ])
+    );
+    assert_eq!(
+        check("::integrity_check repair").as_array().unwrap().len(),
+        4
+    );
+    assert_eq!(check("::integrity_check"), json!([]));
+    assert_eq!(check("?[k] := *a:by_v{v, k}, v > 10"), json!([[2], [3]]));
+    db.run_script(":create c {k}", Default::default()).unwrap();
+    assert_eq!(check("::integrity_check"), json!([]));
+}
+
+#[test]
+fn test_clone_to() {
+    let path = "_test_clone_to.db";
+    let copy_path = "_test_clone_to_copy.db";
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(copy_path);
+    let db = DbInstance::new("sqlite", path, "").unwrap();
+    db.run_script("?[k] <- [[1], [2]] :create a {k}", Default::default())
+        .unwrap();
+    db.clone_to(copy_path).unwrap();
+    assert!(db.clone_to(copy_path).is_err());
+    db.run_script("?[k] <- [[3]] :put a {k}", Default::default())
+        .unwrap();
+
+    let copy = DbInstance::new("sqlite", copy_path, "").unwrap();
+    let rows = |db: &DbInstance| {
+        db.run_script("?[k] := *a{k}", Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(rows(&copy), json!([[1], [2]]));
+    copy.run_script("?[k] <- [[4]] :put a {k}", Default::default())
+        .unwrap();
+    assert_eq!(rows(&db), json!([[1], [2], [3]]));
+    assert!(new_cozo_mem()
+        .unwrap()
+        .clone_to("_test_clone_to_mem")
+        .is_err());
+    drop(copy);
+    drop(db);
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_file(copy_path);
+}
+
+#[test]
+fn test_push() {
+    let path = std::env::temp_dir().join(format!("_test_push_{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let target = DbInstance::new("sqlite", &path, "").unwrap();
+    target
+        .run_script(":create dst {k => v}", Default::default())
+        .unwrap();
+    drop(target);
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    db.run_script(
+        "?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']] :create src {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+    let params = || BTreeMap::from([("path".to_string(), DataValue::from(path.to_str().unwrap()))]);
+    let push = "::push dst to 'sqlite' $path { ?[k, v] := *src[k, v], k < 3 }";
+    let err = db.run_script(push, params()).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::file_access_denied");
+    db.set_script_file_access(true);
+    let res = db.run_script(push, params()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["OK", 2]]));
+    assert!(db
+        .run_script(
+            "::push dst to 'sqlite' $path { ?[k, v] := *src[k, v] :put src {k => v} }",
+            params(),
+        )
+        .is_err());
+
+    let target = DbInstance::new("sqlite", &path, "").unwrap();
+    let rows = |db: &DbInstance| {
+        db.run_script("?[k, v] := *dst[k, v]", Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(rows(&target), json!([[1, "a"], [2, "b"]]));
+    let pushed = db
+        .push_query_result(
+            "?[k, v] := *src[k, v], k > 1",
+            Default::default(),
+            &target,
+            "dst",
+        )
+        .unwrap();
+    assert_eq!(pushed, 2);
+    assert_eq!(rows(&target), json!([[1, "a"], [2, "b"], [3, "c"]]));
+    drop(target);
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_branch() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[k, v] <- [[1, 'a'], [2, 'b']] :create a {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+    let keys = |res: Result<NamedRows, miette::Report>| res.unwrap().into_json()["rows"].clone();
+
+    let branch = db.branch().unwrap();
+    branch
+        .run_script("?[k, v] <- [[3, 'c']] :put a {k => v}", Default::default())
+        .unwrap();
+    branch
+        .run_script("?[k] <- [[1]] :rm a {k}", Default::default())
+        .unwrap();
+    branch
+        .run_script("?[x] <- [[1]] :create b {x}", Default::default())
+        .unwrap();
+    assert_eq!(
+        keys(branch.run_script("?[k] := *a{k}", Default::default())),
+        json!([[2], [3]])
+    );
+    assert_eq!(
+        keys(db.run_script("?[k] := *a{k}", Default::default())),
+        json!([[1], [2]])
+    );
+    assert!(db.run_script("?[x] := *b{x}", Default::default()).is_err());
+
+    // the branch does not see writes to the database made after it was opened
+    db.run_script("?[k, v] <- [[4, 'd']] :put a {k => v}", Default::default())
+        .unwrap();
+    assert_eq!(
+        keys(branch.run_script("?[k] := *a{k}", Default::default())),
+        json!([[2], [3]])
+    );
+
+    db.merge_branch(&branch).unwrap();
+    assert_eq!(
+        keys(db.run_script("?[k] := *a{k}", Default::default())),
+        json!([[2], [3], [4]])
+    );
+    assert_eq!(
+        keys(db.run_script("?[x] := *b{x}", Default::default())),
+        json!([[1]])
+    );
+    db.run_script(":create c {x}", Default::default()).unwrap();
+    assert_eq!(
+        keys(db.run_script("?[x] := *b{x}", Default::default())),
+        json!([[1]])
+    );
+
+    let discarded = db.branch().unwrap();
+    discarded
+        .run_script(":create d {x}", Default::default())
+        .unwrap();
+    discarded
+        .run_script("::remove a", Default::default())
+        .unwrap();
+    assert!(discarded
+        .run_script("?[k] := *a{k}", Default::default())
+        .is_err());
+    drop(discarded);
+    assert!(db.run_script("?[x] := *d{x}", Default::default()).is_err());
+    assert_eq!(
+        keys(db.run_script("?[k] := *a{k}", Default::default())),
+        json!([[2], [3], [4]])
+    );
+
+    let clashing = db.branch().unwrap();
+    clashing
+        .run_script(":create e {x}", Default::default())
+        .unwrap();
+    db.run_script(":create f {x}", Default::default()).unwrap();
+    let err = db.merge_branch(&clashing).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "db::merge_conflict");
+
+    // a change made in the database is not lost to a merge
+    let stale = db.branch().unwrap();
+    stale
+        .run_script("?[k, v] <- [[2, 'x']] :put a {k => v}", Default::default())
+        .unwrap();
+    db.run_script("?[k, v] <- [[2, 'y']] :put a {k => v}", Default::default())
+        .unwrap();
+    let err = db.merge_branch(&stale).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "db::merge_conflict");
+    assert_eq!(
+        keys(db.run_script("?[v] := *a{k: 2, v}", Default::default())),
+        json!([["y"]])
+    );
+}
+
+#[test]
+fn test_export_import_relation_file() {
+    let src = new_cozo_mem().unwrap();
+    src.run_script(
+        ":create rel {k: Int => b: Bytes, a: Any, u: Uuid}",
+        Default::default(),
+    )
+    .unwrap();
+    src.run_script(
+        r#"?[k, b, a, u] <- [[1, decode_base64('AQI='), 1.5, to_uuid('8d8a8b8c-1234-4cde-9f00-000000000001')],
+                          [2, decode_base64('Aw=='), [1, 'x'], to_uuid('8d8a8b8c-1234-4cde-9f00-000000000002')]]
+           :put rel {k => b, a, u}"#,
+        Default::default(),
+    )
+    .unwrap();
+    let expected = src
+        .export_relations(["rel"].iter())
+        .unwrap()
+        .remove("rel")
+        .unwrap();
+
+    let err = src
+        .run_script(
+            "::export_relation rel '_test_denied.jsonl'",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::file_access_denied");
+    assert!(!std::path::Path::new("_test_denied.jsonl").exists());
+    src.set_script_file_access(true);
+
+    for path in [
+        "_test_export_relation.jsonl",
+        "_test_export_relation.msgpack",
+    ] {
+        let _ = std::fs::remove_file(path);
+        src.run_script(
+            "::export_relation rel $path",
+            BTreeMap::from([("path".to_string(), DataValue::from(path))]),
+        )
+        .unwrap();
+        // existing files are not overwritten
+        assert!(src
+            .run_script(
+                "::export_relation rel $path",
+                BTreeMap::from([("path".to_string(), DataValue::from(path))]),
+            )
+            .is_err());
+
+        let dst = new_cozo_mem().unwrap();
+        dst.set_script_file_access(true);
+        dst.run_script(
+            ":create copy {k: Int => b: Bytes, a: Any, u: Uuid}",
+            Default::default(),
+        )
+        .unwrap();
+        dst.run_script(
+            "::import_relation copy $path",
+            BTreeMap::from([("path".to_string(), DataValue::from(path))]),
+        )
+        .unwrap();
+        let imported = dst
+            .export_relations(["copy"].iter())
+            .unwrap()
+            .remove("copy")
+            .unwrap();
+        assert_eq!(imported.headers, expected.headers);
+        assert_eq!(imported.rows, expected.rows);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    assert!(src
+        .run_script(
+            "::import_relation rel '_test_no_such_file.jsonl'",
+            Default::default()
+        )
+        .is_err());
+    let err = src
+        .run_script("::export_relation rel 1", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::bad_file_path");
+}
+
+#[test]
+fn test_relation_file_checksum() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[k, v] := k in [1, 2, 3], v = k * 10 :create rel {k => v}",
+        Default::default(),
+    )
+    .unwrap();
+    db.set_script_file_access(true);
+    let path = "_test_relation_file_checksum.jsonl";
+    let _ = std::fs::remove_file(path);
+    let params = || BTreeMap::from([("path".to_string(), DataValue::from(path))]);
+    db.run_script("::export_relation rel $path", params())
+        .unwrap();
+    let exported = std::fs::read_to_string(path).unwrap();
+    let header: serde_json::Value = serde_json::from_str(exported.lines().next().unwrap()).unwrap();
+    assert_eq!(header["format"], json!("cozo_relation"));
+    assert_eq!(header["version"], json!(1));
+    assert_eq!(header["rows"], json!(3));
+
+    db.run_script(":create copy {k => v}", Default::default())
+        .unwrap();
+    let import_err = |content: &str| {
+        std::fs::write(path, content).unwrap();
+        db.run_script("::import_relation copy $path", params())
+            .unwrap_err()
+            .code()
+            .unwrap()
+            .to_string()
+    };
+    assert_eq!(
+        import_err(&exported.replace("[2,20]", "[2,21]")),
+        "import::checksum_mismatch"
+    );
+    let truncated = exported.lines().take(3).join("\n") + "\n";
+    assert_eq!(import_err(&truncated), "import::bad_relation_file");
+    assert_eq!(
+        import_err(&exported.replace("\"version\":1", "\"version\":99")),
+        "import::unsupported_file_version"
+    );
+    assert_eq!(
+        import_err(&format!("{exported}[4,40]\n")),
+        "import::bad_relation_file"
+    );
+    let res = db
+        .run_script("?[k, v] := *copy{k, v}", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_cached_rule() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[a, b] := a in [1, 2, 3], b = a * 10 :create rel {a => b}",
+        Default::default(),
+    )
+    .unwrap();
+    let rule_names = |script: &str| {
+        db.run_script(&format!("::explain {{ {script} }}"), Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row[2].as_str().unwrap().to_string())
+            .collect::<BTreeSet<_>>()
+    };
+    let body = "?[x, y] := r[1, x], r[y, 30]";
+    // the rule is specialized for each of the two ways it is called
+    let specialized = rule_names(&format!("r[a, b] := *rel{{a, b}} {body}"));
+    assert!(!specialized.contains("r"));
+    let cached = rule_names(&format!("@cached r[a, b] := *rel{{a, b}} {body}"));
+    assert_eq!(cached, BTreeSet::from(["?".to_string(), "r".to_string()]));
+
+    let res = db
+        .run_script(
+            &format!("@cached r[a, b] := *rel{{a, b}} {body}"),
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[10, 3]]));
+    let res = db
+        .run_script(
+            "@cached r[a, b] := *rel{a, b} ?[x] := r[x, _] :limit 1",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.rows.len(), 1);
+
+    let err = db
+        .run_script("@fast r[a] := a = 1 ?[a] := r[a]", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::unknown_rule_annotation"
+    );
+}
+
+#[test]
+fn test_no_magic_rule() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        "?[a, b] := a in [1, 2, 3], b = a * 10 :create rel {a => b}",
+        Default::default(),
+    )
+    .unwrap();
+    let rule_names = |script: &str| {
+        db.run_script(&format!("::explain {{ {script} }}"), Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row[2].as_str().unwrap().to_string())
+            .collect::<BTreeSet<_>>()
+    };
+    let rules = "r[x, y] := x = 1, s[x, y] s[a, b] := *rel{a, b} ?[y] := r[_, y]";
+    // a cached rule still passes its bindings to the rules it applies
+    assert!(rule_names(&format!("@cached {rules}")).contains("s|Mbf"));
+    let no_magic = rule_names(&format!("@no_magic {rules}"));
+    assert!(no_magic.contains("r"));
+    assert!(no_magic.contains("s|Mff"));
+    assert!(!no_magic.contains("s|Mbf"));
+
+    let res = db
+        .run_script(&format!("@no_magic {rules}"), Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[10]]));
+}
+
+#[test]
+fn test_exists() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        {?[a, b] <- [[1, 10], [1, 11], [2, 20], [3, 30]] :create rel {a, b}}
+        {?[a] <- [[1], [2], [4]] :create src {a}}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    let run = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    // each row on the left is kept once, however many witnesses there are
+    assert_eq!(run("?[a] := *src{a}, exists *rel{a}"), json!([[1], [2]]));
+    assert_eq!(
+        run("?[a, c] := *src{a}, c = a * 10, exists *rel{a, b: c}"),
+        json!([[1, 10], [2, 20]])
+    );
+    assert_eq!(
+        run("r[a, b] := *rel{a, b} ?[a] := *src{a}, exists r[a, _]"),
+        json!([[1], [2]])
+    );
+    assert_eq!(run("?[a] := *src{a}, not exists *rel{a}"), json!([[4]]));
+
+    let ops = db
+        .run_script(
+            "::explain { ?[a] := *src{a}, exists *rel{a} }",
+            Default::default(),
+        )
+        .unwrap()
+        .into_json()["rows"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|row| row[4].as_str().unwrap().to_string())
+        .collect_vec();
+    assert!(ops.iter().any(|op| op.contains("semi")));
+
+    let err = db
+        .run_script("?[a] := *src{a}, exists (a = 1)", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::bad_exists");
+    let err = db
+        .run_script("?[a] := *src{a}, exists *rel{b}", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::unbound_exists");
+}
+
+#[test]
+fn test_optional() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        {?[a, b] <- [[1, 10], [1, 11], [2, 20]] :create rel {a, b}}
+        {?[a] <- [[1], [2], [4]] :create src {a}}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    let run = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(
+        run("?[a, b] := *src{a}, optional(*rel{a, b})"),
+        json!([[1, 10], [1, 11], [2, 20], [4, null]])
+    );
+    assert_eq!(
+        run("r[x, y] := *rel{a: x, b: y} ?[a, b] := *src{a}, optional(r[a, b])"),
+        json!([[1, 10], [1, 11], [2, 20], [4, null]])
+    );
+    assert_eq!(
+        run("?[a, b] := *src{a}, optional(*rel[a, b]), a > 1"),
+        json!([[2, 20], [4, null]])
+    );
+    // bindings shared with the rest of the body are join keys, not padded
+    assert_eq!(
+        run("?[a, b] := *src{a}, b = 20, optional(*rel{a, b})"),
+        json!([[1, 20], [2, 20], [4, 20]])
+    );
+    let err = db
+        .run_script("?[a] := *src{a}, optional(a > 1)", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::bad_optional");
+}
+
+#[test]
+fn test_rule_arity_conflict() {
+    let db = new_cozo_mem().unwrap();
+    let script = "r[a, b] := a = 1, b = 2 r[a, b] := a = 3, b = 4 ?[a] := r[a, _], not r[a]";
+    let err = db.run_script(script, Default::default()).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::rule_arity_mismatch");
+    assert_eq!(
+        err.help().unwrap().to_string(),
+        "Rule r is defined with arity 2"
+    );
+    let labels = err
+        .labels()
+        .unwrap()
+        .map(|l| (l.label().unwrap().to_string(), l.offset()))
+        .collect_vec();
+    assert_eq!(
+        labels,
+        vec![
+            ("defined with arity 2".to_string(), 2),
+            ("defined with arity 2".to_string(), 26),
+            ("applied to 2 arguments".to_string(), 56),
+            ("applied to 1 arguments".to_string(), 69),
+        ]
+    );
+}
+
+#[test]
+fn test_data_table() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[] <- | id: Int | name   | score: Float? |
+                   | 1       | 'alice' | 1            |
+                   | 1 + 1   | 'bob'   | null         |
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.headers, vec!["id", "name", "score"]);
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "alice", 1.0], [2, "bob", null]])
+    );
+
+    let res = db
+        .run_script(
+            "r[a, b] <- | x | y | | 1 | 2 | | 3 | 4 | ?[b] := r[_, b]",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [4]]));
+    let res = db
+        .run_script("?[a] <- | a: Int |", Default::default())
+        .unwrap();
+    assert!(res.rows.is_empty());
+
+    let err = db
+        .run_script("?[] <- | a | b | | 1 |", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::data_table_row_width"
+    );
+    assert!(db
+        .run_script("?[] <- | a: Int | | 'x' |", Default::default())
+        .is_err());
+    let err = db
+        .run_script("?[] <- | a | | b |", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::data_table_cell_not_constant"
+    );
+
+    assert_eq!(
+        crate::format_script("?[] <- |id: Int|name| |1|'a'|").unwrap(),
+        "?[] <-\n    | id: Int | name |\n    | 1 | 'a' |"
+    );
+}
+
+#[test]
+fn test_sample_entities() {
+    let db = new_cozo_mem().unwrap();
+    // entity ids are usually random, like UUIDs: scatter them over the key space
+    let ids = (0..1000u32)
+        .map(|i| format!("{:08x}", i.wrapping_mul(2654435761)))
+        .collect_vec();
+    let data = ids
+        .iter()
+        .enumerate()
+        .map(|(i, id)| json!([id, if i % 3 == 0 { "a" } else { "b" }]))
+        .collect_vec();
+    db.run_script(
+        "?[id, kind] <- $data :create ent {id => kind}",
+        BTreeMap::from([("data".to_string(), json!(data).into())]),
+    )
+    .unwrap();
+    let sample = |script: &str| db.run_script(script, Default::default()).unwrap();
+    let sampled_ids = |res: &NamedRows| {
+        res.rows
+            .iter()
+            .map(|row| row[0].get_str().unwrap().to_string())
+            .collect_vec()
+    };
+
+    let res = sample("::sample_entities ent 20 42");
+    assert_eq!(res.headers, vec!["id"]);
+    assert_eq!(res.rows.len(), 20);
+    assert_eq!(res.rows.iter().unique().count(), 20);
+    assert_eq!(res.rows, sample("::sample_entities ent 20 42").rows);
+    assert_ne!(res.rows, sample("::sample_entities ent 20 7").rows);
+    // the sample is spread over the whole key range
+    let median = ids.iter().sorted().nth(500).unwrap();
+    assert!(sampled_ids(&res).iter().any(|id| id < median));
+    assert!(sampled_ids(&res).iter().any(|id| id >= median));
+
+    let res = sample("::sample_entities ent {kind: 'a'} 20 42");
+    assert_eq!(res.rows.len(), 20);
+    let kind_a = ids.iter().step_by(3).collect::<BTreeSet<_>>();
+    assert!(sampled_ids(&res).iter().all(|id| kind_a.contains(id)));
+    let res = sample(&format!("::sample_entities ent {{id: '{}'}} 20 42", ids[5]));
+    assert_eq!(res.into_json()["rows"], json!([[ids[5]]]));
+    let res = sample("::sample_entities ent {kind: 'c'} 20 42");
+    assert!(res.rows.is_empty());
+
+    db.run_script(":create empty {a}", Default::default())
+        .unwrap();
+    assert!(sample("::sample_entities empty 5 1").rows.is_empty());
+    let err = db
+        .run_script("::sample_entities ent {nope: 1} 5 1", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::sample_column_not_found"
+    );
+}
+
+#[test]
+fn test_analyze() {
+    let db = new_cozo_mem().unwrap();
+    let data = (0..100)
+        .map(|id| {
+            json!([
+                id,
+                id % 5,
+                if id % 4 == 0 { json!(null) } else { json!("x") }
+            ])
+        })
+        .collect_vec();
+    db.run_script(
+        "?[id, g, s] <- $data :create r {id => g, s}",
+        BTreeMap::from([("data".to_string(), json!(data).into())]),
+    )
+    .unwrap();
+    let err = db.run_script("::stats r", Default::default()).unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::stats_not_found");
+
+    let res = db.run_script("::analyze r", Default::default()).unwrap();
+    assert_eq!(
+        res.headers,
+        vec![
+            "column",
+            "rows",
+            "distinct",
+            "nulls",
+            "min",
+            "max",
+            "histogram"
+        ]
+    );
+    let res = res.into_json()["rows"].clone();
+    assert_eq!(res[0][0], json!("id"));
+    assert_eq!(res[0][1], json!(100));
+    assert!((95..=100).contains(&res[0][2].as_i64().unwrap()));
+    assert_eq!(res[0][4], json!(0));
+    assert_eq!(res[0][5], json!(99));
+    assert_eq!(res[0][6].as_array().unwrap().len(), 11);
+    assert_eq!(res[1][2], json!(5));
+    assert_eq!(res[1][6], json!([0, 1, 2, 3, 4]));
+    assert_eq!(res[2][2], json!(1));
+    assert_eq!(res[2][3], json!(25));
+    assert_eq!(
+        db.run_script("::stats r", Default::default())
+            .unwrap()
+            .into_json()["rows"],
+        res
+    );
+
+    db.run_script("::rename r -> s", Default::default())
+        .unwrap();
+    assert_eq!(
+        db.run_script("::stats s", Default::default())
+            .unwrap()
+            .into_json()["rows"],
+        res
+    );
+    db.run_script("::remove s", Default::default()).unwrap();
+    db.run_script(":create s {a}", Default::default()).unwrap();
+    assert!(db.run_script("::stats s", Default::default()).is_err());
+    let res = db.run_script("::analyze s", Default::default()).unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["a", 0, 0, 0, null, null, []]])
+    );
+}
+
+#[test]
+fn test_index_hints() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r"
+        {:create r {a => b}}
+        {?[a, b] <- [[1, 'x'], [2, 'y'], [3, 'x']] :put r {a => b}}
+        ",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::index create r:by_b {b}", Default::default())
+        .unwrap();
+    let explain = |script: &str| {
+        let res = db
+            .run_script(&format!("::explain {{ {script} }}"), Default::default())
+            .unwrap();
+        assert_eq!(res.headers.last().unwrap(), "hint");
+        res.into_json()["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| (row[5].clone(), row[9].clone()))
+            .filter(|(rel, _)| rel.as_str().is_some_and(|rel| rel.starts_with(':')))
+            .collect_vec()
+    };
+    let run = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+
+    let script = "?[a] := *r{a, b: 'x'}";
+    assert_eq!(explain(script), vec![(json!(":r:by_b"), json!(null))]);
+    let script = "?[a] := *r{a, b: 'x'} @no_index";
+    assert_eq!(explain(script), vec![(json!(":r"), json!("@no_index"))]);
+    assert_eq!(run(script), json!([[1], [3]]));
+    let script = "?[a, b] := *r[a, b] @use_index(by_b)";
+    assert_eq!(
+        explain(script),
+        vec![(json!(":r:by_b"), json!("@use_index(by_b)"))]
+    );
+    assert_eq!(run(script), json!([[1, "x"], [2, "y"], [3, "x"]]));
+    assert_eq!(
+        run("?[a] := a in [1, 2], not *r{a, b: 'y'} @no_index"),
+        json!([[1]])
+    );
+
+    let err = db
+        .run_script("?[a] := *r{a} @use_index(nope)", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::bad_index_hint");
+    let err = db
+        .run_script("?[a] := *r{a} @broadcast", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::bad_atom_hint");
+    // an annotation of the following rule is not taken for a hint
+    assert_eq!(
+        run("s[a] := *r{a}\n@no_magic\n?[a] := s[a], a > 2"),
+        json!([[3]])
+    );
+    assert_eq!(
+        crate::format_script("?[a] := *r{a} @use_index(by_b)").unwrap(),
+        "?[a] := *r{a} @use_index(by_b)"
+    );
+}
+
+#[test]
+fn test_query_builder() {
+    use crate::builder::{Atom, QueryBuilder, RuleBuilder, Term, WriteOp};
+
+    let db = new_cozo_mem().unwrap();
+    let run = |query: QueryBuilder| {
+        let (script, params) = query.build().unwrap();
+        db.run_script(&script, params).unwrap().into_json()["rows"].clone()
+    };
+    let people = RuleBuilder::constant("people", ["name", "dept"])
+        .row([DataValue::from("alice"), DataValue::from("eng")])
+        .row([DataValue::from("bob"), DataValue::from("eng")])
+        // values never become part of the script
+        .row([DataValue::from("'] := x\""), DataValue::from("ops")]);
+    run(QueryBuilder::new()
+        .rule(people)
+        .rule(
+            RuleBuilder::entry(["name", "dept"])
+                .atom(Atom::rule("people", [Term::var("name"), Term::var("dept")])),
+        )
+        .write(WriteOp::Create, "person", ["name"], ["dept"]));
+
+    let res = run(QueryBuilder::new()
+        .rule(
+            RuleBuilder::entry(["dept"])
+                .aggr("count", "name")
+                .atom(Atom::stored(
+                    "person",
+                    [("name", Term::var("name")), ("dept", Term::var("dept"))],
+                )),
+        )
+        .order_by("dept", true));
+    assert_eq!(res, json!([["ops", 1], ["eng", 2]]));
+
+    let res = run(QueryBuilder::new()
+        .rule(
+            RuleBuilder::entry(["name"])
+                .atom(Atom::stored("person", [("name", Term::var("name"))]))
+                .atom(Atom::negated(Atom::stored(
+                    "person",
+                    [("name", Term::var("name")), ("dept", Term::value("ops"))],
+                )))
+                .atom(Atom::filter(Term::var("name").ne(Term::value("bob")))),
+        )
+        .limit(5));
+    assert_eq!(res, json!([["alice"]]));
+
+    let err = QueryBuilder::new()
+        .rule(RuleBuilder::entry(["a b"]).atom(Atom::unify("a", Term::value(1))))
+        .build()
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "builder::bad_ident");
+    let err = QueryBuilder::new()
+        .rule(RuleBuilder::entry(["a"]))
+        .build()
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "builder::empty_rule");
+}
+
+#[test]
+fn test_generate_rust_types() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        ":create shop.order_line {id: Int, type: String => qty: Int?, tags: [String], at: (Float, Any)}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(":create counter {k: String => v: Any}", Default::default())
+        .unwrap();
+    db.run_script(
+        "::index create shop.order_line:by_qty {qty}",
+        Default::default(),
+    )
+    .unwrap();
+    let code = db.generate_rust_types().unwrap();
+    assert!(code.contains("pub struct Counter {"));
+    assert!(code.contains("pub mod shop {\n    /// Row of the stored relation `shop.order_line`"));
+    assert!(code.contains("    pub struct OrderLine {"));
+    assert!(code.contains("        #[serde(rename = \"type\")]\n        pub type_: String,"));
+    assert!(code.contains("        #[serde(default)]\n        pub qty: Option<i64>,"));
+    assert!(code.contains("        pub tags: Vec<String>,"));
+    assert!(code.contains("        pub at: (f64, serde_json::Value),"));
+    assert!(code.contains("        pub const RELATION: &'static str = \"shop.order_line\";"));
+    assert!(code.contains("        pub const QTY: &'static str = \"qty\";"));
+    assert!(!code.contains("by_qty"));
+
+    #[derive(serde_derive::Deserialize, Debug, PartialEq)]
+    struct OrderLine {
+        id: i64,
+        #[serde(rename = "type")]
+        type_: String,
+        #[serde(default)]
+        qty: Option<i64>,
+    }
+    let res = db
+        .run_script("?[id, type] <- [[1, 'a'], [2, 'b']]", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.deserialize_rows::<OrderLine>().unwrap(),
+        vec![
+            OrderLine {
+                id: 1,
+                type_: "a".to_string(),
+                qty: None
+            },
+            OrderLine {
+                id: 2,
+                type_: "b".to_string(),
+                qty: None
+            }
+        ]
+    );
+    assert!(res.deserialize_rows::<(i64, String)>().is_err());
+}
+
+#[test]
+fn test_row_ttl() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        ":create events {id: Int => payload: String, expires_at: Float?}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::index create events:by_payload {payload}",
+        Default::default(),
+    )
+    .unwrap();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64();
+    db.run_script(
+        "?[id, payload, expires_at] <- $data :put events {id => payload, expires_at}",
+        BTreeMap::from([(
+            "data".to_string(),
+            json!([[1, "a", now - 10.], [2, "b", now + 3600.], [3, "a", null]]).into(),
+        )]),
+    )
+    .unwrap();
+    let err = db
+        .run_script("::set_ttl events payload", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::bad_ttl");
+    db.run_script("::set_ttl events expires_at", Default::default())
+        .unwrap();
+
+    let rows = |script: &str| {
+        db.run_script(script, Default::default())
+            .unwrap()
+            .into_json()["rows"]
+            .clone()
+    };
+    assert_eq!(rows("?[id] := *events{id}"), json!([[2], [3]]));
+    assert_eq!(rows("?[p] := *events{id: 1, payload: p}"), json!([]));
+    assert_eq!(
+        rows("?[x] := x in [1, 2], not *events{id: x}"),
+        json!([[1]])
+    );
+    // the index does not hold the expiry column, so the relation is joined
+    assert_eq!(rows("?[id] := *events{payload: 'a', id}"), json!([[3]]));
+
+    assert_eq!(rows("::sweep_expired events"), json!([[1]]));
+    db.run_script("::set_ttl events", Default::default())
+        .unwrap();
+    assert_eq!(rows("?[id] := *events{id}"), json!([[2], [3]]));
+    assert_eq!(rows("?[id] := *events:by_payload{id}"), json!([[2], [3]]));
+    let err = db
+        .run_script("::sweep_expired events", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::no_ttl");
+}
+
+#[test]
+fn test_storage_stats() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r"
+        ?[k, v] <- [[1, 'a'], [2, 'b'], [3, 'c']]
+        :create t {k => v}
+        ",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::index create t:by_v {v}", Default::default())
+        .unwrap();
+    let res = db
+        .run_script("::storage_stats", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.headers,
+        ["relation", "keys", "bytes", "disk_bytes", "tombstone_ratio"]
+    );
+    let rows = res.into_json()["rows"].clone();
+    let rows = rows.as_array().unwrap();
+    assert_eq!(rows.len(), 2);
+    for (row, name) in rows.iter().zip(["t", "t:by_v"]) {
+        assert_eq!(row[0], json!(name));
+        assert_eq!(row[1], json!(3));
+        assert!(row[2].as_i64().unwrap() > 0);
+        // the memory engine makes no estimates
+        assert_eq!(row[3], json!(null));
+        assert_eq!(row[4], json!(null));
+    }
+}
+
+#[test]
+fn test_run_script_msgpack() {
+    use crate::data::msgpack::MsgpackValue;
+
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let request = MsgpackValue::Map(vec![
+        (
+            MsgpackValue::from("script"),
+            MsgpackValue::from("?[a, b, c] := a = $i, b = $f, c = $b"),
+        ),
+        (
+            MsgpackValue::from("params"),
+            MsgpackValue::Map(vec![
+                (MsgpackValue::from("i"), MsgpackValue::from(1)),
+                (MsgpackValue::from("f"), MsgpackValue::F64(1.0)),
+                (MsgpackValue::from("b"), MsgpackValue::Binary(vec![1, 2])),
+            ]),
+        ),
+    ]);
+    let mut buf = vec![];
+    rmpv::encode::write_value(&mut buf, &request).unwrap();
+    let res = rmpv::decode::read_value(&mut &db.run_script_msgpack(&buf)[..]).unwrap();
+    assert_eq!(res["ok"], MsgpackValue::Boolean(true));
+    assert_eq!(res["headers"][0], MsgpackValue::from("a"));
+    let row = &res["rows"][0];
+    assert_eq!(row[0], MsgpackValue::from(1));
+    assert_eq!(row[1], MsgpackValue::F64(1.0));
+    assert_eq!(row[2], MsgpackValue::Binary(vec![1, 2]));
+
+    let bad_script = MsgpackValue::Map(vec![(
+        MsgpackValue::from("script"),
+        MsgpackValue::from("?[a] := a = $missing"),
+    )]);
+    let mut buf = vec![];
+    rmpv::encode::write_value(&mut buf, &bad_script).unwrap();
+    let res = rmpv::decode::read_value(&mut &db.run_script_msgpack(&buf)[..]).unwrap();
+    assert_eq!(res["ok"], MsgpackValue::Boolean(false));
+    assert!(res["display"].is_str());
+
+    let res = rmpv::decode::read_value(&mut &db.run_script_msgpack(&[0xc1])[..]).unwrap();
+    assert_eq!(res["ok"], MsgpackValue::Boolean(false));
+    assert_eq!(
+        res["message"],
+        MsgpackValue::from("request is not a msgpack map")
+    );
+}
+
+#[test]
+fn test_bigint_and_decimal_columns() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        :create prices {item: String => price: Decimal, units: BigInt}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        r#"
+        ?[item, price, units] <- [['a', '19.99', '100000000000000000000'],
+                                  ['b', 0.1, 3],
+                                  ['c', '-2.50', -7]]
+        :put prices {item => price, units}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[item, price, units, total] := *prices{item, price, units},
+                                            total = price * units
+            :order price
+            "#,
+            Default::default(),
+        )
+        .unwrap()
+        .into_json();
+    assert_eq!(
+        res["rows"],
+        json!([
+            ["c", "-2.5", "-7", "17.5"],
+            ["b", "0.1", "3", "0.3"],
+            [
+                "a",
+                "19.99",
+                "100000000000000000000",
+                "1999000000000000000000"
+            ]
+        ])
+    );
+    assert!(db
+        .run_script(
+            "?[item, price, units] <- [['d', 'abc', 1]] :put prices {item => price, units}",
+            Default::default(),
+        )
+        .is_err());
+}
+
+#[test]
+fn test_deprecated_column() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(
+        r#"
+        :create users {id: Int => name: String, email: String? default null,
+                                 contact: String? default null}
+        "#,
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[id, name, email] <- [[1, 'a', 'a@x']] :put users {id => name, email}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::deprecate users email -> contact", Default::default())
+        .unwrap();
+    let cols = db
+        .run_script("::columns users", Default::default())
+        .unwrap()
+        .into_json();
+    assert_eq!(cols["rows"][2][5], json!(true));
+    assert_eq!(cols["rows"][2][6], json!("contact"));
+    assert_eq!(cols["rows"][1][5], json!(false));
+
+    let err = db
+        .run_script(
+            "?[id, name, email] <- [[2, 'b', 'b@x']] :put users {id => name, email}",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::deprecated_column");
+    assert!(err.help().unwrap().to_string().contains("contact"));
+    db.run_script(
+        "?[id, name, contact] <- [[2, 'b', 'b@x']] :put users {id => name, contact}",
+        Default::default(),
+    )
+    .unwrap();
+    let res = db
+        .run_script("?[id, email] := *users{id, email}", Default::default())
+        .unwrap()
+        .into_json();
+    assert_eq!(res["rows"], json!([[1, "a@x"], [2, null]]));
+
+    for bad in [
+        "::deprecate users id",
+        "::deprecate users name",
+        "::deprecate users email -> nope",
+    ] {
+        let err = db.run_script(bad, Default::default()).unwrap_err();
+        assert_eq!(err.code().unwrap().to_string(), "eval::bad_deprecation");
+    }
+
+    db.run_script("::undeprecate users email", Default::default())
+        .unwrap();
+    db.run_script(
+        "?[id, name, email] <- [[3, 'c', 'c@x']] :put users {id => name, email}",
+        Default::default(),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_max_depth_rule() {
+    let db = new_cozo_mem().unwrap();
+    let counting = "r[x] := x = 0 r[y] := r[x], y = x + 1 ?[x] := r[x]";
+    let res = db
+        .run_script(&format!("@max_depth(5) {counting}"), Default::default())
+        .unwrap();
+    assert!(res.truncated);
+    assert_eq!(res.into_json()["rows"], json!([[0], [1], [2], [3], [4]]));
+    // results are marked truncated even if stored
+    db.run_script(":create counted {x}", Default::default())
+        .unwrap();
+    let res = db
+        .run_script(
+            &format!("@max_depth(5) {counting} :put counted {{x}}"),
+            Default::default(),
+        )
+        .unwrap();
+    assert!(res.truncated);
+    let err = db
+        .run_script(
+            &format!("@strict_max_depth(5) {counting}"),
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::max_depth_exceeded");
+
+    // rules converging within their depth are not affected
+    let res = db
+        .run_script(
+            r#"
+            e[a, b] <- [[1, 2], [2, 3], [3, 1]]
+            @strict_max_depth(4)
+            reach[a, b] := e[a, b]
+            reach[a, b] := reach[a, c], e[c, b]
+            ?[b] := reach[1, b]
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2], [3]]));
+    // reaching the fixpoint exactly at the depth is not a truncation
+    let res = db
+        .run_script(
+            r#"
+            e[a, b] <- [[1, 2], [2, 3], [3, 1]]
+            @max_depth(3)
+            reach[a, b] := e[a, b]
+            reach[a, b] := reach[a, c], e[c, b]
+            ?[b] := reach[1, b]
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert!(!res.truncated);
+    assert_eq!(res.into_json()["rows"], json!([[1], [2], [3]]));
+
+    for bad in [
+        "@max_depth r[x] := x = 0 ?[x] := r[x]",
+        "@max_depth(0) r[x] := x = 0 ?[x] := r[x]",
+        "@cached(3) r[x] := x = 0 ?[x] := r[x]",
+    ] {
+        let err = db.run_script(bad, Default::default()).unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "parser::bad_rule_annotation_args"
+        );
+    }
+}
+
+#[test]
+fn test_cancel_token() {
+    let db = DbInstance::new("mem", "", "").unwrap();
+    let token = CancelToken::new();
+    let res = db
+        .run_script_cancellable("?[a] <- [[1]]", Default::default(), &token)
+        .unwrap();
+    assert_eq!(res.rows, vec![vec![DataValue::from(1)]]);
+
+    let runner = {
+        let db = db.clone();
+        let token = token.clone();
+        thread::spawn(move || {
+            db.run_script_cancellable(
+                "r[x] := x = 0 r[y] := r[x], y = x + 1 ?[x] := r[x]",
+                Default::default(),
+                &token,
+            )
+        })
+    };
+    thread::sleep(Duration::from_millis(100));
+    token.cancel();
+    let err = runner.join().unwrap().unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+
+    // a cancelled token kills the queries started afterwards as well
+    let err = db
+        .run_script_cancellable(
+            "{?[a] <- [[1]]} {?[a] <- [[2]]}",
+            Default::default(),
+            &token,
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::killed");
+}
+
+#[test]
+fn test_since_last() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create events {source, seq: Int => v}", Default::default())
+        .unwrap();
+    db.run_script("::index create events:by_v {v}", Default::default())
+        .unwrap();
+    db.run_script("::relation_kind append_only events", Default::default())
+        .unwrap();
+    db.run_script(":create sources {source => label}", Default::default())
+        .unwrap();
+    db.run_script(
+        "?[source, label] <- [['a', 'A'], ['b', 'B']] :put sources {source => label}",
+        Default::default(),
+    )
+    .unwrap();
+    let append = |rows: &str| {
+        db.run_script(
+            &format!("?[source, v] <- {rows} :put events {{source => v}}"),
+            Default::default(),
+        )
+        .unwrap();
+    };
+    let query = "?[label, v] := *events{source, v}, *sources{source, label} :since_last etl events";
+
+    append("[['a', 1], ['b', 2]]");
+    let res = db.run_script(query, Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["A", 1], ["B", 2]]));
+    // nothing new
+    let res = db.run_script(query, Default::default()).unwrap();
+    assert!(res.rows.is_empty());
+
+    append("[['b', 3]]");
+    let res = db.run_script(query, Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["B", 3]]));
+    // the seen rows are recorded per name
+    let res = db
+        .run_script(
+            "?[v] := *events{v} :since_last other events",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1], [2], [3]]));
+    // also when reading through an index
+    append("[['a', 4]]");
+    let res = db
+        .run_script(
+            "?[v] := *events:by_v{v} :since_last other events",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[4]]));
+
+    // only the given relation is filtered: new rows join old rows of other
+    // append-only relations, and negated atoms see all rows
+    db.run_script(
+        ":create logins {source, seq: Int => at}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script("::relation_kind append_only logins", Default::default())
+        .unwrap();
+    db.run_script(
+        "?[source, at] <- [['a', 10]] :put logins {source => at}",
+        Default::default(),
+    )
+    .unwrap();
+    let join = "?[v, at] := *events{source, v}, *logins{source, at} :since_last join events";
+    let res = db.run_script(join, Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1, 10], [4, 10]]));
+    append("[['a', 5], ['b', 6]]");
+    let res = db.run_script(join, Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[5, 10]]));
+    let res = db
+        .run_script(
+            "?[v] := *events{source, v}, not *logins{source} :since_last neg events",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2], [3], [6]]));
+    let err = db
+        .run_script(
+            "?[label] := *sources{label} :since_last etl sources",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::since_last_not_append_only"
+    );
+
+    // read-only imperative scripts cannot record what they have seen
+    let err = db
+        .run_script(
+            "{?[v] := *events{v} :since_last etl events}",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::since_last_read_only"
+    );
+}
+
+#[test]
+fn test_derived_attr() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create person {id => first, last}", Default::default())
+        .unwrap();
+    db.run_script(
+        "?[id, first, last] <- [[1, 'Ada', 'Lovelace'], [2, 'Alan', 'Turing']] :put person {id => first, last}",
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "::derive person full_name = concat(first, ' ', last)",
+        Default::default(),
+    )
+    .unwrap();
+
+    let res = db
+        .run_script("?[id, n] := *person{id, full_name: n}", Default::default())
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([[1, "Ada Lovelace"], [2, "Alan Turing"]])
+    );
+    // the columns used by the definition can also be bound by the query
+    let res = db
+        .run_script(
+            "?[id] := *person{id, first: 'Alan', full_name: 'Alan Turing'}",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[2]]));
+    let res = db
+        .run_script(
+            "?[first, n] := *person{first, full_name: n}, n = 'Ada Lovelace'",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["Ada", "Ada Lovelace"]]));
+
+    // derived attributes are not stored, so writes cannot give them
+    assert!(db
+        .run_script(
+            "?[id, full_name] <- [[3, 'x']] :put person {id => full_name}",
+            Default::default(),
+        )
+        .is_err());
+    let err = db
+        .run_script(
+            "?[id] := *person{id}, not *person{id, full_name: 'Ada Lovelace'}",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "eval::derived_attr_not_positive"
+    );
+    let err = db
+        .run_script(
+            "::derive person initial = first(middle)",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::bad_derived_attr");
+
+    db.run_script("::underive person full_name", Default::default())
+        .unwrap();
+    assert!(db
+        .run_script("?[n] := *person{full_name: n}", Default::default())
+        .is_err());
+}
+
+#[test]
+fn test_sort_collation() {
+    let db = new_cozo_mem().unwrap();
+    let data = "[['file10', 'b'], ['file9', 'B'], ['File2', 'a'], ['file010', 'C']]";
+    let res = db
+        .run_script(
+            &format!("?[f, l] <- {data} :order f collate numeric"),
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["File2", "a"],
+            ["file9", "B"],
+            ["file010", "C"],
+            ["file10", "b"]
+        ])
+    );
+    let res = db
+        .run_script(
+            &format!("?[f, l] <- {data} :order -l collate nocase, f"),
+            Default::default(),
+        )
+        .unwrap();
+    // 'b' and 'B' are equal without case, and ordered by the file name
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["file010", "C"],
+            ["file10", "b"],
+            ["file9", "B"],
+            ["File2", "a"]
+        ])
+    );
+    let err = db
+        .run_script(
+            &format!("?[f, l] <- {data} :order f collate klingon"),
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::unknown_collation");
+}
+
+#[test]
+fn test_sort_by_expr() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+            ?[name, score, weight] <- [['a', 3, 1], ['b', 1, 5], ['c', 2, 2], ['d', 4, 1]]
+            :order -(score * weight), name
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([["b", 1, 5], ["c", 2, 2], ["d", 4, 1], ["a", 3, 1]])
+    );
+
+    // the computed keys are those ranked by windows, and are not returned
+    let res = db
+        .run_script(
+            r#"
+            ?[name, score, weight] <- [['a', 3, 1], ['b', 1, 5], ['c', 2, 2], ['d', 4, 1]]
+            :order -(score * weight)
+            :window r = rank()
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(
+        res.into_json()["rows"],
+        json!([
+            ["b", 1, 5, 1],
+            ["c", 2, 2, 2],
+            ["d", 4, 1, 2],
+            ["a", 3, 1, 4]
+        ])
+    );
+
+    let err = db
+        .run_script(
+            "?[name] <- [['a']] :order (name ++ suffix)",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::sort_key_not_found"
+    );
+}
+
+#[test]
+fn test_secondary_outputs() {
+    let db = new_cozo_mem().unwrap();
+    db.run_script(":create stats {kind => n}", Default::default())
+        .unwrap();
+    let res = db
+        .run_script(
+            r#"
+            events[kind, v] <- [['ok', 1], ['ok', 2], ['error', 3], ['error', -1]]
+            stats[kind, count(v)] := events[kind, v]
+            errors[v] := events['error', v]
+            ?[kind, n] := stats[kind, n], n > 1
+            :put stats {kind => n = count(v)} <- stats
+            :create errors <- errors
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["error", 2], ["ok", 2]]));
+    let res = db
+        .run_script("?[kind, n] := *stats{kind, n}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["error", 2], ["ok", 2]]));
+    let res = db
+        .run_script("?[v] := *errors{v}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[-1], [3]]));
+
+    // the output rules are evaluated in full even if the query takes only a few rows
+    let res = db
+        .run_script(
+            r#"
+            nums[x] := x in [1, 2, 3, 4]
+            ?[x] := nums[x]
+            :limit 1
+            :replace nums {x} <- nums
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+    let res = db
+        .run_script("?[count(x)] := *nums{x}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[4]]));
+
+    let err = db
+        .run_script("?[x] := x = 1 :put stats <- missing", Default::default())
+        .unwrap_err();
+    assert_eq!(
+        err.code().unwrap().to_string(),
+        "parser::output_rule_not_found"
+    );
+    for script in [
+        "r[x] := x = 1 ?[x] := x = 1 :put nums {x} :put nums {x} <- r",
+        "r[x] := x = 1 ?[x] := x = 1 :put stats {x} <- r :rm stats {x} <- r",
+    ] {
+        let err = db.run_script(script, Default::default()).unwrap_err();
+        assert_eq!(
+            err.code().unwrap().to_string(),
+            "parser::duplicate_write_target"
+        );
+    }
+}
+
+#[test]
+fn test_imperative_read_your_writes() {
+    let db = new_cozo_mem().unwrap();
+
+    // schema, writes and queries in one script, run in order in one transaction
+    let res = db
+        .run_script(
+            r#"
+            { :create kv {k => v} }
+            { ?[k, v] <- [[1, 'a'], [2, 'b']] :put kv {k => v} }
+            { ?[k] <- [[2]] :rm kv {k} }
+            %return { ?[k, v] := *kv{k, v} } { ?[count(k)] := *kv{k} }
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    let results = res.flatten();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0].clone().into_json()["rows"], json!([[1, "a"]]));
+    assert_eq!(results[1].clone().into_json()["rows"], json!([[1]]));
+
+    // a failing statement rolls back the writes of the earlier ones
+    let err = db
+        .run_script(
+            r#"
+            { ?[k, v] <- [[3, 'c']] :put kv {k => v} }
+            { ?[k] := *kv{k}, k == 3 :assert none }
+            "#,
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::assert_none_failure");
+    let res = db.run_script("?[k] := *kv{k}", Default::default()).unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[1]]));
+}
+
+#[test]
+fn test_estimate() {
+    let db = new_cozo_mem().unwrap();
+    let rows = (0..100).map(|i| format!("[{i}, '{i}']")).join(", ");
+    db.run_script(
+        &format!("?[id, name] <- [{rows}] :create people {{id => name}}"),
+        Default::default(),
+    )
+    .unwrap();
+    let estimate = |script: &str| -> i64 {
+        let res = db.run_script(script, Default::default()).unwrap();
+        let last = res.rows.last().unwrap();
+        assert_eq!(last[3], DataValue::from("result"));
+        last[4].get_int().unwrap()
+    };
+
+    // without statistics, a default size is assumed
+    assert_eq!(estimate("::estimate { ?[name] := *people{name} }"), 1000);
+
+    db.run_script("::analyze people", Default::default())
+        .unwrap();
+    assert_eq!(estimate("::estimate { ?[name] := *people{name} }"), 100);
+    assert_eq!(
+        estimate("::estimate { ?[name] := *people{name} :limit 10 }"),
+        10
+    );
+    assert_eq!(
+        estimate("::estimate { ?[a, b] := *people{id: a}, *people{id: b} }"),
+        10000
+    );
+    let joined =
+        estimate("::estimate { ?[n1, n2] := *people{id, name: n1}, *people{id, name: n2} }");
+    assert!((50..=200).contains(&joined), "{joined}");
+
+    // nothing is run
+    let res = db
+        .run_script(
+            "::estimate { ?[id, name] <- [[1000, 'x']] :put people {id => name} }",
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.headers[4], "est_rows");
+    let res = db
+        .run_script("?[count(id)] := *people{id}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[100]]));
+}
+
+#[test]
+fn test_safe_mode() {
+    let db = new_cozo_mem().unwrap();
+    let rows = (0..100).map(|i| format!("[{i}, '{i}']")).join(", ");
+    db.run_script(
+        &format!("?[id, name] <- [{rows}] :create people {{id => name}}"),
+        Default::default(),
+    )
+    .unwrap();
+    db.run_script(
+        "?[name] <- [['red'], ['green'], ['blue']] :create colors {name}",
+        Default::default(),
+    )
+    .unwrap();
+    let session = db.new_session().with_safe_mode(10);
+
+    let res = session
+        .run_script("?[name] := *people{id: 5, name}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([["5"]]));
+
+    let err = session
+        .run_script("?[name] := *people{name}", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::unsafe_full_scan");
+    db.run_script("::analyze people", Default::default())
+        .unwrap();
+    let err = session
+        .run_script("?[name] := *people{name}", Default::default())
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::unsafe_full_scan");
+    let res = session
+        .run_script(
+            r#"
+            @allow_full_scan
+            ?[count(name)] := *people{name}
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[100]]));
+
+    // small relations can be scanned in full once analyzed
+    db.run_script("::analyze colors", Default::default())
+        .unwrap();
+    let res = session
+        .run_script("?[count(name)] := *colors{name}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[3]]));
+
+    let err = session
+        .run_script(
+            r#"
+            r[a] <- [[1], [2]]
+            s[b] <- [[3]]
+            ?[a, b] := r[a], s[b]
+            "#,
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "eval::unsafe_cross_join");
+
+    // outside of safe mode, nothing is rejected
+    let res = db
+        .run_script("?[count(name)] := *people{name}", Default::default())
+        .unwrap();
+    assert_eq!(res.into_json()["rows"], json!([[100]]));
+}
+
+#[test]
+fn test_partial_on_timeout() {
+    let db = new_cozo_mem().unwrap();
+    let res = db
+        .run_script(
+            r#"
+            r[x] := x = 0
+            r[y] := r[x], y = x + 1
+            ?[x] := r[x]
+            :timeout 0.01
+            :partial_on_timeout
+            "#,
+            Default::default(),
+        )
+        .unwrap();
+    assert!(res.truncated);
+    // the rows derived so far are complete
+    let mut xs = res
+        .rows
+        .iter()
+        .map(|row| row[0].get_int().unwrap())
+        .collect_vec();
+    xs.sort();
+    assert!(!xs.is_empty());
+    assert_eq!(xs, (0..xs.len() as i64).collect_vec());
+    assert_eq!(res.into_json()["truncated"], json!(true));
+
+    let res = db
+        .run_script(
+            "?[x] := x in [1, 2, 3] :timeout 10 :partial_on_timeout",
+            Default::default(),
+        )
+        .unwrap();
+    assert!(!res.truncated);
+    let res = res.into_json();
+    assert_eq!(res["rows"], json!([[1], [2], [3]]));
+    assert_eq!(res.get("truncated"), None);
+
+    let err = db
+        .run_script(
+            "?[x] <- [[1]] :create partial {x} :partial_on_timeout",
+            Default::default(),
+        )
+        .unwrap_err();
+    assert_eq!(err.code().unwrap().to_string(), "parser::partial_write");
+
+    let formatted =
+        crate::format_script("?[x] := x in [1] :partial_on_timeout :limit 1 :timeout 1").unwrap();
+    assert_eq!(
+        formatted,
+        "?[x] := x in [1]\n:limit 1\n:timeout 1\n:partial_on_timeout"
+    );
+    assert_eq!(crate::format_script(&formatted).unwrap(), formatted);
+}
+
+#[test]
+fn test_open_with_blank_options() {
+    let path = "_test_blank_options";
+    let db_kind = std::env::var("COZO_TEST_DB_ENGINE").unwrap_or("mem".to_string());
+    for options in ["", "  \n"] {
+        let _ = std::fs::remove_file(path);
+        let _ = std::fs::remove_dir_all(path);
+        let db = DbInstance::new(&db_kind, path, options).unwrap();
+        db.run_script("?[a] <- [[1]] :create blank {a}", Default::default())
+            .unwrap();
+    }
+    let _ = std::fs::remove_file(path);
+    let _ = std::fs::remove_dir_all(path);
+}
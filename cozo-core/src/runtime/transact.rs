@@ -6,14 +6,20 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
-use std::sync::atomic::{AtomicU32, AtomicU64};
-use std::sync::Arc;
+use std::collections::BTreeSet;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
-use miette::{bail, Result};
+use miette::{bail, Diagnostic, Result};
+use smartstring::{LazyCompact, SmartString};
+use thiserror::Error;
 
 use crate::data::tuple::TupleT;
-use crate::data::value::DataValue;
+use crate::data::value::{DataValue, ValidityTs};
+use crate::runtime::db::CancelToken;
 use crate::runtime::relation::RelationId;
+use crate::runtime::since_last::SinceLast;
+use crate::storage::profiled::StorageCounters;
 use crate::storage::temp::TempTx;
 use crate::storage::StoreTx;
 
@@ -22,8 +28,36 @@ pub struct SessionTx<'a> {
     pub(crate) temp_store_tx: TempTx,
     pub(crate) relation_store_id: Arc<AtomicU64>,
     pub(crate) temp_store_id: AtomicU32,
+    pub(crate) default_validity: Option<ValidityTs>,
+    /// Names of relations known not to exist in this transaction,
+    /// to avoid hitting the storage repeatedly for the same miss
+    pub(crate) missing_relations: Mutex<BTreeSet<SmartString<LazyCompact>>>,
+    /// Number of rows written to or removed from stored relations so far
+    pub(crate) writes: usize,
+    /// Limit of `writes`, zero meaning unlimited
+    pub(crate) max_writes: usize,
+    /// Id of the last write transaction visible to this one, or of this one once committed
+    pub(crate) tx_id: u64,
+    /// Source of the ids of write transactions, `None` for read-only ones
+    pub(crate) tx_id_counter: Option<Arc<AtomicU64>>,
+    /// Counters of the operations `store_tx` performs, enabled for queries given `:profile`
+    pub(crate) storage_counters: Arc<StorageCounters>,
+    /// Token with which the embedder can kill the queries run in this transaction
+    pub(crate) cancel_token: Option<CancelToken>,
+    /// Set while a query given `:since_last` is compiled
+    pub(crate) since_last: Option<SinceLast>,
+    /// Set in safe mode: queries scanning in full stored relations with more rows are rejected
+    pub(crate) max_scan_rows: Option<u64>,
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("The transaction exceeds the limit of {0} rows written")]
+#[diagnostic(code(tx::too_many_writes))]
+#[diagnostic(help(
+    "Split the writes over several transactions, e.g. with `import_relations_chunked`"
+))]
+struct TooManyWrites(usize);
+
 pub const CURRENT_STORAGE_VERSION: [u8; 1] = [0x00];
 
 fn storage_version_key() -> Vec<u8> {
@@ -67,8 +101,20 @@ impl<'a> SessionTx<'a> {
         Ok(ret)
     }
 
+    /// Account for a row written to or removed from a stored relation.
+    pub(crate) fn record_write(&mut self) -> Result<()> {
+        self.writes += 1;
+        if self.max_writes != 0 && self.writes > self.max_writes {
+            bail!(TooManyWrites(self.max_writes))
+        }
+        Ok(())
+    }
+
     pub fn commit_tx(&mut self) -> Result<()> {
         self.store_tx.commit()?;
+        if let Some(counter) = &self.tx_id_counter {
+            self.tx_id = counter.fetch_add(1, Ordering::AcqRel) + 1;
+        }
         Ok(())
     }
 }
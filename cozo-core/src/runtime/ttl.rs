@@ -0,0 +1,74 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use itertools::Itertools;
+use miette::{bail, Diagnostic, Result};
+use smartstring::SmartString;
+use thiserror::Error;
+
+use crate::data::symb::Symbol;
+use crate::data::value::DataValue;
+use crate::parse::SourceSpan;
+use crate::runtime::db::seconds_since_the_epoch;
+use crate::runtime::relation::{is_expired, AccessLevel, InsufficientAccessLevel};
+use crate::{Db, NamedRows, Storage};
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Stored relation {0} has no TTL")]
+#[diagnostic(code(eval::no_ttl))]
+#[diagnostic(help("Set the column holding the expiry time of rows with `::set_ttl {0} <column>`"))]
+struct NoTtl(String, #[label] SourceSpan);
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Delete the rows of a stored relation that have expired by the column set with `::set_ttl`,
+    /// together with their entries in the indices of the relation, returning how many rows
+    /// were deleted. Queries already skip expired rows, so this only reclaims their space.
+    ///
+    /// As for [Db::import_relations], triggers and callbacks are _not_ run for the rows
+    /// deleted. Sweeping periodically is done by scheduling a script such as
+    /// `::sweep_expired events` with [DbInstance::schedule_script](crate::DbInstance::schedule_script).
+    pub(crate) fn sweep_expired(&'s self, rel: &Symbol) -> Result<NamedRows> {
+        let rel_names = [SmartString::from(&rel.name as &str)];
+        let locks = self.obtain_relation_locks(rel_names.iter());
+        let _guards = locks.iter().map(|l| l.read().unwrap()).collect_vec();
+
+        let mut tx = self.transact_write()?;
+        let handle = tx.get_relation(&rel.name, false)?;
+        if handle.access_level < AccessLevel::Protected {
+            bail!(InsufficientAccessLevel(
+                handle.name.to_string(),
+                "sweeping expired rows".to_string(),
+                handle.access_level
+            ));
+        }
+        if handle.ttl_col.is_none() {
+            bail!(NoTtl(handle.name.to_string(), rel.span));
+        }
+        let live_at = handle.clone().read_at(seconds_since_the_epoch()?).live_at;
+        // scanning through the handle itself, as the handle read at a time skips expired rows
+        let expired: Vec<_> = handle
+            .scan_all(&tx)
+            .filter_ok(|tuple| is_expired(live_at, tuple))
+            .try_collect()?;
+        for tuple in &expired {
+            tx.record_write()?;
+            for (idx_rel, extractor) in handle.indices.values() {
+                let idx_tup = idx_rel.index_key_from(extractor, tuple);
+                let encoded = idx_rel.encode_key_for_store(&idx_tup, Default::default())?;
+                tx.store_tx.del(&encoded)?;
+            }
+            let key = handle.encode_key_for_store(tuple, Default::default())?;
+            tx.store_tx.del(&key)?;
+        }
+        tx.commit_tx()?;
+        Ok(NamedRows::new(
+            vec!["removed".to_string()],
+            vec![vec![DataValue::from(expired.len() as i64)]],
+        ))
+    }
+}
@@ -15,6 +15,10 @@ use std::iter::Fuse;
 use std::mem;
 use std::ops::Bound;
 use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Mutex;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::JoinHandle;
 
 use itertools::Itertools;
 use miette::{bail, Result};
@@ -39,6 +43,20 @@ pub fn new_cozo_mem() -> Result<crate::Db<MemStorage>> {
 #[derive(Default, Clone)]
 pub struct MemStorage {
     store: Arc<ShardedLock<BTreeMap<Vec<u8>, Vec<u8>>>>,
+    /// the range deletions still running in the background
+    #[cfg(not(target_arch = "wasm32"))]
+    cleanups: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl MemStorage {
+    /// Wait for the range deletions started so far to complete
+    #[cfg(all(test, not(target_arch = "wasm32")))]
+    pub(crate) fn join_cleanups(&self) {
+        let pending = mem::take(&mut *self.cleanups.lock().unwrap());
+        for handle in pending {
+            handle.join().unwrap();
+        }
+    }
 }
 
 impl<'s> Storage<'s> for MemStorage {
@@ -48,6 +66,10 @@ impl<'s> Storage<'s> for MemStorage {
         "mem"
     }
 
+    fn isolates_writes(&self) -> bool {
+        true
+    }
+
     fn transact(&'s self, write: bool) -> Result<Self::Tx> {
         Ok(if write {
             let wtr = self.store.write().unwrap();
@@ -77,7 +99,11 @@ impl<'s> Storage<'s> for MemStorage {
         #[cfg(target_arch = "wasm32")]
         closure();
         #[cfg(not(target_arch = "wasm32"))]
-        std::thread::spawn(closure);
+        {
+            let mut cleanups = self.cleanups.lock().unwrap();
+            cleanups.retain(|handle| !handle.is_finished());
+            cleanups.push(std::thread::spawn(closure));
+        }
         Ok(())
     }
 
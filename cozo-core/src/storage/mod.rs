@@ -6,14 +6,19 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
+use std::path::Path;
+
 use itertools::Itertools;
-use miette::Result;
+use miette::{bail, Result};
 
 use crate::data::tuple::Tuple;
 use crate::data::value::ValidityTs;
 use crate::decode_tuple_from_kv;
 
 pub(crate) mod mem;
+pub(crate) mod overlay;
+pub(crate) mod profiled;
 #[cfg(feature = "storage-rocksdb")]
 pub(crate) mod rocks;
 #[cfg(feature = "storage-sled")]
@@ -33,6 +38,12 @@ pub trait Storage<'s>: Send + Sync + Clone {
     /// Returns a string that identifies the storage kind
     fn storage_kind(&self) -> &'static str;
 
+    /// Whether concurrent write transactions touching the same keys either never interleave
+    /// or fail on commit with a `storage::write_conflict` error. Defaults to `false`.
+    fn isolates_writes(&self) -> bool {
+        false
+    }
+
     /// Create a transaction object. Write ops will only be called when `write == true`.
     fn transact(&'s self, write: bool) -> Result<Self::Tx>;
 
@@ -52,6 +63,48 @@ pub trait Storage<'s>: Send + Sync + Clone {
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
     ) -> Result<()>;
+
+    /// Persist all buffered writes, e.g. before the process exits.
+    /// The default implementation does nothing.
+    fn flush(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Write a consistent copy of the database to `path`, which must not exist yet,
+    /// while it stays available for reads and writes. The copy can be opened as a database
+    /// of the same kind. The default implementation reports that this is not supported.
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        bail!(
+            "The {} storage engine does not support checkpoints to {}",
+            self.storage_kind(),
+            path.display()
+        )
+    }
+
+    /// Statistics reported by the storage engine, keyed by name.
+    /// The default implementation reports nothing.
+    fn metrics(&self) -> BTreeMap<String, u64> {
+        BTreeMap::new()
+    }
+
+    /// Estimates for the data stored in the key range from `lower` (inclusive)
+    /// to `upper` (exclusive), made without scanning it.
+    /// The default implementation returns `None`, for engines unable to estimate.
+    fn estimate_range(&self, _lower: &[u8], _upper: &[u8]) -> Option<RangeEstimate> {
+        None
+    }
+}
+
+/// Estimates made by a storage engine for the data in a key range,
+/// see [Storage::estimate_range]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RangeEstimate {
+    /// Bytes taken by the range, on disk and in memory
+    pub size: u64,
+    /// Entries in the storage units holding the range, including deletion markers
+    pub entries: u64,
+    /// Deletion markers among the entries, not yet removed by compaction
+    pub deletions: u64,
 }
 
 /// Trait for the associated transaction type of a storage engine.
@@ -0,0 +1,351 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::iter::Peekable;
+use std::mem;
+use std::sync::Arc;
+
+use crossbeam::sync::{ShardedLock, ShardedLockReadGuard, ShardedLockWriteGuard};
+use either::{Either, Left, Right};
+use miette::{bail, ensure, Diagnostic, Result};
+use thiserror::Error;
+
+use crate::data::tuple::{check_key_for_validity, Tuple};
+use crate::data::value::ValidityTs;
+use crate::runtime::relation::extend_tuple_from_v;
+use crate::storage::mem::{MemStorage, MemTx};
+use crate::storage::{Storage, StoreTx};
+use crate::Db;
+
+/// Changes made on top of the base storage, with `None` marking deleted keys
+type Changes = BTreeMap<Vec<u8>, Option<Vec<u8>>>;
+
+/// The storage of a branch of a database, obtained by [Db::branch].
+///
+/// Reads fall through to a snapshot of the database for keys the branch has not changed,
+/// whereas writes are kept in memory and never reach the database
+/// unless the branch is merged with [Db::merge_branch].
+#[derive(Clone)]
+pub struct OverlayStorage {
+    /// Copy of the database when the branch was opened, with the merged changes applied
+    base: MemStorage,
+    changes: Arc<ShardedLock<Changes>>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("Cannot merge branch: {0} keys have been changed both in the branch and in the database")]
+#[diagnostic(code(db::merge_conflict))]
+#[diagnostic(help("Open a new branch and make the changes again, the database is left unchanged"))]
+struct MergeConflict(usize);
+
+impl<'s, S: Storage<'s>> Db<S> {
+    /// Open a branch of the database, in which queries see the data of the database
+    /// as it is now and writes are kept in memory without affecting the database,
+    /// e.g. to try out a migration against real data. Dropping the branch discards its changes.
+    ///
+    /// The data of the database is copied into memory when the branch is opened,
+    /// so that later writes to the database do not show in the branch.
+    pub fn branch(&'s self) -> Result<Db<OverlayStorage>> {
+        let base = MemStorage::default();
+        {
+            let tx = self.db.transact(false)?;
+            base.batch_put(tx.total_scan())?;
+        }
+        let ret = Db::new(OverlayStorage {
+            base,
+            changes: Default::default(),
+        })?;
+        ret.initialize()?;
+        Ok(ret)
+    }
+
+    /// Write the changes made in `branch`, which must have been opened from this database,
+    /// into the database in a single transaction, leaving the branch without changes.
+    /// Fails without writing anything if any key changed by the branch has also been
+    /// changed in the database since the branch was opened, e.g. when relations have been
+    /// created in both, instead of losing the change made in the database.
+    pub fn merge_branch(&'s self, branch: &Db<OverlayStorage>) -> Result<()> {
+        let mut changes = branch.db.changes.write().unwrap();
+        {
+            let snapshot = branch.db.base.transact(false)?;
+            let mut tx = self.transact_write()?;
+            let mut conflicts = 0;
+            for k in changes.keys() {
+                if tx.store_tx.get(k, true)? != snapshot.get(k, false)? {
+                    conflicts += 1;
+                }
+            }
+            ensure!(conflicts == 0, MergeConflict(conflicts));
+            for (k, v) in changes.iter() {
+                match v {
+                    Some(v) => tx.store_tx.put(k, v)?,
+                    None => tx.store_tx.del(k)?,
+                }
+            }
+            tx.commit_tx()?;
+        }
+        // the branch goes on from the merged state
+        let mut snapshot = branch.db.base.transact(true)?;
+        for (k, v) in mem::take(&mut *changes) {
+            match v {
+                Some(v) => snapshot.put(&k, &v)?,
+                None => snapshot.del(&k)?,
+            }
+        }
+        snapshot.commit()?;
+        self.load_last_ids()
+    }
+}
+
+impl<'s> Storage<'s> for OverlayStorage {
+    type Tx = OverlayTx<'s>;
+
+    fn storage_kind(&self) -> &'static str {
+        "overlay"
+    }
+
+    fn isolates_writes(&self) -> bool {
+        true
+    }
+
+    fn transact(&'s self, write: bool) -> Result<Self::Tx> {
+        let changes = if write {
+            Right(self.changes.write().unwrap())
+        } else {
+            Left(self.changes.read().unwrap())
+        };
+        Ok(OverlayTx {
+            base: self.base.transact(false)?,
+            changes,
+            cache: Default::default(),
+        })
+    }
+
+    fn del_range(&'s self, lower: &[u8], upper: &[u8]) -> Result<()> {
+        let mut changes = self.changes.write().unwrap();
+        for (_, v) in changes.range_mut(lower.to_vec()..upper.to_vec()) {
+            *v = None;
+        }
+        let base = self.base.transact(false)?;
+        for kv_res in base.range_scan(lower, upper) {
+            let (k, _) = kv_res?;
+            changes.insert(k, None);
+        }
+        Ok(())
+    }
+
+    fn range_compact(&'s self, _lower: &[u8], _upper: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    fn batch_put<'a>(
+        &'a self,
+        data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
+    ) -> Result<()> {
+        let mut changes = self.changes.write().unwrap();
+        for pair in data {
+            let (k, v) = pair?;
+            changes.insert(k, Some(v));
+        }
+        Ok(())
+    }
+}
+
+pub struct OverlayTx<'s> {
+    base: MemTx<'s>,
+    changes: Either<ShardedLockReadGuard<'s, Changes>, ShardedLockWriteGuard<'s, Changes>>,
+    /// Changes made in this transaction, applied to `changes` on commit
+    cache: Changes,
+}
+
+impl<'s> OverlayTx<'s> {
+    fn changes(&self) -> &Changes {
+        match &self.changes {
+            Left(rdr) => rdr,
+            Right(wtr) => wtr,
+        }
+    }
+    fn changed(&self, key: &[u8]) -> Option<&Option<Vec<u8>>> {
+        self.cache.get(key).or_else(|| self.changes().get(key))
+    }
+}
+
+impl<'s> StoreTx<'s> for OverlayTx<'s> {
+    fn get(&self, key: &[u8], _for_update: bool) -> Result<Option<Vec<u8>>> {
+        match self.changed(key) {
+            Some(v) => Ok(v.clone()),
+            None => self.base.get(key, false),
+        }
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        if self.changes.is_left() {
+            bail!("write in read transaction")
+        }
+        self.cache.insert(key.to_vec(), Some(val.to_vec()));
+        Ok(())
+    }
+
+    fn supports_par_put(&self) -> bool {
+        false
+    }
+
+    fn par_put(&self, _key: &[u8], _val: &[u8]) -> Result<()> {
+        panic!()
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        if self.changes.is_left() {
+            bail!("write in read transaction")
+        }
+        self.cache.insert(key.to_vec(), None);
+        Ok(())
+    }
+
+    fn exists(&self, key: &[u8], _for_update: bool) -> Result<bool> {
+        match self.changed(key) {
+            Some(v) => Ok(v.is_some()),
+            None => self.base.exists(key, false),
+        }
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        if let Right(wtr) = &mut self.changes {
+            wtr.extend(mem::take(&mut self.cache));
+        }
+        Ok(())
+    }
+
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        Box::new(SkipIterator {
+            tx: self,
+            upper: upper.to_vec(),
+            valid_at,
+            next_bound: lower.to_vec(),
+        })
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let range = lower.to_vec()..upper.to_vec();
+        Box::new(MergeIter::new(
+            self.cache.range(range.clone()),
+            MergeIter::new(
+                self.changes().range(range),
+                self.base.range_scan(lower, upper),
+            ),
+        ))
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        Box::new(MergeIter::new(
+            self.cache.iter(),
+            MergeIter::new(self.changes().iter(), self.base.total_scan()),
+        ))
+    }
+}
+
+/// Iterator over the pairs of `base` with `changes` applied, both in ascending order of keys
+struct MergeIter<'a, C, B>
+where
+    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)>,
+    B: Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+{
+    changes: Peekable<C>,
+    base: Peekable<B>,
+}
+
+impl<'a, C, B> MergeIter<'a, C, B>
+where
+    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)>,
+    B: Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+{
+    fn new(changes: C, base: B) -> Self {
+        Self {
+            changes: changes.peekable(),
+            base: base.peekable(),
+        }
+    }
+}
+
+impl<'a, C, B> Iterator for MergeIter<'a, C, B>
+where
+    C: Iterator<Item = (&'a Vec<u8>, &'a Option<Vec<u8>>)>,
+    B: Iterator<Item = Result<(Vec<u8>, Vec<u8>)>>,
+{
+    type Item = Result<(Vec<u8>, Vec<u8>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(Err(_)) = self.base.peek() {
+                return self.base.next();
+            }
+            let order = match (self.changes.peek(), self.base.peek()) {
+                (None, None) => return None,
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (Some((ck, _)), Some(Ok((bk, _)))) => ck.as_slice().cmp(bk),
+                (Some(_), Some(Err(_))) => unreachable!(),
+            };
+            match order {
+                Ordering::Greater => return self.base.next(),
+                Ordering::Equal => {
+                    self.base.next();
+                }
+                Ordering::Less => {}
+            }
+            let (k, v) = self.changes.next().unwrap();
+            if let Some(v) = v {
+                return Some(Ok((k.clone(), v.clone())));
+            }
+        }
+    }
+}
+
+struct SkipIterator<'a, 's> {
+    tx: &'a OverlayTx<'s>,
+    upper: Vec<u8>,
+    valid_at: ValidityTs,
+    next_bound: Vec<u8>,
+}
+
+impl<'a, 's> Iterator for SkipIterator<'a, 's> {
+    type Item = Result<Tuple>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (candidate_key, candidate_val) =
+                match self.tx.range_scan(&self.next_bound, &self.upper).next()? {
+                    Ok(kv) => kv,
+                    Err(err) => return Some(Err(err)),
+                };
+            let (ret, nxt_bound) = check_key_for_validity(&candidate_key, self.valid_at);
+            self.next_bound = nxt_bound;
+            if let Some(mut nk) = ret {
+                extend_tuple_from_v(&mut nk, &candidate_val);
+                return Some(Ok(nk));
+            }
+        }
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ * Copyright 2022, The Cozo Project Authors.
+ *
+ * This Source Code Form is subject to the terms of the Mozilla Public License, v. 2.0.
+ * If a copy of the MPL was not distributed with this file,
+ * You can obtain one at https://mozilla.org/MPL/2.0/.
+ */
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use itertools::Itertools;
+use miette::Result;
+
+use crate::data::tuple::Tuple;
+use crate::data::value::ValidityTs;
+use crate::decode_tuple_from_kv;
+use crate::storage::StoreTx;
+
+/// Counters of the operations a transaction performs on the storage while enabled,
+/// gathered for queries given `:profile`
+#[derive(Default)]
+pub(crate) struct StorageCounters {
+    enabled: AtomicBool,
+    gets: AtomicU64,
+    seeks: AtomicU64,
+    nexts: AtomicU64,
+    bytes_read: AtomicU64,
+}
+
+/// The values of [StorageCounters] at some point
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct StorageCounts {
+    /// Point lookups, by `get` or `exists`
+    pub(crate) gets: u64,
+    /// Scans started, each seeking to the start of a range
+    pub(crate) seeks: u64,
+    /// Entries returned by scans
+    pub(crate) nexts: u64,
+    /// Bytes of the keys and values returned by lookups and scans
+    pub(crate) bytes_read: u64,
+}
+
+impl StorageCounters {
+    /// Reset the counters and start counting
+    pub(crate) fn start(&self) {
+        self.gets.store(0, Ordering::Relaxed);
+        self.seeks.store(0, Ordering::Relaxed);
+        self.nexts.store(0, Ordering::Relaxed);
+        self.bytes_read.store(0, Ordering::Relaxed);
+        self.enabled.store(true, Ordering::Release);
+    }
+    /// Stop counting, returning the counts since [StorageCounters::start]
+    pub(crate) fn stop(&self) -> StorageCounts {
+        self.enabled.store(false, Ordering::Release);
+        StorageCounts {
+            gets: self.gets.load(Ordering::Relaxed),
+            seeks: self.seeks.load(Ordering::Relaxed),
+            nexts: self.nexts.load(Ordering::Relaxed),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+        }
+    }
+    fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Acquire)
+    }
+    fn count_read(&self, bytes: usize) {
+        self.nexts.fetch_add(1, Ordering::Relaxed);
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// A transaction of a storage engine that updates [StorageCounters] with the operations
+/// performed on it. Nothing is counted, and calls go straight to the inner transaction,
+/// unless the counters are enabled.
+pub(crate) struct ProfiledTx<T> {
+    inner: T,
+    counters: Arc<StorageCounters>,
+}
+
+impl<T> ProfiledTx<T> {
+    pub(crate) fn new(inner: T, counters: Arc<StorageCounters>) -> Self {
+        Self { inner, counters }
+    }
+}
+
+impl<'s, T: StoreTx<'s>> StoreTx<'s> for ProfiledTx<T> {
+    fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
+        let ret = self.inner.get(key, for_update)?;
+        if self.counters.enabled() {
+            self.counters.gets.fetch_add(1, Ordering::Relaxed);
+            if let Some(val) = &ret {
+                self.counters
+                    .bytes_read
+                    .fetch_add((key.len() + val.len()) as u64, Ordering::Relaxed);
+            }
+        }
+        Ok(ret)
+    }
+
+    fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.put(key, val)
+    }
+
+    fn supports_par_put(&self) -> bool {
+        self.inner.supports_par_put()
+    }
+
+    fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
+        self.inner.par_put(key, val)
+    }
+
+    fn del(&mut self, key: &[u8]) -> Result<()> {
+        self.inner.del(key)
+    }
+
+    fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
+        if self.counters.enabled() {
+            self.counters.gets.fetch_add(1, Ordering::Relaxed);
+        }
+        self.inner.exists(key, for_update)
+    }
+
+    fn commit(&mut self) -> Result<()> {
+        self.inner.commit()
+    }
+
+    fn range_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a>
+    where
+        's: 'a,
+    {
+        if !self.counters.enabled() {
+            return self.inner.range_scan_tuple(lower, upper);
+        }
+        // going through the raw scan, as the size of decoded tuples is not known
+        let it = self.range_scan(lower, upper);
+        Box::new(it.map_ok(|(k, v)| decode_tuple_from_kv(&k, &v)))
+    }
+
+    /// The seeks made by the engine to skip over superseded versions, and the bytes read,
+    /// are not counted for these scans.
+    fn range_skip_scan_tuple<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+        valid_at: ValidityTs,
+    ) -> Box<dyn Iterator<Item = Result<Tuple>> + 'a> {
+        let it = self.inner.range_skip_scan_tuple(lower, upper, valid_at);
+        if !self.counters.enabled() {
+            return it;
+        }
+        self.counters.seeks.fetch_add(1, Ordering::Relaxed);
+        Box::new(it.inspect(|res| {
+            if res.is_ok() {
+                self.counters.count_read(0)
+            }
+        }))
+    }
+
+    fn range_scan<'a>(
+        &'a self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let it = self.inner.range_scan(lower, upper);
+        if !self.counters.enabled() {
+            return it;
+        }
+        self.counters.seeks.fetch_add(1, Ordering::Relaxed);
+        Box::new(it.inspect(|res| {
+            if let Ok((k, v)) = res {
+                self.counters.count_read(k.len() + v.len())
+            }
+        }))
+    }
+
+    fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
+    where
+        's: 'a,
+    {
+        let it = self.inner.total_scan();
+        if !self.counters.enabled() {
+            return it;
+        }
+        self.counters.seeks.fetch_add(1, Ordering::Relaxed);
+        Box::new(it.inspect(|res| {
+            if let Ok((k, v)) = res {
+                self.counters.count_read(k.len() + v.len())
+            }
+        }))
+    }
+}
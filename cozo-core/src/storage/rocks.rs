@@ -6,19 +6,21 @@
  * You can obtain one at https://mozilla.org/MPL/2.0/.
  */
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 
 use log::info;
-use miette::{miette, IntoDiagnostic, Result, WrapErr};
+use miette::{bail, miette, Diagnostic, IntoDiagnostic, Report, Result, WrapErr};
+use thiserror::Error;
 
-use cozorocks::{DbBuilder, DbIter, RocksDb, Tx};
+use cozorocks::{DbBuilder, DbIter, RocksDb, RocksDbStatus, StatusCode, StatusSubCode, Tx};
 
 use crate::data::tuple::{check_key_for_validity, Tuple};
 use crate::data::value::ValidityTs;
 use crate::runtime::db::{BadDbInit, DbManifest};
 use crate::runtime::relation::{decode_tuple_from_kv, extend_tuple_from_v};
-use crate::storage::{Storage, StoreTx};
+use crate::storage::{RangeEstimate, Storage, StoreTx};
 use crate::utils::swap_option_result;
 use crate::Db;
 
@@ -30,6 +32,19 @@ const CURRENT_STORAGE_VERSION: u64 = 1;
 /// sustain huge concurrency.
 /// Supports concurrent readers and writers.
 pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
+    new_cozo_rocksdb_with_tx_mode(path, false)
+}
+
+/// Same as [new_cozo_rocksdb], but choosing the mode of transactions.
+///
+/// With `optimistic` set, writes do not take locks and conflicting transactions fail
+/// when they commit, which suits workloads with little contention. Otherwise transactions
+/// lock the keys they write, making conflicting transactions wait for each other.
+/// The mode is fixed for as long as the database stays open.
+pub fn new_cozo_rocksdb_with_tx_mode(
+    path: impl AsRef<Path>,
+    optimistic: bool,
+) -> Result<Db<RocksDbStorage>> {
     let builder = DbBuilder::default().path(path.as_ref());
     fs::create_dir_all(path.as_ref()).map_err(|err| {
         BadDbInit(format!(
@@ -101,7 +116,8 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
         .use_capped_prefix_extractor(true, KEY_PREFIX_LEN)
         .use_bloom_filter(true, 9.9, true)
         .path(store_path)
-        .options_path(options_path);
+        .options_path(options_path)
+        .optimistic(optimistic);
 
     let db = db_builder.build()?;
 
@@ -110,6 +126,20 @@ pub fn new_cozo_rocksdb(path: impl AsRef<Path>) -> Result<Db<RocksDbStorage>> {
     Ok(ret)
 }
 
+/// Integer-valued RocksDB properties reported as storage metrics
+const ROCKSDB_METRICS: &[&str] = &[
+    "rocksdb.estimate-num-keys",
+    "rocksdb.estimate-live-data-size",
+    "rocksdb.total-sst-files-size",
+    "rocksdb.cur-size-all-mem-tables",
+    "rocksdb.block-cache-usage",
+    "rocksdb.block-cache-pinned-usage",
+    "rocksdb.estimate-pending-compaction-bytes",
+    "rocksdb.num-running-compactions",
+    "rocksdb.num-running-flushes",
+    "rocksdb.num-snapshots",
+];
+
 /// RocksDB storage engine
 #[derive(Clone)]
 pub struct RocksDbStorage {
@@ -129,6 +159,26 @@ impl Storage<'_> for RocksDbStorage {
         "rocksdb"
     }
 
+    fn isolates_writes(&self) -> bool {
+        true
+    }
+
+    fn metrics(&self) -> BTreeMap<String, u64> {
+        ROCKSDB_METRICS
+            .iter()
+            .filter_map(|name| Some((name.to_string(), self.db.int_property(name)?)))
+            .collect()
+    }
+
+    fn estimate_range(&self, lower: &[u8], upper: &[u8]) -> Option<RangeEstimate> {
+        let (entries, deletions) = self.db.table_entry_counts(lower, upper).ok()?;
+        Some(RangeEstimate {
+            size: self.db.approximate_size(lower, upper),
+            entries,
+            deletions,
+        })
+    }
+
     fn transact(&self, _write: bool) -> Result<Self::Tx> {
         let db_tx = self.db.transact().set_snapshot(true).start();
         Ok(RocksDbTx { db_tx })
@@ -142,6 +192,31 @@ impl Storage<'_> for RocksDbStorage {
         self.db.range_compact(lower, upper).into_diagnostic()
     }
 
+    fn flush(&self) -> Result<()> {
+        self.db.flush().into_diagnostic()
+    }
+
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            bail!("Cannot create checkpoint: {} already exists", path.display());
+        }
+        // the store lives in the `data` subdirectory, next to the manifest and options files
+        let store_path = PathBuf::from(self.db.db_path());
+        let root = store_path
+            .parent()
+            .ok_or_else(|| miette!("bad path name"))?;
+        fs::create_dir_all(path).into_diagnostic()?;
+        for file in ["manifest", "options"] {
+            let src = root.join(file);
+            if src.exists() {
+                fs::copy(src, path.join(file)).into_diagnostic()?;
+            }
+        }
+        let target = path.join("data");
+        let target = target.to_str().ok_or_else(|| miette!("bad path name"))?;
+        self.db.create_checkpoint(target).into_diagnostic()
+    }
+
     fn batch_put<'a>(
         &'a self,
         data: Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>,
@@ -154,6 +229,27 @@ impl Storage<'_> for RocksDbStorage {
     }
 }
 
+#[derive(Debug, Error, Diagnostic)]
+#[error("Transaction conflicts with a concurrent write: {0}")]
+#[diagnostic(code(storage::write_conflict))]
+#[diagnostic(help("Retrying the transaction may succeed"))]
+struct WriteConflict(String);
+
+/// Lock contention between transactions is reported as a [WriteConflict],
+/// so that clients can retry without matching on RocksDB status codes.
+fn tx_error(status: RocksDbStatus) -> Report {
+    let is_conflict = match status.code {
+        StatusCode::kBusy | StatusCode::kTryAgain => true,
+        StatusCode::kTimedOut => status.subcode == StatusSubCode::kLockTimeout,
+        _ => false,
+    };
+    if is_conflict {
+        WriteConflict(status.message).into()
+    } else {
+        status.into()
+    }
+}
+
 pub struct RocksDbTx {
     db_tx: Tx,
 }
@@ -163,12 +259,16 @@ unsafe impl Sync for RocksDbTx {}
 impl<'s> StoreTx<'s> for RocksDbTx {
     #[inline]
     fn get(&self, key: &[u8], for_update: bool) -> Result<Option<Vec<u8>>> {
-        Ok(self.db_tx.get(key, for_update)?.map(|v| v.to_vec()))
+        Ok(self
+            .db_tx
+            .get(key, for_update)
+            .map_err(tx_error)?
+            .map(|v| v.to_vec()))
     }
 
     #[inline]
     fn put(&mut self, key: &[u8], val: &[u8]) -> Result<()> {
-        Ok(self.db_tx.put(key, val)?)
+        self.db_tx.put(key, val).map_err(tx_error)
     }
 
     fn supports_par_put(&self) -> bool {
@@ -176,21 +276,21 @@ impl<'s> StoreTx<'s> for RocksDbTx {
     }
 
     fn par_put(&self, key: &[u8], val: &[u8]) -> Result<()> {
-        Ok(self.db_tx.put(key, val)?)
+        self.db_tx.put(key, val).map_err(tx_error)
     }
 
     #[inline]
     fn del(&mut self, key: &[u8]) -> Result<()> {
-        Ok(self.db_tx.del(key)?)
+        self.db_tx.del(key).map_err(tx_error)
     }
 
     #[inline]
     fn exists(&self, key: &[u8], for_update: bool) -> Result<bool> {
-        Ok(self.db_tx.exists(key, for_update)?)
+        self.db_tx.exists(key, for_update).map_err(tx_error)
     }
 
     fn commit(&mut self) -> Result<()> {
-        Ok(self.db_tx.commit()?)
+        self.db_tx.commit().map_err(tx_error)
     }
 
     fn range_scan_tuple<'a>(
@@ -203,11 +303,7 @@ impl<'s> StoreTx<'s> for RocksDbTx {
     {
         let mut inner = self.db_tx.iterator().upper_bound(upper).start();
         inner.seek(lower);
-        Box::new(RocksDbIterator {
-            inner,
-            started: false,
-            upper_bound: upper.to_vec(),
-        })
+        Box::new(RocksDbIterator::new(inner, upper, decode_tuple_from_kv))
     }
 
     fn range_skip_scan_tuple<'a>(
@@ -235,51 +331,95 @@ impl<'s> StoreTx<'s> for RocksDbTx {
     {
         let mut inner = self.db_tx.iterator().upper_bound(upper).start();
         inner.seek(lower);
-        Box::new(RocksDbIteratorRaw {
-            inner,
-            started: false,
-            upper_bound: upper.to_vec(),
-        })
+        Box::new(RocksDbIterator::new(inner, upper, |k, v| {
+            (k.to_vec(), v.to_vec())
+        }))
     }
 
     fn total_scan<'a>(&'a self) -> Box<dyn Iterator<Item = Result<(Vec<u8>, Vec<u8>)>> + 'a>
     where
         's: 'a,
     {
-        self.range_scan(&[], &[u8::MAX])
+        let upper = [u8::MAX];
+        let mut inner = self
+            .db_tx
+            .iterator()
+            .upper_bound(&upper)
+            .readahead_size(TOTAL_SCAN_READAHEAD)
+            .start();
+        inner.seek(&[]);
+        Box::new(RocksDbIterator::new(inner, &upper, |k, v| {
+            (k.to_vec(), v.to_vec())
+        }))
     }
 }
 
-pub(crate) struct RocksDbIterator {
+/// Batches in which scans fetch entries start at this size, so that short scans,
+/// e.g. those cut by `:limit`, read little more than they need
+const MIN_SCAN_BATCH: usize = 16;
+/// Batches double in size up to this one as the scan goes on
+const MAX_SCAN_BATCH: usize = 1024;
+/// Bytes read ahead from files when scanning the whole database, which is long and sequential
+const TOTAL_SCAN_READAHEAD: usize = 2 << 20;
+
+/// Iterator over a range fetching and decoding entries in batches, so that the cost of
+/// crossing the FFI boundary is paid once per batch instead of once per entry
+pub(crate) struct RocksDbIterator<T> {
     inner: DbIter,
-    started: bool,
     upper_bound: Vec<u8>,
+    decode: fn(&[u8], &[u8]) -> T,
+    buffer: std::vec::IntoIter<T>,
+    batch_size: usize,
+    exhausted: bool,
 }
 
-impl RocksDbIterator {
-    #[inline]
-    fn next_inner(&mut self) -> Result<Option<Tuple>> {
-        if self.started {
-            self.inner.next()
-        } else {
-            self.started = true;
+impl<T> RocksDbIterator<T> {
+    fn new(inner: DbIter, upper_bound: &[u8], decode: fn(&[u8], &[u8]) -> T) -> Self {
+        Self {
+            inner,
+            upper_bound: upper_bound.to_vec(),
+            decode,
+            buffer: vec![].into_iter(),
+            batch_size: MIN_SCAN_BATCH,
+            exhausted: false,
         }
-        Ok(match self.inner.pair()? {
-            None => None,
-            Some((k_slice, v_slice)) => {
-                if self.upper_bound.as_slice() <= k_slice {
-                    None
-                } else {
-                    // upper bound is exclusive
-                    Some(decode_tuple_from_kv(k_slice, v_slice))
-                }
+    }
+
+    fn fill_buffer(&mut self) -> Result<()> {
+        let batch = self.inner.next_batch(self.batch_size)?;
+        if batch.len() < self.batch_size {
+            self.exhausted = true;
+        }
+        let mut decoded = Vec::with_capacity(batch.len());
+        for (k_slice, v_slice) in batch {
+            // upper bound is exclusive
+            if self.upper_bound.as_slice() <= k_slice {
+                self.exhausted = true;
+                break;
             }
-        })
+            decoded.push((self.decode)(k_slice, v_slice));
+        }
+        self.buffer = decoded.into_iter();
+        self.batch_size = (self.batch_size * 2).min(MAX_SCAN_BATCH);
+        Ok(())
+    }
+
+    #[inline]
+    fn next_inner(&mut self) -> Result<Option<T>> {
+        loop {
+            if let Some(item) = self.buffer.next() {
+                return Ok(Some(item));
+            }
+            if self.exhausted {
+                return Ok(None);
+            }
+            self.fill_buffer()?;
+        }
     }
 }
 
-impl Iterator for RocksDbIterator {
-    type Item = Result<Tuple>;
+impl<T> Iterator for RocksDbIterator<T> {
+    type Item = Result<T>;
     #[inline]
     fn next(&mut self) -> Option<Self::Item> {
         swap_option_result(self.next_inner())
@@ -324,39 +464,3 @@ impl Iterator for RocksDbSkipIterator {
         swap_option_result(self.next_inner())
     }
 }
-
-pub(crate) struct RocksDbIteratorRaw {
-    inner: DbIter,
-    started: bool,
-    upper_bound: Vec<u8>,
-}
-
-impl RocksDbIteratorRaw {
-    #[inline]
-    fn next_inner(&mut self) -> Result<Option<(Vec<u8>, Vec<u8>)>> {
-        if self.started {
-            self.inner.next()
-        } else {
-            self.started = true;
-        }
-        Ok(match self.inner.pair()? {
-            None => None,
-            Some((k_slice, v_slice)) => {
-                if self.upper_bound.as_slice() <= k_slice {
-                    // upper bound is exclusive
-                    None
-                } else {
-                    Some((k_slice.to_vec(), v_slice.to_vec()))
-                }
-            }
-        })
-    }
-}
-
-impl Iterator for RocksDbIteratorRaw {
-    type Item = Result<(Vec<u8>, Vec<u8>)>;
-    #[inline]
-    fn next(&mut self) -> Option<Self::Item> {
-        swap_option_result(self.next_inner())
-    }
-}
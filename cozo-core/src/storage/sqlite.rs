@@ -131,9 +131,36 @@ impl<'s> Storage<'s> for SqliteStorage {
         Ok(())
     }
 
+    fn flush(&self) -> Result<()> {
+        // closes the pooled connections, so that only those of running transactions stay open
+        let mut pool = self.pool.lock().unwrap();
+        while pool.pop().is_some() {}
+        Ok(())
+    }
+
     fn storage_kind(&self) -> &'static str {
         "sqlite"
     }
+
+    fn isolates_writes(&self) -> bool {
+        true
+    }
+
+    fn checkpoint(&self, path: &Path) -> Result<()> {
+        if path.exists() {
+            bail!("Cannot create checkpoint: {} already exists", path.display());
+        }
+        let target = path
+            .to_str()
+            .ok_or_else(|| miette!("bad path name {}", path.display()))?;
+        // keeps writers out, so that the copy reflects the last committed transaction
+        let _locked = self.lock.read().unwrap();
+        let conn = Connection::open_with_full_mutex(&self.name).into_diagnostic()?;
+        let mut statement = conn.prepare("vacuum into ?;").into_diagnostic()?;
+        statement.bind((1, target)).into_diagnostic()?;
+        while statement.next().into_diagnostic()? != State::Done {}
+        Ok(())
+    }
 }
 
 pub struct SqliteTx<'a> {
@@ -14,3 +14,40 @@ pub(crate) fn swap_option_result<T, E>(d: Result<Option<T>, E>) -> Option<Result
         Err(e) => Some(Err(e)),
     }
 }
+
+/// Levenshtein edit distance between two strings, counted in chars.
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+    let mut cur = vec![0; b_chars.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b_chars.iter().enumerate() {
+            let subst = prev[j] + usize::from(ca != *cb);
+            cur[j + 1] = subst.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b_chars.len()]
+}
+
+/// Names among `candidates` that are likely typos of `target`, closest first.
+pub(crate) fn near_misses<'a>(
+    target: &str,
+    candidates: impl IntoIterator<Item = &'a str>,
+) -> Vec<&'a str> {
+    let threshold = (target.chars().count() / 3).max(2);
+    let mut found: Vec<(usize, &'a str)> = candidates
+        .into_iter()
+        .filter_map(|c| {
+            let d = edit_distance(target, c);
+            if d <= threshold {
+                Some((d, c))
+            } else {
+                None
+            }
+        })
+        .collect();
+    found.sort();
+    found.into_iter().map(|(_, c)| c).collect()
+}
@@ -125,6 +125,8 @@ fn value2js<'a>(cx: &mut impl Context<'a>, val: &DataValue) -> JsResult<'a, JsVa
             target_l.set(cx, 1, a)?;
             target_l.as_value(cx)
         }
+        DataValue::BigInt(i) => cx.string(i.0.to_string()).as_value(cx),
+        DataValue::Decimal(d) => cx.string(d.to_string()).as_value(cx),
         DataValue::Bot => cx.undefined().as_value(cx),
     })
 }
@@ -124,11 +124,17 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
 
     db->db_path = convert_vec_to_string(opts.db_path);
 
-    TransactionDB *txn_db = nullptr;
-    write_status(
-            TransactionDB::Open(options, TransactionDBOptions(), db->db_path, &txn_db),
-            status);
-    db->db.reset(txn_db);
+    if (opts.optimistic) {
+        OptimisticTransactionDB *txn_db = nullptr;
+        write_status(OptimisticTransactionDB::Open(options, db->db_path, &txn_db), status);
+        db->odb.reset(txn_db);
+    } else {
+        TransactionDB *txn_db = nullptr;
+        write_status(
+                TransactionDB::Open(options, TransactionDBOptions(), db->db_path, &txn_db),
+                status);
+        db->db.reset(txn_db);
+    }
     db->destroy_on_exit = opts.destroy_on_exit;
 
 
@@ -136,13 +142,14 @@ shared_ptr <RocksDbBridge> open_db(const DbOpts &opts, RocksDbStatus &status) {
 }
 
 RocksDbBridge::~RocksDbBridge() {
-    if (destroy_on_exit && (db != nullptr)) {
+    if (destroy_on_exit && (db != nullptr || odb != nullptr)) {
         cerr << "destroying database on exit: " << db_path << endl;
-        auto status = db->Close();
+        auto status = odb != nullptr ? odb->Close() : db->Close();
         if (!status.ok()) {
             cerr << status.ToString() << endl;
         }
         db.reset();
+        odb.reset();
         Options options{};
         auto status2 = DestroyDB(db_path, options);
         if (!status2.ok()) {
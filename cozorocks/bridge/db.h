@@ -46,13 +46,14 @@ static WriteOptions DEFAULT_WRITE_OPTIONS = WriteOptions();
 
 struct RocksDbBridge {
     unique_ptr<TransactionDB> db;
+    unique_ptr<OptimisticTransactionDB> odb;
 
     bool destroy_on_exit;
     string db_path;
 
     inline unique_ptr<SstFileWriterBridge> get_sst_writer(rust::Str path, RocksDbStatus &status) const {
         DB *db_ = get_base_db();
-        auto cf = db->DefaultColumnFamily();
+        auto cf = db_->DefaultColumnFamily();
         Options options_ = db_->GetOptions(cf);
         auto sst_file_writer = std::make_unique<SstFileWriterBridge>(EnvOptions(), options_);
         string path_(path);
@@ -65,7 +66,7 @@ struct RocksDbBridge {
         IngestExternalFileOptions ifo;
         DB *db_ = get_base_db();
         string path_(path);
-        auto cf = db->DefaultColumnFamily();
+        auto cf = db_->DefaultColumnFamily();
         write_status(db_->IngestExternalFile(cf, {std::move(path_)}, ifo), status);
     }
 
@@ -73,21 +74,62 @@ struct RocksDbBridge {
         return db_path;
     }
 
+    inline bool get_int_property(rust::Str name, uint64_t &value) const {
+        string name_(name);
+        return get_base_db()->GetIntProperty(name_, &value);
+    }
+
+    inline uint64_t get_approximate_size(RustBytes start, RustBytes end) const {
+        auto db_ = get_base_db();
+        Range range(convert_slice(start), convert_slice(end));
+        SizeApproximationOptions options;
+        options.include_memtables = true;
+        options.include_files = true;
+        uint64_t size = 0;
+        auto s = db_->GetApproximateSizes(options, db_->DefaultColumnFamily(), &range, 1, &size);
+        return s.ok() ? size : 0;
+    }
+
+    inline void get_table_entry_counts(RustBytes start, RustBytes end, uint64_t &entries,
+                                       uint64_t &deletions, RocksDbStatus &status) const {
+        auto db_ = get_base_db();
+        Range range(convert_slice(start), convert_slice(end));
+        TablePropertiesCollection props;
+        auto s = db_->GetPropertiesOfTablesInRange(db_->DefaultColumnFamily(), &range, 1, &props);
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        entries = 0;
+        deletions = 0;
+        for (const auto &file: props) {
+            entries += file.second->num_entries;
+            deletions += file.second->num_deletions;
+        }
+    }
+
 
     [[nodiscard]] inline unique_ptr<TxBridge> transact() const {
+        if (odb != nullptr) {
+            return make_unique<TxBridge>(&*this->odb, odb->DefaultColumnFamily());
+        }
         auto ret = make_unique<TxBridge>(&*this->db, db->DefaultColumnFamily());
         return ret;
     }
 
     inline void del_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
         WriteBatch batch;
-        auto cf = db->DefaultColumnFamily();
+        auto cf = get_base_db()->DefaultColumnFamily();
         auto s = batch.DeleteRange(cf, convert_slice(start), convert_slice(end));
         if (!s.ok()) {
             write_status(s, status);
             return;
         }
         WriteOptions w_opts;
+        if (odb != nullptr) {
+            write_status(odb->Write(w_opts, &batch), status);
+            return;
+        }
         TransactionDBWriteOptimizations optimizations;
         optimizations.skip_concurrency_control = true;
         optimizations.skip_duplicate_key_check = true;
@@ -103,14 +145,40 @@ struct RocksDbBridge {
 
     void compact_range(RustBytes start, RustBytes end, RocksDbStatus &status) const {
         CompactRangeOptions options;
-        auto cf = db->DefaultColumnFamily();
+        auto db_ = get_base_db();
+        auto cf = db_->DefaultColumnFamily();
         auto start_s = convert_slice(start);
         auto end_s = convert_slice(end);
-        auto s = db->CompactRange(options, cf, &start_s, &end_s);
+        auto s = db_->CompactRange(options, cf, &start_s, &end_s);
         write_status(s, status);
     }
 
+    void flush(RocksDbStatus &status) const {
+        auto db_ = get_base_db();
+        auto s = db_->Flush(FlushOptions());
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        write_status(db_->FlushWAL(true), status);
+    }
+
+    void create_checkpoint(rust::Str path, RocksDbStatus &status) const {
+        Checkpoint *checkpoint = nullptr;
+        auto s = Checkpoint::Create(get_base_db(), &checkpoint);
+        if (!s.ok()) {
+            write_status(s, status);
+            return;
+        }
+        unique_ptr<Checkpoint> guard(checkpoint);
+        string path_(path);
+        write_status(checkpoint->CreateCheckpoint(path_), status);
+    }
+
     DB *get_base_db() const {
+        if (odb != nullptr) {
+            return odb->GetBaseDB();
+        }
         return db->GetBaseDB();
     }
 
@@ -20,6 +20,7 @@ struct IterBridge {
     Slice lower_bound;
     Slice upper_bound;
     unique_ptr<ReadOptions> r_opts;
+    string batch_storage;
 
     explicit IterBridge(Transaction *tx_) : db(nullptr), tx(tx_), iter(nullptr), lower_bound(),
                                                                      upper_bound(),
@@ -64,6 +65,10 @@ struct IterBridge {
         r_opts->pin_data = val;
     }
 
+    inline void readahead_size(size_t val) {
+        r_opts->readahead_size = val;
+    }
+
     inline void clear_bounds() {
         r_opts->iterate_lower_bound = nullptr;
         r_opts->iterate_upper_bound = nullptr;
@@ -135,6 +140,37 @@ struct IterBridge {
     [[nodiscard]] inline RustBytes val() const {
         return convert_slice_back(iter->value());
     }
+
+    // Fetch up to `max` entries starting at the current position into the batch,
+    // leaving the iterator after the last one fetched, so that a whole batch crosses
+    // the FFI boundary at once. Returns the number of entries fetched.
+    inline size_t fill_batch(size_t max, RocksDbStatus &status) {
+        batch_storage.clear();
+        size_t n = 0;
+        while (n < max && iter->Valid()) {
+            append_to_batch(iter->key());
+            append_to_batch(iter->value());
+            iter->Next();
+            ++n;
+        }
+        write_status(iter->status(), status);
+        return n;
+    }
+
+    // Each key and value in the batch is preceded by its length as 4 bytes in little-endian
+    [[nodiscard]] inline RustBytes batch() const {
+        return convert_slice_back(batch_storage);
+    }
+
+    inline void append_to_batch(const Slice &s) {
+        auto len = static_cast<uint32_t>(s.size());
+        char len_bytes[4];
+        for (int i = 0; i < 4; ++i) {
+            len_bytes[i] = static_cast<char>((len >> (8 * i)) & 0xff);
+        }
+        batch_storage.append(len_bytes, 4);
+        batch_storage.append(s.data(), s.size());
+    }
 };
 
 #endif //COZOROCKS_ITER_H
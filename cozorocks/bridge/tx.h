@@ -34,6 +34,18 @@ struct TxBridge {
         r_opts->ignore_range_deletions = true;
     }
 
+    explicit TxBridge(OptimisticTransactionDB *odb_, ColumnFamilyHandle * cf_handle_) :
+            odb(odb_),
+            tdb(nullptr),
+            tx(),
+            w_opts(new WriteOptions),
+            r_opts(new ReadOptions),
+            o_tx_opts(new OptimisticTransactionOptions),
+            p_tx_opts(nullptr),
+            cf_handle(cf_handle_) {
+        r_opts->ignore_range_deletions = true;
+    }
+
     inline WriteOptions &get_w_opts() {
         return *w_opts;
     }
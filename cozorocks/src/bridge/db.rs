@@ -54,6 +54,7 @@ impl Default for DbOpts {
             fixed_prefix_extractor_len: 0,
             destroy_on_exit: false,
             block_cache_size: 0,
+            optimistic: false,
         }
     }
 }
@@ -121,6 +122,12 @@ impl DbBuilder {
         self.opts.fixed_prefix_extractor_len = len;
         self
     }
+    /// Open an optimistic transaction DB, which checks for conflicts when transactions commit,
+    /// instead of the default pessimistic one, which locks keys as they are written.
+    pub fn optimistic(mut self, val: bool) -> Self {
+        self.opts.optimistic = val;
+        self
+    }
     pub fn build(self) -> Result<RocksDb, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
 
@@ -147,6 +154,41 @@ impl RocksDb {
             inner: self.inner.transact(),
         }
     }
+    /// The value of the integer-valued RocksDB property `name`, e.g. `rocksdb.estimate-num-keys`,
+    /// or `None` if there is no such property.
+    #[inline]
+    pub fn int_property(&self, name: &str) -> Option<u64> {
+        let mut value = 0;
+        if self.inner.get_int_property(name, &mut value) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+    /// The approximate number of bytes taken by the keys in `lower..upper`,
+    /// in the files on disk and in the memtables.
+    #[inline]
+    pub fn approximate_size(&self, lower: &[u8], upper: &[u8]) -> u64 {
+        self.inner.get_approximate_size(lower, upper)
+    }
+    /// The numbers of entries and of deletion entries in the table files
+    /// overlapping `lower..upper`, which may also hold keys outside the range.
+    #[inline]
+    pub fn table_entry_counts(
+        &self,
+        lower: &[u8],
+        upper: &[u8],
+    ) -> Result<(u64, u64), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        let (mut entries, mut deletions) = (0, 0);
+        self.inner
+            .get_table_entry_counts(lower, upper, &mut entries, &mut deletions, &mut status);
+        if status.is_ok() {
+            Ok((entries, deletions))
+        } else {
+            Err(status)
+        }
+    }
     #[inline]
     pub fn range_del(&self, lower: &[u8], upper: &[u8]) -> Result<(), RocksDbStatus> {
         let mut status = RocksDbStatus::default();
@@ -177,6 +219,27 @@ impl RocksDb {
             Err(status)
         }
     }
+    /// Flush the memtables and the write-ahead log to disk.
+    pub fn flush(&self) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.flush(&mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
+    /// Create an openable copy of the database at `path`, which must not exist yet.
+    /// Files are hard-linked where possible.
+    pub fn create_checkpoint(&self, path: &str) -> Result<(), RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        self.inner.create_checkpoint(path, &mut status);
+        if status.is_ok() {
+            Ok(())
+        } else {
+            Err(status)
+        }
+    }
     pub fn get_sst_writer(&self, path: &str) -> Result<SstWriter, RocksDbStatus> {
         let mut status = RocksDbStatus::default();
         let ret = self.inner.get_sst_writer(path, &mut status);
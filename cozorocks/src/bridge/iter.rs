@@ -73,6 +73,12 @@ impl IterBuilder {
         self.inner.pin_mut().pin_data(val);
         self
     }
+    /// Bytes to read ahead from files during the scan, zero meaning automatic readahead
+    #[inline]
+    pub fn readahead_size(mut self, val: usize) -> Self {
+        self.inner.pin_mut().readahead_size(val);
+        self
+    }
 }
 
 impl DbIter {
@@ -141,6 +147,21 @@ impl DbIter {
             }
         }
     }
+    /// Fetch up to `max` entries starting at the current position in a single call,
+    /// leaving the iterator after the last one fetched.
+    /// Fewer than `max` entries are returned only at the end of the iteration.
+    #[inline]
+    pub fn next_batch(&mut self, max: usize) -> Result<BatchEntries<'_>, RocksDbStatus> {
+        let mut status = RocksDbStatus::default();
+        let n = self.inner.pin_mut().fill_batch(max, &mut status);
+        if !status.is_ok() {
+            return Err(status);
+        }
+        Ok(BatchEntries {
+            data: self.inner.batch(),
+            remaining: n,
+        })
+    }
     #[inline]
     pub fn pair(&self) -> Result<Option<(&[u8], &[u8])>, RocksDbStatus> {
         if self.is_valid() {
@@ -155,3 +176,41 @@ impl DbIter {
         }
     }
 }
+
+/// Key-value pairs fetched by [DbIter::next_batch]
+pub struct BatchEntries<'a> {
+    data: &'a [u8],
+    remaining: usize,
+}
+
+impl<'a> BatchEntries<'a> {
+    #[inline]
+    fn take_slice(&mut self) -> &'a [u8] {
+        let (len, rest) = self.data.split_at(4);
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        let (ret, rest) = rest.split_at(len);
+        self.data = rest;
+        ret
+    }
+}
+
+impl<'a> Iterator for BatchEntries<'a> {
+    type Item = (&'a [u8], &'a [u8]);
+
+    #[inline]
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let key = self.take_slice();
+        let val = self.take_slice();
+        Some((key, val))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl ExactSizeIterator for BatchEntries<'_> {}
@@ -41,6 +41,7 @@ pub(crate) mod ffi {
         pub fixed_prefix_extractor_len: usize,
         pub destroy_on_exit: bool,
         pub block_cache_size: usize,
+        pub optimistic: bool,
     }
 
     #[derive(Clone, Debug, Eq, PartialEq)]
@@ -122,10 +123,22 @@ pub(crate) mod ffi {
 
         type RocksDbBridge;
         fn get_db_path(self: &RocksDbBridge) -> &CxxString;
+        fn get_int_property(self: &RocksDbBridge, name: &str, value: &mut u64) -> bool;
+        fn get_approximate_size(self: &RocksDbBridge, lower: &[u8], upper: &[u8]) -> u64;
+        fn get_table_entry_counts(
+            self: &RocksDbBridge,
+            lower: &[u8],
+            upper: &[u8],
+            entries: &mut u64,
+            deletions: &mut u64,
+            status: &mut RocksDbStatus,
+        );
         fn open_db(builder: &DbOpts, status: &mut RocksDbStatus) -> SharedPtr<RocksDbBridge>;
         fn transact(self: &RocksDbBridge) -> UniquePtr<TxBridge>;
         fn del_range(self: &RocksDbBridge, lower: &[u8], upper: &[u8], status: &mut RocksDbStatus);
         fn put(self: &RocksDbBridge, key: &[u8], val: &[u8], status: &mut RocksDbStatus);
+        fn flush(self: &RocksDbBridge, status: &mut RocksDbStatus);
+        fn create_checkpoint(self: &RocksDbBridge, path: &str, status: &mut RocksDbStatus);
         fn compact_range(
             self: &RocksDbBridge,
             lower: &[u8],
@@ -186,6 +199,7 @@ pub(crate) mod ffi {
         fn auto_prefix_mode(self: Pin<&mut IterBridge>, val: bool);
         fn prefix_same_as_start(self: Pin<&mut IterBridge>, val: bool);
         fn pin_data(self: Pin<&mut IterBridge>, val: bool);
+        fn readahead_size(self: Pin<&mut IterBridge>, val: usize);
 
         fn to_start(self: Pin<&mut IterBridge>);
         fn to_end(self: Pin<&mut IterBridge>);
@@ -197,6 +211,8 @@ pub(crate) mod ffi {
         fn status(self: &IterBridge, status: &mut RocksDbStatus);
         fn key(self: &IterBridge) -> &[u8];
         fn val(self: &IterBridge) -> &[u8];
+        fn fill_batch(self: Pin<&mut IterBridge>, max: usize, status: &mut RocksDbStatus) -> usize;
+        fn batch(self: &IterBridge) -> &[u8];
     }
 }
 
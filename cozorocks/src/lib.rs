@@ -16,6 +16,7 @@ pub use bridge::ffi::SnapshotBridge;
 pub use bridge::ffi::StatusCode;
 pub use bridge::ffi::StatusSeverity;
 pub use bridge::ffi::StatusSubCode;
+pub use bridge::iter::BatchEntries;
 pub use bridge::iter::DbIter;
 pub use bridge::iter::IterBuilder;
 pub use bridge::tx::PinSlice;